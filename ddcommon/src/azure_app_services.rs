@@ -15,7 +15,7 @@ const SERVICE_CONTEXT: &str = "DD_AZURE_APP_SERVICES";
 const FUNCTIONS_WORKER_RUNTIME: &str = "FUNCTIONS_WORKER_RUNTIME";
 const FUNCTIONS_EXTENSION_VERSION: &str = "FUNCTIONS_EXTENSION_VERSION";
 
-const UNKNOWN_VALUE: &str = "unknown";
+pub(crate) const UNKNOWN_VALUE: &str = "unknown";
 
 enum AzureContext {
     AzureFunctions,
@@ -51,7 +51,7 @@ pub trait QueryEnv {
     fn get_var(&self, var: &str) -> Option<String>;
 }
 
-struct RealEnv;
+pub(crate) struct RealEnv;
 
 impl QueryEnv for RealEnv {
     fn get_var(&self, var: &str) -> Option<String> {
@@ -0,0 +1,262 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::{env, fs, path::Path};
+
+use crate::azure_app_services::{self, AzureMetadata, QueryEnv, RealEnv};
+use crate::tag::Tag;
+
+/* Collect the metadata describing the host the process runs on
+
+The hostname is resolved from the environment the process is deployed in, in order:
+    - Kubernetes: the node name, exposed through the downward API as an env var, ie.
+      `env: [{name: DD_KUBERNETES_NODE_NAME, valueFrom: {fieldRef: {fieldPath: spec.nodeName}}}]`
+    - ECS: the private address of the container instance, read from the metadata file pointed
+      to by ECS_CONTAINER_METADATA_FILE
+    - Azure App Service: the instance name
+    - otherwise the hostname reported by the OS
+*/
+
+const KUBERNETES_SERVICE_HOST: &str = "KUBERNETES_SERVICE_HOST";
+const KUBERNETES_NODE_NAME_VARS: [&str; 2] = ["DD_KUBERNETES_NODE_NAME", "KUBERNETES_NODE_NAME"];
+const ECS_CONTAINER_METADATA_FILE: &str = "ECS_CONTAINER_METADATA_FILE";
+
+const UNKNOWN_HOSTNAME: &str = "unknown_hostname";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostEnvironment {
+    Kubernetes,
+    Ecs,
+    AzureAppService,
+}
+
+impl HostEnvironment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HostEnvironment::Kubernetes => "kubernetes",
+            HostEnvironment::Ecs => "ecs",
+            HostEnvironment::AzureAppService => "azure_app_service",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostInfo {
+    pub hostname: String,
+    pub environment: Option<HostEnvironment>,
+    pub os: String,
+    pub os_version: Option<String>,
+    pub kernel_name: Option<String>,
+    pub kernel_release: Option<String>,
+    pub kernel_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EcsMetadata {
+    #[serde(rename = "HostPrivateIPv4Address")]
+    host_private_ipv4_address: Option<String>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.is_empty())
+}
+
+fn kubernetes_hostname<T: QueryEnv>(query: &T) -> Option<Option<String>> {
+    query.get_var(KUBERNETES_SERVICE_HOST)?;
+    Some(
+        KUBERNETES_NODE_NAME_VARS
+            .iter()
+            .find_map(|var| non_empty(query.get_var(var))),
+    )
+}
+
+fn ecs_hostname<T: QueryEnv>(query: &T) -> Option<Option<String>> {
+    let path = non_empty(query.get_var(ECS_CONTAINER_METADATA_FILE))?;
+    Some(read_ecs_metadata(Path::new(&path)))
+}
+
+fn read_ecs_metadata(path: &Path) -> Option<String> {
+    let metadata: EcsMetadata = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    non_empty(metadata.host_private_ipv4_address)
+}
+
+fn azure_hostname(metadata: &AzureMetadata) -> Option<String> {
+    Some(metadata.get_instance_name())
+        .filter(|name| *name != azure_app_services::UNKNOWN_VALUE)
+        .map(str::to_owned)
+}
+
+/// (kernel name, kernel release, kernel version) as reported by uname
+#[cfg(unix)]
+fn uname() -> Option<(String, String, String)> {
+    use std::ffi::CStr;
+
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    // Safety: uname only writes nul terminated strings into the provided struct
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return None;
+    }
+    let field = |f: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(f.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Some((
+        field(&name.sysname),
+        field(&name.release),
+        field(&name.version),
+    ))
+}
+
+#[cfg(not(unix))]
+fn uname() -> Option<(String, String, String)> {
+    None
+}
+
+impl HostInfo {
+    /// Collects the host information. Prefer `get_host_info` which caches the result
+    pub fn collect() -> Self {
+        Self::collect_with(&RealEnv, azure_app_services::get_metadata().as_ref())
+    }
+
+    fn collect_with<T: QueryEnv>(query: &T, azure: Option<&AzureMetadata>) -> Self {
+        let (environment, hostname) = if let Some(hostname) = kubernetes_hostname(query) {
+            (Some(HostEnvironment::Kubernetes), hostname)
+        } else if let Some(hostname) = ecs_hostname(query) {
+            (Some(HostEnvironment::Ecs), hostname)
+        } else if let Some(metadata) = azure {
+            (
+                Some(HostEnvironment::AzureAppService),
+                azure_hostname(metadata),
+            )
+        } else {
+            (None, None)
+        };
+
+        let (kernel_name, kernel_release, kernel_version) = match uname() {
+            Some((name, release, version)) => (Some(name), Some(release), Some(version)),
+            None => (None, None, None),
+        };
+
+        HostInfo {
+            hostname: hostname
+                .or_else(|| sys_info::hostname().ok())
+                .unwrap_or_else(|| String::from(UNKNOWN_HOSTNAME)),
+            environment,
+            os: String::from(env::consts::OS),
+            os_version: sys_info::os_release().ok(),
+            kernel_name,
+            kernel_release,
+            kernel_version,
+        }
+    }
+
+    /// Host information as tags, to be attached to payloads sent to the backend
+    pub fn tags(&self) -> Vec<Tag> {
+        let mut tags = vec![("host", self.hostname.as_str()), ("os", self.os.as_str())];
+        if let Some(environment) = self.environment {
+            tags.push(("host_environment", environment.as_str()));
+        }
+        for (name, value) in [
+            ("os_version", &self.os_version),
+            ("kernel_name", &self.kernel_name),
+            ("kernel_release", &self.kernel_release),
+        ] {
+            if let Some(value) = value {
+                tags.push((name, value.as_str()));
+            }
+        }
+        tags.into_iter()
+            .filter_map(|(name, value)| Tag::new(name, value).ok())
+            .collect()
+    }
+}
+
+pub fn get_host_info() -> &'static HostInfo {
+    // host information doesn't change during the lifetime of the process
+    lazy_static! {
+        static ref HOST_INFO: HostInfo = HostInfo::collect();
+    }
+    &HOST_INFO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    struct MockEnv(HashMap<&'static str, String>);
+
+    impl QueryEnv for MockEnv {
+        fn get_var(&self, var: &str) -> Option<String> {
+            self.0.get(var).cloned()
+        }
+    }
+
+    fn mock_env(vars: &[(&'static str, &str)]) -> MockEnv {
+        MockEnv(vars.iter().map(|(k, v)| (*k, v.to_string())).collect())
+    }
+
+    #[test]
+    fn test_kubernetes_hostname() {
+        let env = mock_env(&[
+            (KUBERNETES_SERVICE_HOST, "10.0.0.1"),
+            ("DD_KUBERNETES_NODE_NAME", "node-1"),
+        ]);
+        let info = HostInfo::collect_with(&env, None);
+        assert_eq!(info.environment, Some(HostEnvironment::Kubernetes));
+        assert_eq!(info.hostname, "node-1");
+
+        // Falls back to the OS hostname if the node name isn't exposed
+        let env = mock_env(&[(KUBERNETES_SERVICE_HOST, "10.0.0.1")]);
+        let info = HostInfo::collect_with(&env, None);
+        assert_eq!(info.environment, Some(HostEnvironment::Kubernetes));
+        assert_eq!(info.hostname, sys_info::hostname().unwrap());
+    }
+
+    #[test]
+    fn test_ecs_hostname() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(
+            br#"{"Cluster": "default", "MetadataFileStatus": "READY", "HostPrivateIPv4Address": "192.0.2.1"}"#,
+        )
+        .unwrap();
+        let env = mock_env(&[(ECS_CONTAINER_METADATA_FILE, file.path().to_str().unwrap())]);
+        let info = HostInfo::collect_with(&env, None);
+        assert_eq!(info.environment, Some(HostEnvironment::Ecs));
+        assert_eq!(info.hostname, "192.0.2.1");
+    }
+
+    #[test]
+    fn test_azure_hostname() {
+        let metadata = AzureMetadata::new(mock_env(&[
+            ("DD_AZURE_APP_SERVICES", "1"),
+            ("COMPUTERNAME", "aas-instance"),
+        ]))
+        .unwrap();
+        let info = HostInfo::collect_with(&mock_env(&[]), Some(&metadata));
+        assert_eq!(info.environment, Some(HostEnvironment::AzureAppService));
+        assert_eq!(info.hostname, "aas-instance");
+    }
+
+    #[test]
+    fn test_default_host_info() {
+        let info = HostInfo::collect_with(&mock_env(&[]), None);
+        assert_eq!(info.environment, None);
+        assert_eq!(info.hostname, sys_info::hostname().unwrap());
+        assert_eq!(info.os, env::consts::OS);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(info.kernel_name.as_deref(), Some("Linux"));
+            assert!(info.kernel_release.is_some());
+            assert!(info.kernel_version.is_some());
+        }
+        assert!(info
+            .tags()
+            .iter()
+            .any(|t| t.as_ref() == format!("os:{}", env::consts::OS)));
+    }
+}
@@ -13,6 +13,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 pub mod azure_app_services;
 pub mod connector;
 pub mod container_id;
+pub mod host_info;
 #[macro_use]
 pub mod cstr;
 pub mod tag;
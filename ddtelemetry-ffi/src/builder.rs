@@ -90,6 +90,7 @@ crate::c_setters!(
     convert_fn => (|b: bool| -> Result<_, String> { Ok(b) }),
     SETTERS {
         config.telemetry_debug_logging_enabled,
+        config.telemetry_compression_enabled,
     }
 );
 
@@ -125,6 +126,19 @@ pub unsafe extern "C" fn ddog_builder_with_rust_shared_lib_deps(
     MaybeError::None
 }
 
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+/// Stack traces of logs will only keep frames containing one of the given patterns
+pub unsafe extern "C" fn ddog_builder_with_stack_trace_library_frame(
+    builder: &mut TelemetryWorkerBuilder,
+    pattern: ffi::CharSlice,
+) -> MaybeError {
+    builder
+        .stack_trace_redactor
+        .add_library_frame_pattern(pattern.to_utf8_lossy().into_owned());
+    MaybeError::None
+}
+
 #[allow(clippy::missing_safety_doc)]
 #[no_mangle]
 pub unsafe extern "C" fn ddog_builder_with_config(
@@ -139,6 +153,8 @@ pub unsafe extern "C" fn ddog_builder_with_config(
         name,
         value,
         origin,
+        error: None,
+        seq_id: None,
     });
     MaybeError::None
 }
@@ -258,6 +258,9 @@ mod test_c_ffi {
             let handle = Box::from_raw(handle);
 
             ddog_handle_start(&handle);
+            let stats = ddog_handle_stats(&handle);
+            assert_eq!(stats.last_error.len(), 0);
+            ddog_WorkerStats_drop(stats);
             ddog_handle_stop(&handle);
             ddog_handle_wait_for_shutdown(handle);
         }
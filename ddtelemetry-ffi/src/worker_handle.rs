@@ -1,7 +1,10 @@
 // Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 use ddcommon_ffi as ffi;
-use ddtelemetry::worker::TelemetryWorkerHandle;
+use ddtelemetry::worker::{
+    stats::{DroppedItems, RequestStats},
+    TelemetryWorkerHandle,
+};
 use ffi::slice::AsBytes;
 
 use crate::MaybeError;
@@ -53,18 +56,68 @@ pub unsafe extern "C" fn ddog_handle_add_log(
     message: ffi::CharSlice,
     level: ddtelemetry::data::LogLevel,
     stack_trace: ffi::CharSlice,
+    tags: ffi::CharSlice,
+    count: u32,
+    is_sensitive: bool,
 ) -> MaybeError {
-    crate::try_c!(handle.add_log(
+    crate::try_c!(handle.add_log_entry(
         indentifier.as_bytes(),
-        message.to_utf8_lossy().into_owned(),
-        level,
-        stack_trace
-            .is_empty()
-            .then(|| stack_trace.to_utf8_lossy().into_owned()),
+        ddtelemetry::data::Log {
+            message: message.to_utf8_lossy().into_owned(),
+            level,
+            stack_trace: (!stack_trace.is_empty())
+                .then(|| stack_trace.to_utf8_lossy().into_owned()),
+            tags: tags.to_utf8_lossy().into_owned(),
+            count,
+            tracer_time: 0,
+            is_sensitive,
+        },
     ));
     MaybeError::None
 }
 
+#[repr(C)]
+pub struct RequestTypeStats {
+    pub request_type: ffi::Vec<u8>,
+    pub stats: RequestStats,
+}
+
+/// Activity of a telemetry worker since it was started
+#[repr(C)]
+pub struct WorkerStats {
+    pub requests: ffi::Vec<RequestTypeStats>,
+    pub bytes_sent: u64,
+    /// Error of the last failed request, empty if none failed
+    pub last_error: ffi::Vec<u8>,
+    pub mailbox_depth: usize,
+    pub deduplicated_logs: u64,
+    pub dropped: DroppedItems,
+}
+
+#[no_mangle]
+pub extern "C" fn ddog_handle_stats(handle: &TelemetryWorkerHandle) -> WorkerStats {
+    let stats = handle.stats();
+    WorkerStats {
+        requests: stats
+            .requests
+            .into_iter()
+            .map(|(request_type, stats)| RequestTypeStats {
+                request_type: request_type.into_bytes().into(),
+                stats,
+            })
+            .collect::<Vec<_>>()
+            .into(),
+        bytes_sent: stats.bytes_sent,
+        last_error: stats.last_error.unwrap_or_default().into_bytes().into(),
+        mailbox_depth: stats.mailbox_depth,
+        deduplicated_logs: stats.deduplicated_logs,
+        dropped: stats.dropped,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ddog_WorkerStats_drop(_: WorkerStats) {}
+
 #[no_mangle]
 pub extern "C" fn ddog_handle_start(handle: &TelemetryWorkerHandle) -> MaybeError {
     crate::try_c!(handle.send_start());
@@ -2,7 +2,9 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 use ddcommon::{parse_uri, Endpoint};
-use std::{borrow::Cow, time::Duration};
+use std::{borrow::Cow, collections::HashMap, env, fs, path::Path, time::Duration};
+
+use crate::data::{self, ConfigurationOrigin};
 
 use http::{uri::PathAndQuery, Uri};
 use lazy_static::lazy_static;
@@ -16,13 +18,23 @@ const AGENT_TELEMETRY_URL_PATH: &str = "/telemetry/proxy/api/v2/apmtelemetry";
 const DEFAULT_AGENT_HOST: &str = "localhost";
 const DEFAULT_AGENT_PORT: u16 = 8126;
 
+/// Default upper bound on the size of a single telemetry request body, before compression
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 5 * 1024 * 1024;
+
 #[derive(Clone, Debug)]
 pub struct Config {
     /// Endpoint to send the data to
     pub endpoint: Option<Endpoint>,
+    /// Endpoints receiving a copy of the data sent to `endpoint`, ie. to dual-ship to the agent
+    /// and directly to an intake
+    pub additional_endpoints: Vec<Endpoint>,
     /// Enables debug logging
     pub telemetry_debug_logging_enabled: bool,
     pub telemetry_hearbeat_interval: Duration,
+    /// Compresses request bodies with gzip
+    pub telemetry_compression_enabled: bool,
+    /// Requests bigger than this (in bytes, before compression) are split in multiple requests
+    pub telemetry_max_payload_size: usize,
 }
 
 fn endpoint_with_telemetry_path(mut endpoint: Endpoint) -> anyhow::Result<Endpoint> {
@@ -39,37 +51,121 @@ fn endpoint_with_telemetry_path(mut endpoint: Endpoint) -> anyhow::Result<Endpoi
     Ok(endpoint)
 }
 
-mod parse_env {
+mod parse {
     use ddcommon::parse_uri;
     use http::Uri;
-    use std::{env, str::FromStr, time::Duration};
+    use std::{str::FromStr, time::Duration};
 
-    pub fn duration(name: &str) -> Option<Duration> {
-        Some(Duration::from_secs_f32(
-            env::var(name).ok()?.parse::<f32>().ok()?,
-        ))
+    pub fn duration(value: &str) -> Option<Duration> {
+        Some(Duration::from_secs_f32(value.parse::<f32>().ok()?))
     }
 
-    pub fn int<T: FromStr>(name: &str) -> Option<T> {
-        env::var(name).ok()?.parse::<T>().ok()
+    pub fn int<T: FromStr>(value: &str) -> Option<T> {
+        value.parse::<T>().ok()
     }
 
-    pub fn bool(name: &str) -> Option<bool> {
-        let var = env::var(name).ok()?;
-        Some(var == "true" || var == "1")
+    pub fn bool(value: &str) -> Option<bool> {
+        Some(value == "true" || value == "1")
     }
 
-    pub fn str_not_empty(name: &str) -> Option<String> {
-        env::var(name).ok().filter(|s| !s.is_empty())
+    pub fn str_not_empty(value: &str) -> Option<String> {
+        Some(value.to_owned()).filter(|s| !s.is_empty())
     }
 
-    pub fn uri(name: &str) -> Option<Uri> {
-        parse_uri(&str_not_empty(name)?).ok()
+    pub fn uri(value: &str) -> Option<Uri> {
+        parse_uri(&str_not_empty(value)?).ok()
+    }
+}
+
+/// Raw values of the settings, read from a configuration file
+///
+/// The file is a flat map, in YAML or TOML if the file name ends with `.toml`. Keys are the
+/// names of the env variables, lowercased and without the `DD_` prefix, ie. `agent_host`
+/// for `DD_AGENT_HOST`.
+#[derive(Debug, Default)]
+pub struct SettingsFile {
+    values: HashMap<String, String>,
+}
+
+impl SettingsFile {
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        if path.extension().and_then(|e| e.to_str()) == Some("toml") {
+            Self::from_toml(&content)
+        } else {
+            Self::from_yaml(&content)
+        }
+    }
+
+    pub fn from_yaml(content: &str) -> anyhow::Result<Self> {
+        let map: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(content)?;
+        let values = map
+            .into_iter()
+            .filter_map(|(k, v)| {
+                let v = match v {
+                    serde_yaml::Value::Bool(b) => b.to_string(),
+                    serde_yaml::Value::Number(n) => n.to_string(),
+                    serde_yaml::Value::String(s) => s,
+                    _ => return None,
+                };
+                Some((k, v))
+            })
+            .collect();
+        Ok(Self { values })
+    }
+
+    pub fn from_toml(content: &str) -> anyhow::Result<Self> {
+        let map: HashMap<String, toml::Value> = toml::from_str(content)?;
+        let values = map
+            .into_iter()
+            .filter_map(|(k, v)| {
+                let v = match v {
+                    toml::Value::Boolean(b) => b.to_string(),
+                    toml::Value::Integer(i) => i.to_string(),
+                    toml::Value::Float(f) => f.to_string(),
+                    toml::Value::String(s) => s,
+                    _ => return None,
+                };
+                Some((k, v))
+            })
+            .collect();
+        Ok(Self { values })
+    }
+
+    fn get(&self, env_name: &str) -> Option<&str> {
+        let key = env_name
+            .trim_start_matches('_')
+            .trim_start_matches("DD_")
+            .to_lowercase();
+        self.values.get(&key).map(String::as_str)
+    }
+}
+
+/// Looks settings up in env variables first, then in the settings file, and records where
+/// each value was found
+struct SettingsLoader<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    file: Option<&'a SettingsFile>,
+    origins: HashMap<&'static str, ConfigurationOrigin>,
+}
+
+impl SettingsLoader<'_> {
+    fn get<T>(&mut self, name: &'static str, parse: fn(&str) -> Option<T>) -> Option<T> {
+        if let Some(v) = (self.env)(name).and_then(|v| parse(&v)) {
+            self.origins.insert(name, ConfigurationOrigin::EnvVar);
+            return Some(v);
+        }
+        if let Some(v) = self.file.and_then(|f| parse(f.get(name)?)) {
+            self.origins.insert(name, ConfigurationOrigin::DdConfig);
+            return Some(v);
+        }
+        None
     }
 }
 
 /// Settings gathers configuration options we receive from the environment
-/// (either through env variable, or that could be set from the )
+/// (either through env variables, or a [SettingsFile])
 pub struct Settings {
     pub agent_host: String,
     pub trace_agent_port: u16,
@@ -80,7 +176,11 @@ pub struct Settings {
     pub telemetry_dd_url: Option<String>,
     pub telemetry_heartbeat_interval: Duration,
     pub telemetry_extended_heartbeat_interval: Duration,
+    pub telemetry_compression_enabled: bool,
+    pub telemetry_max_payload_size: usize,
     pub shared_lib_debug: bool,
+    /// Where each setting was read from, by env variable name. Missing settings are defaults
+    pub origins: HashMap<&'static str, ConfigurationOrigin>,
 }
 
 impl Default for Settings {
@@ -95,7 +195,10 @@ impl Default for Settings {
             telemetry_dd_url: None,
             telemetry_heartbeat_interval: Duration::from_secs(60),
             telemetry_extended_heartbeat_interval: Duration::from_secs(60 * 60 * 24),
+            telemetry_compression_enabled: false,
+            telemetry_max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
             shared_lib_debug: false,
+            origins: HashMap::new(),
         }
     }
 }
@@ -112,34 +215,156 @@ impl Settings {
     const DD_SITE: &'static str = "DD_SITE";
     const DD_APM_TELEMETRY_DD_URL: &'static str = "DD_APM_TELEMETRY_DD_URL";
 
+    // Payload encoding configuration
+    const DD_TELEMETRY_COMPRESSION_ENABLED: &'static str = "DD_TELEMETRY_COMPRESSION_ENABLED";
+    const DD_TELEMETRY_MAX_PAYLOAD_SIZE: &'static str = "DD_TELEMETRY_MAX_PAYLOAD_SIZE";
+
+    // Path to a settings file, see [SettingsFile]
+    const DD_TELEMETRY_CONFIG_FILE: &'static str = "DD_TELEMETRY_CONFIG_FILE";
+
     // Development and test env variables - should not be used by customers
     const DD_TELEMETRY_HEARTBEAT_INTERVAL: &'static str = "DD_TELEMETRY_HEARTBEAT_INTERVAL";
     const DD_TELEMETRY_EXTENDED_HEARTBEAT_INTERVAL: &'static str =
         "DD_TELEMETRY_EXTENDED_HEARTBEAT_INTERVAL";
     const _DD_SHARED_LIB_DEBUG: &'static str = "_DD_SHARED_LIB_DEBUG";
 
+    /// Reads the settings from env variables, and from the file DD_TELEMETRY_CONFIG_FILE
+    /// points to if set. Env variables take precedence over the file
     pub fn from_env() -> Self {
+        let file = env::var(Self::DD_TELEMETRY_CONFIG_FILE)
+            .ok()
+            .filter(|p| !p.is_empty())
+            .and_then(|p| match SettingsFile::read(&p) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    tracing::warn!(
+                        "Ignoring the telemetry settings file {p} set in {}: {e}",
+                        Self::DD_TELEMETRY_CONFIG_FILE
+                    );
+                    None
+                }
+            });
+        Self::load(file.as_ref())
+    }
+
+    /// Reads the settings from the given file. Env variables take precedence over the file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(Self::load(Some(&SettingsFile::read(path)?)))
+    }
+
+    pub fn load(file: Option<&SettingsFile>) -> Self {
+        Self::load_with_env(file, |name| env::var(name).ok())
+    }
+
+    /// Reads the settings from the given file, and from the env variables `env` looks up
+    pub fn load_with_env<F>(file: Option<&SettingsFile>, env: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
         let default = Self::default();
+        let mut loader = SettingsLoader {
+            env: &env,
+            file,
+            origins: HashMap::new(),
+        };
         Self {
-            agent_host: parse_env::str_not_empty(Self::DD_AGENT_HOST).unwrap_or(default.agent_host),
-            trace_agent_port: parse_env::int(Self::DD_TRACE_AGENT_PORT)
+            agent_host: loader
+                .get(Self::DD_AGENT_HOST, parse::str_not_empty)
+                .unwrap_or(default.agent_host),
+            trace_agent_port: loader
+                .get(Self::DD_TRACE_AGENT_PORT, parse::int)
                 .unwrap_or(default.trace_agent_port),
-            trace_agent_url: parse_env::uri(Self::DD_TRACE_AGENT_URL).or(default.trace_agent_url),
-            direct_submission_enabled: parse_env::bool(Self::_DD_DIRECT_SUBMISSION_ENABLED)
+            trace_agent_url: loader
+                .get(Self::DD_TRACE_AGENT_URL, parse::uri)
+                .or(default.trace_agent_url),
+            direct_submission_enabled: loader
+                .get(Self::_DD_DIRECT_SUBMISSION_ENABLED, parse::bool)
                 .unwrap_or(default.direct_submission_enabled),
-            api_key: parse_env::str_not_empty(Self::DD_API_KEY),
-            site: parse_env::str_not_empty(Self::DD_SITE),
-            telemetry_dd_url: parse_env::str_not_empty(Self::DD_APM_TELEMETRY_DD_URL),
-            telemetry_heartbeat_interval: parse_env::duration(
+            api_key: loader.get(Self::DD_API_KEY, parse::str_not_empty),
+            site: loader.get(Self::DD_SITE, parse::str_not_empty),
+            telemetry_dd_url: loader.get(Self::DD_APM_TELEMETRY_DD_URL, parse::str_not_empty),
+            telemetry_heartbeat_interval: loader
+                .get(Self::DD_TELEMETRY_HEARTBEAT_INTERVAL, parse::duration)
+                .unwrap_or(default.telemetry_heartbeat_interval),
+            telemetry_extended_heartbeat_interval: loader
+                .get(
+                    Self::DD_TELEMETRY_EXTENDED_HEARTBEAT_INTERVAL,
+                    parse::duration,
+                )
+                .unwrap_or(default.telemetry_extended_heartbeat_interval),
+            telemetry_compression_enabled: loader
+                .get(Self::DD_TELEMETRY_COMPRESSION_ENABLED, parse::bool)
+                .unwrap_or(default.telemetry_compression_enabled),
+            telemetry_max_payload_size: loader
+                .get(Self::DD_TELEMETRY_MAX_PAYLOAD_SIZE, parse::int)
+                .unwrap_or(default.telemetry_max_payload_size),
+            shared_lib_debug: loader
+                .get(Self::_DD_SHARED_LIB_DEBUG, parse::bool)
+                .unwrap_or(default.shared_lib_debug),
+            origins: loader.origins,
+        }
+    }
+
+    /// The settings and where they come from, to be reported in telemetry.
+    /// The api key is never reported
+    pub fn configurations(&self) -> Vec<data::Configuration> {
+        let values = [
+            (Self::DD_AGENT_HOST, Some(self.agent_host.clone())),
+            (
+                Self::DD_TRACE_AGENT_PORT,
+                Some(self.trace_agent_port.to_string()),
+            ),
+            (
+                Self::DD_TRACE_AGENT_URL,
+                self.trace_agent_url.as_ref().map(Uri::to_string),
+            ),
+            (
+                Self::_DD_DIRECT_SUBMISSION_ENABLED,
+                Some(self.direct_submission_enabled.to_string()),
+            ),
+            (Self::DD_SITE, self.site.clone()),
+            (Self::DD_APM_TELEMETRY_DD_URL, self.telemetry_dd_url.clone()),
+            (
                 Self::DD_TELEMETRY_HEARTBEAT_INTERVAL,
-            )
-            .unwrap_or(Duration::from_secs(60)),
-            telemetry_extended_heartbeat_interval: parse_env::duration(
+                Some(self.telemetry_heartbeat_interval.as_secs_f32().to_string()),
+            ),
+            (
                 Self::DD_TELEMETRY_EXTENDED_HEARTBEAT_INTERVAL,
-            )
-            .unwrap_or(Duration::from_secs(60 * 60 * 24)),
-            shared_lib_debug: parse_env::bool(Self::_DD_SHARED_LIB_DEBUG).unwrap_or(false),
-        }
+                Some(
+                    self.telemetry_extended_heartbeat_interval
+                        .as_secs_f32()
+                        .to_string(),
+                ),
+            ),
+            (
+                Self::DD_TELEMETRY_COMPRESSION_ENABLED,
+                Some(self.telemetry_compression_enabled.to_string()),
+            ),
+            (
+                Self::DD_TELEMETRY_MAX_PAYLOAD_SIZE,
+                Some(self.telemetry_max_payload_size.to_string()),
+            ),
+            (
+                Self::_DD_SHARED_LIB_DEBUG,
+                Some(self.shared_lib_debug.to_string()),
+            ),
+        ];
+        values
+            .into_iter()
+            .filter_map(|(name, value)| {
+                Some(data::Configuration {
+                    name: name.to_owned(),
+                    value: value?,
+                    origin: self
+                        .origins
+                        .get(name)
+                        .cloned()
+                        .unwrap_or(ConfigurationOrigin::Default),
+                    error: None,
+                    seq_id: None,
+                })
+            })
+            .collect()
     }
 }
 
@@ -147,8 +372,11 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             endpoint: None,
+            additional_endpoints: Vec::new(),
             telemetry_debug_logging_enabled: false,
             telemetry_hearbeat_interval: Duration::from_secs(60),
+            telemetry_compression_enabled: false,
+            telemetry_max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
         }
     }
 }
@@ -188,14 +416,23 @@ impl Config {
         Ok(())
     }
 
+    pub fn add_additional_endpoint(&mut self, endpoint: Endpoint) -> anyhow::Result<()> {
+        self.additional_endpoints
+            .push(endpoint_with_telemetry_path(endpoint)?);
+        Ok(())
+    }
+
     pub fn from_settings(settings: &Settings) -> Self {
         let url = Self::url_from_settings(settings);
         let api_key = Self::api_key_from_settings(settings);
 
         let mut this = Self {
             endpoint: None,
+            additional_endpoints: Vec::new(),
             telemetry_debug_logging_enabled: settings.shared_lib_debug,
             telemetry_hearbeat_interval: settings.telemetry_heartbeat_interval,
+            telemetry_compression_enabled: settings.telemetry_compression_enabled,
+            telemetry_max_payload_size: settings.telemetry_max_payload_size,
         };
         if let Ok(url) = parse_uri(&url) {
             let _res = this.set_endpoint(Endpoint { url, api_key });
@@ -209,6 +446,11 @@ impl Config {
         Self::from_settings(&settings)
     }
 
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let settings = Settings::from_file(path)?;
+        Ok(Self::from_settings(&settings))
+    }
+
     pub fn get() -> &'static Self {
         lazy_static! {
             static ref CFG: Config = Config::from_env();
@@ -229,7 +471,9 @@ impl Config {
 mod test {
     use ddcommon::connector::uds;
 
-    use super::Config;
+    use super::{Config, Settings, SettingsFile};
+    use crate::data::ConfigurationOrigin;
+    use std::time::Duration;
 
     #[test]
     fn test_config_url_update() {
@@ -305,4 +549,62 @@ mod test {
                 .to_string_lossy()
         );
     }
+
+    #[test]
+    fn test_settings_file_formats() {
+        let yaml = SettingsFile::from_yaml(
+            "agent_host: datadog-agent\ntrace_agent_port: 9126\ntelemetry_compression_enabled: true\n",
+        )
+        .unwrap();
+        let toml = SettingsFile::from_toml(
+            "agent_host = \"datadog-agent\"\ntrace_agent_port = 9126\ntelemetry_compression_enabled = true\n",
+        )
+        .unwrap();
+        for file in [yaml, toml] {
+            let settings = Settings::load_with_env(Some(&file), |_| None);
+            assert_eq!(settings.agent_host, "datadog-agent");
+            assert_eq!(settings.trace_agent_port, 9126);
+            assert!(settings.telemetry_compression_enabled);
+        }
+    }
+
+    #[test]
+    fn test_settings_origins() {
+        let file = SettingsFile::from_yaml(
+            "telemetry_extended_heartbeat_interval: 10\ntelemetry_max_payload_size: 1000\napi_key: secret\n",
+        )
+        .unwrap();
+        let settings = Settings::load_with_env(Some(&file), |name| {
+            (name == "DD_TELEMETRY_EXTENDED_HEARTBEAT_INTERVAL").then(|| "20".to_owned())
+        });
+
+        // Env variables take precedence over the file
+        assert_eq!(
+            settings.telemetry_extended_heartbeat_interval,
+            Duration::from_secs(20)
+        );
+        assert_eq!(settings.telemetry_max_payload_size, 1000);
+
+        let configurations = settings.configurations();
+        let origin = |name: &str| {
+            configurations
+                .iter()
+                .find(|c| c.name == name)
+                .map(|c| (c.value.as_str(), c.origin.clone()))
+        };
+        assert_eq!(
+            origin("DD_TELEMETRY_EXTENDED_HEARTBEAT_INTERVAL"),
+            Some(("20", ConfigurationOrigin::EnvVar))
+        );
+        assert_eq!(
+            origin("DD_TELEMETRY_MAX_PAYLOAD_SIZE"),
+            Some(("1000", ConfigurationOrigin::DdConfig))
+        );
+        assert_eq!(
+            origin("DD_TELEMETRY_COMPRESSION_ENABLED"),
+            Some(("false", ConfigurationOrigin::Default))
+        );
+        assert_eq!(settings.api_key.as_deref(), Some("secret"));
+        assert_eq!(origin("DD_API_KEY"), None);
+    }
 }
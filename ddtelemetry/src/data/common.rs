@@ -34,7 +34,7 @@ pub struct Telemetry<'a> {
     pub payload: &'a Payload,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Application {
     pub service_name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -52,7 +52,7 @@ pub struct Application {
     pub runtime_patches: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Host {
     pub hostname: String,
     #[serde(skip_serializing_if = "Option::is_none")]
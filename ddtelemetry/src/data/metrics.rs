@@ -2,7 +2,7 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 use ddcommon::tag::Tag;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Debug)]
 pub struct Serie {
@@ -15,7 +15,16 @@ pub struct Serie {
     pub _type: MetricType,
 }
 
-#[derive(Serialize, Debug, Clone, Copy)]
+#[derive(Serialize, Debug)]
+pub struct Distribution {
+    pub namespace: MetricNamespace,
+    pub metric: String,
+    pub points: Vec<f64>,
+    pub tags: Vec<Tag>,
+    pub common: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum MetricNamespace {
     Trace,
@@ -23,11 +32,13 @@ pub enum MetricNamespace {
     Appsec,
 }
 
-#[derive(Serialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum MetricType {
     #[serde(rename = "gauge")]
     Gauge,
     #[serde(rename = "count")]
     Count,
+    #[serde(rename = "distribution")]
+    Distribution,
 }
@@ -12,9 +12,12 @@ pub enum Payload {
     AppDependenciesLoaded(AppDependenciesLoaded),
     AppIntegrationsChange(AppIntegrationsChange),
     AppClientConfigurationChange(AppClientConfigurationChange),
+    AppProductChange(AppProductChange),
+    AppEndpoints(AppEndpoints),
     AppHeartbeat(#[serde(skip_serializing)] ()),
     AppClosing(#[serde(skip_serializing)] ()),
     GenerateMetrics(GenerateMetrics),
+    Distributions(Distributions),
     Logs(Vec<Log>),
     MessageBatch(Vec<Payload>),
     AppExtendedHeartbeat(AppStarted),
@@ -28,9 +31,12 @@ impl Payload {
             AppDependenciesLoaded(_) => "app-dependencies-loaded",
             AppIntegrationsChange(_) => "app-integrations-change",
             AppClientConfigurationChange(_) => "app-client-configuration-change",
+            AppProductChange(_) => "app-product-change",
+            AppEndpoints(_) => "app-endpoints",
             AppHeartbeat(_) => "app-heartbeat",
             AppClosing(_) => "app-closing",
             GenerateMetrics(_) => "generate-metrics",
+            Distributions(_) => "distributions",
             Logs(_) => "logs",
             MessageBatch(_) => "message-batch",
             AppExtendedHeartbeat(_) => "app-extended-heartbeat",
@@ -3,7 +3,7 @@
 
 use crate::data::metrics;
 
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, Default)]
 pub struct Dependency {
@@ -29,6 +29,40 @@ pub struct Configuration {
     pub name: String,
     pub value: String,
     pub origin: ConfigurationOrigin,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    /// Orders successive changes of the same configuration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq_id: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct Error {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct Product {
+    pub name: String,
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, Default)]
+pub struct Endpoint {
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub operation_name: String,
+    pub resource_name: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone)]
@@ -61,17 +95,78 @@ pub struct AppClientConfigurationChange {
     pub configuration: Vec<Configuration>,
 }
 
+#[derive(Serialize, Debug)]
+pub struct AppProductChange {
+    #[serde(serialize_with = "serialize_products")]
+    pub products: Vec<Product>,
+}
+
+/// Products are sent as an object keyed by product name
+fn serialize_products<S: Serializer>(
+    products: &[Product],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct ProductDetails<'a> {
+        enabled: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: &'a Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: &'a Option<Error>,
+    }
+
+    let mut map = serializer.serialize_map(Some(products.len()))?;
+    for p in products {
+        map.serialize_entry(
+            &p.name,
+            &ProductDetails {
+                enabled: p.enabled,
+                version: &p.version,
+                error: &p.error,
+            },
+        )?;
+    }
+    map.end()
+}
+
+#[derive(Serialize, Debug)]
+pub struct AppEndpoints {
+    /// Whether the previously sent endpoints should be discarded
+    pub is_first: bool,
+    pub endpoints: Vec<Endpoint>,
+}
+
 #[derive(Serialize, Debug)]
 pub struct GenerateMetrics {
     pub series: Vec<metrics::Serie>,
 }
 
+#[derive(Serialize, Debug)]
+pub struct Distributions {
+    pub series: Vec<metrics::Distribution>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Log {
     pub message: String,
     pub level: LogLevel,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stack_trace: Option<String>,
+    /// Comma separated list of key:value tags
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tags: String,
+    /// Number of times this log was emitted
+    #[serde(default = "default_log_count")]
+    pub count: u32,
+    /// Unix timestamp, in seconds, of the first occurrence of the log
+    #[serde(default)]
+    pub tracer_time: u64,
+    #[serde(default)]
+    pub is_sensitive: bool,
+}
+
+fn default_log_count() -> u32 {
+    1
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
@@ -81,4 +176,5 @@ pub enum LogLevel {
     Error,
     Warn,
     Debug,
+    Trace,
 }
@@ -2,9 +2,11 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 pub mod os {
-    // TODO: this function will call API's (fargate, k8s, etc) in the future to get to real host API
+    use ddcommon::host_info;
+
+    /// Hostname resolved from the orchestrator or cloud environment, or from the OS
     pub fn real_hostname() -> anyhow::Result<String> {
-        Ok(sys_info::hostname()?)
+        Ok(host_info::get_host_info().hostname.clone())
     }
 
     pub const fn os_name() -> &'static str {
@@ -3,22 +3,24 @@
 #![allow(clippy::mutex_atomic)]
 #![allow(clippy::nonminimal_bool)]
 
-use ddcommon::container_id;
+use ddcommon::{container_id, host_info};
 
 pub mod config;
 pub mod data;
 pub mod info;
 pub mod metrics;
+pub mod native_deps;
 pub mod worker;
 
 pub fn build_host() -> data::Host {
+    let host_info = host_info::get_host_info();
     data::Host {
-        hostname: info::os::real_hostname().unwrap_or_else(|_| String::from("unknown_hostname")),
+        hostname: host_info.hostname.clone(),
         container_id: container_id::get_container_id().map(|f| f.to_string()),
-        os: Some(String::from(info::os::os_name())),
-        os_version: info::os::os_version().ok(),
-        kernel_name: None,
-        kernel_release: None,
-        kernel_version: None,
+        os: Some(host_info.os.clone()),
+        os_version: host_info.os_version.clone(),
+        kernel_name: host_info.kernel_name.clone(),
+        kernel_release: host_info.kernel_release.clone(),
+        kernel_version: host_info.kernel_version.clone(),
     }
 }
@@ -31,13 +31,8 @@ enum MetricAggreg {
 }
 
 impl MetricBucket {
-    fn new(metric_type: data::metrics::MetricType) -> Self {
-        Self {
-            aggreg: match metric_type {
-                data::metrics::MetricType::Count => MetricAggreg::Count { count: 0.0 },
-                data::metrics::MetricType::Gauge => MetricAggreg::Gauge { value: 0.0 },
-            },
-        }
+    fn new(aggreg: MetricAggreg) -> Self {
+        Self { aggreg }
     }
 
     fn add_point(&mut self, point: f64) {
@@ -68,6 +63,7 @@ struct BucketKey {
 pub struct MetricBuckets {
     buckets: HashMap<BucketKey, MetricBucket>,
     series: HashMap<BucketKey, Vec<(u64, f64)>>,
+    distributions: HashMap<BucketKey, Vec<f64>>,
 }
 
 impl MetricBuckets {
@@ -95,6 +91,20 @@ impl MetricBuckets {
         )
     }
 
+    pub fn flush_distributions(
+        &mut self,
+    ) -> impl Iterator<Item = (ContextKey, Vec<Tag>, Vec<f64>)> + '_ {
+        self.distributions.drain().map(
+            |(
+                BucketKey {
+                    context_key,
+                    extra_tags,
+                },
+                points,
+            )| (context_key, extra_tags, points),
+        )
+    }
+
     pub fn add_point(
         &mut self,
         context_key: ContextKey,
@@ -106,17 +116,26 @@ impl MetricBuckets {
             context_key,
             extra_tags,
         };
+        let aggreg = match contexts.get_metric_type(context_key).unwrap() {
+            data::metrics::MetricType::Count => MetricAggreg::Count { count: 0.0 },
+            data::metrics::MetricType::Gauge => MetricAggreg::Gauge { value: 0.0 },
+            // Every point of a distribution is sent
+            data::metrics::MetricType::Distribution => {
+                self.distributions
+                    .entry(bucket_key)
+                    .or_default()
+                    .push(point);
+                return;
+            }
+        };
         self.buckets
             .entry(bucket_key)
-            .or_insert_with(|| {
-                let metric_type = contexts.get_metric_type(context_key).unwrap();
-                MetricBucket::new(metric_type)
-            })
+            .or_insert_with(|| MetricBucket::new(aggreg))
             .add_point(point)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetricContext {
     pub namespace: data::metrics::MetricNamespace,
     pub name: String,
@@ -182,6 +201,22 @@ impl MetricContexts {
     }
 }
 
+impl MetricContexts {
+    /// Returns a copy of all registered contexts, indexed by their ContextKey
+    pub fn snapshot(&self) -> Vec<MetricContext> {
+        self.inner.lock().unwrap().store.clone()
+    }
+}
+
+impl From<Vec<MetricContext>> for MetricContexts {
+    /// Restores contexts from a snapshot, keeping the ContextKeys previously handed out valid
+    fn from(store: Vec<MetricContext>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(InnerMetricContexts { store })),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::fmt::Debug;
@@ -306,4 +341,30 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn test_distribution_points() {
+        let mut buckets = MetricBuckets::default();
+        let contexts = MetricContexts::default();
+
+        let context_key = contexts.register_metric_context(
+            "distribution".into(),
+            Vec::new(),
+            MetricType::Distribution,
+            false,
+            MetricNamespace::Trace,
+        );
+
+        buckets.add_point(context_key, &contexts, 0.1, Vec::new());
+        buckets.add_point(context_key, &contexts, 0.2, Vec::new());
+        buckets.flush_agregates();
+        buckets.add_point(context_key, &contexts, 0.3, Vec::new());
+
+        assert_eq!(buckets.flush_series().count(), 0);
+        let distributions: Vec<_> = buckets.flush_distributions().collect();
+        assert_eq!(distributions.len(), 1);
+        assert_eq!(distributions[0].0, context_key);
+        assert_eq!(distributions[0].2, vec![0.1, 0.2, 0.3]);
+        assert_eq!(buckets.flush_distributions().count(), 0);
+    }
 }
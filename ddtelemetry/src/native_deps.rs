@@ -0,0 +1,323 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! Discovery of the shared libraries loaded in the current process
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::data::Dependency;
+
+/// Reports shared libraries loaded by the process as dependencies
+///
+/// Libraries built by rustc are reported only if `rust_shared_lib_deps` is set, and
+/// all others only if `native_deps` is set.
+#[derive(Debug, Default)]
+pub struct NativeDepsCollector {
+    native_deps: bool,
+    rust_shared_lib_deps: bool,
+    seen: HashSet<PathBuf>,
+}
+
+impl NativeDepsCollector {
+    pub fn new(native_deps: bool, rust_shared_lib_deps: bool) -> Self {
+        Self {
+            native_deps,
+            rust_shared_lib_deps,
+            seen: HashSet::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.native_deps || self.rust_shared_lib_deps
+    }
+
+    /// Returns the dependencies loaded since the last call
+    pub fn collect(&mut self) -> Vec<Dependency> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        let mut deps = Vec::new();
+        for path in loaded_shared_objects() {
+            if !self.seen.insert(path.clone()) {
+                continue;
+            }
+            let wanted = if self.native_deps == self.rust_shared_lib_deps {
+                true
+            } else if elf::is_built_by_rustc(&path) {
+                self.rust_shared_lib_deps
+            } else {
+                self.native_deps
+            };
+            if !wanted {
+                continue;
+            }
+            if let Some(dep) = dependency_from_path(&path) {
+                deps.push(dep);
+            }
+        }
+        deps
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn loaded_shared_objects() -> Vec<PathBuf> {
+    match std::fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => parse_maps(&maps),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn loaded_shared_objects() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+/// Extracts the unique shared object paths from the content of /proc/<pid>/maps
+#[cfg(any(target_os = "linux", test))]
+fn parse_maps(maps: &str) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    maps.lines()
+        // address perms offset dev inode pathname
+        .filter_map(|line| line.splitn(6, char::is_whitespace).nth(5))
+        .map(str::trim)
+        .filter(|path| path.starts_with('/') && !path.ends_with("(deleted)"))
+        .filter(|path| {
+            Path::new(path)
+                .file_name()
+                .and_then(|f| f.to_str())
+                .is_some_and(|f| f.ends_with(".so") || f.contains(".so."))
+        })
+        .filter(|path| seen.insert(*path))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Derives the name and version of a library from it's file name
+///
+/// Both `libname.so.1.2` and `libname-1.2.so` styles of versioning are understood
+pub fn dependency_from_path(path: &Path) -> Option<Dependency> {
+    lazy_static! {
+        static ref SONAME: Regex =
+            Regex::new(r"^(?P<name>.+?)(?:-(?P<v1>\d[\w.]*))?\.so(?:\.(?P<v2>[\w.]+))?$").unwrap();
+    }
+    let file_name = path.file_name()?.to_str()?;
+    let captures = SONAME.captures(file_name)?;
+    Some(Dependency {
+        name: captures.name("name")?.as_str().to_owned(),
+        version: captures
+            .name("v2")
+            .or_else(|| captures.name("v1"))
+            .map(|v| v.as_str().to_owned()),
+    })
+}
+
+mod elf {
+    use std::{fs::File, io, path::Path};
+
+    #[cfg(unix)]
+    fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+
+    #[cfg(not(unix))]
+    fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+
+    struct Reader {
+        is_64: bool,
+        little_endian: bool,
+    }
+
+    impl Reader {
+        fn u16(&self, b: &[u8], at: usize) -> u64 {
+            let bytes = [b[at], b[at + 1]];
+            if self.little_endian {
+                u16::from_le_bytes(bytes) as u64
+            } else {
+                u16::from_be_bytes(bytes) as u64
+            }
+        }
+
+        fn u32(&self, b: &[u8], at: usize) -> u64 {
+            let bytes = [b[at], b[at + 1], b[at + 2], b[at + 3]];
+            if self.little_endian {
+                u32::from_le_bytes(bytes) as u64
+            } else {
+                u32::from_be_bytes(bytes) as u64
+            }
+        }
+
+        fn u64(&self, b: &[u8], at: usize) -> u64 {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&b[at..at + 8]);
+            if self.little_endian {
+                u64::from_le_bytes(bytes)
+            } else {
+                u64::from_be_bytes(bytes)
+            }
+        }
+
+        fn addr(&self, b: &[u8], at32: usize, at64: usize) -> u64 {
+            if self.is_64 {
+                self.u64(b, at64)
+            } else {
+                self.u32(b, at32)
+            }
+        }
+    }
+
+    /// Reads the content of a section of an ELF file by name
+    fn read_section(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let file = File::open(path)?;
+        let mut header = [0; 64];
+        read_at(&file, &mut header[..52], 0)?;
+        if header[..4] != *b"\x7fELF" {
+            return Ok(None);
+        }
+        let r = Reader {
+            is_64: header[4] == 2,
+            little_endian: header[5] == 1,
+        };
+        if r.is_64 {
+            read_at(&file, &mut header[52..], 52)?;
+        }
+
+        let (shoff, shentsize, shnum, shstrndx) = if r.is_64 {
+            (
+                r.u64(&header, 0x28),
+                r.u16(&header, 0x3A),
+                r.u16(&header, 0x3C),
+                r.u16(&header, 0x3E),
+            )
+        } else {
+            (
+                r.u32(&header, 0x20),
+                r.u16(&header, 0x2E),
+                r.u16(&header, 0x30),
+                r.u16(&header, 0x32),
+            )
+        };
+        if shoff == 0 || shnum == 0 || shstrndx >= shnum || shentsize < 0x28 {
+            return Ok(None);
+        }
+
+        let mut sections = vec![0; (shentsize * shnum) as usize];
+        read_at(&file, &mut sections, shoff)?;
+        let section = |i: u64| &sections[(i * shentsize) as usize..];
+        // (name offset, content offset, content size)
+        let describe = |s: &[u8]| (r.u32(s, 0), r.addr(s, 0x10, 0x18), r.addr(s, 0x14, 0x20));
+
+        let (_, strtab_offset, strtab_size) = describe(section(shstrndx));
+        if strtab_size > 1 << 20 {
+            return Ok(None);
+        }
+        let mut strtab = vec![0; strtab_size as usize];
+        read_at(&file, &mut strtab, strtab_offset)?;
+
+        for i in 0..shnum {
+            let (name_offset, offset, size) = describe(section(i));
+            let section_name = strtab
+                .get(name_offset as usize..)
+                .and_then(|s| s.split(|c| *c == 0).next());
+            if section_name == Some(name.as_bytes()) {
+                // Don't read abnormally big sections
+                if size > 1 << 20 {
+                    return Ok(None);
+                }
+                let mut content = vec![0; size as usize];
+                read_at(&file, &mut content, offset)?;
+                return Ok(Some(content));
+            }
+        }
+        Ok(None)
+    }
+
+    /// rustc records it's version in the .comment section of the objects it links
+    pub fn is_built_by_rustc(path: &Path) -> bool {
+        match read_section(path, ".comment") {
+            Ok(Some(comment)) => comment.windows(5).any(|w| w == b"rustc"),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = "\
+55d0c6a0e000-55d0c6a10000 r--p 00000000 08:01 1048601 /usr/bin/cat
+7f3b1c400000-7f3b1c428000 r--p 00000000 08:01 1054532 /usr/lib/x86_64-linux-gnu/libc.so.6
+7f3b1c428000-7f3b1c5bd000 r-xp 00028000 08:01 1054532 /usr/lib/x86_64-linux-gnu/libc.so.6
+7f3b1c600000-7f3b1c700000 r-xp 00000000 08:01 1054533 /opt/lib/libfoo-1.2.so (deleted)
+7f3b1c800000-7f3b1c900000 r-xp 00000000 08:01 1054534 /opt/lib/libdatadog.so
+7ffd5a5e0000-7ffd5a5e2000 r-xp 00000000 00:00 0                          [vdso]
+7ffd5a5e3000-7ffd5a5e4000 rw-p 00000000 00:00 0
+";
+        assert_eq!(
+            parse_maps(maps),
+            vec![
+                PathBuf::from("/usr/lib/x86_64-linux-gnu/libc.so.6"),
+                PathBuf::from("/opt/lib/libdatadog.so"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependency_from_path() {
+        let dep = |name: &str, version: Option<&str>| {
+            Some(Dependency {
+                name: name.to_owned(),
+                version: version.map(str::to_owned),
+            })
+        };
+        assert_eq!(
+            dependency_from_path(Path::new("/lib/libssl.so.1.1")),
+            dep("libssl", Some("1.1"))
+        );
+        assert_eq!(
+            dependency_from_path(Path::new("/lib/ld-2.31.so")),
+            dep("ld", Some("2.31"))
+        );
+        assert_eq!(
+            dependency_from_path(Path::new("/usr/lib/libpython3.9.so.1.0")),
+            dep("libpython3.9", Some("1.0"))
+        );
+        assert_eq!(
+            dependency_from_path(Path::new("ddtrace.so")),
+            dep("ddtrace", None)
+        );
+        assert_eq!(dependency_from_path(Path::new("/usr/bin/cat")), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_is_built_by_rustc() {
+        assert!(elf::is_built_by_rustc(&std::env::current_exe().unwrap()));
+        assert!(!elf::is_built_by_rustc(Path::new("/proc/self/maps")));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_collect() {
+        let mut collector = NativeDepsCollector::new(true, false);
+        let deps = collector.collect();
+        assert!(deps.iter().any(|d| d.name.starts_with("libc")));
+        // Already reported libraries are not reported again
+        assert!(collector.collect().is_empty());
+
+        assert!(NativeDepsCollector::new(false, false).collect().is_empty());
+    }
+}
@@ -9,20 +9,33 @@ use ddcommon::Endpoint;
 #[derive(Default, Debug)]
 pub struct ConfigBuilder {
     pub endpoint: Option<Endpoint>,
+    /// Added to the additional endpoints of the merged config
+    pub additional_endpoints: Vec<Endpoint>,
     pub telemetry_debug_logging_enabled: Option<bool>,
     pub telemetry_hearbeat_interval: Option<Duration>,
+    pub telemetry_compression_enabled: Option<bool>,
+    pub telemetry_max_payload_size: Option<usize>,
 }
 
 impl ConfigBuilder {
     pub fn merge(self, other: Config) -> Config {
+        let mut additional_endpoints = other.additional_endpoints;
+        additional_endpoints.extend(self.additional_endpoints);
         Config {
             endpoint: self.endpoint.or(other.endpoint),
+            additional_endpoints,
             telemetry_debug_logging_enabled: self
                 .telemetry_debug_logging_enabled
                 .unwrap_or(other.telemetry_debug_logging_enabled),
             telemetry_hearbeat_interval: self
                 .telemetry_hearbeat_interval
                 .unwrap_or(other.telemetry_hearbeat_interval),
+            telemetry_compression_enabled: self
+                .telemetry_compression_enabled
+                .unwrap_or(other.telemetry_compression_enabled),
+            telemetry_max_payload_size: self
+                .telemetry_max_payload_size
+                .unwrap_or(other.telemetry_max_payload_size),
         }
     }
 }
@@ -36,11 +49,38 @@ mod tests {
         let builder = ConfigBuilder {
             telemetry_debug_logging_enabled: Some(true),
             endpoint: None,
+            additional_endpoints: vec![Endpoint {
+                url: "https://instrumentation-telemetry-intake.datadoghq.eu"
+                    .parse()
+                    .unwrap(),
+                api_key: Some("key".into()),
+            }],
             telemetry_hearbeat_interval: None,
+            telemetry_compression_enabled: Some(true),
+            telemetry_max_payload_size: None,
         };
 
-        let merged = builder.merge(Config::default());
+        let mut config = Config::default();
+        config
+            .add_additional_endpoint(Endpoint {
+                url: "https://instrumentation-telemetry-intake.datadoghq.com"
+                    .parse()
+                    .unwrap(),
+                api_key: Some("key".into()),
+            })
+            .unwrap();
+        let merged = builder.merge(config);
 
         assert!(merged.telemetry_debug_logging_enabled);
+        assert!(merged.telemetry_compression_enabled);
+        assert_eq!(
+            merged.telemetry_max_payload_size,
+            crate::config::DEFAULT_MAX_PAYLOAD_SIZE
+        );
+        assert_eq!(merged.additional_endpoints.len(), 2);
+        assert_eq!(
+            merged.additional_endpoints[0].url.path(),
+            "/api/v2/apmtelemetry"
+        );
     }
 }
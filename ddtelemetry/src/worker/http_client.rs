@@ -2,16 +2,9 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 use ddcommon::HttpRequestBuilder;
-use http::uri::Parts;
 use http::{Request, Response};
 use hyper::Body;
-use std::{
-    fs::File,
-    future::Future,
-    io::Write,
-    pin::Pin,
-    sync::{Arc, Mutex},
-};
+use std::{future::Future, pin::Pin};
 
 use crate::config::Config;
 
@@ -40,22 +33,7 @@ pub fn request_builder(c: &Config) -> anyhow::Result<HttpRequestBuilder> {
     }
 }
 
-pub fn from_config(c: &Config) -> Box<dyn HttpClient + Sync + Send> {
-    if let Some(Parts {
-        scheme: Some(scheme),
-        path_and_query: Some(path),
-        ..
-    }) = c.endpoint.as_ref().map(|e| e.url.clone().into_parts())
-    {
-        if scheme.as_str() == "file" {
-            return Box::new(MockClient {
-                file: Arc::new(Mutex::new(Box::new(
-                    File::create(path.path()).expect("Couldn't open mock client file"),
-                ))),
-            });
-        }
-    }
-
+pub fn new_default_client() -> Box<dyn HttpClient + Sync + Send> {
     Box::new(HyperClient {
         inner: hyper::Client::builder()
             .pool_idle_timeout(std::time::Duration::from_secs(30))
@@ -72,50 +50,3 @@ impl HttpClient for HyperClient {
         Box::pin(self.inner.request(req))
     }
 }
-
-#[derive(Clone)]
-pub struct MockClient {
-    file: Arc<Mutex<Box<dyn Write + Sync + Send>>>,
-}
-
-impl HttpClient for MockClient {
-    fn request(&self, mut req: Request<hyper::Body>) -> ResponseFuture {
-        let s = self.clone();
-        Box::pin(async move {
-            let body = hyper::body::to_bytes(req.body_mut()).await?;
-
-            {
-                let mut writer = s.file.lock().expect("mutex poisoned");
-                writer.write_all(body.as_ref()).unwrap();
-                writer.write_all(b"\n").unwrap();
-            }
-
-            Ok(Response::builder()
-                .status(202)
-                .body(hyper::Body::empty())
-                .unwrap())
-        })
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use ddcommon::HttpRequestBuilder;
-
-    use super::*;
-
-    #[tokio::test]
-    async fn test_mock_client() {
-        let output: Vec<u8> = Vec::new();
-        let c = MockClient {
-            file: Arc::new(Mutex::new(Box::new(output))),
-        };
-        c.request(
-            HttpRequestBuilder::new()
-                .body(hyper::Body::from("hello world\n"))
-                .unwrap(),
-        )
-        .await
-        .unwrap();
-    }
-}
@@ -3,14 +3,24 @@
 
 mod builder;
 pub mod http_client;
+pub mod redaction;
 mod scheduler;
+pub mod stats;
 pub mod store;
+#[cfg(feature = "tracing")]
+pub mod tracing_layer;
+pub mod transport;
 
 use crate::{
     config::{self, Config},
     data::{self, Application, Dependency, Host, Integration, Log, Payload, Telemetry},
-    metrics::{ContextKey, MetricBuckets, MetricContexts},
-    worker::builder::ConfigBuilder,
+    metrics::{ContextKey, MetricBuckets, MetricContext, MetricContexts},
+    native_deps::NativeDepsCollector,
+    worker::{
+        builder::ConfigBuilder,
+        stats::TelemetryWorkerStats,
+        transport::{TelemetryRequest, Transport},
+    },
 };
 use ddcommon::tag::Tag;
 
@@ -26,12 +36,11 @@ use std::{
 };
 
 use anyhow::Result;
-use futures::future::{self};
-use http::{header, HeaderValue, Request};
+use http::{header, HeaderValue};
 use serde::{Deserialize, Serialize};
 use tokio::{
     runtime::{self, Handle},
-    sync::mpsc,
+    sync::{mpsc, oneshot},
     task::JoinHandle,
     time::Instant,
 };
@@ -73,8 +82,13 @@ pub enum TelemetryActions {
     AddConfig(data::Configuration),
     AddDependecy(Dependency),
     AddIntegration(Integration),
+    AddProduct(data::Product),
+    AddEndpoint(data::Endpoint),
     AddLog((LogIdentifier, Log)),
     Lifecycle(LifecycleAction),
+    /// Replies with a snapshot of the worker state. Only usable within a process
+    #[serde(skip)]
+    CollectSnapshot(oneshot::Sender<TelemetryWorkerSnapshot>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -84,6 +98,10 @@ pub enum LifecycleAction {
     FlushMetricAggr,
     FlushData,
     ExtendedHeartbeat,
+    CollectNativeDeps,
+    /// Flushes the data and stops like Stop, without sending app-closing, when another worker
+    /// continues the telemetry of the app
+    Detach,
 }
 
 /// Identifies a logging location uniquely
@@ -105,9 +123,15 @@ struct UnfluhsedLogEntry {
 // Holds the current state of the telemetry worker
 struct TelemetryWorkerData {
     started: bool,
+    // Set when continuing the telemetry stream of another worker, which already sent app-started
+    restored: bool,
     dependencies: store::Store<Dependency>,
     configurations: store::Store<data::Configuration>,
     integrations: store::Store<data::Integration>,
+    products: store::Store<data::Product>,
+    endpoints: store::Store<data::Endpoint>,
+    // Set until an app-endpoints payload with all the known endpoints is sent
+    endpoints_first: bool,
     logs: store::QueueHashMap<LogIdentifier, UnfluhsedLogEntry>,
     metric_contexts: MetricContexts,
     metric_buckets: MetricBuckets,
@@ -121,19 +145,125 @@ pub struct TelemetryWorker {
     cancellation_token: CancellationToken,
     seq_id: AtomicU64,
     runtime_id: String,
-    client: Box<dyn http_client::HttpClient + Sync + Send>,
+    transport: Arc<dyn Transport>,
     deadlines: scheduler::Scheduler<LifecycleAction>,
+    stack_trace_redactor: redaction::StackTraceRedactor,
+    native_deps: NativeDepsCollector,
+    stats: Arc<Mutex<TelemetryWorkerStats>>,
     data: TelemetryWorkerData,
 }
 
+/// Serializable state of a telemetry worker
+///
+/// Allows a child or successor process to continue the telemetry stream of a worker
+/// instead of starting a new one, see [TelemetryWorkerBuilder::from_snapshot]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TelemetryWorkerSnapshot {
+    pub runtime_id: String,
+    pub seq_id: u64,
+    pub started: bool,
+    pub host: Host,
+    pub application: Application,
+    pub dependencies: store::StoreSnapshot<Dependency>,
+    pub configurations: store::StoreSnapshot<data::Configuration>,
+    pub integrations: store::StoreSnapshot<data::Integration>,
+    pub products: store::StoreSnapshot<data::Product>,
+    pub endpoints: store::StoreSnapshot<data::Endpoint>,
+    pub metric_contexts: Vec<MetricContext>,
+}
+
+impl TelemetryWorkerSnapshot {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Prepares the snapshot of a worker which keeps running for the worker of a forked child
+    ///
+    /// The items the parent did not flush yet are left to it, and the child numbers its requests
+    /// from its pid shifted by 32 bits, so that the seq ids of the processes sharing the runtime
+    /// id don't collide as long as each sends less than 2^32 requests.
+    pub fn for_forked_child(mut self, pid: u32) -> Self {
+        self.seq_id = (pid as u64) << 32;
+        self.dependencies.mark_flushed();
+        self.configurations.mark_flushed();
+        self.integrations.mark_flushed();
+        self.products.mark_flushed();
+        self.endpoints.mark_flushed();
+        self
+    }
+}
+
 mod serialize {
     use crate::data;
+    use flate2::{write::GzEncoder, Compression};
     use http::HeaderValue;
+    use serde::Serialize;
+    use std::io::{self, Write};
+
     #[allow(clippy::declare_interior_mutable_const)]
     pub const CONTENT_TYPE_VALUE: HeaderValue = ddcommon::header::APPLICATION_JSON;
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub const CONTENT_ENCODING_GZIP: HeaderValue = HeaderValue::from_static("gzip");
+
     pub fn serialize(telemetry: &data::Telemetry) -> anyhow::Result<Vec<u8>> {
         Ok(serde_json::to_vec(telemetry)?)
     }
+
+    pub fn compress(body: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(body)?;
+        Ok(encoder.finish()?)
+    }
+
+    struct CountingWriter(usize);
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns the length of the JSON representation of an item, without allocating it
+    pub fn serialized_size<T: Serialize + ?Sized>(item: &T) -> usize {
+        let mut writer = CountingWriter(0);
+        match serde_json::to_writer(&mut writer, item) {
+            Ok(()) => writer.0,
+            Err(_) => 0,
+        }
+    }
+
+    /// Splits items in consecutive chunks whose serialized size stays under max_size
+    ///
+    /// Every chunk contains at least one item, so a single item bigger than max_size
+    /// will still end up in it's own chunk
+    pub fn chunk_by_size<T: Serialize>(items: Vec<T>, max_size: usize) -> Vec<Vec<T>> {
+        let mut chunks = Vec::new();
+        let mut current = Vec::new();
+        let mut current_size = 0;
+        for item in items {
+            // account for the separating comma
+            let size = serialized_size(&item) + 1;
+            if !current.is_empty() && current_size + size > max_size {
+                chunks.push(std::mem::take(&mut current));
+                current_size = 0;
+            }
+            current_size += size;
+            current.push(item);
+        }
+        if !current.is_empty() {
+            chunks.push(current);
+        }
+        chunks
+    }
 }
 
 impl TelemetryWorker {
@@ -174,13 +304,26 @@ impl TelemetryWorker {
 
             let action = self.recv_next_action().await;
 
-            match self.dispatch_action(action).await {
+            let flow = self.dispatch_action(action).await;
+            self.update_dropped_stats();
+            match flow {
                 ControlFlow::Continue(()) => {}
                 ControlFlow::Break(()) => break,
             };
         }
     }
 
+    fn update_dropped_stats(&self) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.dropped = stats::DroppedItems {
+            dependencies: self.data.dependencies.dropped(),
+            configurations: self.data.configurations.dropped(),
+            integrations: self.data.integrations.dropped(),
+            products: self.data.products.dropped(),
+            endpoints: self.data.endpoints.dropped(),
+        };
+    }
+
     async fn dispatch_action(&mut self, action: TelemetryActions) -> ControlFlow<()> {
         telemetry_worker_log!(self, DEBUG, "Handling action {:?}", action);
 
@@ -189,31 +332,46 @@ impl TelemetryWorker {
         match action {
             Lifecycle(Start) => {
                 if !self.data.started {
-                    let app_started = data::Payload::AppStarted(self.build_app_started());
-                    match self.send_payload(&app_started).await {
-                        Ok(()) => self.payload_sent_success(&app_started),
-                        Err(err) => self.log_err(&err),
+                    if !self.data.restored {
+                        let app_started = data::Payload::AppStarted(self.build_app_started());
+                        self.flush_payload(app_started).await;
                     }
                     self.deadlines
                         .schedule_event(LifecycleAction::FlushData)
                         .unwrap();
+                    if self.native_deps.is_enabled() {
+                        self.collect_native_deps();
+                    }
                     self.data.started = true;
                 }
             }
+            CollectSnapshot(reply) => {
+                // The requester might have given up waiting, nothing to do then
+                let _ = reply.send(self.snapshot());
+            }
             AddDependecy(dep) => self.data.dependencies.insert(dep),
             AddIntegration(integration) => self.data.integrations.insert(integration),
+            AddProduct(product) => self.data.products.insert(product),
+            AddEndpoint(endpoint) => self.data.endpoints.insert(endpoint),
             AddConfig(cfg) => self.data.configurations.insert(cfg),
-            AddLog((identifier, log)) => {
-                self.data
-                    .logs
-                    .get_mut_or_insert(
-                        identifier,
-                        UnfluhsedLogEntry {
-                            number_received: 0,
-                            log,
-                        },
-                    )
-                    .number_received += 1;
+            AddLog((identifier, mut log)) => {
+                let count = log.count.max(1);
+                if self.stack_trace_redactor.is_enabled() {
+                    log.stack_trace = log
+                        .stack_trace
+                        .map(|s| self.stack_trace_redactor.redact(&s));
+                }
+                if self.data.logs.get(&identifier).is_some() {
+                    self.stats.lock().unwrap().deduplicated_logs += 1;
+                }
+                let entry = self.data.logs.get_mut_or_insert(
+                    identifier,
+                    UnfluhsedLogEntry {
+                        number_received: 0,
+                        log,
+                    },
+                );
+                entry.number_received = entry.number_received.saturating_add(count);
             }
             AddPoint((point, key, extra_tags)) => self.data.metric_buckets.add_point(
                 key,
@@ -238,27 +396,24 @@ impl TelemetryWorker {
                     batch.push(data::Payload::AppHeartbeat(()));
                     data::Payload::MessageBatch(batch)
                 };
-                match self.send_payload(&payload).await {
-                    Ok(()) => self.payload_sent_success(&payload),
-                    Err(err) => self.log_err(&err),
-                }
+                self.flush_payload(payload).await;
 
                 let logs = self.build_logs();
                 if !logs.is_empty() {
-                    let logs = data::Payload::Logs(logs);
-                    match self.send_payload(&logs).await {
-                        Ok(()) => self.payload_sent_success(&logs),
-                        Err(err) => self.log_err(&err),
-                    }
+                    self.flush_payload(data::Payload::Logs(logs)).await;
                 }
 
                 let metrics = self.build_metrics_series();
                 if !metrics.series.is_empty() {
                     // TODO Paul LGDC: flush metrics only if success
-                    let metrics = data::Payload::GenerateMetrics(metrics);
-                    if let Err(err) = self.send_payload(&metrics).await {
-                        self.log_err(&err);
-                    }
+                    self.flush_payload(data::Payload::GenerateMetrics(metrics))
+                        .await;
+                }
+
+                let distributions = self.build_distributions();
+                if !distributions.series.is_empty() {
+                    self.flush_payload(data::Payload::Distributions(distributions))
+                        .await;
                 }
 
                 self.deadlines
@@ -269,12 +424,12 @@ impl TelemetryWorker {
                 self.data.dependencies.unflush_stored();
                 self.data.integrations.unflush_stored();
                 self.data.configurations.unflush_stored();
+                self.data.products.unflush_stored();
+                self.data.endpoints.unflush_stored();
+                self.data.endpoints_first = true;
 
                 let app_started = data::Payload::AppStarted(self.build_app_started());
-                match self.send_payload(&app_started).await {
-                    Ok(()) => self.payload_sent_success(&app_started),
-                    Err(err) => self.log_err(&err),
-                }
+                self.flush_payload(app_started).await;
                 self.deadlines
                     .schedule_events(
                         &mut [
@@ -285,46 +440,29 @@ impl TelemetryWorker {
                     )
                     .unwrap();
             }
-            Lifecycle(Stop) => {
+            Lifecycle(CollectNativeDeps) => self.collect_native_deps(),
+            Lifecycle(action @ (Stop | Detach)) => {
                 if !self.data.started {
                     return BREAK;
                 }
                 self.data.metric_buckets.flush_agregates();
 
                 let mut app_events = self.build_app_events_batch();
-                app_events.push(data::Payload::AppClosing(()));
+                if action == Stop {
+                    app_events.push(data::Payload::AppClosing(()));
+                }
 
                 let obsevability_events = self.build_observability_batch();
 
-                future::join_all(
-                    [
-                        Some(self.build_request(&data::Payload::MessageBatch(app_events))),
-                        if obsevability_events.is_empty() {
-                            None
-                        } else {
-                            Some(
-                                self.build_request(&data::Payload::MessageBatch(
-                                    obsevability_events,
-                                )),
-                            )
-                        },
-                    ]
-                    .into_iter()
-                    .flatten()
-                    .filter_map(|r| match r {
-                        Ok(r) => Some(r),
-                        Err(e) => {
-                            self.log_err(&e);
-                            None
-                        }
-                    })
-                    .map(|r| async {
-                        if let Err(e) = self.send_request(r).await {
-                            self.log_err(&e);
-                        }
-                    }),
-                )
-                .await;
+                // the chunks are sent one after the other, so that AppClosing comes last
+                if !app_events.is_empty() {
+                    self.flush_payload(data::Payload::MessageBatch(app_events))
+                        .await;
+                }
+                if !obsevability_events.is_empty() {
+                    self.flush_payload(data::Payload::MessageBatch(obsevability_events))
+                        .await;
+                }
 
                 return BREAK;
             }
@@ -333,6 +471,32 @@ impl TelemetryWorker {
         CONTINUE
     }
 
+    /// Adds the shared libraries loaded since the last scan to the dependencies, and schedules
+    /// the next scan
+    fn collect_native_deps(&mut self) {
+        let deps = self.native_deps.collect();
+        self.data.dependencies.extend(deps);
+        self.deadlines
+            .schedule_event(LifecycleAction::CollectNativeDeps)
+            .unwrap();
+    }
+
+    fn snapshot(&self) -> TelemetryWorkerSnapshot {
+        TelemetryWorkerSnapshot {
+            runtime_id: self.runtime_id.clone(),
+            seq_id: self.seq_id.load(Ordering::Acquire),
+            started: self.data.started || self.data.restored,
+            host: self.data.host.clone(),
+            application: self.data.app.clone(),
+            dependencies: self.data.dependencies.snapshot(),
+            configurations: self.data.configurations.snapshot(),
+            integrations: self.data.integrations.snapshot(),
+            products: self.data.products.snapshot(),
+            endpoints: self.data.endpoints.snapshot(),
+            metric_contexts: self.data.metric_contexts.snapshot(),
+        }
+    }
+
     fn build_app_events_batch(&self) -> Vec<Payload> {
         let mut payloads = Vec::new();
 
@@ -357,6 +521,17 @@ impl TelemetryWorker {
                 },
             ))
         }
+        if self.data.products.flush_not_empty() {
+            payloads.push(data::Payload::AppProductChange(data::AppProductChange {
+                products: self.data.products.unflushed().cloned().collect(),
+            }))
+        }
+        if self.data.endpoints.flush_not_empty() {
+            payloads.push(data::Payload::AppEndpoints(data::AppEndpoints {
+                is_first: self.data.endpoints_first,
+                endpoints: self.data.endpoints.unflushed().cloned().collect(),
+            }))
+        }
         payloads
     }
 
@@ -371,6 +546,10 @@ impl TelemetryWorker {
         if !metrics.series.is_empty() {
             payloads.push(data::Payload::GenerateMetrics(metrics))
         }
+        let distributions = self.build_distributions();
+        if !distributions.series.is_empty() {
+            payloads.push(data::Payload::Distributions(distributions))
+        }
         payloads
     }
 
@@ -407,6 +586,37 @@ impl TelemetryWorker {
         data::GenerateMetrics { series }
     }
 
+    fn build_distributions(&mut self) -> data::Distributions {
+        let mut series = Vec::new();
+        for (context_key, extra_tags, points) in self.data.metric_buckets.flush_distributions() {
+            let context_guard = self.data.metric_contexts.get_context(context_key);
+            let context = match context_guard.read() {
+                Some(context) => context,
+                None => {
+                    telemetry_worker_log!(
+                        self,
+                        ERROR,
+                        "Context not found for key {:?}",
+                        context_key
+                    );
+                    continue;
+                }
+            };
+
+            let mut tags = extra_tags;
+            tags.extend(context.tags.iter().cloned());
+            series.push(data::metrics::Distribution {
+                namespace: context.namespace,
+                metric: context.name.clone(),
+                tags,
+                points,
+                common: context.common,
+            });
+        }
+
+        data::Distributions { series }
+    }
+
     fn build_app_started(&mut self) -> data::AppStarted {
         data::AppStarted {
             configuration: self.data.configurations.unflushed().cloned().collect(),
@@ -434,6 +644,11 @@ impl TelemetryWorker {
                 .data
                 .configurations
                 .removed_flushed(p.configuration.len()),
+            AppProductChange(p) => self.data.products.removed_flushed(p.products.len()),
+            AppEndpoints(p) => {
+                self.data.endpoints.removed_flushed(p.endpoints.len());
+                self.data.endpoints_first = false;
+            }
             MessageBatch(batch) => {
                 for p in batch {
                     self.payload_sent_success(p);
@@ -446,7 +661,7 @@ impl TelemetryWorker {
             }
             AppHeartbeat(()) | AppClosing(()) => {}
             // TODO Paul lgdc flush metrics only if success
-            GenerateMetrics(_) => {}
+            GenerateMetrics(_) | Distributions(_) => {}
         }
     }
 
@@ -455,33 +670,130 @@ impl TelemetryWorker {
             .data
             .logs
             .iter()
-            .map(|(_, e)| {
-                use std::fmt::Write;
-                let mut log = e.log.clone();
-                if e.number_received > 1 {
-                    write!(
-                        &mut log.message,
-                        "\nSkipped {} messages",
-                        e.number_received - 1
-                    )
-                    .unwrap();
-                }
-                log
+            .map(|(_, e)| data::Log {
+                count: e.number_received,
+                ..e.log.clone()
             })
             .collect();
         logs
     }
 
+    /// Size left for the payload in a request, once the telemetry envelope is accounted for
+    fn max_payload_size(&self) -> usize {
+        let envelope = serialize::serialized_size(&Telemetry {
+            api_version: data::ApiVersion::V2,
+            tracer_time: 0,
+            runtime_id: &self.runtime_id,
+            seq_id: u64::MAX,
+            host: &self.data.host,
+            application: &self.data.app,
+            payload: &data::Payload::MessageBatch(Vec::new()),
+        });
+        self.config
+            .telemetry_max_payload_size
+            .saturating_sub(envelope)
+    }
+
+    /// Splits a payload in as many payloads as needed for each one to fit in a single request
+    ///
+    /// Batches are split across multiple batches, and dependencies, logs and metrics are chunked
+    /// across multiple payloads of the same type. The order of the data is preserved.
+    fn split_payload(&self, payload: data::Payload) -> Vec<data::Payload> {
+        let max_size = self.max_payload_size();
+        if serialize::serialized_size(&payload) <= max_size {
+            return vec![payload];
+        }
+        match payload {
+            data::Payload::MessageBatch(batch) => {
+                let items = batch
+                    .into_iter()
+                    .flat_map(|p| Self::split_batch_item(p, max_size))
+                    .collect();
+                serialize::chunk_by_size(items, max_size)
+                    .into_iter()
+                    .map(data::Payload::MessageBatch)
+                    .collect()
+            }
+            p => Self::split_batch_item(p, max_size),
+        }
+    }
+
+    fn split_batch_item(payload: data::Payload, max_size: usize) -> Vec<data::Payload> {
+        use data::Payload::*;
+        if serialize::serialized_size(&payload) <= max_size {
+            return vec![payload];
+        }
+        match payload {
+            AppDependenciesLoaded(p) => {
+                let max_size = max_size.saturating_sub(serialize::serialized_size(
+                    &AppDependenciesLoaded(data::AppDependenciesLoaded {
+                        dependencies: Vec::new(),
+                    }),
+                ));
+                serialize::chunk_by_size(p.dependencies, max_size)
+                    .into_iter()
+                    .map(|dependencies| {
+                        AppDependenciesLoaded(data::AppDependenciesLoaded { dependencies })
+                    })
+                    .collect()
+            }
+            Logs(logs) => {
+                let max_size =
+                    max_size.saturating_sub(serialize::serialized_size(&Logs(Vec::new())));
+                serialize::chunk_by_size(logs, max_size)
+                    .into_iter()
+                    .map(Logs)
+                    .collect()
+            }
+            GenerateMetrics(p) => {
+                let max_size = max_size.saturating_sub(serialize::serialized_size(
+                    &GenerateMetrics(data::GenerateMetrics { series: Vec::new() }),
+                ));
+                serialize::chunk_by_size(p.series, max_size)
+                    .into_iter()
+                    .map(|series| GenerateMetrics(data::GenerateMetrics { series }))
+                    .collect()
+            }
+            Distributions(p) => {
+                let max_size = max_size.saturating_sub(serialize::serialized_size(&Distributions(
+                    data::Distributions { series: Vec::new() },
+                )));
+                serialize::chunk_by_size(p.series, max_size)
+                    .into_iter()
+                    .map(|series| Distributions(data::Distributions { series }))
+                    .collect()
+            }
+            p => vec![p],
+        }
+    }
+
+    /// Sends a payload, split across multiple requests if it is too big, and marks the data
+    /// it contains as flushed.
+    ///
+    /// Requests are sent in order, and sending stops at the first failure so that
+    /// the stores are only ever flushed from the front.
+    async fn flush_payload(&mut self, payload: data::Payload) {
+        for payload in self.split_payload(payload) {
+            match self.send_payload(&payload).await {
+                Ok(()) => self.payload_sent_success(&payload),
+                Err(err) => {
+                    self.log_err(&err);
+                    break;
+                }
+            }
+        }
+    }
+
     fn next_seq_id(&self) -> u64 {
         self.seq_id.fetch_add(1, Ordering::Release)
     }
 
     async fn send_payload(&self, payload: &data::Payload) -> Result<()> {
         let req = self.build_request(payload)?;
-        self.send_request(req).await
+        self.send_request(&req).await
     }
 
-    fn build_request(&self, payload: &data::Payload) -> Result<Request<hyper::Body>> {
+    fn build_request(&self, payload: &data::Payload) -> Result<TelemetryRequest> {
         let seq_id = self.next_seq_id();
         let tel = Telemetry {
             api_version: data::ApiVersion::V2,
@@ -498,45 +810,49 @@ impl TelemetryWorker {
 
         telemetry_worker_log!(self, DEBUG, "Prepared payload: {:?}", tel);
 
-        let req = http_client::request_builder(&self.config)?
-            .method(http::Method::POST)
-            .header(header::CONTENT_TYPE, serialize::CONTENT_TYPE_VALUE)
-            .header(
-                http_client::header::REQUEST_TYPE,
-                HeaderValue::from_static(payload.request_type()),
-            )
-            .header(
-                http_client::header::API_VERSION,
-                HeaderValue::from_static(data::ApiVersion::V2.to_str()),
-            )
-            .header(
-                http_client::header::LIBRARY_LANGUAGE,
-                // Note: passing by ref here just causes the clone to happen underneath
-                tel.application.language_name.clone(),
-            )
-            .header(
-                http_client::header::LIBRARY_VERSION,
-                &tel.application.tracer_version.clone(),
-            );
-
-        let body = hyper::Body::from(serialize::serialize(&tel)?);
-        Ok(req.body(body)?)
+        let mut headers = http::HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, serialize::CONTENT_TYPE_VALUE);
+        headers.insert(
+            http_client::header::REQUEST_TYPE,
+            HeaderValue::from_static(payload.request_type()),
+        );
+        headers.insert(
+            http_client::header::API_VERSION,
+            HeaderValue::from_static(data::ApiVersion::V2.to_str()),
+        );
+        headers.insert(
+            http_client::header::LIBRARY_LANGUAGE,
+            HeaderValue::from_str(&tel.application.language_name)?,
+        );
+        headers.insert(
+            http_client::header::LIBRARY_VERSION,
+            HeaderValue::from_str(&tel.application.tracer_version)?,
+        );
+
+        let mut body = serialize::serialize(&tel)?;
+        if self.config.telemetry_compression_enabled {
+            body = serialize::compress(&body)?;
+            headers.insert(header::CONTENT_ENCODING, serialize::CONTENT_ENCODING_GZIP);
+        }
+        Ok(TelemetryRequest {
+            request_type: payload.request_type(),
+            headers,
+            body: body.into(),
+        })
     }
 
-    async fn send_request(&self, req: Request<hyper::Body>) -> Result<()> {
-        tokio::select! {
+    async fn send_request(&self, req: &TelemetryRequest) -> Result<()> {
+        let res = tokio::select! {
             _ = self.cancellation_token.cancelled() => {
                 Err(anyhow::anyhow!("Request cancelled"))
             },
-            r = self.client.request(req) => {
-                match r {
-                    Ok(_) => {
-                        Ok(())
-                    }
-                    Err(e) => Err(e.into()),
-                }
-            }
-        }
+            r = self.transport.send(req) => r,
+        };
+        self.stats
+            .lock()
+            .unwrap()
+            .record_request(req.request_type, req.body.len(), &res);
+        res
     }
 }
 
@@ -569,9 +885,36 @@ pub struct TelemetryWorkerHandle {
     cancellation_token: CancellationToken,
     runtime: runtime::Handle,
     contexts: MetricContexts,
+    // the settings workers of forked children get
+    #[cfg(unix)]
+    config: Config,
+    #[cfg(unix)]
+    stack_trace_redactor: redaction::StackTraceRedactor,
+    #[cfg(unix)]
+    native_deps: bool,
+    #[cfg(unix)]
+    rust_shared_lib_deps: bool,
+    #[cfg(unix)]
+    transport: Arc<dyn Transport>,
+    stats: Arc<Mutex<TelemetryWorkerStats>>,
+}
+
+/// Outcome of [TelemetryWorkerHandle::fork]
+#[cfg(unix)]
+pub enum ForkedWorker {
+    Parent(libc::pid_t),
+    /// Handle to the worker of the child process, continuing the parent's telemetry stream
+    Child(Box<TelemetryWorkerHandle>),
 }
 
 impl TelemetryWorkerHandle {
+    /// Returns a snapshot of the activity of the worker
+    pub fn stats(&self) -> TelemetryWorkerStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.mailbox_depth = self.sender.max_capacity() - self.sender.capacity();
+        stats
+    }
+
     pub fn register_metric_context(
         &self,
         name: String,
@@ -657,6 +1000,29 @@ impl TelemetryWorkerHandle {
         Ok(())
     }
 
+    pub fn add_product(
+        &self,
+        name: String,
+        enabled: bool,
+        version: Option<String>,
+        error: Option<data::Error>,
+    ) -> Result<()> {
+        self.sender
+            .try_send(TelemetryActions::AddProduct(data::Product {
+                name,
+                enabled,
+                version,
+                error,
+            }))?;
+        Ok(())
+    }
+
+    pub fn add_endpoint(&self, endpoint: data::Endpoint) -> Result<()> {
+        self.sender
+            .try_send(TelemetryActions::AddEndpoint(endpoint))?;
+        Ok(())
+    }
+
     pub fn add_log<T: Hash>(
         &self,
         identifier: T,
@@ -664,17 +1030,37 @@ impl TelemetryWorkerHandle {
         level: data::LogLevel,
         stack_trace: Option<String>,
     ) -> Result<()> {
+        self.add_log_entry(
+            identifier,
+            data::Log {
+                message,
+                level,
+                stack_trace,
+                tags: String::new(),
+                count: 1,
+                tracer_time: 0,
+                is_sensitive: false,
+            },
+        )
+    }
+
+    /// Adds a log, deduplicated with other logs sharing the same identifier
+    ///
+    /// The tracer_time of the log is set to now if left to 0
+    pub fn add_log_entry<T: Hash>(&self, identifier: T, mut log: data::Log) -> Result<()> {
         let mut hasher = DefaultHasher::new();
         identifier.hash(&mut hasher);
+        if log.tracer_time == 0 {
+            log.tracer_time = time::SystemTime::now()
+                .duration_since(time::SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+        }
         self.sender.try_send(TelemetryActions::AddLog((
             LogIdentifier {
                 indentifier: hasher.finish(),
             },
-            data::Log {
-                message,
-                level,
-                stack_trace,
-            },
+            log,
         )))?;
         Ok(())
     }
@@ -688,6 +1074,76 @@ impl TelemetryWorkerHandle {
     pub fn wait_for_shutdown(&self) {
         self.shutdown.wait_for_shutdown();
     }
+
+    pub async fn snapshot(&self) -> Result<TelemetryWorkerSnapshot> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(TelemetryActions::CollectSnapshot(tx))
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Blocking version of [Self::snapshot]
+    ///
+    /// Panics if called from within an asynchronous execution context
+    pub fn snapshot_blocking(&self) -> Result<TelemetryWorkerSnapshot> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .blocking_send(TelemetryActions::CollectSnapshot(tx))?;
+        Ok(rx.blocking_recv()?)
+    }
+
+    /// Forks the process, and starts a new worker in the child, restored from the
+    /// state of the current one
+    ///
+    /// The worker of the parent keeps running. The worker of the child gets a new tokio runtime
+    /// and mailbox and continues the same telemetry stream, without sending app-started, or
+    /// the dependencies, integrations and configurations known before the fork, which the parent
+    /// sends if it did not already. The child numbers its requests in its own range of seq ids,
+    /// see [TelemetryWorkerSnapshot::for_forked_child]. Actions sent to this handle while forking
+    /// might not be part of the child's state. The child keeps the redaction and native
+    /// dependency settings of the parent, and its transport if it has an equivalent for the
+    /// child, see [Transport::for_forked_child].
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as fork(2): the child must not rely on resources held by other threads
+    /// of the parent, such as locks, including allocator locks on platforms where the
+    /// allocator is not fork safe.
+    /// Must not be called from within an asynchronous execution context.
+    #[cfg(unix)]
+    pub unsafe fn fork(&self) -> Result<ForkedWorker> {
+        let snapshot = self.snapshot_blocking()?;
+        match libc::fork() {
+            -1 => Err(std::io::Error::last_os_error().into()),
+            0 => {
+                let started = snapshot.started;
+                let builder = self.forked_child_builder(snapshot, std::process::id());
+                let handle = builder.run_with_config(self.config.clone())?;
+                if started {
+                    handle.send_start()?;
+                }
+                Ok(ForkedWorker::Child(Box::new(handle)))
+            }
+            pid => Ok(ForkedWorker::Parent(pid)),
+        }
+    }
+
+    /// The builder of the worker of the child, configured as the current one
+    #[cfg(unix)]
+    fn forked_child_builder(
+        &self,
+        snapshot: TelemetryWorkerSnapshot,
+        child_pid: u32,
+    ) -> TelemetryWorkerBuilder {
+        let mut builder =
+            TelemetryWorkerBuilder::from_snapshot(snapshot.for_forked_child(child_pid));
+        builder.stack_trace_redactor = self.stack_trace_redactor.clone();
+        builder.native_deps = self.native_deps;
+        builder.rust_shared_lib_deps = self.rust_shared_lib_deps;
+        builder.transport = self.transport.for_forked_child();
+        builder
+    }
 }
 
 /// How many dependencies/integrations/configs we keep in memory at most
@@ -700,9 +1156,23 @@ pub struct TelemetryWorkerBuilder {
     pub dependencies: store::Store<data::Dependency>,
     pub integrations: store::Store<data::Integration>,
     pub configurations: store::Store<data::Configuration>,
+    pub products: store::Store<data::Product>,
+    pub endpoints: store::Store<data::Endpoint>,
     pub native_deps: bool,
     pub rust_shared_lib_deps: bool,
     pub config: builder::ConfigBuilder,
+    pub stack_trace_redactor: redaction::StackTraceRedactor,
+    pub restored_state: Option<RestoredState>,
+    /// Where requests are sent, picked from the configuration if not set
+    pub transport: Option<Box<dyn Transport>>,
+}
+
+/// Part of a [TelemetryWorkerSnapshot] which isn't exposed through the builder fields
+#[derive(Debug)]
+pub struct RestoredState {
+    pub seq_id: u64,
+    pub started: bool,
+    pub metric_contexts: Vec<MetricContext>,
 }
 
 impl TelemetryWorkerBuilder {
@@ -747,9 +1217,39 @@ impl TelemetryWorkerBuilder {
             dependencies: store::Store::new(MAX_ITEMS),
             integrations: store::Store::new(MAX_ITEMS),
             configurations: store::Store::new(MAX_ITEMS),
+            products: store::Store::new(MAX_ITEMS),
+            endpoints: store::Store::new(MAX_ITEMS),
             native_deps: true,
             rust_shared_lib_deps: false,
             config: ConfigBuilder::default(),
+            stack_trace_redactor: redaction::StackTraceRedactor::default(),
+            restored_state: None,
+            transport: None,
+        }
+    }
+
+    /// Creates a builder for a worker continuing the telemetry stream of the worker
+    /// the snapshot was taken from
+    pub fn from_snapshot(snapshot: TelemetryWorkerSnapshot) -> Self {
+        Self {
+            host: snapshot.host,
+            application: snapshot.application,
+            runtime_id: Some(snapshot.runtime_id),
+            dependencies: snapshot.dependencies.into(),
+            integrations: snapshot.integrations.into(),
+            configurations: snapshot.configurations.into(),
+            products: snapshot.products.into(),
+            endpoints: snapshot.endpoints.into(),
+            native_deps: true,
+            rust_shared_lib_deps: false,
+            config: ConfigBuilder::default(),
+            stack_trace_redactor: redaction::StackTraceRedactor::default(),
+            restored_state: Some(RestoredState {
+                seq_id: snapshot.seq_id,
+                started: snapshot.started,
+                metric_contexts: snapshot.metric_contexts,
+            }),
+            transport: None,
         }
     }
 
@@ -763,18 +1263,32 @@ impl TelemetryWorkerBuilder {
             is_shutdown: Mutex::new(false),
             condvar: Condvar::new(),
         });
-        let contexts = MetricContexts::default();
+        let (seq_id, restored, contexts) = match self.restored_state {
+            Some(state) => (state.seq_id, state.started, state.metric_contexts.into()),
+            None => (0, false, MetricContexts::default()),
+        };
         let token = CancellationToken::new();
         let config = self.config.merge(external_config);
+        #[cfg(unix)]
+        let handle_config = config.clone();
         let telemetry_hearbeat_interval = config.telemetry_hearbeat_interval;
-        let client = http_client::from_config(&config);
+        let stats = Arc::new(Mutex::new(TelemetryWorkerStats::default()));
+        let transport: Arc<dyn Transport> = self
+            .transport
+            .unwrap_or_else(|| transport::from_config(&config))
+            .into();
 
         let worker = TelemetryWorker {
             data: TelemetryWorkerData {
                 started: false,
+                restored,
                 dependencies: self.dependencies,
                 integrations: self.integrations,
                 configurations: self.configurations,
+                products: self.products,
+                endpoints: self.endpoints,
+                // The worker restored from already sent the endpoints it knew about
+                endpoints_first: !restored,
                 logs: store::QueueHashMap::default(),
                 metric_contexts: contexts.clone(),
                 metric_buckets: MetricBuckets::default(),
@@ -783,11 +1297,11 @@ impl TelemetryWorkerBuilder {
             },
             config,
             mailbox,
-            seq_id: AtomicU64::new(0),
+            seq_id: AtomicU64::new(seq_id),
             runtime_id: self
                 .runtime_id
                 .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
-            client,
+            transport: transport.clone(),
             deadlines: scheduler::Scheduler::new(vec![
                 (
                     time::Duration::from_secs(10),
@@ -798,7 +1312,14 @@ impl TelemetryWorkerBuilder {
                     time::Duration::from_secs(60 * 60 * 24),
                     LifecycleAction::ExtendedHeartbeat,
                 ),
+                (
+                    time::Duration::from_secs(60),
+                    LifecycleAction::CollectNativeDeps,
+                ),
             ]),
+            stack_trace_redactor: self.stack_trace_redactor.clone(),
+            native_deps: NativeDepsCollector::new(self.native_deps, self.rust_shared_lib_deps),
+            stats: stats.clone(),
             cancellation_token: token.clone(),
         };
 
@@ -809,6 +1330,17 @@ impl TelemetryWorkerBuilder {
                 cancellation_token: token,
                 runtime: tokio_runtime,
                 contexts,
+                #[cfg(unix)]
+                config: handle_config,
+                #[cfg(unix)]
+                stack_trace_redactor: self.stack_trace_redactor,
+                #[cfg(unix)]
+                native_deps: self.native_deps,
+                #[cfg(unix)]
+                rust_shared_lib_deps: self.rust_shared_lib_deps,
+                #[cfg(unix)]
+                transport,
+                stats,
             },
             worker,
         ))
@@ -816,8 +1348,19 @@ impl TelemetryWorkerBuilder {
 
     pub async fn spawn(self) -> Result<(TelemetryWorkerHandle, JoinHandle<()>)> {
         // TODO Paul LGDC: Is that really what we want?
-        let config = config::Config::from_env();
-        self.spawn_with_config(config).await
+        let settings = config::Settings::from_env();
+        self.spawn_with_settings(&settings).await
+    }
+
+    /// Spawns a worker configured from the settings, which are also reported, along with their
+    /// origin, in the configuration of app-started
+    pub async fn spawn_with_settings(
+        mut self,
+        settings: &config::Settings,
+    ) -> Result<(TelemetryWorkerHandle, JoinHandle<()>)> {
+        self.configurations.extend(settings.configurations());
+        self.spawn_with_config(Config::from_settings(settings))
+            .await
     }
 
     pub async fn spawn_with_config(
@@ -834,13 +1377,26 @@ impl TelemetryWorkerBuilder {
     }
 
     pub fn run(self) -> Result<TelemetryWorkerHandle> {
+        // TODO Paul LGDC: Is that really what we want?
+        let settings = config::Settings::from_env();
+        self.run_with_settings(&settings)
+    }
+
+    /// Runs a worker configured from the settings, which are also reported, along with their
+    /// origin, in the configuration of app-started
+    pub fn run_with_settings(
+        mut self,
+        settings: &config::Settings,
+    ) -> Result<TelemetryWorkerHandle> {
+        self.configurations.extend(settings.configurations());
+        self.run_with_config(Config::from_settings(settings))
+    }
+
+    pub fn run_with_config(self, config: Config) -> Result<TelemetryWorkerHandle> {
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()?;
 
-        // TODO Paul LGDC: Is that really what we want?
-        let config = config::Config::from_env();
-
         let (handle, worker) = self.build_worker(config, runtime.handle().clone())?;
 
         let notify_shutdown = handle.shutdown.clone();
@@ -853,3 +1409,471 @@ impl TelemetryWorkerBuilder {
         Ok(handle)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn build_test_worker(max_payload_size: usize) -> (TelemetryWorkerHandle, TelemetryWorker) {
+        let mut builder = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        );
+        builder.config.telemetry_max_payload_size = Some(max_payload_size);
+        builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap()
+    }
+
+    fn dependencies(count: usize) -> Vec<Dependency> {
+        (0..count)
+            .map(|i| Dependency {
+                name: format!("dependency-{i}"),
+                version: Some("1.0.0".into()),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restore() {
+        let (handle, mut worker) = build_test_worker(config::DEFAULT_MAX_PAYLOAD_SIZE);
+        let context = handle.register_metric_context(
+            "metric".into(),
+            Vec::new(),
+            data::metrics::MetricType::Count,
+            false,
+            data::metrics::MetricNamespace::Trace,
+        );
+        worker.data.started = true;
+        worker.data.dependencies.extend(dependencies(3));
+        worker.data.dependencies.removed_flushed(2);
+        worker.next_seq_id();
+        worker.next_seq_id();
+
+        let bytes = worker.snapshot().to_bytes().unwrap();
+        let snapshot = TelemetryWorkerSnapshot::from_bytes(&bytes).unwrap();
+        let (restored_handle, restored) = TelemetryWorkerBuilder::from_snapshot(snapshot)
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        assert_eq!(restored.runtime_id, worker.runtime_id);
+        assert_eq!(restored.next_seq_id(), 2);
+        assert!(restored.data.restored);
+        assert!(!restored.data.started);
+        assert_eq!(restored.data.app.service_name, "service");
+        assert_eq!(
+            restored.data.dependencies.unflushed().collect::<Vec<_>>(),
+            dependencies(3)[2..].iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            restored_handle
+                .contexts
+                .get_context(context)
+                .read()
+                .unwrap()
+                .name,
+            "metric"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_for_forked_child() {
+        let (_, mut worker) = build_test_worker(config::DEFAULT_MAX_PAYLOAD_SIZE);
+        worker.data.started = true;
+        worker.data.dependencies.extend(dependencies(3));
+        worker.data.dependencies.removed_flushed(2);
+        worker.next_seq_id();
+
+        let snapshot = worker.snapshot().for_forked_child(1234);
+        let (_, child) = TelemetryWorkerBuilder::from_snapshot(snapshot)
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        assert_eq!(child.runtime_id, worker.runtime_id);
+        assert_eq!(child.next_seq_id(), 1234 << 32);
+        assert!(child.data.restored);
+        // the parent still sends its unflushed dependency, the child only the new ones
+        assert!(child.data.dependencies.unflushed().next().is_none());
+        assert_eq!(
+            worker.data.dependencies.unflushed().collect::<Vec<_>>(),
+            dependencies(3)[2..].iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_forked_child_keeps_the_settings() {
+        let transport = transport::InMemoryTransport::new();
+        let mut builder = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        );
+        builder.native_deps = false;
+        builder.rust_shared_lib_deps = true;
+        builder.transport = Some(Box::new(transport.clone()));
+        let (handle, mut worker) = builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+        worker.data.started = true;
+
+        let builder = handle.forked_child_builder(worker.snapshot(), 1234);
+        assert!(!builder.native_deps);
+        assert!(builder.rust_shared_lib_deps);
+        let (_, mut child) = builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        let _ = child
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Start))
+            .await;
+        child.data.dependencies.extend(dependencies(1));
+        let _ = child
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::FlushData))
+            .await;
+        let payloads = transport.payloads().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["seq_id"], 1234u64 << 32);
+    }
+
+    #[tokio::test]
+    async fn test_app_events_batch_products_and_endpoints() {
+        let (_, mut worker) = build_test_worker(config::DEFAULT_MAX_PAYLOAD_SIZE);
+        worker.data.products.insert(data::Product {
+            name: "appsec".into(),
+            enabled: true,
+            version: Some("1.0.0".into()),
+            error: None,
+        });
+        worker.data.endpoints.insert(data::Endpoint {
+            _type: Some("REST".into()),
+            method: Some("GET".into()),
+            path: Some("/users".into()),
+            operation_name: "http.request".into(),
+            resource_name: "GET /users".into(),
+        });
+
+        let batch = worker.build_app_events_batch();
+        let json = serde_json::to_value(&batch).unwrap();
+        assert_eq!(
+            json[0],
+            serde_json::json!({
+                "request_type": "app-product-change",
+                "payload": {
+                    "products": {"appsec": {"enabled": true, "version": "1.0.0"}}
+                }
+            })
+        );
+        assert_eq!(json[1]["request_type"], "app-endpoints");
+        assert_eq!(json[1]["payload"]["is_first"], true);
+        assert_eq!(json[1]["payload"]["endpoints"][0]["type"], "REST");
+
+        worker.payload_sent_success(&data::Payload::MessageBatch(batch));
+        assert!(worker.build_app_events_batch().is_empty());
+
+        worker.data.endpoints.insert(data::Endpoint {
+            operation_name: "http.request".into(),
+            resource_name: "GET /orders".into(),
+            ..Default::default()
+        });
+        match worker.build_app_events_batch().as_slice() {
+            [data::Payload::AppEndpoints(p)] => {
+                assert!(!p.is_first);
+                assert_eq!(p.endpoints.len(), 1);
+            }
+            batch => panic!("unexpected batch {batch:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logs_deduplication_and_redaction() {
+        let (_, mut worker) = build_test_worker(config::DEFAULT_MAX_PAYLOAD_SIZE);
+        worker
+            .stack_trace_redactor
+            .add_library_frame_pattern("libdatadog".into());
+
+        let log = |message: &str, count| data::Log {
+            message: message.into(),
+            level: data::LogLevel::Error,
+            stack_trace: Some("app.c:12\nlibdatadog.so".into()),
+            tags: "lib_language:rust".into(),
+            count,
+            tracer_time: 1,
+            is_sensitive: false,
+        };
+        for (id, message, count) in [(1, "first", 1), (1, "second", 3), (2, "other", 1)] {
+            let _ = worker
+                .dispatch_action(TelemetryActions::AddLog((
+                    LogIdentifier { indentifier: id },
+                    log(message, count),
+                )))
+                .await;
+        }
+
+        let logs = worker.build_logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].message, "first");
+        assert_eq!(logs[0].count, 4);
+        assert_eq!(
+            logs[0].stack_trace.as_deref(),
+            Some("REDACTED\nlibdatadog.so")
+        );
+        assert_eq!(logs[1].count, 1);
+
+        let json = serde_json::to_value(&logs[0]).unwrap();
+        assert_eq!(json["tags"], "lib_language:rust");
+        assert_eq!(json["count"], 4);
+        assert_eq!(json["level"], "ERROR");
+    }
+
+    #[test]
+    fn test_chunk_by_size() {
+        let chunks = serialize::chunk_by_size(vec!["aaaa"; 10], 20);
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|c| !c.is_empty()));
+        assert_eq!(chunks.iter().map(Vec::len).sum::<usize>(), 10);
+
+        // Items bigger than the limit still get their own chunk
+        let chunks = serialize::chunk_by_size(vec!["a".repeat(100), "b".into()], 10);
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[test]
+    fn test_compress() {
+        let body = br#"{"hello":"world"}"#.repeat(100);
+        let compressed = serialize::compress(&body).unwrap();
+        assert!(compressed.len() < body.len());
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[tokio::test]
+    async fn test_split_payload_keeps_small_payloads() {
+        let (_, worker) = build_test_worker(config::DEFAULT_MAX_PAYLOAD_SIZE);
+        let payloads = worker.split_payload(data::Payload::MessageBatch(vec![
+            data::Payload::AppDependenciesLoaded(data::AppDependenciesLoaded {
+                dependencies: dependencies(10),
+            }),
+            data::Payload::AppHeartbeat(()),
+        ]));
+        assert_eq!(payloads.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_split_payload_chunks_dependencies() {
+        let max_payload_size = 4096;
+        let (_, worker) = build_test_worker(max_payload_size);
+        let payloads = worker.split_payload(data::Payload::MessageBatch(vec![
+            data::Payload::AppDependenciesLoaded(data::AppDependenciesLoaded {
+                dependencies: dependencies(1000),
+            }),
+            data::Payload::AppHeartbeat(()),
+        ]));
+        assert!(payloads.len() > 1);
+
+        let mut sent_dependencies = Vec::new();
+        for payload in &payloads {
+            let batch = match payload {
+                data::Payload::MessageBatch(batch) => batch,
+                p => panic!("unexpected payload {p:?}"),
+            };
+            for p in batch {
+                if let data::Payload::AppDependenciesLoaded(p) = p {
+                    sent_dependencies.extend(p.dependencies.iter().cloned());
+                }
+            }
+            let body = serialize::serialize(&Telemetry {
+                api_version: data::ApiVersion::V2,
+                tracer_time: 0,
+                runtime_id: &worker.runtime_id,
+                seq_id: 0,
+                host: &worker.data.host,
+                application: &worker.data.app,
+                payload,
+            })
+            .unwrap();
+            assert!(body.len() <= max_payload_size);
+        }
+        assert_eq!(sent_dependencies, dependencies(1000));
+        assert!(matches!(
+            payloads.last(),
+            Some(data::Payload::MessageBatch(batch))
+                if matches!(batch.last(), Some(data::Payload::AppHeartbeat(())))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_custom_transport() {
+        let transport = transport::InMemoryTransport::new();
+        let mut builder = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        );
+        builder.native_deps = false;
+        builder.config.telemetry_compression_enabled = Some(true);
+        builder.transport = Some(Box::new(transport.clone()));
+        let (_, mut worker) = builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        let _ = worker
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Start))
+            .await;
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].request_type, "app-started");
+        assert!(requests[0].is_compressed());
+        let payloads = transport.payloads().unwrap();
+        assert_eq!(payloads[0]["request_type"], "app-started");
+        assert_eq!(payloads[0]["application"]["service_name"], "service");
+        assert!(worker.data.started);
+    }
+
+    #[tokio::test]
+    async fn test_detach_does_not_close_the_app() {
+        let transport = transport::InMemoryTransport::new();
+        let mut builder = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        );
+        builder.native_deps = false;
+        builder.transport = Some(Box::new(transport.clone()));
+        let (_, mut worker) = builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        let _ = worker
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Start))
+            .await;
+        worker.data.dependencies.extend(dependencies(1));
+        let flow = worker
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Detach))
+            .await;
+        assert!(flow.is_break());
+
+        let payloads = transport.payloads().unwrap();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[1]["request_type"], "message-batch");
+        assert_eq!(payloads[1]["payload"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            payloads[1]["payload"][0]["request_type"],
+            "app-dependencies-loaded"
+        );
+    }
+
+    /// Records the requests once answered, the first ones taking the longest to be
+    #[derive(Clone, Default)]
+    struct SlowingDownTransport {
+        sent: Arc<AtomicU64>,
+        inner: transport::InMemoryTransport,
+    }
+
+    impl transport::Transport for SlowingDownTransport {
+        fn send<'a>(&'a self, request: &'a TelemetryRequest) -> transport::TransportFuture<'a> {
+            let delay = 50u64.saturating_sub(10 * self.sent.fetch_add(1, Ordering::Relaxed));
+            Box::pin(async move {
+                tokio::time::sleep(time::Duration::from_millis(delay)).await;
+                self.inner.send(request).await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_sends_the_chunks_in_order() {
+        let transport = SlowingDownTransport::default();
+        let mut builder = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        );
+        builder.native_deps = false;
+        builder.config.telemetry_max_payload_size = Some(4096);
+        builder.transport = Some(Box::new(transport.clone()));
+        let (_, mut worker) = builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        let _ = worker
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Start))
+            .await;
+        worker.data.dependencies.extend(dependencies(200));
+        let _ = worker
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Stop))
+            .await;
+
+        let payloads = transport.inner.payloads().unwrap();
+        assert!(payloads.len() > 2);
+        let seq_ids: Vec<_> = payloads.iter().map(|p| p["seq_id"].as_u64()).collect();
+        let mut sorted = seq_ids.clone();
+        sorted.sort();
+        assert_eq!(seq_ids, sorted);
+        let last = payloads.last().unwrap()["payload"].as_array().unwrap();
+        assert_eq!(last.last().unwrap()["request_type"], "app-closing");
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let transport = transport::InMemoryTransport::new();
+        let mut builder = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        );
+        builder.native_deps = false;
+        builder.dependencies = store::Store::new(2);
+        builder.transport = Some(Box::new(transport.clone()));
+        let (handle, mut worker) = builder
+            .build_worker(Config::default(), Handle::current())
+            .unwrap();
+
+        let _ = worker
+            .dispatch_action(TelemetryActions::Lifecycle(LifecycleAction::Start))
+            .await;
+        for log in ["a", "a", "b", "a"] {
+            handle
+                .add_log(log, log.into(), data::LogLevel::Warn, None)
+                .unwrap();
+        }
+        for dependency in dependencies(3) {
+            handle
+                .add_dependency(dependency.name, dependency.version)
+                .unwrap();
+        }
+        assert_eq!(handle.stats().mailbox_depth, 7);
+
+        while let Ok(action) = worker.mailbox.try_recv() {
+            let _ = worker.dispatch_action(action).await;
+        }
+        worker.update_dropped_stats();
+
+        let stats = handle.stats();
+        assert_eq!(stats.mailbox_depth, 0);
+        assert_eq!(stats.requests["app-started"].sent, 1);
+        assert_eq!(stats.requests_failed(), 0);
+        assert_eq!(stats.bytes_sent, transport.requests()[0].body.len() as u64);
+        assert_eq!(stats.deduplicated_logs, 2);
+        assert_eq!(stats.dropped.dependencies, 1);
+        assert_eq!(stats.last_error, None);
+    }
+}
@@ -0,0 +1,80 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+pub const REDACTED_FRAMES: &str = "REDACTED";
+
+#[derive(Debug, Default, Clone)]
+/// Removes frames not belonging to the library from stack traces
+///
+/// Stack traces are processed line by line. Lines containing one of the library frame patterns
+/// are kept, and every run of consecutive other lines is replaced by a single REDACTED line, so
+/// that application code never ends up in telemetry.
+pub struct StackTraceRedactor {
+    library_frame_patterns: Vec<String>,
+}
+
+impl StackTraceRedactor {
+    pub fn new(library_frame_patterns: Vec<String>) -> Self {
+        Self {
+            library_frame_patterns,
+        }
+    }
+
+    pub fn add_library_frame_pattern(&mut self, pattern: String) {
+        self.library_frame_patterns.push(pattern);
+    }
+
+    /// Redaction only happens once at least one library pattern is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.library_frame_patterns.is_empty()
+    }
+
+    fn is_library_frame(&self, frame: &str) -> bool {
+        self.library_frame_patterns
+            .iter()
+            .any(|p| frame.contains(p.as_str()))
+    }
+
+    pub fn redact(&self, stack_trace: &str) -> String {
+        let mut redacted = Vec::new();
+        let mut previous_redacted = false;
+        for frame in stack_trace.lines() {
+            if self.is_library_frame(frame) {
+                redacted.push(frame);
+                previous_redacted = false;
+            } else if !previous_redacted {
+                redacted.push(REDACTED_FRAMES);
+                previous_redacted = true;
+            }
+        }
+        redacted.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let redactor = StackTraceRedactor::new(vec!["ddtrace/".into(), "libdatadog".into()]);
+        let stack_trace = "#0 app/main.php(12)\n\
+             #1 app/controller.php(40)\n\
+             #2 ddtrace/hooks.php(3)\n\
+             #3 libdatadog.so\n\
+             #4 app/index.php(1)";
+
+        assert_eq!(
+            redactor.redact(stack_trace),
+            "REDACTED\n#2 ddtrace/hooks.php(3)\n#3 libdatadog.so\nREDACTED"
+        );
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let mut redactor = StackTraceRedactor::default();
+        assert!(!redactor.is_enabled());
+        redactor.add_library_frame_pattern("ddtrace".into());
+        assert!(redactor.is_enabled());
+    }
+}
@@ -0,0 +1,89 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(C)]
+pub struct RequestStats {
+    pub sent: u64,
+    pub failed: u64,
+}
+
+/// Items evicted from the stores because they were full, before they could be sent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(C)]
+pub struct DroppedItems {
+    pub dependencies: u64,
+    pub configurations: u64,
+    pub integrations: u64,
+    pub products: u64,
+    pub endpoints: u64,
+}
+
+/// Activity of a telemetry worker since it was started, see [super::TelemetryWorkerHandle::stats]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetryWorkerStats {
+    /// Outcome of the requests, by request type
+    pub requests: BTreeMap<String, RequestStats>,
+    /// Size of the bodies of the requests successfully sent, after compression
+    pub bytes_sent: u64,
+    pub last_error: Option<String>,
+    /// Number of actions waiting to be processed by the worker
+    pub mailbox_depth: usize,
+    /// Number of logs merged into an identical log which wasn't sent yet
+    pub deduplicated_logs: u64,
+    pub dropped: DroppedItems,
+}
+
+impl TelemetryWorkerStats {
+    pub fn requests_sent(&self) -> u64 {
+        self.requests.values().map(|r| r.sent).sum()
+    }
+
+    pub fn requests_failed(&self) -> u64 {
+        self.requests.values().map(|r| r.failed).sum()
+    }
+
+    pub(crate) fn record_request(
+        &mut self,
+        request_type: &str,
+        body_size: usize,
+        result: &anyhow::Result<()>,
+    ) {
+        let stats = self.requests.entry(request_type.to_owned()).or_default();
+        match result {
+            Ok(()) => {
+                stats.sent += 1;
+                self.bytes_sent += body_size as u64;
+            }
+            Err(e) => {
+                stats.failed += 1;
+                self.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_request() {
+        let mut stats = TelemetryWorkerStats::default();
+        stats.record_request("app-started", 100, &Ok(()));
+        stats.record_request("app-heartbeat", 10, &Ok(()));
+        stats.record_request("app-heartbeat", 10, &Err(anyhow::anyhow!("timeout")));
+
+        assert_eq!(stats.requests_sent(), 2);
+        assert_eq!(stats.requests_failed(), 1);
+        assert_eq!(
+            stats.requests["app-heartbeat"],
+            RequestStats { sent: 1, failed: 1 }
+        );
+        assert_eq!(stats.bytes_sent, 110);
+        assert_eq!(stats.last_error.as_deref(), Some("timeout"));
+    }
+}
@@ -1,8 +1,11 @@
 // Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
+use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, hash::Hash};
 
+use crate::data;
+
 mod queuehasmpap {
     use hashbrown::{hash_map::DefaultHashBuilder, raw::RawTable};
     use std::{
@@ -129,50 +132,124 @@ mod queuehasmpap {
 
 pub use queuehasmpap::QueueHashMap;
 
-#[derive(Default)]
+/// Identifies an item of a [Store], inserting an item with the key of a stored one replaces it
+pub trait StoreKey {
+    type Key: PartialEq + Eq + Hash;
+
+    fn store_key(&self) -> Self::Key;
+}
+
+impl StoreKey for data::Dependency {
+    // Several versions of a library can be loaded
+    type Key = (String, Option<String>);
+
+    fn store_key(&self) -> Self::Key {
+        (self.name.clone(), self.version.clone())
+    }
+}
+
+impl StoreKey for data::Configuration {
+    type Key = String;
+
+    fn store_key(&self) -> Self::Key {
+        self.name.clone()
+    }
+}
+
+impl StoreKey for data::Integration {
+    type Key = String;
+
+    fn store_key(&self) -> Self::Key {
+        self.name.clone()
+    }
+}
+
+impl StoreKey for data::Product {
+    type Key = String;
+
+    fn store_key(&self) -> Self::Key {
+        self.name.clone()
+    }
+}
+
+impl StoreKey for data::Endpoint {
+    type Key = data::Endpoint;
+
+    fn store_key(&self) -> Self::Key {
+        self.clone()
+    }
+}
+
 /// Stores telemetry data item, like dependencies and integrations
 ///
 /// * Bounds the length of the collection it uses to prevent memory leaks
 /// * Tries to keep a list of items that it has seen (within max number of items)
 /// * Tries to keep a list of items that haven't been sent to datadog yet
 /// * Deduplicates items, to make sure we don't send the item twice
-pub struct Store<T> {
+/// * Replaces the items changing value, like a toggled product, and sends them again
+pub struct Store<T: StoreKey> {
     // unflushed and set contain indices into
     unflushed: VecDeque<usize>,
-    items: QueueHashMap<T, ()>,
+    items: QueueHashMap<T::Key, T>,
     max_items: usize,
+    // unflushed items evicted to make room for new ones
+    dropped: u64,
+}
+
+impl<T: StoreKey> Default for Store<T> {
+    fn default() -> Self {
+        Self {
+            unflushed: VecDeque::new(),
+            items: QueueHashMap::default(),
+            max_items: 0,
+            dropped: 0,
+        }
+    }
 }
 
 impl<T> Store<T>
 where
-    T: PartialEq + Eq + Hash,
+    T: StoreKey + PartialEq,
 {
     pub fn new(max_items: usize) -> Self {
         Self {
             unflushed: VecDeque::new(),
             items: QueueHashMap::default(),
             max_items,
+            dropped: 0,
         }
     }
 
     pub fn insert(&mut self, item: T) {
-        if self.items.get(&item).is_some() {
-            return;
+        let key = item.store_key();
+        match self.items.get(&key) {
+            Some(stored) if *stored == item => return,
+            Some(_) => {
+                let (idx, _) = self.items.insert(key, item);
+                if !self.unflushed.contains(&idx) {
+                    self.push_unflushed(idx);
+                }
+                return;
+            }
+            None => {}
         }
         if self.items.len() == self.max_items {
             self.items.pop_front();
         }
-        let (idx, _) = self.items.insert(item, ());
+        let (idx, _) = self.items.insert(key, item);
+        self.push_unflushed(idx);
+    }
+
+    fn push_unflushed(&mut self, idx: usize) {
         if self.unflushed.len() == self.max_items {
             self.unflushed.pop_front();
+            self.dropped += 1;
         }
         self.unflushed.push_back(idx);
     }
 
     pub fn unflush_stored(&mut self) {
-        for i in self.items.iter_idx() {
-            self.unflushed.push_front(i);
-        }
+        self.unflushed = self.items.iter_idx().collect();
     }
 
     pub fn removed_flushed(&mut self, count: usize) {
@@ -185,16 +262,72 @@ where
         !self.unflushed.is_empty()
     }
 
+    /// Number of items dropped before they could be flushed, because the store was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
     pub fn unflushed(&self) -> impl Iterator<Item = &T> {
         self.unflushed
             .iter()
-            .flat_map(|i| Some(&self.items.get_idx(*i)?.0))
+            .flat_map(|i| Some(&self.items.get_idx(*i)?.1))
+    }
+}
+
+/// Serializable copy of the content of a store, including which items were already flushed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoreSnapshot<T> {
+    items: Vec<T>,
+    // indices into items
+    unflushed: Vec<usize>,
+    max_items: usize,
+}
+
+impl<T> Store<T>
+where
+    T: StoreKey + Clone,
+{
+    pub fn snapshot(&self) -> StoreSnapshot<T> {
+        let first_idx = self.items.iter_idx().next().unwrap_or(0);
+        StoreSnapshot {
+            items: self.items.iter().map(|(_, item)| item.clone()).collect(),
+            unflushed: self
+                .unflushed
+                .iter()
+                .filter_map(|i| i.checked_sub(first_idx))
+                .collect(),
+            max_items: self.max_items,
+        }
+    }
+}
+
+impl<T> StoreSnapshot<T> {
+    /// Leaves the items not flushed yet to the store the snapshot was taken from
+    pub fn mark_flushed(&mut self) {
+        self.unflushed.clear();
+    }
+}
+
+impl<T> From<StoreSnapshot<T>> for Store<T>
+where
+    T: StoreKey + PartialEq,
+{
+    fn from(snapshot: StoreSnapshot<T>) -> Self {
+        let mut store = Self::new(snapshot.max_items);
+        let len = snapshot.items.len();
+        store.extend(snapshot.items);
+        store.unflushed = snapshot
+            .unflushed
+            .into_iter()
+            .filter(|i| *i < len)
+            .collect();
+        store
     }
 }
 
 impl<T> Extend<T> for Store<T>
 where
-    T: PartialEq + Eq + Hash,
+    T: StoreKey + PartialEq,
 {
     fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
         for i in iter {
@@ -207,6 +340,31 @@ where
 mod tests {
     use super::*;
 
+    impl StoreKey for i32 {
+        type Key = i32;
+
+        fn store_key(&self) -> Self::Key {
+            *self
+        }
+    }
+
+    impl StoreKey for &'static str {
+        type Key = &'static str;
+
+        fn store_key(&self) -> Self::Key {
+            self
+        }
+    }
+
+    fn product(name: &str, enabled: bool) -> data::Product {
+        data::Product {
+            name: name.into(),
+            enabled,
+            version: None,
+            error: None,
+        }
+    }
+
     #[test]
     fn test_smoke_insert() {
         let mut store = Store::new(10);
@@ -242,7 +400,8 @@ mod tests {
         assert_eq!(
             store.unflushed().collect::<Vec<_>>(),
             &[&10, &11, &12, &13, &14]
-        )
+        );
+        assert_eq!(store.dropped(), 8);
     }
 
     #[test]
@@ -263,4 +422,47 @@ mod tests {
         assert_eq!(store.unflushed.len(), 4);
         assert_eq!(store.unflushed().collect::<Vec<_>>(), &[&6, &7, &8, &9]);
     }
+
+    #[test]
+    fn test_insert_replaces_changed_items() {
+        let mut store = Store::new(5);
+        store.insert(product("appsec", true));
+        store.insert(product("profiler", true));
+        store.removed_flushed(2);
+
+        store.insert(product("appsec", true));
+        assert!(store.unflushed().next().is_none());
+
+        store.insert(product("appsec", false));
+        store.insert(product("appsec", true));
+        assert_eq!(store.items.len(), 2);
+        assert_eq!(
+            store.unflushed().collect::<Vec<_>>(),
+            &[&product("appsec", true)]
+        );
+
+        store.unflush_stored();
+        assert_eq!(
+            store.unflushed().collect::<Vec<_>>(),
+            &[&product("appsec", true), &product("profiler", true)]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut store = Store::new(5);
+        for i in 2..9 {
+            store.insert(i);
+        }
+        store.removed_flushed(3);
+
+        let restored: Store<i32> = store.snapshot().into();
+        assert_eq!(restored.items.len(), 5);
+        assert_eq!(restored.unflushed().collect::<Vec<_>>(), &[&7, &8]);
+
+        let mut restored = restored;
+        restored.insert(4);
+        restored.insert(9);
+        assert_eq!(restored.unflushed().collect::<Vec<_>>(), &[&7, &8, &9]);
+    }
 }
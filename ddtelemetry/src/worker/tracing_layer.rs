@@ -0,0 +1,285 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    fmt::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use super::TelemetryWorkerHandle;
+use crate::data;
+
+/// Events emitted by the telemetry worker itself are never forwarded, to avoid feedback loops
+const TELEMETRY_TARGET: &str = "ddtelemetry";
+
+/// Forwards `tracing` events to the telemetry worker as logs
+///
+/// * Only events at or above the configured level, and from the selected targets are forwarded
+/// * Logs are identified by their callsite, so repeated events are deduplicated by the worker
+/// * Fields of the event and of the spans it happens in are sent as the tags of the log
+/// * At most `max_logs_per_interval` logs are forwarded per interval, others are dropped
+pub struct TelemetryLogLayer {
+    handle: TelemetryWorkerHandle,
+    level: Level,
+    targets: Vec<String>,
+    rate_limiter: Mutex<RateLimiter>,
+}
+
+impl TelemetryLogLayer {
+    pub fn new(handle: TelemetryWorkerHandle, level: Level) -> Self {
+        Self {
+            handle,
+            level,
+            targets: Vec::new(),
+            rate_limiter: Mutex::new(RateLimiter::new(100, Duration::from_secs(60))),
+        }
+    }
+
+    /// Only forward events whose target starts with one of the given prefixes.
+    /// All targets are forwarded if none is given
+    pub fn with_targets<I: IntoIterator<Item = String>>(mut self, targets: I) -> Self {
+        self.targets.extend(targets);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, max_logs_per_interval: u32, interval: Duration) -> Self {
+        self.rate_limiter = Mutex::new(RateLimiter::new(max_logs_per_interval, interval));
+        self
+    }
+
+    fn is_forwarded(&self, metadata: &Metadata<'_>) -> bool {
+        let target = metadata.target();
+        // Level ordering is inverted: ERROR is the "smallest" level
+        *metadata.level() <= self.level
+            && !target.starts_with(TELEMETRY_TARGET)
+            && (self.targets.is_empty() || self.targets.iter().any(|t| target.starts_with(t)))
+    }
+}
+
+fn log_level(level: &Level) -> data::LogLevel {
+    match *level {
+        Level::ERROR => data::LogLevel::Error,
+        Level::WARN => data::LogLevel::Warn,
+        Level::INFO | Level::DEBUG => data::LogLevel::Debug,
+        Level::TRACE => data::LogLevel::Trace,
+    }
+}
+
+struct RateLimiter {
+    max_per_interval: u32,
+    interval: Duration,
+    window_start: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    fn new(max_per_interval: u32, interval: Duration) -> Self {
+        Self {
+            max_per_interval,
+            interval,
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= self.interval {
+            self.window_start = now;
+            self.count = 0;
+        }
+        if self.count >= self.max_per_interval {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+/// Tags recorded from the fields of a span, stored in the span extensions
+struct SpanTags(String);
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    tags: String,
+}
+
+impl FieldVisitor {
+    fn push_tag(&mut self, field: &Field, value: &str) {
+        if !self.tags.is_empty() {
+            self.tags.push(',');
+        }
+        // tags are comma separated
+        let _ = write!(self.tags, "{}:{}", field.name(), value.replace(',', "_"));
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_owned());
+        } else {
+            self.push_tag(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{value:?}"));
+        } else {
+            self.push_tag(field, &format!("{value:?}"));
+        }
+    }
+}
+
+impl<S> Layer<S> for TelemetryLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanTags(visitor.tags));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        let mut visitor = FieldVisitor::default();
+        if let Some(SpanTags(tags)) = extensions.get_mut::<SpanTags>() {
+            visitor.tags = std::mem::take(tags);
+        }
+        values.record(&mut visitor);
+        extensions.replace(SpanTags(visitor.tags));
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !self.is_forwarded(metadata) {
+            return;
+        }
+        if !self
+            .rate_limiter
+            .lock()
+            .map(|mut r| r.allow(Instant::now()))
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        let mut visitor = FieldVisitor::default();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanTags(tags)) = span.extensions().get::<SpanTags>() {
+                    if !tags.is_empty() {
+                        if !visitor.tags.is_empty() {
+                            visitor.tags.push(',');
+                        }
+                        visitor.tags.push_str(tags);
+                    }
+                }
+            }
+        }
+        event.record(&mut visitor);
+
+        let identifier = (
+            metadata.target(),
+            metadata.module_path(),
+            metadata.file(),
+            metadata.line(),
+        );
+        let _ = self.handle.add_log_entry(
+            identifier,
+            data::Log {
+                message: visitor
+                    .message
+                    .unwrap_or_else(|| metadata.name().to_owned()),
+                level: log_level(metadata.level()),
+                stack_trace: None,
+                tags: visitor.tags,
+                count: 1,
+                tracer_time: 0,
+                is_sensitive: false,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        worker::{TelemetryActions, TelemetryWorkerBuilder},
+    };
+    use tokio::runtime::Handle;
+    use tracing_subscriber::prelude::*;
+
+    #[tokio::test]
+    async fn test_forward_events() {
+        let (handle, mut worker) = TelemetryWorkerBuilder::new(
+            "hostname".into(),
+            "service".into(),
+            "rust".into(),
+            "1.0".into(),
+            "0.0.1".into(),
+        )
+        .build_worker(Config::default(), Handle::current())
+        .unwrap();
+
+        let layer = TelemetryLogLayer::new(handle, Level::WARN)
+            .with_targets(["forwarded".to_owned()])
+            .with_rate_limit(2, Duration::from_secs(3600));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::error_span!("request", endpoint = "/users");
+            let _guard = span.enter();
+            tracing::error!(target: "forwarded", status = 500, "request failed");
+            tracing::info!(target: "forwarded", "below the level");
+            tracing::error!(target: "ignored", "not a selected target");
+            tracing::error!(target: "ddtelemetry::worker", "telemetry own logs");
+            tracing::warn!(target: "forwarded::submodule", "warning");
+            tracing::warn!(target: "forwarded", "rate limited");
+        });
+
+        let mut logs = Vec::new();
+        while let Ok(action) = worker.mailbox.try_recv() {
+            match action {
+                TelemetryActions::AddLog((_, log)) => logs.push(log),
+                action => panic!("unexpected action {action:?}"),
+            }
+        }
+
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].message, "request failed");
+        assert_eq!(logs[0].level, data::LogLevel::Error);
+        assert_eq!(logs[0].tags, "endpoint:/users,status:500");
+        assert_eq!(logs[1].message, "warning");
+        assert_eq!(logs[1].level, data::LogLevel::Warn);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let start = limiter.window_start;
+        assert!(limiter.allow(start));
+        assert!(limiter.allow(start));
+        assert!(!limiter.allow(start + Duration::from_secs(9)));
+        assert!(limiter.allow(start + Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,529 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! Destinations the telemetry worker delivers its requests to
+
+use ddcommon::Endpoint;
+use flate2::read::GzDecoder;
+use futures::future;
+use http::{header, uri::Parts, HeaderMap, StatusCode};
+use hyper::body::Bytes;
+use std::{
+    fs::File,
+    future::Future,
+    io::{Read, Write},
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::http_client::{self, HttpClient};
+use crate::config::Config;
+
+/// A serialized telemetry request, independent of where it is sent
+#[derive(Debug, Clone)]
+pub struct TelemetryRequest {
+    pub request_type: &'static str,
+    /// Content and telemetry headers. Destination specific headers, like the api key,
+    /// are added by the transport
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TelemetryRequest {
+    pub fn is_compressed(&self) -> bool {
+        self.headers.get(header::CONTENT_ENCODING) == Some(&http::HeaderValue::from_static("gzip"))
+    }
+
+    /// The body of the request, decompressed if needed
+    pub fn decoded_body(&self) -> anyhow::Result<Vec<u8>> {
+        if !self.is_compressed() {
+            return Ok(self.body.to_vec());
+        }
+        let mut decoded = Vec::new();
+        GzDecoder::new(self.body.as_ref()).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
+pub type TransportFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+
+/// Delivers telemetry requests to a destination
+///
+/// The worker considers the data contained in a request flushed once `send` succeeds,
+/// so implementations should only return `Ok` once the request has been accepted.
+pub trait Transport: Send + Sync {
+    fn send<'a>(&'a self, request: &'a TelemetryRequest) -> TransportFuture<'a>;
+
+    /// The transport of the worker of a child process, see
+    /// [super::TelemetryWorkerHandle::fork]
+    ///
+    /// The child must not reuse the connections of the parent, whose tasks only run in the
+    /// parent. Without an equivalent transport, the child picks one from the configuration.
+    fn for_forked_child(&self) -> Option<Box<dyn Transport>> {
+        None
+    }
+}
+
+/// Picks the transport described by the configuration
+///
+/// * `file://` endpoints write requests to a file
+/// * other endpoints are sent over HTTP, to the agent proxy or directly to the intake
+///   (agentless) if the endpoint has an api key
+/// * additional endpoints receive a copy of every request (dual-shipping)
+pub fn from_config(c: &Config) -> Box<dyn Transport> {
+    let primary: Box<dyn Transport> = match &c.endpoint {
+        Some(endpoint) => endpoint_transport(endpoint),
+        None => Box::new(Unconfigured),
+    };
+    if c.additional_endpoints.is_empty() {
+        return primary;
+    }
+    let mut fan_out = FanOutTransport::new(primary);
+    for endpoint in &c.additional_endpoints {
+        fan_out = fan_out.with_destination(endpoint_transport(endpoint));
+    }
+    Box::new(fan_out)
+}
+
+fn endpoint_transport(endpoint: &Endpoint) -> Box<dyn Transport> {
+    if let Parts {
+        scheme: Some(scheme),
+        path_and_query: Some(path),
+        ..
+    } = endpoint.url.clone().into_parts()
+    {
+        if scheme.as_str() == "file" {
+            return Box::new(
+                FileTransport::create(path.path()).expect("Couldn't open mock client file"),
+            );
+        }
+    }
+    Box::new(HttpTransport::new(endpoint.clone()))
+}
+
+struct Unconfigured;
+
+impl Transport for Unconfigured {
+    fn send<'a>(&'a self, _: &'a TelemetryRequest) -> TransportFuture<'a> {
+        Box::pin(future::ready(Err(anyhow::Error::msg(
+            "no valid endpoint found, can't send the request",
+        ))))
+    }
+
+    fn for_forked_child(&self) -> Option<Box<dyn Transport>> {
+        Some(Box::new(Unconfigured))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Number of attempts to send a request, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled before each following one
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Sends requests to an endpoint over HTTP
+///
+/// Failed requests are retried with an exponential backoff. Once a request exhausted its
+/// retries, the destination is considered down and following requests are only tried once,
+/// until one succeeds, so that an unreachable destination doesn't hold the worker back.
+pub struct HttpTransport {
+    endpoint: Endpoint,
+    client: Box<dyn HttpClient + Sync + Send>,
+    retry_policy: RetryPolicy,
+    consecutive_failures: AtomicU32,
+}
+
+enum HttpError {
+    Retryable(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+impl HttpTransport {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self::with_client(endpoint, http_client::new_default_client())
+    }
+
+    pub fn with_client(endpoint: Endpoint, client: Box<dyn HttpClient + Sync + Send>) -> Self {
+        Self {
+            endpoint,
+            client,
+            retry_policy: RetryPolicy::default(),
+            consecutive_failures: AtomicU32::new(0),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Number of requests which failed in a row
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures.load(Ordering::Relaxed)
+    }
+
+    async fn try_send(&self, request: &TelemetryRequest) -> Result<(), HttpError> {
+        let mut req = self
+            .endpoint
+            .into_request_builder(concat!("telemetry/", env!("CARGO_PKG_VERSION")))
+            .map_err(HttpError::Permanent)?
+            .method(http::Method::POST);
+        if let Some(headers) = req.headers_mut() {
+            headers.extend(request.headers.clone());
+        }
+        let req = req
+            .body(hyper::Body::from(request.body.clone()))
+            .map_err(|e| HttpError::Permanent(e.into()))?;
+
+        let status = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| HttpError::Retryable(e.into()))?
+            .status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let err = anyhow::anyhow!("Telemetry error: response status: {status}");
+        if status.is_server_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS
+        {
+            Err(HttpError::Retryable(err))
+        } else {
+            Err(HttpError::Permanent(err))
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send<'a>(&'a self, request: &'a TelemetryRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let max_attempts = if self.consecutive_failures() > 0 {
+                1
+            } else {
+                self.retry_policy.max_attempts.max(1)
+            };
+            let mut backoff = self.retry_policy.initial_backoff;
+            let mut attempt = 1;
+            let res = loop {
+                match self.try_send(request).await {
+                    Ok(()) => break Ok(()),
+                    Err(HttpError::Retryable(_)) if attempt < max_attempts => {
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                        attempt += 1;
+                    }
+                    Err(HttpError::Retryable(e)) | Err(HttpError::Permanent(e)) => break Err(e),
+                }
+            };
+            match res {
+                Ok(()) => self.consecutive_failures.store(0, Ordering::Relaxed),
+                Err(_) => {
+                    self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            res
+        })
+    }
+
+    /// Sends to the same endpoint with the same retry policy, through a new default client
+    fn for_forked_child(&self) -> Option<Box<dyn Transport>> {
+        Some(Box::new(
+            HttpTransport::new(self.endpoint.clone()).with_retry_policy(self.retry_policy),
+        ))
+    }
+}
+
+/// Appends the decoded body of each request as a line of a file
+pub struct FileTransport {
+    file: Arc<Mutex<Box<dyn Write + Sync + Send>>>,
+}
+
+impl FileTransport {
+    pub fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(Self::new(Box::new(File::create(path)?)))
+    }
+
+    pub fn new(writer: Box<dyn Write + Sync + Send>) -> Self {
+        Self {
+            file: Arc::new(Mutex::new(writer)),
+        }
+    }
+}
+
+impl Transport for FileTransport {
+    fn send<'a>(&'a self, request: &'a TelemetryRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            // Decode compressed bodies, to keep the output file readable
+            let body = request.decoded_body()?;
+            let mut writer = self.file.lock().expect("mutex poisoned");
+            writer.write_all(&body)?;
+            writer.write_all(b"\n")?;
+            Ok(())
+        })
+    }
+
+    /// The child appends to the same file
+    fn for_forked_child(&self) -> Option<Box<dyn Transport>> {
+        Some(Box::new(Self {
+            file: self.file.clone(),
+        }))
+    }
+}
+
+/// Keeps the requests in memory, mostly useful to inspect what the worker sends in tests
+#[derive(Clone, Default)]
+pub struct InMemoryTransport {
+    requests: Arc<Mutex<Vec<TelemetryRequest>>>,
+}
+
+impl InMemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn requests(&self) -> Vec<TelemetryRequest> {
+        self.requests.lock().expect("mutex poisoned").clone()
+    }
+
+    /// The decoded bodies of the requests received so far, parsed as json
+    pub fn payloads(&self) -> anyhow::Result<Vec<serde_json::Value>> {
+        self.requests
+            .lock()
+            .expect("mutex poisoned")
+            .iter()
+            .map(|r| Ok(serde_json::from_slice(&r.decoded_body()?)?))
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.requests.lock().expect("mutex poisoned").clear();
+    }
+}
+
+impl Transport for InMemoryTransport {
+    fn send<'a>(&'a self, request: &'a TelemetryRequest) -> TransportFuture<'a> {
+        self.requests
+            .lock()
+            .expect("mutex poisoned")
+            .push(request.clone());
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn for_forked_child(&self) -> Option<Box<dyn Transport>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// Sends each request to multiple destinations concurrently
+///
+/// The outcome of a request is the one of the primary destination: the data is considered
+/// flushed if the primary accepted it, even if a secondary destination failed. Each
+/// destination handles its own retries.
+pub struct FanOutTransport {
+    primary: Box<dyn Transport>,
+    secondaries: Vec<Box<dyn Transport>>,
+}
+
+impl FanOutTransport {
+    pub fn new(primary: Box<dyn Transport>) -> Self {
+        Self {
+            primary,
+            secondaries: Vec::new(),
+        }
+    }
+
+    pub fn with_destination(mut self, destination: Box<dyn Transport>) -> Self {
+        self.secondaries.push(destination);
+        self
+    }
+}
+
+impl Transport for FanOutTransport {
+    fn send<'a>(&'a self, request: &'a TelemetryRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let (res, _) = future::join(
+                self.primary.send(request),
+                future::join_all(self.secondaries.iter().map(|t| t.send(request))),
+            )
+            .await;
+            res
+        })
+    }
+
+    /// Only if all the destinations have an equivalent for the child
+    fn for_forked_child(&self) -> Option<Box<dyn Transport>> {
+        let mut fan_out = FanOutTransport::new(self.primary.for_forked_child()?);
+        for destination in &self.secondaries {
+            fan_out = fan_out.with_destination(destination.for_forked_child()?);
+        }
+        Some(Box::new(fan_out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::http_client::ResponseFuture;
+    use http::{Request, Response};
+
+    fn request(body: &'static str) -> TelemetryRequest {
+        TelemetryRequest {
+            request_type: "app-heartbeat",
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    /// Answers with the given statuses in order, then with 202
+    struct ScriptedClient {
+        statuses: Mutex<Vec<u16>>,
+        calls: Arc<AtomicU32>,
+    }
+
+    impl HttpClient for ScriptedClient {
+        fn request(&self, _: Request<hyper::Body>) -> ResponseFuture {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            let mut statuses = self.statuses.lock().unwrap();
+            let status = if statuses.is_empty() {
+                202
+            } else {
+                statuses.remove(0)
+            };
+            Box::pin(future::ready(Ok(Response::builder()
+                .status(status)
+                .body(hyper::Body::empty())
+                .unwrap())))
+        }
+    }
+
+    fn scripted_transport(statuses: Vec<u16>) -> (HttpTransport, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let transport = HttpTransport::with_client(
+            Endpoint {
+                url: "http://localhost:8126".parse().unwrap(),
+                api_key: None,
+            },
+            Box::new(ScriptedClient {
+                statuses: Mutex::new(statuses),
+                calls: calls.clone(),
+            }),
+        )
+        .with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+        });
+        (transport, calls)
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_retries() {
+        let (transport, calls) = scripted_transport(vec![503, 500]);
+        transport.send(&request("{}")).await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(transport.consecutive_failures(), 0);
+
+        // Client errors aren't retried
+        let (transport, calls) = scripted_transport(vec![400]);
+        assert!(transport.send(&request("{}")).await.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_fails_fast_when_down() {
+        let (transport, calls) = scripted_transport(vec![503, 503, 503, 503]);
+        assert!(transport.send(&request("{}")).await.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(transport.consecutive_failures(), 1);
+
+        // Only one attempt while the destination is down
+        assert!(transport.send(&request("{}")).await.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+
+        transport.send(&request("{}")).await.unwrap();
+        assert_eq!(transport.consecutive_failures(), 0);
+    }
+
+    /// Without an equivalent for forked children
+    struct Opaque;
+
+    impl Transport for Opaque {
+        fn send<'a>(&'a self, _: &'a TelemetryRequest) -> TransportFuture<'a> {
+            Box::pin(future::ready(Ok(())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transports_for_forked_children() {
+        let primary = InMemoryTransport::new();
+        let secondary = InMemoryTransport::new();
+        let transport = FanOutTransport::new(Box::new(primary.clone()))
+            .with_destination(Box::new(secondary.clone()));
+        let child = transport.for_forked_child().unwrap();
+        child.send(&request("{}")).await.unwrap();
+        assert_eq!(primary.requests().len(), 1);
+        assert_eq!(secondary.requests().len(), 1);
+
+        let transport = FanOutTransport::new(Box::new(primary)).with_destination(Box::new(Opaque));
+        assert!(transport.for_forked_child().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let primary = InMemoryTransport::new();
+        let secondary = InMemoryTransport::new();
+        let (failing, _) = scripted_transport(vec![400]);
+        let transport = FanOutTransport::new(Box::new(primary.clone()))
+            .with_destination(Box::new(secondary.clone()))
+            .with_destination(Box::new(failing));
+
+        // A failing secondary destination doesn't fail the request
+        transport.send(&request(r#"{"a": 1}"#)).await.unwrap();
+        assert_eq!(
+            primary.payloads().unwrap(),
+            vec![serde_json::json!({"a": 1})]
+        );
+        assert_eq!(secondary.requests().len(), 1);
+
+        let (failing, _) = scripted_transport(vec![400]);
+        let transport =
+            FanOutTransport::new(Box::new(failing)).with_destination(Box::new(secondary.clone()));
+        assert!(transport.send(&request("{}")).await.is_err());
+        assert_eq!(secondary.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_file_transport_decodes_body() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let transport = FileTransport::create(f.path()).unwrap();
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello world").unwrap();
+        let mut req = request("");
+        req.body = Bytes::from(encoder.finish().unwrap());
+        req.headers.insert(
+            header::CONTENT_ENCODING,
+            http::HeaderValue::from_static("gzip"),
+        );
+        transport.send(&req).await.unwrap();
+
+        assert_eq!(std::fs::read(f.path()).unwrap(), b"hello world\n");
+    }
+}
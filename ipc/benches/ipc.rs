@@ -8,6 +8,8 @@ use datadog_ipc::example_interface::{
     ExampleInterfaceRequest, ExampleInterfaceResponse, ExampleServer, ExampleTransport,
 };
 #[cfg(not(windows))]
+use datadog_ipc::transport::Codec;
+#[cfg(not(windows))]
 use std::{
     os::unix::net::UnixStream as StdUnixStream,
     thread::{self},
@@ -17,6 +19,12 @@ use tokio::{net::UnixStream, runtime};
 
 #[cfg(not(windows))]
 fn criterion_benchmark(c: &mut Criterion) {
+    bench_codec(c, Codec::Json);
+    bench_codec(c, Codec::MessagePack);
+}
+
+#[cfg(not(windows))]
+fn bench_codec(c: &mut Criterion, codec: Codec) {
     let (sock_a, sock_b) = StdUnixStream::pair().unwrap();
 
     let worker = thread::spawn(move || {
@@ -34,15 +42,28 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     let mut transport = ExampleTransport::from(sock_b);
     transport.set_nonblocking(false).unwrap();
+    if codec != Codec::Json {
+        transport.negotiate_codec(codec).unwrap();
+    }
 
-    c.bench_function("write only interface", |b| {
+    c.bench_function(&format!("write only interface ({codec:?})"), |b| {
         b.iter(|| transport.send(ExampleInterfaceRequest::Notify {}).unwrap())
     });
 
-    c.bench_function("two way interface", |b| {
+    c.bench_function(&format!("two way interface ({codec:?})"), |b| {
         b.iter(|| transport.call(ExampleInterfaceRequest::ReqCnt {}).unwrap())
     });
 
+    // about the size of a small trace payload
+    let data: Vec<u8> = (0..16384).map(|i| i as u8).collect();
+    c.bench_function(&format!("two way interface with 16KiB ({codec:?})"), |b| {
+        b.iter(|| {
+            transport
+                .call(ExampleInterfaceRequest::DataLen { data: data.clone() })
+                .unwrap()
+        })
+    });
+
     let requests_received = match transport.call(ExampleInterfaceRequest::ReqCnt {}).unwrap() {
         ExampleInterfaceResponse::ReqCnt(cnt) => cnt,
         _ => panic!("shouldn't happen"),
@@ -5,7 +5,10 @@ use proc_macro::TokenStream;
 use quote::{format_ident, quote, ToTokens};
 use syn::FnArg::Typed;
 use syn::__private::Span;
-use syn::{parse_quote, Arm, FieldPat, Ident, ItemTrait, Member, Pat, Stmt, TraitItem};
+use syn::{
+    parse_macro_input, parse_quote, Arm, FieldPat, Ident, ItemTrait, LitInt, LitStr, Member, Pat,
+    Stmt, TraitItem,
+};
 
 fn snake_to_camel(ident_str: &str) -> String {
     let mut camel_ty = String::with_capacity(ident_str.len());
@@ -54,10 +57,10 @@ pub fn impl_transfer_handles(_attr: TokenStream, input: TokenStream) -> TokenStr
                                 colon_token: None,
                                 pat: Box::new(parse_quote! { #ident }),
                             });
-                            stmts_move.push(
-                                parse_quote! { __transport.move_handle(#ident.clone().into())?; },
-                            );
-                            stmts_recv.push(parse_quote! { #ident.receive_handles(__transport)?; });
+                            stmts_move
+                                .push(parse_quote! { #ident.move_handles(&mut __transport)?; });
+                            stmts_recv
+                                .push(parse_quote! { #ident.receive_handles(&mut __transport)?; });
                         }
                     }
                 }
@@ -69,12 +72,14 @@ pub fn impl_transfer_handles(_attr: TokenStream, input: TokenStream) -> TokenStr
             if !params.is_empty() {
                 arms_req_move.push(parse_quote! {
                     #req_name::#method { #(#params,)* .. } => {
+                        let mut __transport = __transport;
                         #(#stmts_move)*
                         Ok(())
                     }
                 });
                 arms_req_recv.push(parse_quote! {
                     #req_name::#method { #(#params,)* .. } => {
+                        let mut __transport = __transport;
                         #(#stmts_recv)*
                         Ok(())
                     }
@@ -151,3 +156,89 @@ pub fn impl_transfer_handles(_attr: TokenStream, input: TokenStream) -> TokenStr
         }
     })
 }
+
+/// FNV-1a, which unlike the hashers of std is stable across builds
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Implements datadog_ipc::transport::Versioned for the requests of the interface, given its
+/// version with `#[protocol(version = 1)]`. The methods marked `#[Optional]` are capabilities,
+/// the servers not handling them are then not sent these requests. The signature of the protocol
+/// is derived from the other methods, in order: their attributes but the docs, and their
+/// signatures as written, so the types of the arguments only count by their names.
+#[proc_macro_attribute]
+pub fn protocol(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let mut version: Option<LitInt> = None;
+    let attr_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("version") {
+            version = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported protocol property"))
+        }
+    });
+    parse_macro_input!(attr with attr_parser);
+    let version = match version {
+        Some(version) => version,
+        None => {
+            return syn::Error::new(Span::call_site(), "the protocol version is missing")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let mut item: ItemTrait = syn::parse(input).unwrap();
+    let name = LitStr::new(&item.ident.to_string(), Span::call_site());
+    let req_name = format_ident!("{}Request", item.ident);
+    let mut capabilities: Vec<LitStr> = vec![];
+    let mut arms: Vec<Arm> = vec![];
+    let mut signature = 0xcbf29ce484222325;
+    for inner in item.items.iter_mut() {
+        if let TraitItem::Fn(ref mut func) = inner {
+            let orig_attr_num = func.attrs.len();
+            func.attrs
+                .retain(|attr| attr.meta.path().to_token_stream().to_string() != "Optional");
+            if orig_attr_num != func.attrs.len() {
+                let capability = LitStr::new(&func.sig.ident.to_string(), Span::call_site());
+                let method = Ident::new(
+                    &snake_to_camel(&func.sig.ident.to_string()),
+                    Span::mixed_site(),
+                );
+                arms.push(parse_quote! {
+                    #req_name::#method { .. } => Some(#capability)
+                });
+                capabilities.push(capability);
+            } else {
+                for attr in func.attrs.iter().filter(|attr| !attr.path().is_ident("doc")) {
+                    signature = fnv1a(signature, attr.to_token_stream().to_string().as_bytes());
+                }
+                signature = fnv1a(signature, func.sig.to_token_stream().to_string().as_bytes());
+            }
+        }
+    }
+    let signature = LitInt::new(&format!("{signature:#x}"), Span::call_site());
+
+    TokenStream::from(quote! {
+        #item
+
+        impl datadog_ipc::transport::Versioned for #req_name {
+            const PROTOCOL_NAME: &'static str = #name;
+            const PROTOCOL_VERSION: u32 = #version;
+            const PROTOCOL_SIGNATURE: u64 = #signature;
+            const CAPABILITIES: &'static [&'static str] = &[#(#capabilities),*];
+
+            #[allow(unreachable_patterns)]
+            fn capability(&self) -> Option<&'static str> {
+                match self {
+                    #(
+                        #arms,
+                    )*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
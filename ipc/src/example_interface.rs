@@ -9,17 +9,18 @@ use std::{
     time::{Duration, Instant},
 };
 
-use futures::future::{pending, ready, Pending, Ready};
+use futures::future::{pending, ready, BoxFuture, Pending, Ready};
 use tarpc::{context::Context, server::Channel};
 use tokio::net::UnixStream;
 
 use super::{
-    platform::{AsyncChannel, PlatformHandle},
-    transport::{blocking::BlockingTransport, Transport},
+    platform::{ring_buffer::RingBufferHandle, AsyncChannel, PlatformHandle},
+    transport::{blocking::BlockingTransport, Protocol, Transport},
 };
 
 extern crate self as datadog_ipc;
 
+#[datadog_ipc_macros::protocol(version = 1)]
 #[datadog_ipc_macros::impl_transfer_handles]
 #[tarpc::service]
 pub trait ExampleInterface {
@@ -27,9 +28,16 @@ pub trait ExampleInterface {
     async fn ping() -> ();
     async fn time_now() -> Duration;
     async fn req_cnt() -> u32;
+    async fn data_len(data: Vec<u8>) -> usize;
     async fn store_file(#[SerializedHandle] file: PlatformHandle<File>) -> ();
     #[SerializedHandle]
     async fn retrieve_file() -> Option<PlatformHandle<File>>;
+    /// Reads the given number of messages, returning their total length
+    #[Optional]
+    async fn read_ring_buffer(
+        #[SerializedHandle] ring_buffer: RingBufferHandle,
+        messages: usize,
+    ) -> usize;
 }
 
 pub type ExampleTransport = BlockingTransport<ExampleInterfaceResponse, ExampleInterfaceRequest>;
@@ -42,10 +50,13 @@ pub struct ExampleServer {
 
 impl ExampleServer {
     pub async fn accept_connection(self, socket: UnixStream) {
-        let server = tarpc::server::BaseChannel::new(
-            tarpc::server::Config::default(),
-            Transport::try_from(AsyncChannel::from(socket)).unwrap(),
-        );
+        let protocol = Protocol::of::<ExampleInterfaceRequest>();
+        let channel = AsyncChannel::from(socket);
+        let transport = match Transport::accept_versioned(channel, &protocol).await {
+            Ok(transport) => transport,
+            Err(_) => return,
+        };
+        let server = tarpc::server::BaseChannel::new(tarpc::server::Config::default(), transport);
 
         server.execute(self.serve()).await
     }
@@ -79,6 +90,13 @@ impl ExampleInterface for ExampleServer {
         ready(self.req_cnt.fetch_add(1, Ordering::AcqRel))
     }
 
+    type DataLenFut = Ready<usize>;
+
+    fn data_len(self, _: Context, data: Vec<u8>) -> Self::DataLenFut {
+        self.req_cnt.fetch_add(1, Ordering::AcqRel);
+        ready(data.len())
+    }
+
     type StoreFileFut = Ready<()>;
 
     fn store_file(self, _: Context, file: PlatformHandle<File>) -> Self::StoreFileFut {
@@ -94,4 +112,23 @@ impl ExampleInterface for ExampleServer {
         self.req_cnt.fetch_add(1, Ordering::AcqRel);
         ready(self.stored_files.lock().unwrap().pop())
     }
+
+    type ReadRingBufferFut = BoxFuture<'static, usize>;
+
+    fn read_ring_buffer(
+        self,
+        _: Context,
+        ring_buffer: RingBufferHandle,
+        messages: usize,
+    ) -> Self::ReadRingBufferFut {
+        self.req_cnt.fetch_add(1, Ordering::AcqRel);
+        Box::pin(async move {
+            let mut receiver = ring_buffer.into_receiver().unwrap();
+            let mut len = 0;
+            for _ in 0..messages {
+                len += receiver.recv().await.unwrap().len();
+            }
+            len
+        })
+    }
 }
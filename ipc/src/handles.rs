@@ -10,10 +10,29 @@ pub trait HandlesTransport {
     type Error: Error;
 
     /// Move handle out of an object, to send it to remote process
-    fn move_handle<T>(self, handle: PlatformHandle<T>) -> Result<(), Self::Error>;
+    fn move_handle<T>(&mut self, handle: PlatformHandle<T>) -> Result<(), Self::Error>;
 
     /// Fetch handle received from a remote process based on supplied hint
-    fn provide_handle<T>(self, hint: &PlatformHandle<T>) -> Result<PlatformHandle<T>, Self::Error>;
+    fn provide_handle<T>(
+        &mut self,
+        hint: &PlatformHandle<T>,
+    ) -> Result<PlatformHandle<T>, Self::Error>;
+}
+
+/// Allows objects holding several handles to pass the transport on to each of them
+impl<H: HandlesTransport> HandlesTransport for &mut H {
+    type Error = H::Error;
+
+    fn move_handle<T>(&mut self, handle: PlatformHandle<T>) -> Result<(), Self::Error> {
+        (**self).move_handle(handle)
+    }
+
+    fn provide_handle<T>(
+        &mut self,
+        hint: &PlatformHandle<T>,
+    ) -> Result<PlatformHandle<T>, Self::Error> {
+        (**self).provide_handle(hint)
+    }
 }
 
 /// TransferHandles allows moving PlatformHandles from
@@ -151,5 +170,3 @@ mod transport_impls {
         }
     }
 }
-
-pub use transport_impls::*;
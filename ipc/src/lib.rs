@@ -3,9 +3,7 @@
 
 #[cfg(unix)]
 pub mod example_interface;
-#[cfg(unix)]
 pub mod handles;
-#[cfg(unix)]
 pub mod transport;
 
 pub mod platform;
@@ -0,0 +1,271 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::ffi::{c_void, CString};
+use std::io;
+
+use io_lifetimes::OwnedFilelike;
+use serde::{Deserialize, Serialize};
+
+use crate::handles::{HandlesTransport, TransferHandles};
+use crate::platform::{mmap_handle, munmap_handle, resize_handle, PlatformHandle};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ShmHandle {
+    pub(crate) handle: PlatformHandle<OwnedFilelike>,
+    pub(crate) size: usize,
+}
+
+#[derive(Debug)]
+pub struct AnonHandle {
+    size: usize,
+}
+
+pub struct MappedMem<T>
+where
+    T: MemoryHandle,
+{
+    pub(crate) ptr: *mut c_void,
+    pub(crate) mem: T,
+}
+
+pub(crate) struct ShmPath {
+    pub(crate) name: CString,
+}
+
+pub struct NamedShmHandle {
+    pub(crate) inner: ShmHandle,
+    pub(crate) path: Option<ShmPath>,
+}
+
+impl NamedShmHandle {
+    pub fn get_path(&self) -> &[u8] {
+        if let Some(ref shm_path) = &self.path {
+            shm_path.name.as_bytes()
+        } else {
+            b""
+        }
+    }
+}
+
+pub(crate) fn page_aligned_size(size: usize) -> usize {
+    let page_size = page_size::get();
+    // round up to nearest page
+    ((size - 1) & !(page_size - 1)) + page_size
+}
+
+pub trait MemoryHandle {
+    fn get_size(&self) -> usize;
+}
+
+impl MemoryHandle for AnonHandle {
+    fn get_size(&self) -> usize {
+        self.size
+    }
+}
+
+impl<T> MemoryHandle for T
+where
+    T: FileBackedHandle,
+{
+    fn get_size(&self) -> usize {
+        self.get_shm().size
+    }
+}
+
+pub trait FileBackedHandle
+where
+    Self: Sized,
+{
+    fn map(self) -> io::Result<MappedMem<Self>>;
+    fn get_shm(&self) -> &ShmHandle;
+    fn get_shm_mut(&mut self) -> &mut ShmHandle;
+    fn resize(&mut self, size: usize) -> anyhow::Result<()> {
+        unsafe {
+            self.set_mapping_size(size)?;
+        }
+        resize_handle(self.get_shm())
+    }
+    /// # Safety
+    /// Calling function needs to ensure it's appropriately resized
+    unsafe fn set_mapping_size(&mut self, size: usize) -> anyhow::Result<()> {
+        if size == 0 {
+            anyhow::bail!("Cannot allocate mapping of size zero");
+        }
+
+        self.get_shm_mut().size = page_aligned_size(size);
+        Ok(())
+    }
+}
+
+impl FileBackedHandle for ShmHandle {
+    fn map(self) -> io::Result<MappedMem<ShmHandle>> {
+        mmap_handle(self)
+    }
+
+    fn get_shm(&self) -> &ShmHandle {
+        self
+    }
+    fn get_shm_mut(&mut self) -> &mut ShmHandle {
+        self
+    }
+}
+
+impl FileBackedHandle for NamedShmHandle {
+    fn map(self) -> io::Result<MappedMem<NamedShmHandle>> {
+        mmap_handle(self)
+    }
+
+    fn get_shm(&self) -> &ShmHandle {
+        &self.inner
+    }
+    fn get_shm_mut(&mut self) -> &mut ShmHandle {
+        &mut self.inner
+    }
+}
+
+impl<T: MemoryHandle> MappedMem<T> {
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.mem.get_size()) }
+    }
+
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr as *mut u8, self.mem.get_size()) }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.mem.get_size()
+    }
+
+    /// For memory concurrently accessed through atomics, where no slice may be borrowed
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr as *mut u8
+    }
+}
+
+impl<T: FileBackedHandle + From<MappedMem<T>>> MappedMem<T> {
+    pub fn ensure_space(self, expected_size: usize) -> MappedMem<T> {
+        if expected_size <= self.mem.get_shm().size {
+            return self;
+        }
+
+        let mut handle: T = self.into();
+        _ = handle.resize(expected_size);
+        handle.map().unwrap()
+    }
+}
+
+impl MappedMem<NamedShmHandle> {
+    pub fn get_path(&self) -> &[u8] {
+        self.mem.get_path()
+    }
+}
+
+impl<T: FileBackedHandle> From<MappedMem<T>> for ShmHandle {
+    fn from(handle: MappedMem<T>) -> ShmHandle {
+        ShmHandle {
+            handle: handle.mem.get_shm().handle.clone(),
+            size: handle.mem.get_shm().size,
+        }
+    }
+}
+
+impl From<MappedMem<NamedShmHandle>> for NamedShmHandle {
+    fn from(mut handle: MappedMem<NamedShmHandle>) -> NamedShmHandle {
+        NamedShmHandle {
+            path: handle.mem.path.take(),
+            inner: handle.into(),
+        }
+    }
+}
+
+impl<T> Drop for MappedMem<T>
+where
+    T: MemoryHandle,
+{
+    fn drop(&mut self) {
+        munmap_handle(self);
+    }
+}
+
+impl TransferHandles for ShmHandle {
+    fn move_handles<Transport: HandlesTransport>(
+        &self,
+        transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        self.handle.move_handles(transport)
+    }
+
+    fn receive_handles<Transport: HandlesTransport>(
+        &mut self,
+        transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        self.handle.receive_handles(transport)
+    }
+}
+
+impl From<ShmHandle> for PlatformHandle<OwnedFilelike> {
+    fn from(shm: ShmHandle) -> Self {
+        shm.handle
+    }
+}
+
+unsafe impl<T> Sync for MappedMem<T> where T: FileBackedHandle {}
+unsafe impl<T> Send for MappedMem<T> where T: FileBackedHandle {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_aligned_size() {
+        let page_size = page_size::get();
+        assert_eq!(page_size, page_aligned_size(1));
+        assert_eq!(page_size, page_aligned_size(page_size));
+        assert_eq!(2 * page_size, page_aligned_size(page_size + 1));
+    }
+
+    #[test]
+    fn test_shm_resize_and_remap() {
+        let mut mapped = ShmHandle::new(16).unwrap().map().unwrap();
+        assert_eq!(16, mapped.get_size());
+        mapped.as_slice_mut()[..4].copy_from_slice(b"test");
+
+        let mapped = mapped.ensure_space(page_size::get() + 1);
+        assert_eq!(2 * page_size::get(), mapped.get_size());
+        assert_eq!(b"test", &mapped.as_slice()[..4]);
+
+        // another mapping of the same handle sees the same memory
+        let shm: ShmHandle = mapped.into();
+        let other = shm.clone().map().unwrap();
+        assert_eq!(b"test", &other.as_slice()[..4]);
+    }
+
+    #[test]
+    fn test_ensure_space_keeps_large_enough_mappings() {
+        let mut mapped = ShmHandle::new(page_size::get()).unwrap().map().unwrap();
+        mapped.as_slice_mut()[..4].copy_from_slice(b"test");
+        let ptr = mapped.as_ptr();
+
+        let mapped = mapped.ensure_space(16);
+        assert_eq!(page_size::get(), mapped.get_size());
+        assert_eq!(ptr, mapped.as_ptr());
+    }
+
+    #[test]
+    fn test_named_shm_open_sees_the_current_size() {
+        let path = CString::new(format!("/libdatadog-test-shm-{}", std::process::id())).unwrap();
+        let mut mapped = NamedShmHandle::create(path.clone(), 16)
+            .unwrap()
+            .map()
+            .unwrap();
+        mapped.as_slice_mut()[..4].copy_from_slice(b"test");
+        let mapped = mapped.ensure_space(3 * page_size::get());
+        assert_eq!(path.as_bytes(), mapped.get_path());
+
+        let other = NamedShmHandle::open(path).unwrap().map().unwrap();
+        assert_eq!(3 * page_size::get(), other.get_size());
+        assert_eq!(b"test", &other.as_slice()[..4]);
+    }
+}
@@ -6,3 +6,14 @@ mod unix;
 
 #[cfg(unix)]
 pub use unix::*;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub use windows::*;
+
+mod mem_handle;
+pub use mem_handle::*;
+
+pub mod ring_buffer;
@@ -0,0 +1,599 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! A ring buffer in shared memory, streaming messages to another process without a syscall or a
+//! mapping per message. Any number of senders, from any number of processes, push messages which
+//! are read by a single receiver.
+//!
+//! Senders reserve the space of a message by advancing the tail of the buffer, copy the message,
+//! then commit it by writing its header. The receiver reads the committed messages in order and
+//! zeroes them, so that the header of a message not committed yet always reads as 0. Messages
+//! which would wrap around the end of the buffer are preceded by padding up to the end.
+//!
+//! Senders only wake the receiver up, through an eventfd (a pipe on other unixes, an event on
+//! windows), when it announced it waits for messages.
+
+use std::{
+    io, mem, ptr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::handles::{HandlesTransport, TransferHandles};
+use crate::platform::{FileBackedHandle, MappedMem, ShmHandle};
+
+const COMMITTED: u64 = 1 << 63;
+const PADDING: u64 = 1 << 62;
+const LEN_MASK: u64 = u32::MAX as u64;
+const RECORD_HEADER_SIZE: u64 = mem::size_of::<u64>() as u64;
+
+const MAX_BLOCKING_BACKOFF: Duration = Duration::from_millis(1);
+
+/// What senders do when a message doesn't fit in the buffer until the receiver catches up
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// The message is dropped and counted in [`RingBufferSender::dropped`]
+    Drop,
+    /// The push fails with WouldBlock, for the message to be sent another way
+    Reject,
+    /// Waits for room, up to the given time, after which the push fails with TimedOut
+    Block(Duration),
+}
+
+#[repr(C, align(64))]
+struct CacheLine<T>(T);
+
+#[repr(C)]
+struct RawHeader {
+    dropped: CacheLine<AtomicU64>,
+    /// Position of the next message to read, only advanced by the receiver
+    head: CacheLine<AtomicU64>,
+    /// End of the space reserved by the senders
+    tail: CacheLine<AtomicU64>,
+    receiver_waiting: CacheLine<AtomicU32>,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<RawHeader>();
+
+fn record_size(len: u64) -> u64 {
+    RECORD_HEADER_SIZE + ((len + 7) & !7)
+}
+
+struct RingBuffer {
+    mem: MappedMem<ShmHandle>,
+    capacity: u64,
+}
+
+impl RingBuffer {
+    fn new(shm: ShmHandle) -> io::Result<Self> {
+        let mem = shm.map()?;
+        let capacity = (mem.get_size().saturating_sub(HEADER_SIZE) & !7) as u64;
+        if !(RECORD_HEADER_SIZE..=LEN_MASK).contains(&capacity) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid ring buffer size",
+            ));
+        }
+        Ok(RingBuffer { mem, capacity })
+    }
+
+    fn header(&self) -> &RawHeader {
+        // Safety: the mapping is page aligned and larger than the header
+        unsafe { &*(self.mem.as_ptr() as *const RawHeader) }
+    }
+
+    /// # Safety
+    /// pos + len must not exceed the capacity
+    unsafe fn data(&self, pos: u64) -> *mut u8 {
+        self.mem.as_ptr().add(HEADER_SIZE + pos as usize)
+    }
+
+    fn record_header(&self, pos: u64) -> &AtomicU64 {
+        // Safety: positions are 8 byte aligned and records have room for their header
+        unsafe { &*(self.data(pos) as *const AtomicU64) }
+    }
+
+    /// The position where `size` bytes may be written, None if the buffer is full
+    fn reserve(&self, size: u64) -> Option<u64> {
+        let header = self.header();
+        let mut tail = header.tail.0.load(Ordering::Relaxed);
+        loop {
+            let head = header.head.0.load(Ordering::Acquire);
+            let pos = tail % self.capacity;
+            let until_end = self.capacity - pos;
+            let padding = if size > until_end { until_end } else { 0 };
+            let end = tail + padding + size;
+            // A stale tail may be behind the head, in which case the exchange fails
+            if end.saturating_sub(head) > self.capacity {
+                return None;
+            }
+            match header.tail.0.compare_exchange_weak(
+                tail,
+                end,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    if padding == 0 {
+                        return Some(pos);
+                    }
+                    self.record_header(pos).store(
+                        COMMITTED | PADDING | (padding - RECORD_HEADER_SIZE),
+                        Ordering::SeqCst,
+                    );
+                    return Some(0);
+                }
+                Err(current) => tail = current,
+            }
+        }
+    }
+
+    fn dropped(&self) -> u64 {
+        self.header().dropped.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(unix)]
+mod wake {
+    use std::{
+        io,
+        os::unix::prelude::{AsRawFd, FromRawFd, RawFd},
+    };
+
+    use io_lifetimes::OwnedFd;
+    use tokio::io::unix::AsyncFd;
+
+    use crate::platform::PlatformHandle;
+
+    pub type WakeHandle = PlatformHandle<OwnedFd>;
+
+    #[cfg(target_os = "linux")]
+    pub fn wake_pair() -> io::Result<(WakeHandle, WakeHandle)> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let handle = unsafe { PlatformHandle::from_raw_fd(fd) };
+        Ok((handle.clone(), handle))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn wake_pair() -> io::Result<(WakeHandle, WakeHandle)> {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+
+        let (read, write) = nix::unistd::pipe()?;
+        let handles = unsafe {
+            (
+                PlatformHandle::from_raw_fd(read),
+                PlatformHandle::from_raw_fd(write),
+            )
+        };
+        for fd in [read, write] {
+            fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        Ok(handles)
+    }
+
+    pub fn wake(handle: &WakeHandle) {
+        #[cfg(target_os = "linux")]
+        let buf = 1u64.to_ne_bytes();
+        #[cfg(not(target_os = "linux"))]
+        let buf = [0u8];
+        // A write only fails if the receiver already has pending wake ups
+        unsafe {
+            libc::write(
+                handle.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+            )
+        };
+    }
+
+    fn drain_wake_ups(fd: RawFd) {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+    }
+
+    pub struct Waiter(AsyncFd<WakeHandle>);
+
+    impl Waiter {
+        pub fn new(handle: WakeHandle) -> io::Result<Self> {
+            Ok(Waiter(AsyncFd::new(handle)?))
+        }
+
+        pub async fn wait(&self) -> io::Result<()> {
+            let mut guard = self.0.readable().await?;
+            drain_wake_ups(guard.get_inner().as_raw_fd());
+            guard.clear_ready();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(windows)]
+mod wake {
+    use std::{
+        io,
+        os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle},
+        ptr,
+    };
+
+    use windows_sys::Win32::{
+        Foundation::{HANDLE, WAIT_FAILED},
+        System::Threading::{CreateEventW, SetEvent, WaitForSingleObject},
+    };
+
+    use crate::platform::PlatformHandle;
+
+    pub type WakeHandle = PlatformHandle<OwnedHandle>;
+
+    /// Waits are bounded, for the blocking thread not to outlive a receiver which was dropped
+    const WAIT_TIMEOUT_MS: u32 = 100;
+
+    /// An auto-reset event, both ends of the pair are the same handle
+    pub fn wake_pair() -> io::Result<(WakeHandle, WakeHandle)> {
+        let event = unsafe { CreateEventW(ptr::null(), 0, 0, ptr::null()) };
+        if event == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let handle: WakeHandle = unsafe { PlatformHandle::from_raw_handle(event as _) };
+        Ok((handle.clone(), handle))
+    }
+
+    pub fn wake(handle: &WakeHandle) {
+        unsafe { SetEvent(handle.as_raw_handle() as HANDLE) };
+    }
+
+    pub struct Waiter(WakeHandle);
+
+    impl Waiter {
+        pub fn new(handle: WakeHandle) -> io::Result<Self> {
+            Ok(Waiter(handle))
+        }
+
+        /// May return without a wake up, once the wait timed out
+        pub async fn wait(&self) -> io::Result<()> {
+            let handle = self.0.clone();
+            let result = tokio::task::spawn_blocking(move || unsafe {
+                WaitForSingleObject(handle.as_raw_handle() as HANDLE, WAIT_TIMEOUT_MS)
+            })
+            .await
+            .map_err(io::Error::other)?;
+            if result == WAIT_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+}
+
+use wake::{wake, wake_pair, Waiter, WakeHandle};
+
+/// Shared memory and wake up handles of a ring buffer, to be sent to the other process
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RingBufferHandle {
+    shm: ShmHandle,
+    wake_read: WakeHandle,
+    wake_write: WakeHandle,
+}
+
+impl RingBufferHandle {
+    /// Messages may be up to about `size` bytes
+    pub fn new(size: usize) -> io::Result<Self> {
+        let shm = ShmHandle::new(HEADER_SIZE + size).map_err(io::Error::other)?;
+        let (wake_read, wake_write) = wake_pair()?;
+        Ok(RingBufferHandle {
+            shm,
+            wake_read,
+            wake_write,
+        })
+    }
+
+    pub fn sender(&self, policy: OverflowPolicy) -> io::Result<RingBufferSender> {
+        Ok(RingBufferSender {
+            ring: Arc::new(RingBuffer::new(self.shm.clone())?),
+            wake: self.wake_write.clone(),
+            policy,
+        })
+    }
+
+    /// There must be a single receiver per ring buffer. Must be called within a tokio runtime.
+    pub fn into_receiver(self) -> io::Result<RingBufferReceiver> {
+        let ring = RingBuffer::new(self.shm)?;
+        // Senders of other processes can't be trusted to leave the head alone
+        let head = ring.header().head.0.load(Ordering::Acquire);
+        Ok(RingBufferReceiver {
+            ring,
+            head,
+            wake: Waiter::new(self.wake_read)?,
+        })
+    }
+}
+
+impl TransferHandles for RingBufferHandle {
+    fn move_handles<Transport: HandlesTransport>(
+        &self,
+        mut transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        self.shm.move_handles(&mut transport)?;
+        self.wake_read.move_handles(&mut transport)?;
+        self.wake_write.move_handles(&mut transport)
+    }
+
+    fn receive_handles<Transport: HandlesTransport>(
+        &mut self,
+        mut transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        self.shm.receive_handles(&mut transport)?;
+        self.wake_read.receive_handles(&mut transport)?;
+        self.wake_write.receive_handles(&mut transport)
+    }
+}
+
+#[derive(Clone)]
+pub struct RingBufferSender {
+    ring: Arc<RingBuffer>,
+    wake: WakeHandle,
+    policy: OverflowPolicy,
+}
+
+impl RingBufferSender {
+    pub fn push(&self, data: &[u8]) -> io::Result<()> {
+        let len = data.len() as u64;
+        let size = record_size(len);
+        if size > self.ring.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Message larger than the ring buffer",
+            ));
+        }
+
+        let mut backoff = Duration::from_micros(10);
+        let start = Instant::now();
+        let pos = loop {
+            if let Some(pos) = self.ring.reserve(size) {
+                break pos;
+            }
+            match self.policy {
+                OverflowPolicy::Drop => {
+                    self.ring.header().dropped.0.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                OverflowPolicy::Reject => return Err(io::ErrorKind::WouldBlock.into()),
+                OverflowPolicy::Block(timeout) => {
+                    if start.elapsed() >= timeout {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BLOCKING_BACKOFF);
+                }
+            }
+        };
+
+        // Safety: the reserved record fits at pos
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.ring.data(pos + RECORD_HEADER_SIZE),
+                data.len(),
+            );
+        }
+        self.ring
+            .record_header(pos)
+            .store(COMMITTED | len, Ordering::SeqCst);
+
+        if self
+            .ring
+            .header()
+            .receiver_waiting
+            .0
+            .swap(0, Ordering::SeqCst)
+            != 0
+        {
+            wake(&self.wake);
+        }
+        Ok(())
+    }
+
+    /// Messages dropped by senders with the Drop policy
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped()
+    }
+}
+
+pub struct RingBufferReceiver {
+    ring: RingBuffer,
+    head: u64,
+    wake: Waiter,
+}
+
+impl RingBufferReceiver {
+    pub fn try_recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let pos = self.head % self.ring.capacity;
+            let record = self.ring.record_header(pos).load(Ordering::SeqCst);
+            if record & COMMITTED == 0 {
+                return Ok(None);
+            }
+            let len = record & LEN_MASK;
+            let size = record_size(len);
+            if size > self.ring.capacity - pos {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Corrupted ring buffer",
+                ));
+            }
+
+            // Safety: the record was checked to fit in the buffer
+            let message = unsafe {
+                let message = if record & PADDING == 0 {
+                    let data = self.ring.data(pos + RECORD_HEADER_SIZE);
+                    Some(std::slice::from_raw_parts(data, len as usize).to_vec())
+                } else {
+                    None
+                };
+                ptr::write_bytes(self.ring.data(pos), 0, size as usize);
+                message
+            };
+            self.head += size;
+            self.ring
+                .header()
+                .head
+                .0
+                .store(self.head, Ordering::Release);
+
+            if message.is_some() {
+                return Ok(message);
+            }
+        }
+    }
+
+    pub async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(message) = self.try_recv()? {
+                return Ok(message);
+            }
+            let waiting = &self.ring.header().receiver_waiting.0;
+            waiting.store(1, Ordering::SeqCst);
+            // Messages committed before the senders could see the flag
+            if let Some(message) = self.try_recv()? {
+                self.ring
+                    .header()
+                    .receiver_waiting
+                    .0
+                    .store(0, Ordering::Relaxed);
+                return Ok(message);
+            }
+
+            self.wake.wait().await?;
+        }
+    }
+
+    /// Messages dropped by senders with the Drop policy
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(i: usize) -> Vec<u8> {
+        (0..(i * 37) % 300).map(|b| (b + i) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_push_wraps_around() {
+        let handle = RingBufferHandle::new(1000).unwrap();
+        let sender = handle.sender(OverflowPolicy::Reject).unwrap();
+        let mut receiver = handle.into_receiver().unwrap();
+
+        let mut received = 0;
+        for i in 0..2000 {
+            if let Err(e) = sender.push(&message(i)) {
+                assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+                while let Some(data) = receiver.try_recv().unwrap() {
+                    assert_eq!(data, message(received));
+                    received += 1;
+                }
+                sender.push(&message(i)).unwrap();
+            }
+        }
+        while let Some(data) = receiver.try_recv().unwrap() {
+            assert_eq!(data, message(received));
+            received += 1;
+        }
+        assert_eq!(received, 2000);
+        assert_eq!(receiver.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policies() {
+        let handle = RingBufferHandle::new(1000).unwrap();
+        let capacity = handle.sender(OverflowPolicy::Drop).unwrap().ring.capacity as usize;
+        let mut receiver = handle.clone().into_receiver().unwrap();
+
+        let too_large = vec![0; capacity];
+        let sender = handle.sender(OverflowPolicy::Drop).unwrap();
+        assert_eq!(
+            sender.push(&too_large).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let half = vec![1; (capacity / 2 - RECORD_HEADER_SIZE as usize) & !7];
+        sender.push(&half).unwrap();
+        sender.push(&half).unwrap();
+        sender.push(&half).unwrap();
+        assert_eq!(sender.dropped(), 1);
+
+        let sender = handle.sender(OverflowPolicy::Reject).unwrap();
+        assert_eq!(
+            sender.push(&half).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let sender = handle
+            .sender(OverflowPolicy::Block(Duration::from_millis(20)))
+            .unwrap();
+        assert_eq!(
+            sender.push(&half).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        let blocked = thread::spawn(move || sender.push(&[2; 10]));
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(receiver.try_recv().unwrap(), Some(half.clone()));
+        blocked.join().unwrap().unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), Some(half));
+        assert_eq!(receiver.try_recv().unwrap(), Some(vec![2; 10]));
+        assert_eq!(receiver.try_recv().unwrap(), None);
+        assert_eq!(receiver.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_senders() {
+        const SENDERS: usize = 4;
+        const MESSAGES: usize = 5000;
+
+        let handle = RingBufferHandle::new(4096).unwrap();
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|id| {
+                let sender = handle
+                    .sender(OverflowPolicy::Block(Duration::from_secs(10)))
+                    .unwrap();
+                thread::spawn(move || {
+                    for i in 0..MESSAGES {
+                        let mut data = vec![id as u8];
+                        data.extend_from_slice(&(i as u64).to_le_bytes());
+                        data.resize(9 + i % 100, 0);
+                        sender.push(&data).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let mut receiver = handle.into_receiver().unwrap();
+        let mut next = [0u64; SENDERS];
+        for _ in 0..SENDERS * MESSAGES {
+            let data = receiver.recv().await.unwrap();
+            let id = data[0] as usize;
+            let i = u64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert_eq!(i, next[id]);
+            assert_eq!(data.len(), 9 + i as usize % 100);
+            next[id] += 1;
+        }
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert_eq!(receiver.try_recv().unwrap(), None);
+    }
+}
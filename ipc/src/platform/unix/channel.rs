@@ -2,6 +2,7 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 use nix::sys::select::FdSet;
+use nix::sys::socket::MsgFlags;
 use nix::sys::time::{TimeVal, TimeValLike};
 use std::{
     io::{self, ErrorKind, Read, Write},
@@ -60,6 +61,16 @@ impl Channel {
             .is_err()
             || fds.contains(raw_fd)
     }
+
+    /// Whether there is data to read right away, unlike on a socket closed by the peer
+    pub fn probe_data(&self) -> bool {
+        let mut buf = [0; 1];
+        let flags = MsgFlags::MSG_PEEK | MsgFlags::MSG_DONTWAIT;
+        matches!(
+            nix::sys::socket::recv(self.inner.as_raw_fd(), &mut buf, flags),
+            Ok(n) if n > 0
+        )
+    }
 }
 
 impl Read for Channel {
@@ -35,16 +35,19 @@ impl Default for ChannelMetadata {
     }
 }
 
-impl HandlesTransport for &mut ChannelMetadata {
+impl HandlesTransport for ChannelMetadata {
     type Error = io::Error;
 
-    fn move_handle<'h, T>(self, handle: PlatformHandle<T>) -> Result<(), Self::Error> {
+    fn move_handle<T>(&mut self, handle: PlatformHandle<T>) -> Result<(), Self::Error> {
         self.enqueue_for_sending(handle);
 
         Ok(())
     }
 
-    fn provide_handle<T>(self, hint: &PlatformHandle<T>) -> Result<PlatformHandle<T>, Self::Error> {
+    fn provide_handle<T>(
+        &mut self,
+        hint: &PlatformHandle<T>,
+    ) -> Result<PlatformHandle<T>, Self::Error> {
         self.find_handle(hint).ok_or_else(|| {
             io::Error::new(
                 io::ErrorKind::Other,
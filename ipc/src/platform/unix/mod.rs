@@ -5,7 +5,7 @@ mod platform_handle;
 pub use platform_handle::*;
 
 mod mem_handle;
-pub use mem_handle::*;
+pub(crate) use mem_handle::*;
 
 mod channel;
 pub use async_channel::*;
@@ -158,14 +158,14 @@ impl<T> AsRawFd for PlatformHandle<T> {
 impl<T> TransferHandles for PlatformHandle<T> {
     fn move_handles<Transport: crate::handles::HandlesTransport>(
         &self,
-        transport: Transport,
+        mut transport: Transport,
     ) -> Result<(), Transport::Error> {
         transport.move_handle(self.clone())
     }
 
     fn receive_handles<Transport: crate::handles::HandlesTransport>(
         &mut self,
-        transport: Transport,
+        mut transport: Transport,
     ) -> Result<(), Transport::Error> {
         let received_handle = transport.provide_handle(self)?;
         self.inner = received_handle.inner;
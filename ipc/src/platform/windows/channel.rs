@@ -0,0 +1,158 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::windows::io::{AsRawHandle, RawHandle},
+    ptr,
+    time::{Duration, Instant},
+};
+
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::Pipes::{
+        PeekNamedPipe, SetNamedPipeHandleState, PIPE_NOWAIT, PIPE_READMODE_BYTE, PIPE_WAIT,
+    },
+};
+
+pub mod async_channel;
+pub mod metadata;
+
+use self::metadata::ChannelMetadata;
+
+use super::PlatformHandle;
+
+/// Blocking end of a named pipe, the handle must not have been opened for overlapped I/O
+#[derive(Debug)]
+pub struct Channel {
+    inner: PlatformHandle<File>,
+    pub metadata: ChannelMetadata,
+    read_timeout: Option<Duration>,
+}
+
+impl Clone for Channel {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            metadata: ChannelMetadata::for_pipe(self.inner.as_raw_handle()),
+            read_timeout: self.read_timeout,
+        }
+    }
+}
+
+impl Channel {
+    /// Pipes have no read timeout, reads wait for data to be available up to `timeout` instead
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// Writes to pipes can't time out, the timeout is ignored
+    pub fn set_write_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        let mode = PIPE_READMODE_BYTE | if nonblocking { PIPE_NOWAIT } else { PIPE_WAIT };
+        let success =
+            unsafe { SetNamedPipeHandleState(self.pipe(), &mode, ptr::null(), ptr::null()) };
+        if success == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn probe_readable(&self) -> bool {
+        // a broken pipe is readable, as reading from it returns right away
+        !matches!(self.available(), Ok(0))
+    }
+
+    /// Whether there is data to read right away, unlike on a pipe closed by the peer
+    pub fn probe_data(&self) -> bool {
+        matches!(self.available(), Ok(available) if available > 0)
+    }
+
+    fn pipe(&self) -> HANDLE {
+        self.inner.as_raw_handle() as HANDLE
+    }
+
+    fn available(&self) -> io::Result<u32> {
+        let mut available = 0;
+        let success = unsafe {
+            PeekNamedPipe(
+                self.pipe(),
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                &mut available,
+                ptr::null_mut(),
+            )
+        };
+        if success == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(available)
+    }
+
+    fn wait_readable(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        while !self.probe_readable() {
+            if Instant::now() >= deadline {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(timeout) = self.read_timeout {
+            self.wait_readable(timeout)?;
+        }
+        let mut pipe = &*self.inner.as_filelike_view()?;
+        pipe.read(buf)
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = &*self.inner.as_filelike_view()?;
+        pipe.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut pipe = &*self.inner.as_filelike_view()?;
+        pipe.flush()
+    }
+}
+
+impl From<Channel> for PlatformHandle<File> {
+    fn from(c: Channel) -> Self {
+        c.inner
+    }
+}
+
+impl From<PlatformHandle<File>> for Channel {
+    fn from(h: PlatformHandle<File>) -> Self {
+        Channel {
+            metadata: ChannelMetadata::for_pipe(h.as_raw_handle()),
+            inner: h,
+            read_timeout: None,
+        }
+    }
+}
+
+impl From<File> for Channel {
+    fn from(pipe: File) -> Self {
+        Channel::from(PlatformHandle::from(pipe))
+    }
+}
+
+impl AsRawHandle for Channel {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.inner.as_raw_handle()
+    }
+}
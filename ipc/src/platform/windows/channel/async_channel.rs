@@ -0,0 +1,103 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    io,
+    os::windows::io::AsRawHandle,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::windows::named_pipe::{NamedPipeClient, NamedPipeServer},
+};
+
+use super::{Channel, ChannelMetadata};
+
+#[derive(Debug)]
+enum NamedPipe {
+    Client(NamedPipeClient),
+    Server(NamedPipeServer),
+}
+
+/// Either end of a named pipe, the handles are sent within the messages
+#[derive(Debug)]
+pub struct AsyncChannel {
+    inner: NamedPipe,
+    pub metadata: Arc<Mutex<ChannelMetadata>>,
+}
+
+impl From<NamedPipeServer> for AsyncChannel {
+    fn from(pipe: NamedPipeServer) -> Self {
+        AsyncChannel {
+            metadata: Arc::new(Mutex::new(ChannelMetadata::for_pipe(pipe.as_raw_handle()))),
+            inner: NamedPipe::Server(pipe),
+        }
+    }
+}
+
+impl From<NamedPipeClient> for AsyncChannel {
+    fn from(pipe: NamedPipeClient) -> Self {
+        AsyncChannel {
+            metadata: Arc::new(Mutex::new(ChannelMetadata::for_pipe(pipe.as_raw_handle()))),
+            inner: NamedPipe::Client(pipe),
+        }
+    }
+}
+
+impl TryFrom<Channel> for AsyncChannel {
+    type Error = io::Error;
+
+    /// Pipes opened for blocking I/O can't be driven by tokio, which needs overlapped I/O: async
+    /// clients connect with [`tokio::net::windows::named_pipe::ClientOptions`] instead
+    fn try_from(_: Channel) -> Result<Self, Self::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "blocking named pipes can't be used asynchronously",
+        ))
+    }
+}
+
+impl AsyncWrite for AsyncChannel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match self.inner {
+            NamedPipe::Client(ref mut pipe) => Pin::new(pipe).poll_write(cx, buf),
+            NamedPipe::Server(ref mut pipe) => Pin::new(pipe).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match self.inner {
+            NamedPipe::Client(ref mut pipe) => Pin::new(pipe).poll_flush(cx),
+            NamedPipe::Server(ref mut pipe) => Pin::new(pipe).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        match self.inner {
+            NamedPipe::Client(ref mut pipe) => Pin::new(pipe).poll_shutdown(cx),
+            NamedPipe::Server(ref mut pipe) => Pin::new(pipe).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncRead for AsyncChannel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.inner {
+            NamedPipe::Client(ref mut pipe) => Pin::new(pipe).poll_read(cx, buf),
+            NamedPipe::Server(ref mut pipe) => Pin::new(pipe).poll_read(cx, buf),
+        }
+    }
+}
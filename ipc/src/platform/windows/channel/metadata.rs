@@ -0,0 +1,169 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    collections::VecDeque,
+    io,
+    os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle},
+    sync::Arc,
+};
+
+use windows_sys::Win32::{
+    Foundation::{DuplicateHandle, DUPLICATE_SAME_ACCESS, HANDLE},
+    System::{
+        Pipes::{GetNamedPipeClientProcessId, GetNamedPipeServerProcessId},
+        Threading::{GetCurrentProcess, OpenProcess, PROCESS_DUP_HANDLE},
+    },
+};
+
+use crate::{
+    handles::{HandlesTransport, TransferHandles},
+    platform::{Message, PlatformHandle},
+};
+
+/// Without SCM_RIGHTS, the handles are duplicated into the process at the other end of the pipe
+/// when the message referring to them is created, and the message carries their new values.
+#[derive(Debug)]
+pub struct ChannelMetadata {
+    handles_to_send: Vec<isize>,
+    handles_received: VecDeque<isize>,
+    peer: Option<Arc<OwnedHandle>>, // opened with PROCESS_DUP_HANDLE
+    pid: u32,                       // must always be set to current Process ID
+}
+
+impl Default for ChannelMetadata {
+    fn default() -> Self {
+        Self {
+            handles_to_send: Default::default(),
+            handles_received: Default::default(),
+            peer: None,
+            pid: std::process::id(),
+        }
+    }
+}
+
+impl HandlesTransport for ChannelMetadata {
+    type Error = io::Error;
+
+    fn move_handle<T>(&mut self, handle: PlatformHandle<T>) -> Result<(), Self::Error> {
+        self.enqueue_for_sending(handle)
+    }
+
+    fn provide_handle<T>(
+        &mut self,
+        hint: &PlatformHandle<T>,
+    ) -> Result<PlatformHandle<T>, Self::Error> {
+        self.find_handle(hint).ok_or_else(|| {
+            io::Error::other(format!(
+                "can't provide expected handle for hint: {:?}",
+                hint.as_raw_handle()
+            ))
+        })
+    }
+}
+
+impl ChannelMetadata {
+    /// For either end of the pipe
+    pub(crate) fn for_pipe(pipe: RawHandle) -> Self {
+        let pid = std::process::id();
+        let peer = peer_process_id(pipe, pid).and_then(|peer_pid| {
+            let process = unsafe { OpenProcess(PROCESS_DUP_HANDLE, 0, peer_pid) };
+            if process == 0 {
+                return None;
+            }
+            Some(Arc::new(unsafe {
+                OwnedHandle::from_raw_handle(process as RawHandle)
+            }))
+        });
+        ChannelMetadata {
+            peer,
+            ..Default::default()
+        }
+    }
+
+    pub fn unwrap_message<T>(&mut self, message: Message<T>) -> Result<T, io::Error>
+    where
+        T: TransferHandles,
+    {
+        self.handles_received = message.handles.into();
+        let mut item = message.item;
+
+        let result = item.receive_handles(&mut *self);
+        // the handles the item did not claim are ours to close
+        for handle in self.handles_received.drain(..) {
+            drop(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) });
+        }
+        result?;
+        Ok(item)
+    }
+
+    pub fn create_message<T>(&mut self, item: T) -> Result<Message<T>, io::Error>
+    where
+        T: TransferHandles,
+    {
+        item.move_handles(&mut *self)?;
+
+        let message = Message {
+            item,
+            handles: self.handles_to_send.drain(..).collect(),
+            pid: self.pid,
+        };
+
+        Ok(message)
+    }
+
+    pub(crate) fn enqueue_for_sending<T>(&mut self, handle: PlatformHandle<T>) -> io::Result<()> {
+        if !handle.is_valid() {
+            return Ok(());
+        }
+        let peer = self.peer.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "can't duplicate handles into the process at the other end of the pipe",
+            )
+        })?;
+        let mut duplicate: HANDLE = 0;
+        let success = unsafe {
+            DuplicateHandle(
+                GetCurrentProcess(),
+                handle.as_raw_handle() as HANDLE,
+                peer.as_raw_handle() as HANDLE,
+                &mut duplicate,
+                0,
+                0,
+                DUPLICATE_SAME_ACCESS,
+            )
+        };
+        if success == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.handles_to_send.push(duplicate);
+        Ok(())
+    }
+
+    pub(crate) fn find_handle<T>(&mut self, hint: &PlatformHandle<T>) -> Option<PlatformHandle<T>> {
+        if !hint.is_valid() {
+            return Some(hint.clone());
+        }
+
+        let handle = self.handles_received.pop_front();
+
+        handle.map(|handle| unsafe { PlatformHandle::from_raw_handle(handle as RawHandle) })
+    }
+}
+
+/// Named pipes know the process at either end, the peer is the one which is not us
+fn peer_process_id(pipe: RawHandle, pid: u32) -> Option<u32> {
+    let mut server_pid = 0;
+    if unsafe { GetNamedPipeServerProcessId(pipe as HANDLE, &mut server_pid) } == 0 {
+        return None;
+    }
+    if server_pid != pid {
+        return Some(server_pid);
+    }
+    let mut client_pid = 0;
+    if unsafe { GetNamedPipeClientProcessId(pipe as HANDLE, &mut client_pid) } == 0 {
+        return None;
+    }
+    Some(client_pid)
+}
@@ -0,0 +1,141 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use crate::platform::{
+    FileBackedHandle, MappedMem, MemoryHandle, NamedShmHandle, PlatformHandle, ShmHandle, ShmPath,
+};
+use std::ffi::{c_void, CString};
+use std::io;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::{mem, ptr};
+use windows_sys::Win32::Foundation::{HANDLE, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::System::Memory::{
+    CreateFileMappingA, MapViewOfFile, OpenFileMappingA, UnmapViewOfFile, VirtualAlloc,
+    VirtualQuery, FILE_MAP_ALL_ACCESS, MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_READWRITE,
+    SEC_RESERVE,
+};
+
+/// Sections backed by the paging file can't grow once created, so they reserve up to this size,
+/// committing only the pages mapped so far
+#[cfg(target_pointer_width = "64")]
+const RESERVED_SIZE: usize = 1 << 32;
+#[cfg(not(target_pointer_width = "64"))]
+const RESERVED_SIZE: usize = 1 << 26;
+
+fn map_view(handle: &PlatformHandle<impl Sized>) -> io::Result<*mut c_void> {
+    let view = unsafe {
+        MapViewOfFile(
+            handle.as_raw_handle() as HANDLE,
+            FILE_MAP_ALL_ACCESS,
+            0,
+            0,
+            0,
+        )
+    };
+    if view == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(view as *mut c_void)
+}
+
+pub(crate) fn mmap_handle<T: FileBackedHandle>(handle: T) -> io::Result<MappedMem<T>> {
+    let ptr = map_view(&handle.get_shm().handle)?;
+    let mapped = MappedMem { ptr, mem: handle };
+    // pages committed through a view are committed for all the views of the section
+    let size = mapped.mem.get_size();
+    if size > 0 && unsafe { VirtualAlloc(ptr, size, MEM_COMMIT, PAGE_READWRITE) }.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(mapped)
+}
+
+pub(crate) fn munmap_handle<T: MemoryHandle>(mapped: &mut MappedMem<T>) {
+    unsafe {
+        UnmapViewOfFile(mapped.ptr as isize);
+    }
+}
+
+/// The pages are committed when mapped
+pub(crate) fn resize_handle(shm: &ShmHandle) -> anyhow::Result<()> {
+    if shm.size > RESERVED_SIZE {
+        anyhow::bail!("Cannot grow a mapping beyond {RESERVED_SIZE} bytes");
+    }
+    Ok(())
+}
+
+fn create_mapping(name: Option<&CString>) -> io::Result<PlatformHandle<io_lifetimes::OwnedHandle>> {
+    let handle = unsafe {
+        CreateFileMappingA(
+            INVALID_HANDLE_VALUE,
+            ptr::null(),
+            PAGE_READWRITE | SEC_RESERVE,
+            (RESERVED_SIZE as u64 >> 32) as u32,
+            RESERVED_SIZE as u32,
+            name.map_or(ptr::null(), |name| name.as_ptr() as *const u8),
+        )
+    };
+    if handle == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { PlatformHandle::from_raw_handle(handle as RawHandle) })
+}
+
+impl ShmHandle {
+    pub fn new(size: usize) -> anyhow::Result<ShmHandle> {
+        if size > RESERVED_SIZE {
+            anyhow::bail!("Cannot allocate a mapping beyond {RESERVED_SIZE} bytes");
+        }
+        let handle = create_mapping(None)?;
+        Ok(ShmHandle { handle, size })
+    }
+}
+
+impl NamedShmHandle {
+    pub fn create(path: CString, size: usize) -> io::Result<NamedShmHandle> {
+        if size > RESERVED_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mapping larger than the reserved size",
+            ));
+        }
+        let handle = create_mapping(Some(&path))?;
+        Ok(Self::new(handle, path, size))
+    }
+
+    /// The size is the one committed so far by the processes mapping it
+    pub fn open(path: CString) -> io::Result<NamedShmHandle> {
+        let handle =
+            unsafe { OpenFileMappingA(FILE_MAP_ALL_ACCESS, 0, path.as_ptr() as *const u8) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let handle = unsafe { PlatformHandle::from_raw_handle(handle as RawHandle) };
+
+        let view = map_view(&handle)?;
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        let queried = unsafe { VirtualQuery(view, &mut info, mem::size_of_val(&info)) };
+        unsafe {
+            UnmapViewOfFile(view as isize);
+        }
+        if queried == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let size = if info.State == MEM_COMMIT {
+            info.RegionSize
+        } else {
+            0
+        };
+        Ok(Self::new(handle, path, size))
+    }
+
+    fn new(
+        handle: PlatformHandle<io_lifetimes::OwnedHandle>,
+        path: CString,
+        size: usize,
+    ) -> NamedShmHandle {
+        NamedShmHandle {
+            inner: ShmHandle { handle, size },
+            path: Some(ShmPath { name: path }),
+        }
+    }
+}
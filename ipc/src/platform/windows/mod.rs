@@ -0,0 +1,166 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+mod platform_handle;
+pub use platform_handle::*;
+
+mod mem_handle;
+pub(crate) use mem_handle::*;
+
+mod channel;
+pub use async_channel::*;
+pub use channel::*;
+
+pub mod named_pipe;
+
+mod message;
+
+pub use message::*;
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{
+        ffi::CString,
+        fs::File,
+        io::{self, Read, Seek, Write},
+        os::windows::io::{AsRawHandle, IntoRawHandle},
+        path::PathBuf,
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::windows::named_pipe::{NamedPipeServer, ServerOptions},
+    };
+
+    use crate::platform::{named_pipe, Channel, NamedShmHandle, PlatformHandle, ShmHandle};
+
+    fn unique_pipe_path(test: &str) -> PathBuf {
+        named_pipe::pipe_path(&format!("libdatadog-test-{test}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_pipe_path() {
+        assert_eq!(
+            PathBuf::from(r"\\.\pipe\datadog-sidecar"),
+            named_pipe::pipe_path("datadog-sidecar")
+        );
+    }
+
+    #[test]
+    fn test_missing_pipe_is_not_listening() {
+        let path = unique_pipe_path("missing");
+        assert!(!named_pipe::is_listening(&path).unwrap());
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            named_pipe::connect(&path, Duration::from_millis(10))
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocking_client_roundtrip() {
+        let path = unique_pipe_path("roundtrip");
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&path)
+            .unwrap();
+        let client = named_pipe::connect(&path, Duration::from_secs(1)).unwrap();
+        server.connect().await.unwrap();
+
+        let mut client = tokio::task::spawn_blocking(move || {
+            let mut client = client;
+            client.write_all(b"ping").unwrap();
+            client
+        })
+        .await
+        .unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"ping", &buf);
+
+        assert!(!client.probe_data());
+        server.write_all(b"pong").await.unwrap();
+        assert!(client.probe_data());
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"pong", &buf);
+
+        // nothing left to read: reads wait up to the timeout
+        client
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            client.read(&mut buf).unwrap_err().kind()
+        );
+
+        drop(server);
+        assert!(client.probe_readable());
+        assert!(!client.probe_data());
+    }
+
+    #[tokio::test]
+    async fn test_first_instance_is_created_once() {
+        let path = unique_pipe_path("first-instance");
+        let pipe = named_pipe::create_first_instance(&path).unwrap().unwrap();
+        assert!(named_pipe::create_first_instance(&path).unwrap().is_none());
+        assert!(named_pipe::is_listening(&path).unwrap());
+
+        let server = unsafe { NamedPipeServer::from_raw_handle(pipe.into_raw_handle()) }.unwrap();
+        let _client = named_pipe::connect(&path, Duration::from_secs(1)).unwrap();
+        server.connect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handles_are_duplicated_into_the_peer() {
+        let path = unique_pipe_path("handles");
+        let server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&path)
+            .unwrap();
+        // both ends are in this process, which is then the peer to duplicate the handles into
+        let mut client = named_pipe::connect(&path, Duration::from_secs(1)).unwrap();
+        server.connect().await.unwrap();
+
+        let mut file = tempfile::tempfile().unwrap();
+        write!(file, "test_string").unwrap();
+        let handle = PlatformHandle::from(file);
+
+        let message = client.metadata.create_message(handle.clone()).unwrap();
+        assert_eq!(1, message.handles.len());
+        assert_ne!(handle.as_raw_handle() as isize, message.handles[0]);
+
+        let received = client.metadata.unwrap_message(message).unwrap();
+        assert_ne!(handle.as_raw_handle(), received.as_raw_handle());
+        let mut file: File = received.into_instance().unwrap();
+        file.rewind().unwrap();
+        let mut data = String::new();
+        file.read_to_string(&mut data).unwrap();
+        assert_eq!("test_string", data);
+    }
+
+    #[test]
+    fn test_default_handles_are_not_transferred() {
+        let mut client = Channel::from(PlatformHandle::<File>::default());
+        let message = client
+            .metadata
+            .create_message(PlatformHandle::<File>::default())
+            .unwrap();
+        assert!(message.handles.is_empty());
+        client.metadata.unwrap_message(message).unwrap();
+    }
+
+    #[test]
+    fn test_shm_beyond_the_reserved_size() {
+        assert!(ShmHandle::new(usize::MAX).is_err());
+        let path = CString::new(format!("libdatadog-test-shm-{}", std::process::id())).unwrap();
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            NamedShmHandle::create(path, usize::MAX)
+                .map(|_| ())
+                .unwrap_err()
+                .kind()
+        );
+    }
+}
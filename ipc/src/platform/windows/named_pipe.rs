@@ -0,0 +1,99 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    ffi::CString,
+    fs::OpenOptions,
+    io,
+    os::windows::{
+        ffi::OsStrExt,
+        io::{FromRawHandle, OwnedHandle, RawHandle},
+    },
+    path::{Path, PathBuf},
+    ptr,
+    time::Duration,
+};
+
+use windows_sys::Win32::{
+    Foundation::{ERROR_ACCESS_DENIED, ERROR_PIPE_BUSY, INVALID_HANDLE_VALUE},
+    Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX,
+    },
+    System::Pipes::{
+        CreateNamedPipeW, WaitNamedPipeA, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    },
+};
+
+use super::Channel;
+
+/// Path of the pipe named `name` on the local machine
+pub fn pipe_path(name: &str) -> PathBuf {
+    PathBuf::from(format!(r"\\.\pipe\{name}"))
+}
+
+pub fn is_listening<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    match open(path.as_ref()) {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates the first server end of the pipe, None if the pipe exists already. The handle is opened
+/// for overlapped I/O, for tokio's NamedPipeServer::from_raw_handle, which further server ends
+/// are then created like.
+pub fn create_first_instance<P: AsRef<Path>>(path: P) -> io::Result<Option<OwnedHandle>> {
+    let name: Vec<u16> = path
+        .as_ref()
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            65536,
+            65536,
+            0,
+            ptr::null(),
+        )
+    };
+    if pipe == INVALID_HANDLE_VALUE {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) {
+            return Ok(None);
+        }
+        return Err(error);
+    }
+    Ok(Some(unsafe {
+        OwnedHandle::from_raw_handle(pipe as RawHandle)
+    }))
+}
+
+/// Connects to a server end of the pipe, waiting up to `timeout` for one to be available
+pub fn connect<P: AsRef<Path>>(path: P, timeout: Duration) -> io::Result<Channel> {
+    let path = path.as_ref();
+    loop {
+        match open(path) {
+            Ok(channel) => return Ok(channel),
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                let name = CString::new(path.to_string_lossy().as_bytes())?;
+                let timeout = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+                if unsafe { WaitNamedPipeA(name.as_ptr() as *const u8, timeout) } == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<Channel> {
+    let pipe = OpenOptions::new().read(true).write(true).open(path)?;
+    Ok(Channel::from(pipe))
+}
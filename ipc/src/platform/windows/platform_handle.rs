@@ -0,0 +1,165 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    io,
+    marker::PhantomData,
+    os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle, OwnedHandle, RawHandle},
+    sync::Arc,
+};
+
+use io_lifetimes::{
+    views::{FilelikeView, FilelikeViewType},
+    AsFilelike,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::handles::TransferHandles;
+
+/// PlatformHandle contains a valid reference counted Handle and associated Type information
+/// allowing safe transfer and sharing of handles across processes, and threads
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlatformHandle<T> {
+    handle: isize, // Just the handle value in the sending process, e.g. when serializing, not for accessing the actual handle
+    #[serde(skip)]
+    inner: Option<Arc<OwnedHandle>>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Default for PlatformHandle<T> {
+    fn default() -> Self {
+        Self {
+            handle: -1,
+            inner: None,
+            phantom: Default::default(),
+        }
+    }
+}
+
+impl<T> Clone for PlatformHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle,
+            inner: self.inner.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> PlatformHandle<T> {
+    fn as_owned_handle(&self) -> io::Result<&Arc<OwnedHandle>> {
+        match &self.inner {
+            Some(handle) => Ok(handle),
+            None => Err(io::Error::other(
+                "attempting to unwrap handle from invalid handle",
+            )),
+        }
+    }
+
+    /// Whether the handle refers to an object, unlike the default one
+    pub(crate) fn is_valid(&self) -> bool {
+        self.inner.is_some() || self.handle >= 0
+    }
+}
+
+impl<T> PlatformHandle<T> {
+    pub fn into_instance(self) -> Result<T, io::Error>
+    where
+        T: From<OwnedHandle>,
+    {
+        Ok(self.into_owned_handle()?.into())
+    }
+
+    pub fn into_owned_handle(self) -> Result<OwnedHandle, io::Error> {
+        let shared_handle = match self.inner {
+            Some(shared_handle) => shared_handle,
+            None => {
+                return Err(io::Error::other(
+                    "attempting to unwrap handle from invalid handle",
+                ))
+            }
+        };
+
+        Arc::try_unwrap(shared_handle).map_err(|_| {
+            io::Error::other("attempting to unwrap handle from shared platform handle")
+        })
+    }
+
+    /// casts the associated type
+    ///
+    /// # Safety
+    /// Caller must ensure the  type is compatible with the stored handle
+    pub unsafe fn to_any_type<U>(self) -> PlatformHandle<U> {
+        PlatformHandle {
+            handle: self.handle,
+            inner: self.inner,
+            phantom: PhantomData,
+        }
+    }
+
+    /// OwnedHandle innertype is safe to instantiate via into_instance
+    pub fn to_untyped(self) -> PlatformHandle<OwnedHandle> {
+        unsafe { self.to_any_type() }
+    }
+}
+
+impl<T> PlatformHandle<T>
+where
+    T: FilelikeViewType,
+{
+    pub fn as_filelike_view(&self) -> io::Result<FilelikeView<'_, T>> {
+        Ok(self.as_owned_handle()?.as_filelike_view())
+    }
+}
+
+impl<T> FromRawHandle for PlatformHandle<T> {
+    /// Creates PlatformHandle instance from supplied RawHandle
+    ///
+    /// # Safety caller must ensure the RawHandle is valid and open, and that the resulting PlatformHandle will
+    /// # have exclusive ownership of the handle
+    ///
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        let inner = Some(Arc::new(OwnedHandle::from_raw_handle(handle)));
+        Self {
+            handle: handle as isize,
+            inner,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> From<T> for PlatformHandle<T>
+where
+    T: IntoRawHandle,
+{
+    fn from(src: T) -> Self {
+        unsafe { PlatformHandle::from_raw_handle(src.into_raw_handle()) }
+    }
+}
+
+impl<T> AsRawHandle for PlatformHandle<T> {
+    fn as_raw_handle(&self) -> RawHandle {
+        match &self.inner {
+            Some(h) => h.as_raw_handle(),
+            None => self.handle as RawHandle,
+        }
+    }
+}
+
+impl<T> TransferHandles for PlatformHandle<T> {
+    fn move_handles<Transport: crate::handles::HandlesTransport>(
+        &self,
+        mut transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        transport.move_handle(self.clone())
+    }
+
+    fn receive_handles<Transport: crate::handles::HandlesTransport>(
+        &mut self,
+        mut transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        let received_handle = transport.provide_handle(self)?;
+        self.inner = received_handle.inner;
+        Ok(())
+    }
+}
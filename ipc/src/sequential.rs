@@ -2,23 +2,43 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 use std::{
+    io,
     pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
 };
 
-use futures::{ready, Future, Stream};
+use futures::{future::BoxFuture, ready, Future, FutureExt, Stream};
 use tarpc::{
     self,
     server::{Channel, InFlightRequest, Requests, Serve},
+    ServerError,
 };
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, info, warn};
 
 #[allow(type_alias_bounds)]
 type Request<S, C: Channel> = (S, InFlightRequest<C::Req, C::Resp>);
 
+/// What happens to the requests read from a connection while the queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueueFullPolicy {
+    /// The request is answered with a [`io::ErrorKind::WouldBlock`] error, also when the client
+    /// discards the responses, letting it know that it should slow down
+    #[default]
+    Drop,
+    /// The connection is not read from until the queue has room again
+    Block,
+}
+
 /// Replaces tarpc::server::Channel::execute which spawns one task per message with an executor
 /// that spawns a single worker and queues requests for this task.
 ///
-/// If the queue is full, the requests is dropped and will be cancelled by tarpc.
+/// If the queue is full, the requests are handled according to the [`QueueFullPolicy`], which
+/// defaults to dropping them.
 pub fn execute_sequential<C, S>(
     reqs: Requests<C>,
     serve: S,
@@ -46,6 +66,10 @@ where
         inner: reqs,
         serve,
         tx,
+        policy: QueueFullPolicy::default(),
+        dropped: Default::default(),
+        dropped_since_full: 0,
+        blocked: None,
     }
 }
 
@@ -58,6 +82,11 @@ where
     inner: Requests<C>,
     serve: S,
     tx: tokio::sync::mpsc::Sender<Request<S, C>>,
+    policy: QueueFullPolicy,
+    dropped: Arc<AtomicU64>,
+    dropped_since_full: u64,
+    /// Queues the request which didn't fit, with the [`QueueFullPolicy::Block`] policy
+    blocked: Option<BoxFuture<'static, ()>>,
 }
 
 impl<C, S> Future for SequentialExecutor<C, S>
@@ -71,16 +100,37 @@ where
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        while let Some(response_handler) = ready!(self.as_mut().project().inner.poll_next(cx)) {
+        loop {
+            if let Some(blocked) = self.as_mut().project().blocked {
+                ready!(blocked.poll_unpin(cx));
+                *self.as_mut().project().blocked = None;
+            }
+            let response_handler = match ready!(self.as_mut().project().inner.poll_next(cx)) {
+                Some(response_handler) => response_handler,
+                None => break,
+            };
             match response_handler {
                 Ok(resp) => {
                     let server = self.serve.clone();
-                    if let Err(_err) = self.as_ref().tx.try_send((server, resp)) {
-                        // TODO: should we log something in case we drop the request on the floor?
+                    match self.as_ref().tx.try_send((server, resp)) {
+                        Ok(()) => self.as_mut().queued(),
+                        Err(TrySendError::Full(req)) if self.policy == QueueFullPolicy::Block => {
+                            let tx = self.tx.clone();
+                            *self.as_mut().project().blocked = Some(
+                                async move {
+                                    _ = tx.send(req).await;
+                                }
+                                .boxed(),
+                            );
+                        }
+                        Err(TrySendError::Full((_, req))) => self.as_mut().drop_request(req),
+                        Err(TrySendError::Closed(_)) => {
+                            debug!("Dropping request as the worker has stopped");
+                        }
                     }
                 }
                 Err(_e) => {
-                    // TODO: should we log something in case we drop the request on the floor?
+                    debug!("Closing the connection after failing to read a request");
                     break;
                 }
             }
@@ -100,4 +150,43 @@ where
         std::mem::swap(&mut self.tx, &mut sender);
         sender
     }
+
+    pub fn set_queue_full_policy(&mut self, policy: QueueFullPolicy) {
+        self.policy = policy;
+    }
+
+    /// Counts the requests dropped by this executor, kept up to date after it is consumed
+    pub fn dropped_requests(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
+
+    fn drop_request(self: Pin<&mut Self>, req: InFlightRequest<C::Req, C::Resp>) {
+        let this = self.project();
+        let dropped = this.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        if *this.dropped_since_full == 0 {
+            warn!(
+                dropped,
+                "Request queue is full, dropping requests until it has room again"
+            );
+        }
+        *this.dropped_since_full += 1;
+        let error = ServerError::new(
+            io::ErrorKind::WouldBlock,
+            "The request queue is full".to_string(),
+        );
+        if req.reject(error).is_err() {
+            debug!("Could not notify the client of the dropped request");
+        }
+    }
+
+    fn queued(self: Pin<&mut Self>) {
+        let this = self.project();
+        if *this.dropped_since_full > 0 {
+            info!(
+                dropped = *this.dropped_since_full,
+                "Request queue has room again"
+            );
+            *this.dropped_since_full = 0;
+        }
+    }
 }
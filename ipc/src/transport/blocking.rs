@@ -3,19 +3,19 @@
 
 use std::{
     io::{self, Read, Write},
+    marker::PhantomData,
     mem::MaybeUninit,
-    os::unix::net::UnixStream,
-    pin::Pin,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
-use bytes::{BufMut, BytesMut};
-use serde::{Deserialize, Serialize};
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
 use tarpc::{context::Context, ClientMessage, Request, Response};
 
-use tokio_serde::{Deserializer, Serializer};
-
 use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
 
 use crate::{
@@ -23,19 +23,42 @@ use crate::{
     platform::{Channel, Message},
 };
 
-use super::DefaultCodec;
+use super::{
+    protocol::{handshake_frame, HandshakeReply},
+    Codec, Protocol, Versioned,
+};
 
 pub struct BlockingTransport<IncomingItem, OutgoingItem> {
-    pid: libc::pid_t,
+    pid: u32,
     requests_id: Arc<AtomicU64>,
+    dropped_requests: Arc<AtomicU64>,
+    negotiated: Option<Negotiated<OutgoingItem>>,
     transport: FramedBlocking<Response<IncomingItem>, ClientMessage<OutgoingItem>>,
 }
 
+/// Outcome of negotiate_protocol
+struct Negotiated<OutgoingItem> {
+    /// None for the servers which don't check protocols
+    peer: Option<Arc<Protocol>>,
+    capability: fn(&OutgoingItem) -> Option<&'static str>,
+}
+
+impl<OutgoingItem> Clone for Negotiated<OutgoingItem> {
+    fn clone(&self) -> Self {
+        Self {
+            peer: self.peer.clone(),
+            capability: self.capability,
+        }
+    }
+}
+
 impl<IncomingItem, OutgoingItem> Clone for BlockingTransport<IncomingItem, OutgoingItem> {
     fn clone(&self) -> Self {
         Self {
             pid: self.pid,
             requests_id: self.requests_id.clone(),
+            dropped_requests: self.dropped_requests.clone(),
+            negotiated: self.negotiated.clone(),
             transport: self.transport.clone(),
         }
     }
@@ -43,23 +66,28 @@ impl<IncomingItem, OutgoingItem> Clone for BlockingTransport<IncomingItem, Outgo
 
 impl<IncomingItem, OutgoingItem> From<Channel> for BlockingTransport<IncomingItem, OutgoingItem> {
     fn from(c: Channel) -> Self {
-        let pid = unsafe { libc::getpid() };
+        let pid = std::process::id();
         BlockingTransport {
             pid,
             requests_id: Arc::from(AtomicU64::new(0)),
+            dropped_requests: Default::default(),
+            negotiated: None,
             transport: c.into(),
         }
     }
 }
 
-impl<IncomingItem, OutgoingItem> From<UnixStream>
+#[cfg(unix)]
+impl<IncomingItem, OutgoingItem> From<std::os::unix::net::UnixStream>
     for BlockingTransport<IncomingItem, OutgoingItem>
 {
-    fn from(s: UnixStream) -> Self {
-        let pid = unsafe { libc::getpid() };
+    fn from(s: std::os::unix::net::UnixStream) -> Self {
+        let pid = std::process::id();
         BlockingTransport {
             pid,
             requests_id: Arc::from(AtomicU64::new(0)),
+            dropped_requests: Default::default(),
+            negotiated: None,
             transport: Channel::from(s).into(),
         }
     }
@@ -69,24 +97,27 @@ pub struct FramedBlocking<IncomingItem, OutgoingItem> {
     codec: LengthDelimitedCodec,
     read_buffer: BytesMut,
     channel: Channel,
-    serde_codec: Pin<Box<DefaultCodec<Message<IncomingItem>, Message<OutgoingItem>>>>,
+    serde_codec: Codec,
+    _items: PhantomData<fn(OutgoingItem) -> IncomingItem>,
 }
 
 impl<IncomingItem, OutgoingItem> FramedBlocking<IncomingItem, OutgoingItem>
 where
-    IncomingItem: for<'de> Deserialize<'de> + TransferHandles,
+    IncomingItem: DeserializeOwned + TransferHandles,
     OutgoingItem: Serialize + TransferHandles,
 {
     pub fn read_item(&mut self) -> Result<IncomingItem, io::Error> {
+        let frame = self.read_frame()?;
+        let message: Message<IncomingItem> = self.serde_codec.deserialize(&frame)?;
+        self.channel.metadata.unwrap_message(message)
+    }
+
+    fn read_frame(&mut self) -> Result<BytesMut, io::Error> {
         let buf = &mut self.read_buffer;
         while buf.has_remaining_mut() {
             buf.reserve(1);
             match self.codec.decode(buf)? {
-                Some(frame) => {
-                    let message = self.serde_codec.as_mut().deserialize(&frame)?;
-                    let item = self.channel.metadata.unwrap_message(message)?;
-                    return Ok(item);
-                }
+                Some(frame) => return Ok(frame),
                 None => {
                     let n = unsafe {
                         let dst = buf.chunk_mut();
@@ -104,6 +135,9 @@ where
                             as *mut [u8]);
 
                         let n = self.channel.read(b)?;
+                        if n == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
                         buf_window.assume_init(n);
                         buf_window.advance(n);
 
@@ -125,14 +159,46 @@ where
     }
 
     fn do_send(&mut self, req: OutgoingItem) -> Result<(), io::Error> {
-        let msg = self.channel.metadata.create_message(req)?;
+        self.try_do_send(req).map_err(|(e, _)| e)
+    }
 
-        let mut buf = BytesMut::new();
-        let data = self.serde_codec.as_mut().serialize(&msg)?;
+    /// Gives the request back when it could not be written
+    fn try_do_send(&mut self, req: OutgoingItem) -> Result<(), (io::Error, Option<OutgoingItem>)> {
+        let msg = self
+            .channel
+            .metadata
+            .create_message(req)
+            .map_err(|e| (e, None))?;
+
+        let data = match self.serde_codec.serialize(&msg) {
+            Ok(data) => data,
+            Err(e) => return Err((e, Some(msg.item))),
+        };
+        self.write_frame(data).map_err(|e| (e, Some(msg.item)))
+    }
 
-        self.codec.encode(data, &mut buf)?;
+    fn write_frame(&mut self, data: Vec<u8>) -> Result<(), io::Error> {
+        let mut buf = BytesMut::new();
+        self.codec.encode(Bytes::from(data), &mut buf)?;
         self.channel.write_all(&buf)
     }
+
+    /// Servers not knowing about handshakes close the connection. Returns the codec and the
+    /// protocol of the server, if it told it.
+    fn handshake(
+        &mut self,
+        codec: Codec,
+        protocol: Option<&Protocol>,
+    ) -> Result<(Codec, Option<Protocol>), io::Error> {
+        self.write_frame(handshake_frame(codec, protocol)?)?;
+        let frame = self.read_frame()?;
+        let (codec, payload) = Codec::parse_handshake(&frame).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Invalid handshake response")
+        })?;
+        let peer = HandshakeReply::parse(payload)?;
+        self.serde_codec = codec;
+        Ok((codec, peer))
+    }
 }
 
 impl<IncomingItem, OutgoingItem> From<Channel> for FramedBlocking<IncomingItem, OutgoingItem> {
@@ -141,7 +207,8 @@ impl<IncomingItem, OutgoingItem> From<Channel> for FramedBlocking<IncomingItem,
             codec: Default::default(),
             read_buffer: BytesMut::with_capacity(4000),
             channel: c,
-            serde_codec: Box::pin(Default::default()),
+            serde_codec: Codec::Json,
+            _items: PhantomData,
         }
     }
 }
@@ -150,9 +217,10 @@ impl<IncomingItem, OutgoingItem> Clone for FramedBlocking<IncomingItem, Outgoing
     fn clone(&self) -> Self {
         Self {
             codec: self.codec.clone(),
-            serde_codec: Box::pin(Default::default()),
+            serde_codec: self.serde_codec,
             read_buffer: self.read_buffer.clone(),
             channel: self.channel.clone(),
+            _items: PhantomData,
         }
     }
 }
@@ -160,16 +228,14 @@ impl<IncomingItem, OutgoingItem> Clone for FramedBlocking<IncomingItem, Outgoing
 impl<IncomingItem, OutgoingItem> BlockingTransport<IncomingItem, OutgoingItem>
 where
     OutgoingItem: Serialize + TransferHandles,
-    IncomingItem: for<'de> Deserialize<'de> + TransferHandles,
+    IncomingItem: DeserializeOwned + TransferHandles,
 {
     fn new_client_message(
         &self,
         item: OutgoingItem,
         context: Context,
     ) -> (u64, ClientMessage<OutgoingItem>) {
-        let request_id = self
-            .requests_id
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let request_id = self.requests_id.fetch_add(1, Ordering::Relaxed);
 
         (
             request_id,
@@ -181,6 +247,43 @@ where
         )
     }
 
+    /// Asks the server to use another codec than Json. Must be done before sending anything.
+    /// Returns the codec picked by the server, which then applies in both directions.
+    pub fn negotiate_codec(&mut self, codec: Codec) -> io::Result<Codec> {
+        Ok(self.transport.handshake(codec, None)?.0)
+    }
+
+    /// The protocol of the server, if it told it during negotiate_protocol
+    pub fn peer_protocol(&self) -> Option<&Protocol> {
+        self.negotiated.as_ref()?.peer.as_deref()
+    }
+
+    /// Whether the server handles the request. Only the optional requests of a negotiated
+    /// protocol may not be.
+    pub fn supports(&self, item: &OutgoingItem) -> bool {
+        let negotiated = match self.negotiated {
+            Some(ref negotiated) => negotiated,
+            None => return true,
+        };
+        match (negotiated.capability)(item) {
+            Some(capability) => {
+                matches!(negotiated.peer, Some(ref peer) if peer.supports(capability))
+            }
+            None => true,
+        }
+    }
+
+    fn check_supported(&self, item: &OutgoingItem) -> io::Result<()> {
+        if self.supports(item) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The server does not handle this request",
+            ))
+        }
+    }
+
     pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
         self.transport.channel.set_nonblocking(nonblocking)
     }
@@ -194,24 +297,63 @@ where
     }
 
     pub fn is_closed(&self) -> bool {
-        // The blocking transport is not supposed to be readable on the client side unless it's a response.
-        // So, outside of waiting for a response, it will never be readable unless the server side closed its socket.
-        self.transport.channel.probe_readable()
+        // The blocking transport is not supposed to be readable on the client side unless it's a response,
+        // or the rejection of a request the server had no room for. So, outside of waiting for a response,
+        // it will never be readable without data unless the server side closed its socket.
+        self.transport.channel.probe_readable() && !self.transport.channel.probe_data()
+    }
+
+    /// Counts the requests the server dropped as it could not keep up, the sender should slow down
+    /// or keep the data aside when it increases. Shared with the clones of the transport.
+    pub fn dropped_requests(&self) -> u64 {
+        self.dropped_requests.load(Ordering::Relaxed)
+    }
+
+    /// Reads the rejections of the requests of which the responses are discarded
+    fn receive_rejections(&mut self) -> io::Result<()> {
+        while self.transport.channel.probe_data() {
+            let resp = self.transport.read_item()?;
+            count_dropped(&self.dropped_requests, &resp);
+        }
+        Ok(())
     }
 
     pub fn send(&mut self, item: OutgoingItem) -> io::Result<()> {
+        self.try_send(item).map_err(|(e, _)| e)
+    }
+
+    /// As [Self::send], giving the item back when it was not written, e.g. to send it again to
+    /// another server when this one went away
+    pub fn try_send(
+        &mut self,
+        item: OutgoingItem,
+    ) -> Result<(), (io::Error, Option<OutgoingItem>)> {
+        if let Err(e) = self.check_supported(&item) {
+            return Err((e, Some(item)));
+        }
+        if let Err(e) = self.receive_rejections() {
+            return Err((e, Some(item)));
+        }
         let mut ctx = Context::current();
         ctx.discard_response = true;
         let (_, req) = self.new_client_message(item, ctx);
-        self.transport.do_send(req)
+        self.transport
+            .try_do_send(req)
+            .map_err(|(e, req)| match req {
+                Some(ClientMessage::Request(req)) => (e, Some(req.message)),
+                _ => (e, None),
+            })
     }
 
     pub fn call(&mut self, item: OutgoingItem) -> io::Result<IncomingItem> {
+        self.check_supported(&item)?;
         let (request_id, req) = self.new_client_message(item, Context::current());
         self.transport.do_send(req)?;
 
+        let dropped_requests = self.dropped_requests.clone();
         for resp in self {
             let resp = resp?;
+            count_dropped(&dropped_requests, &resp);
             if resp.request_id == request_id {
                 return resp.message.map_err(|e| io::Error::new(e.kind, e.detail));
             }
@@ -223,9 +365,34 @@ where
     }
 }
 
+impl<IncomingItem, OutgoingItem> BlockingTransport<IncomingItem, OutgoingItem>
+where
+    OutgoingItem: Serialize + TransferHandles + Versioned,
+    IncomingItem: DeserializeOwned + TransferHandles,
+{
+    /// Like negotiate_codec, also announcing the protocol of the client. Servers refuse the
+    /// clients speaking an incompatible protocol with an Unsupported error. The optional requests
+    /// the server does not handle are then refused with an Unsupported error, without being sent.
+    pub fn negotiate_protocol(&mut self, codec: Codec) -> io::Result<Codec> {
+        let protocol = Protocol::of::<OutgoingItem>();
+        let (codec, peer) = self.transport.handshake(codec, Some(&protocol))?;
+        self.negotiated = Some(Negotiated {
+            peer: peer.map(Arc::new),
+            capability: OutgoingItem::capability,
+        });
+        Ok(codec)
+    }
+}
+
+fn count_dropped<T>(dropped_requests: &AtomicU64, resp: &Response<T>) {
+    if matches!(resp.message, Err(ref e) if e.kind == io::ErrorKind::WouldBlock) {
+        dropped_requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 impl<IncomingItem, OutgoingItem> Iterator for BlockingTransport<IncomingItem, OutgoingItem>
 where
-    IncomingItem: for<'de> Deserialize<'de> + TransferHandles,
+    IncomingItem: DeserializeOwned + TransferHandles,
     OutgoingItem: Serialize + TransferHandles,
 {
     type Item = io::Result<Response<IncomingItem>>;
@@ -0,0 +1,134 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::io;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Format of the frames exchanged over a connection. Json is understood by every peer, other
+/// codecs are agreed on through a handshake, which is the first frame sent by the client.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    Json,
+    /// Maps with named fields, as the messages skip serializing some of their fields
+    MessagePack,
+}
+
+/// Not a valid start of a json document, so that servers can tell handshakes from the first
+/// message of clients which don't send one
+const HANDSHAKE_MAGIC: &[u8] = b"\0DDIPC";
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Json => 0,
+            Codec::MessagePack => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::Json),
+            1 => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, item: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(item)?),
+            Codec::MessagePack => rmp_serde::to_vec_named(item)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self, data: &[u8]) -> io::Result<T> {
+        match self {
+            Codec::Json => Ok(serde_json::from_slice(data)?),
+            Codec::MessagePack => rmp_serde::from_slice(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    /// The frame asking for the codec, which is also the answer of the server with the codec it
+    /// picked. Either may be followed by a payload, see the protocol module.
+    pub fn handshake(self) -> Vec<u8> {
+        let mut frame = HANDSHAKE_MAGIC.to_vec();
+        frame.push(self.id());
+        frame
+    }
+
+    /// None if the frame is not a handshake. Codecs unknown to this side are answered with Json.
+    pub fn from_handshake(frame: &[u8]) -> Option<Self> {
+        Self::parse_handshake(frame).map(|(codec, _)| codec)
+    }
+
+    /// The codec and the payload following it, if the frame is a handshake
+    pub(crate) fn parse_handshake(frame: &[u8]) -> Option<(Self, &[u8])> {
+        match frame.strip_prefix(HANDSHAKE_MAGIC)? {
+            [id, payload @ ..] => Some((Codec::from_id(*id).unwrap_or_default(), payload)),
+            [] => None,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::example_interface::ExampleInterfaceRequest;
+    use crate::platform::Message;
+    use tarpc::{context::Context, ClientMessage, Request};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut context = Context::current();
+        context.discard_response = true;
+        let message = Message {
+            item: ClientMessage::Request(Request {
+                context,
+                id: 7,
+                message: ExampleInterfaceRequest::ReqCnt {},
+            }),
+            acked_handles: vec![3],
+            pid: 42,
+        };
+
+        for codec in [Codec::Json, Codec::MessagePack] {
+            let data = codec.serialize(&message).unwrap();
+            let decoded: Message<ClientMessage<ExampleInterfaceRequest>> =
+                codec.deserialize(&data).unwrap();
+            assert_eq!(decoded.acked_handles, vec![3]);
+            assert_eq!(decoded.pid, 42);
+            match decoded.item {
+                ClientMessage::Request(request) => {
+                    assert_eq!(request.id, 7);
+                    assert!(request.context.discard_response);
+                    assert!(matches!(
+                        request.message,
+                        ExampleInterfaceRequest::ReqCnt {}
+                    ));
+                }
+                _ => panic!("shouldn't happen"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        for codec in [Codec::Json, Codec::MessagePack] {
+            assert_eq!(Codec::from_handshake(&codec.handshake()), Some(codec));
+        }
+        assert_eq!(Codec::from_handshake(b"\0DDIPC\xff"), Some(Codec::Json));
+        assert_eq!(Codec::from_handshake(b"\0DDIPC"), None);
+        assert_eq!(
+            Codec::parse_handshake(b"\0DDIPC\x01{}"),
+            Some((Codec::MessagePack, &b"{}"[..]))
+        );
+        assert_eq!(Codec::from_handshake(b"{\"item\":{}}"), None);
+        assert_eq!(
+            Codec::from_handshake(&Codec::Json.serialize(&"\0DDIPC").unwrap()),
+            None
+        );
+    }
+}
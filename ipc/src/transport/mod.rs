@@ -2,23 +2,25 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 pub mod blocking;
+mod codec;
+mod protocol;
+pub mod reconnecting;
+
+pub use codec::Codec;
+pub use protocol::{Protocol, Versioned};
 
 use std::{
     io,
+    marker::PhantomData,
     pin::Pin,
     sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
-// TODO keep Json for now, however MessagePack seems to fail at deserialization
-
+use bytes::{Bytes, BytesMut};
+use futures::{ready, Sink, SinkExt, Stream, StreamExt};
 use pin_project::pin_project;
-use tokio_serde::formats::Json;
-
-use tokio_serde::Framed as SerdeFramed;
-
-use futures::{Sink, Stream};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Serialize};
 
 use tokio_util::codec::Framed;
 use tokio_util::codec::LengthDelimitedCodec;
@@ -27,111 +29,156 @@ use super::{
     handles::TransferHandles,
     platform::{metadata::ChannelMetadata, AsyncChannel, Channel, Message},
 };
-
-pub type DefaultCodec<Item, SinkItem> = Json<Item, SinkItem>;
-
-type DefaultSerdeFramed<Item, SinkItem> = SerdeFramed<
-    Framed<AsyncChannel, LengthDelimitedCodec>,
-    Message<Item>,
-    Message<SinkItem>,
-    DefaultCodec<Message<Item>, Message<SinkItem>>,
->;
+use protocol::{handshake_frame, HandshakeReply};
 
 /// A transport that serializes to, and deserializes from, a byte stream.
 #[pin_project]
 pub struct Transport<Item, SinkItem> {
     #[pin]
-    inner: DefaultSerdeFramed<Item, SinkItem>,
+    inner: Framed<AsyncChannel, LengthDelimitedCodec>,
+    codec: Codec,
+    /// The first frame of a client which did not start with a handshake
+    pending: Option<BytesMut>,
+    peer_protocol: Option<Protocol>,
 
     channel_metadata: Arc<Mutex<ChannelMetadata>>,
+    _items: PhantomData<fn(SinkItem) -> Item>,
 }
 
 impl<Item, SinkItem> Transport<Item, SinkItem> {
     /// Returns the inner transport over which messages are sent and received.
     pub fn get_ref(&self) -> &AsyncChannel {
-        self.inner.get_ref().get_ref()
+        self.inner.get_ref()
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// The protocol the client announced, if accepted with accept_versioned
+    pub fn peer_protocol(&self) -> Option<&Protocol> {
+        self.peer_protocol.as_ref()
+    }
+
+    /// Server side of a connection: answers the handshake of the client if it sent one, and uses
+    /// Json otherwise
+    pub async fn accept(channel: AsyncChannel) -> io::Result<Self> {
+        Self::handshake(channel, None).await
+    }
+
+    /// Like accept, also refusing the clients which announce an incompatible protocol, with an
+    /// Unsupported error. Clients which don't announce any predate the protocol handshake and
+    /// are served.
+    pub async fn accept_versioned(channel: AsyncChannel, protocol: &Protocol) -> io::Result<Self> {
+        Self::handshake(channel, Some(protocol)).await
+    }
+
+    async fn handshake(channel: AsyncChannel, protocol: Option<&Protocol>) -> io::Result<Self> {
+        let mut transport = Self::new(channel, Codec::Json);
+        let frame = match transport.inner.next().await.transpose()? {
+            Some(frame) => frame,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        let (codec, payload) = match Codec::parse_handshake(&frame) {
+            Some(handshake) => handshake,
+            None => {
+                transport.pending = Some(frame);
+                return Ok(transport);
+            }
+        };
+        let mut rejection = None;
+        let reply = match protocol {
+            Some(protocol) if !payload.is_empty() => {
+                let client: Protocol = serde_json::from_slice(payload)?;
+                match protocol.check_client(&client) {
+                    Ok(()) => {
+                        transport.peer_protocol = Some(client);
+                        Some(HandshakeReply::Accepted(protocol.clone()))
+                    }
+                    Err(e) => {
+                        let reply = HandshakeReply::Rejected(e.to_string());
+                        rejection = Some(e);
+                        Some(reply)
+                    }
+                }
+            }
+            // clients only announcing a codec expect nothing else
+            _ => None,
+        };
+        let frame = handshake_frame(codec, reply.as_ref())?;
+        transport.inner.send(Bytes::from(frame)).await?;
+        if let Some(e) = rejection {
+            return Err(e);
+        }
+        transport.codec = codec;
+        Ok(transport)
+    }
+
+    fn new(channel: AsyncChannel, codec: Codec) -> Self {
+        let channel_metadata = channel.metadata.clone();
+        Transport {
+            inner: Framed::new(channel, LengthDelimitedCodec::new()),
+            codec,
+            pending: None,
+            peer_protocol: None,
+            channel_metadata,
+            _items: PhantomData,
+        }
     }
 }
 
-impl<CodecError, Item, SinkItem> Stream for Transport<Item, SinkItem>
+impl<Item, SinkItem> Stream for Transport<Item, SinkItem>
 where
-    Item: for<'a> Deserialize<'a> + TransferHandles,
-    CodecError: Into<Box<dyn std::error::Error + Send + Sync>>,
-    DefaultSerdeFramed<Item, SinkItem>: Stream<Item = Result<Message<Item>, CodecError>>,
+    Item: DeserializeOwned + TransferHandles,
 {
     type Item = io::Result<Item>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Item>>> {
         let this = self.project();
-        this.inner
-            .poll_next(cx)
-            .map(|res| match res {
-                Some(Ok(message)) => Some(
-                    this.channel_metadata
-                        .lock()
-                        .unwrap()
-                        .unwrap_message(message)
-                        .map_err(Into::into),
-                ),
-                Some(Err(e)) => Some(Err(e.into())),
-                None => None,
-            })
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        let frame = match this.pending.take() {
+            Some(frame) => frame,
+            None => match ready!(this.inner.poll_next(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            },
+        };
+        let codec = *this.codec;
+        Poll::Ready(Some(codec.deserialize(&frame).and_then(
+            |message: Message<Item>| {
+                this.channel_metadata
+                    .lock()
+                    .unwrap()
+                    .unwrap_message(message)
+            },
+        )))
     }
 }
 
-impl<CodecError, Item, SinkItem> Sink<SinkItem> for Transport<Item, SinkItem>
+impl<Item, SinkItem> Sink<SinkItem> for Transport<Item, SinkItem>
 where
     SinkItem: Serialize + TransferHandles,
-    CodecError: Into<Box<dyn std::error::Error + Send + Sync>>,
-    DefaultSerdeFramed<Item, SinkItem>: Sink<Message<SinkItem>, Error = CodecError>,
 {
     type Error = io::Error;
 
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.project()
-            .inner
-            .poll_ready(cx)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        self.project().inner.poll_ready(cx)
     }
 
     fn start_send(self: Pin<&mut Self>, item: SinkItem) -> io::Result<()> {
         let this = self.project();
         let message = this.channel_metadata.lock().unwrap().create_message(item)?;
+        let data = this.codec.serialize(&message)?;
 
-        this.inner
-            .start_send(message)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        this.inner.start_send(Bytes::from(data))
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.project()
-            .inner
-            .poll_flush(cx)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        self.project().inner.poll_flush(cx)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.project()
-            .inner
-            .poll_close(cx)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-    }
-}
-
-/// Constructs a new transport from a framed transport and a serialization codec.
-fn new<Item, SinkItem>(
-    io: AsyncChannel,
-    codec: DefaultCodec<Message<Item>, Message<SinkItem>>,
-) -> Transport<Item, SinkItem>
-where
-    Item: for<'de> Deserialize<'de>,
-    SinkItem: Serialize,
-{
-    let channel_metadata = io.metadata.clone();
-    Transport {
-        inner: SerdeFramed::new(Framed::new(io, LengthDelimitedCodec::new()), codec),
-        channel_metadata,
+        self.project().inner.poll_close(cx)
     }
 }
 
@@ -139,18 +186,18 @@ pub type SymmetricalTransport<T> = Transport<T, T>;
 
 impl<Item, SinkItem> From<AsyncChannel> for Transport<Item, SinkItem>
 where
-    Item: for<'de> Deserialize<'de> + TransferHandles,
+    Item: DeserializeOwned + TransferHandles,
     SinkItem: Serialize + TransferHandles,
 {
+    /// Without handshake, the peer must use Json
     fn from(channel: AsyncChannel) -> Self {
-        let codec = DefaultCodec::default();
-        new(channel, codec)
+        Self::new(channel, Codec::Json)
     }
 }
 
 impl<Item, SinkItem> TryFrom<Channel> for Transport<Item, SinkItem>
 where
-    Item: for<'de> Deserialize<'de> + TransferHandles,
+    Item: DeserializeOwned + TransferHandles,
     SinkItem: Serialize + TransferHandles,
 {
     type Error = <AsyncChannel as TryFrom<Channel>>::Error;
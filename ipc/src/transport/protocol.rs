@@ -0,0 +1,192 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::Codec;
+
+/// Describes the interface of which the type is the requests, implemented by
+/// `#[datadog_ipc_macros::protocol]`
+pub trait Versioned {
+    const PROTOCOL_NAME: &'static str;
+    /// Bumped on the changes breaking the compatibility of the requests or the responses
+    const PROTOCOL_VERSION: u32;
+    /// Derived from the required requests: interfaces announced with the same version but
+    /// different signatures are not compatible, the version was not bumped
+    const PROTOCOL_SIGNATURE: u64;
+    /// The optional requests, which servers of the same version may not handle
+    const CAPABILITIES: &'static [&'static str];
+
+    /// The capability the server must have to handle the request, None if all servers do
+    fn capability(&self) -> Option<&'static str>;
+}
+
+/// The protocol a peer speaks, announced along with the codec in the handshake
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Protocol {
+    pub name: String,
+    pub version: u32,
+    #[serde(default)]
+    pub signature: u64,
+    pub capabilities: Vec<String>,
+}
+
+impl Protocol {
+    pub fn of<T: Versioned>() -> Self {
+        Protocol {
+            name: T::PROTOCOL_NAME.to_owned(),
+            version: T::PROTOCOL_VERSION,
+            signature: T::PROTOCOL_SIGNATURE,
+            capabilities: T::CAPABILITIES.iter().map(|c| (*c).to_owned()).collect(),
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Clients must speak the same version of the interface as the server, only the optional
+    /// requests may differ
+    pub fn check_client(&self, client: &Protocol) -> io::Result<()> {
+        if self.name != client.name || self.version != client.version {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Incompatible IPC protocol: the client speaks {} version {}, the server {} version {}",
+                    client.name, client.version, self.name, self.version
+                ),
+            ));
+        }
+        if self.signature != client.signature {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Incompatible IPC protocol: the client and the server speak different interfaces as {} version {}",
+                    self.name, self.version
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Answer of the servers checking the protocol of the clients announcing one
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum HandshakeReply {
+    Accepted(Protocol),
+    Rejected(String),
+}
+
+impl HandshakeReply {
+    /// The protocol of the server, None if it does not check protocols
+    pub(crate) fn parse(payload: &[u8]) -> io::Result<Option<Protocol>> {
+        if payload.is_empty() {
+            return Ok(None);
+        }
+        match serde_json::from_slice(payload)? {
+            HandshakeReply::Accepted(protocol) => Ok(Some(protocol)),
+            HandshakeReply::Rejected(reason) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, reason))
+            }
+        }
+    }
+}
+
+/// The handshake frame of the codec, followed by the payload in json whatever the codec is
+pub(crate) fn handshake_frame<T: Serialize>(
+    codec: Codec,
+    payload: Option<&T>,
+) -> io::Result<Vec<u8>> {
+    let mut frame = codec.handshake();
+    if let Some(payload) = payload {
+        frame.extend(serde_json::to_vec(payload)?);
+    }
+    Ok(frame)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::example_interface::ExampleInterfaceRequest;
+    use crate::platform::ring_buffer::RingBufferHandle;
+
+    #[test]
+    fn test_generated_protocol() {
+        let protocol = Protocol::of::<ExampleInterfaceRequest>();
+        assert_eq!(protocol.name, "ExampleInterface");
+        assert_eq!(protocol.version, 1);
+        assert_eq!(protocol.capabilities, vec!["read_ring_buffer"]);
+
+        assert_eq!(ExampleInterfaceRequest::Ping {}.capability(), None);
+        let request = ExampleInterfaceRequest::ReadRingBuffer {
+            ring_buffer: RingBufferHandle::new(100).unwrap(),
+            messages: 1,
+        };
+        assert_eq!(request.capability(), Some("read_ring_buffer"));
+    }
+
+    #[test]
+    fn test_check_client() {
+        let server = Protocol::of::<ExampleInterfaceRequest>();
+        let client = Protocol {
+            capabilities: vec![],
+            ..server.clone()
+        };
+        server.check_client(&client).unwrap();
+
+        let client = Protocol {
+            version: 2,
+            ..server.clone()
+        };
+        let err = server.check_client(&client).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "Incompatible IPC protocol: the client speaks ExampleInterface version 2, the server ExampleInterface version 1"
+        );
+
+        let client = Protocol {
+            name: "OtherInterface".into(),
+            ..server.clone()
+        };
+        assert!(server.check_client(&client).is_err());
+
+        // an interface changed without bumping its version
+        let client = Protocol {
+            signature: server.signature ^ 1,
+            ..server.clone()
+        };
+        let err = server.check_client(&client).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "Incompatible IPC protocol: the client and the server speak different interfaces as ExampleInterface version 1"
+        );
+    }
+
+    #[test]
+    fn test_handshake_reply() {
+        let server = Protocol::of::<ExampleInterfaceRequest>();
+        let frame = handshake_frame(
+            Codec::MessagePack,
+            Some(&HandshakeReply::Accepted(server.clone())),
+        )
+        .unwrap();
+        let (codec, payload) = Codec::parse_handshake(&frame).unwrap();
+        assert_eq!(codec, Codec::MessagePack);
+        assert_eq!(HandshakeReply::parse(payload).unwrap(), Some(server));
+
+        let frame =
+            handshake_frame(Codec::Json, Some(&HandshakeReply::Rejected("no".into()))).unwrap();
+        let (_, payload) = Codec::parse_handshake(&frame).unwrap();
+        let err = HandshakeReply::parse(payload).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(err.to_string(), "no");
+
+        let frame = handshake_frame::<Protocol>(Codec::Json, None).unwrap();
+        assert_eq!(frame, Codec::Json.handshake());
+        assert_eq!(HandshakeReply::parse(&[]).unwrap(), None);
+    }
+}
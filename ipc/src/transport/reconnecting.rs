@@ -0,0 +1,563 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::handles::TransferHandles;
+
+use super::blocking::BlockingTransport;
+
+pub const DEFAULT_MAX_PENDING: usize = 100;
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+type Connect<IncomingItem, OutgoingItem> =
+    Arc<dyn Fn() -> io::Result<BlockingTransport<IncomingItem, OutgoingItem>> + Send + Sync>;
+/// Builds the message, told whether it was already sent to a previous server
+type StateItem<OutgoingItem> = Arc<dyn Fn(bool) -> OutgoingItem + Send + Sync>;
+
+struct State<OutgoingItem> {
+    key: String,
+    item: StateItem<OutgoingItem>,
+    sent: bool,
+}
+
+impl<OutgoingItem> Clone for State<OutgoingItem> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            item: self.item.clone(),
+            sent: self.sent,
+        }
+    }
+}
+
+/// Connects again when the server went away, e.g. restarted. The messages describing the state of
+/// the client are replayed to the new server, and messages sent while the server can't be reached
+/// are buffered, up to a limit, until it can be again.
+///
+/// The connection attempts made while sending are spaced out exponentially, up to a maximum
+/// interval.
+pub struct ReconnectingTransport<IncomingItem, OutgoingItem> {
+    connect: Connect<IncomingItem, OutgoingItem>,
+    transport: Option<BlockingTransport<IncomingItem, OutgoingItem>>,
+    state: Vec<State<OutgoingItem>>,
+    pending: VecDeque<OutgoingItem>,
+    max_pending: usize,
+    retry_interval: Duration,
+    max_retry_interval: Duration,
+    // until the next attempt after a failed one
+    backoff: Duration,
+    next_attempt: Instant,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl<IncomingItem, OutgoingItem> Clone for ReconnectingTransport<IncomingItem, OutgoingItem> {
+    /// The clone shares the connection until it breaks, but buffers its own messages
+    fn clone(&self) -> Self {
+        Self {
+            connect: self.connect.clone(),
+            transport: self.transport.clone(),
+            state: self.state.clone(),
+            pending: VecDeque::new(),
+            max_pending: self.max_pending,
+            retry_interval: self.retry_interval,
+            max_retry_interval: self.max_retry_interval,
+            backoff: self.backoff,
+            next_attempt: self.next_attempt,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+        }
+    }
+}
+
+impl<IncomingItem, OutgoingItem> ReconnectingTransport<IncomingItem, OutgoingItem>
+where
+    OutgoingItem: Serialize + TransferHandles,
+    IncomingItem: DeserializeOwned + TransferHandles,
+{
+    /// `connect` establishes the connections after `transport` is lost. As it is called while
+    /// sending, it should not do more than connecting, e.g. not start a server.
+    pub fn new<F>(transport: BlockingTransport<IncomingItem, OutgoingItem>, connect: F) -> Self
+    where
+        F: Fn() -> io::Result<BlockingTransport<IncomingItem, OutgoingItem>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        ReconnectingTransport {
+            connect: Arc::new(connect),
+            transport: Some(transport),
+            state: Vec::new(),
+            pending: VecDeque::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            max_retry_interval: DEFAULT_MAX_RETRY_INTERVAL,
+            backoff: DEFAULT_RETRY_INTERVAL,
+            next_attempt: Instant::now(),
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    /// Maximum number of messages buffered while disconnected, sends then fail
+    pub fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending;
+    }
+
+    /// Time between the first two connection attempts, doubling after each failed attempt
+    pub fn set_retry_interval(&mut self, retry_interval: Duration) {
+        self.retry_interval = retry_interval;
+        self.backoff = retry_interval;
+    }
+
+    /// Maximum time between two connection attempts
+    pub fn set_max_retry_interval(&mut self, max_retry_interval: Duration) {
+        self.max_retry_interval = max_retry_interval;
+    }
+
+    /// Applies to the current connection and the ones established later
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        match self.transport {
+            Some(ref mut transport) => transport.set_read_timeout(timeout),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies to the current connection and the ones established later
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.write_timeout = timeout;
+        match self.transport {
+            Some(ref mut transport) => transport.set_write_timeout(timeout),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the connection to the server is lost, without trying to connect again
+    pub fn is_closed(&self) -> bool {
+        match self.transport {
+            Some(ref transport) => transport.is_closed(),
+            None => true,
+        }
+    }
+
+    /// Whether the server can be reached, connecting again if it is time to
+    pub fn is_connected(&mut self) -> bool {
+        if !self.is_closed() {
+            return true;
+        }
+        self.disconnected();
+
+        let now = Instant::now();
+        if now < self.next_attempt {
+            return false;
+        }
+        let connect = self.connect.clone();
+        match self.establish(&*connect) {
+            Ok(()) => true,
+            Err(_) => {
+                self.next_attempt = now + self.backoff;
+                self.backoff = (self.backoff * 2).min(self.max_retry_interval);
+                false
+            }
+        }
+    }
+
+    /// Connects with `connect` rather than the factory the transport was created with, e.g. to
+    /// start the server again, unless still connected. Not subject to the retry interval.
+    pub fn reconnect_with<F>(&mut self, connect: F) -> io::Result<()>
+    where
+        F: Fn() -> io::Result<BlockingTransport<IncomingItem, OutgoingItem>>,
+    {
+        if !self.is_closed() {
+            return Ok(());
+        }
+        self.disconnected();
+        self.establish(&connect)
+    }
+
+    /// Forgets the closed connection. The first attempt to replace it is made right away.
+    fn disconnected(&mut self) {
+        if self.transport.take().is_some() {
+            self.next_attempt = Instant::now();
+            self.backoff = self.retry_interval;
+        }
+    }
+
+    /// Connects, then replays the state and the pending messages
+    fn establish(
+        &mut self,
+        connect: &dyn Fn() -> io::Result<BlockingTransport<IncomingItem, OutgoingItem>>,
+    ) -> io::Result<()> {
+        let mut transport = connect()?;
+        transport.set_read_timeout(self.read_timeout)?;
+        transport.set_write_timeout(self.write_timeout)?;
+        for state in self.state.iter_mut() {
+            replay(&mut transport, (state.item)(state.sent))?;
+            state.sent = true;
+        }
+        while let Some(item) = self.pending.pop_front() {
+            match transport.try_send(item) {
+                Ok(()) => {}
+                // the messages the new server does not handle are left out
+                Err((e, _)) if e.kind() == io::ErrorKind::Unsupported => {}
+                Err((e, item)) => {
+                    if let Some(item) = item {
+                        self.pending.push_front(item);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.transport = Some(transport);
+        self.backoff = self.retry_interval;
+        Ok(())
+    }
+
+    fn buffer(&mut self, item: OutgoingItem) -> io::Result<()> {
+        if self.pending.len() >= self.max_pending {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.pending.push_back(item);
+        Ok(())
+    }
+
+    /// Buffers the message when the server went away, including when this is only noticed while
+    /// sending it
+    pub fn send(&mut self, item: OutgoingItem) -> io::Result<()> {
+        if !self.is_connected() {
+            return self.buffer(item);
+        }
+        let transport = match self.transport {
+            Some(ref mut transport) => transport,
+            None => return self.buffer(item),
+        };
+        match transport.try_send(item) {
+            Ok(()) => Ok(()),
+            Err((e, item)) if is_disconnection(&e) || transport.is_closed() => {
+                self.disconnected();
+                match item {
+                    Some(item) => self.buffer(item),
+                    None => Err(e),
+                }
+            }
+            Err((e, _)) => Err(e),
+        }
+    }
+
+    /// Calls are not buffered, they fail while disconnected
+    pub fn call(&mut self, item: OutgoingItem) -> io::Result<IncomingItem> {
+        if !self.is_connected() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.with_transport(|transport| transport.call(item))
+    }
+
+    fn with_transport<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut BlockingTransport<IncomingItem, OutgoingItem>) -> io::Result<T>,
+    {
+        let transport = match self.transport {
+            Some(ref mut transport) => transport,
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        };
+        let result = f(transport);
+        if result.is_err() && transport.is_closed() {
+            self.disconnected();
+        }
+        result
+    }
+
+    /// Sends the message built by `item`, which is sent again to the servers connected to later.
+    /// `item` is told whether the message was already sent to a previous server. Replaces the
+    /// message previously registered with the same key, keeping its place in the order of the
+    /// replayed messages. Messages the server does not handle are not registered.
+    pub fn send_state<F>(&mut self, key: String, item: F) -> io::Result<()>
+    where
+        F: Fn(bool) -> OutgoingItem + Send + Sync + 'static,
+    {
+        // a new connection replays the state known so far
+        let item: StateItem<OutgoingItem> = Arc::new(item);
+        let mut result = Ok(());
+        let mut sent = false;
+        if self.is_connected() {
+            match self.with_transport(|transport| transport.send(item(false))) {
+                Ok(()) => sent = true,
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => return Err(e),
+                // sent once connected again
+                Err(e) if is_disconnection(&e) || self.is_closed() => self.disconnected(),
+                Err(e) => result = Err(e),
+            }
+        }
+        let state = State { key, item, sent };
+        match self
+            .state
+            .iter_mut()
+            .find(|existing| existing.key == state.key)
+        {
+            Some(existing) => *existing = state,
+            None => self.state.push(state),
+        }
+        result
+    }
+
+    /// Stops replaying the messages of which the key starts with `prefix`
+    pub fn forget_state(&mut self, prefix: &str) {
+        self.state.retain(|state| !state.key.starts_with(prefix));
+    }
+
+    /// Access to the current connection, e.g. to set timeouts
+    pub fn transport(&mut self) -> Option<&mut BlockingTransport<IncomingItem, OutgoingItem>> {
+        self.transport.as_mut()
+    }
+}
+
+fn is_disconnection(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
+/// The messages the new server does not handle are left out
+fn replay<IncomingItem, OutgoingItem>(
+    transport: &mut BlockingTransport<IncomingItem, OutgoingItem>,
+    item: OutgoingItem,
+) -> io::Result<()>
+where
+    OutgoingItem: Serialize + TransferHandles,
+    IncomingItem: DeserializeOwned + TransferHandles,
+{
+    match transport.send(item) {
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream as StdUnixStream;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use tokio::{net::UnixStream, runtime, task::JoinHandle};
+
+    use super::*;
+    use crate::example_interface::{
+        ExampleInterfaceRequest, ExampleInterfaceResponse, ExampleServer, ExampleTransport,
+    };
+
+    fn req_cnt(
+        transport: &mut ReconnectingTransport<ExampleInterfaceResponse, ExampleInterfaceRequest>,
+    ) -> u32 {
+        match transport.call(ExampleInterfaceRequest::ReqCnt {}).unwrap() {
+            ExampleInterfaceResponse::ReqCnt(cnt) => cnt,
+            _ => panic!("shouldn't happen"),
+        }
+    }
+
+    fn stop_server(
+        transport: &mut ReconnectingTransport<ExampleInterfaceResponse, ExampleInterfaceRequest>,
+        servers: &Mutex<Vec<JoinHandle<()>>>,
+    ) {
+        let connection = transport.transport().unwrap().clone();
+        servers.lock().unwrap().pop().unwrap().abort();
+        while !connection.is_closed() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Connects to servers handling the requests in order, unlike the multi thread runtime
+    struct TestServers {
+        down: Arc<AtomicBool>,
+        // the next connection is half closed by the server, which can't read it
+        broken: Arc<AtomicBool>,
+        connections: Arc<AtomicUsize>,
+        servers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    }
+
+    impl TestServers {
+        fn start() -> (
+            Self,
+            impl Fn() -> io::Result<ExampleTransport> + Send + Sync,
+        ) {
+            let rt = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let handle = rt.handle().clone();
+            std::thread::spawn(move || rt.block_on(std::future::pending::<()>()));
+            let server = ExampleServer::default();
+            let servers = TestServers {
+                down: Default::default(),
+                broken: Default::default(),
+                connections: Default::default(),
+                servers: Default::default(),
+            };
+
+            let connect = {
+                let down = servers.down.clone();
+                let broken = servers.broken.clone();
+                let connections = servers.connections.clone();
+                let servers = servers.servers.clone();
+                move || -> io::Result<ExampleTransport> {
+                    if down.load(Ordering::SeqCst) {
+                        return Err(io::ErrorKind::ConnectionRefused.into());
+                    }
+                    let (sock_a, sock_b) = StdUnixStream::pair()?;
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    if broken.swap(false, Ordering::SeqCst) {
+                        sock_a.shutdown(Shutdown::Read)?;
+                        servers.lock().unwrap().push(handle.spawn(async move {
+                            let _sock_a = sock_a;
+                            std::future::pending::<()>().await
+                        }));
+                        return Ok(ExampleTransport::from(sock_b));
+                    }
+                    sock_a.set_nonblocking(true)?;
+                    let _g = handle.enter();
+                    let socket = UnixStream::from_std(sock_a)?;
+                    servers
+                        .lock()
+                        .unwrap()
+                        .push(handle.spawn(server.clone().accept_connection(socket)));
+                    Ok(ExampleTransport::from(sock_b))
+                }
+            };
+            (servers, connect)
+        }
+
+        fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_reconnect_and_replay() {
+        let (test_servers, connect) = TestServers::start();
+        let server_down = test_servers.down.clone();
+        let servers = test_servers.servers.clone();
+        let mut transport = ReconnectingTransport::new(connect().unwrap(), connect);
+        transport.set_max_pending(2);
+        transport.set_retry_interval(Duration::ZERO);
+
+        transport
+            .send_state("ping".into(), |_| ExampleInterfaceRequest::Ping {})
+            .unwrap();
+        assert_eq!(req_cnt(&mut transport), 1);
+
+        server_down.store(true, Ordering::SeqCst);
+        stop_server(&mut transport, &servers);
+
+        transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+        transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+        assert_eq!(
+            transport
+                .send(ExampleInterfaceRequest::Notify {})
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotConnected
+        );
+        assert_eq!(
+            transport
+                .call(ExampleInterfaceRequest::ReqCnt {})
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        // the state and the pending messages are sent before the new message
+        server_down.store(false, Ordering::SeqCst);
+        transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+        assert_eq!(req_cnt(&mut transport), 6);
+        assert_eq!(test_servers.connections(), 2);
+
+        transport.forget_state("pi");
+        stop_server(&mut transport, &servers);
+        assert_eq!(req_cnt(&mut transport), 7);
+        assert_eq!(test_servers.connections(), 3);
+    }
+
+    #[test]
+    fn test_backoff_and_is_closed() {
+        let (test_servers, connect) = TestServers::start();
+        let mut transport = ReconnectingTransport::new(connect().unwrap(), connect);
+        transport.set_retry_interval(Duration::from_secs(10));
+        transport.set_max_retry_interval(Duration::from_secs(30));
+
+        test_servers.down.store(true, Ordering::SeqCst);
+        stop_server(&mut transport, &test_servers.servers);
+        // only noticed, no connection attempt
+        assert!(transport.is_closed());
+        assert_eq!(test_servers.connections(), 1);
+
+        for backoff in [20, 30, 30] {
+            assert!(!transport.is_connected());
+            assert_eq!(transport.backoff, Duration::from_secs(backoff));
+            // not before the interval elapsed
+            assert!(!transport.is_connected());
+            assert_eq!(transport.backoff, Duration::from_secs(backoff));
+            transport.next_attempt = Instant::now();
+        }
+
+        test_servers.down.store(false, Ordering::SeqCst);
+        assert!(transport.is_connected());
+        assert_eq!(transport.backoff, Duration::from_secs(10));
+        assert_eq!(test_servers.connections(), 2);
+    }
+
+    #[test]
+    fn test_send_buffers_on_broken_connections() {
+        let (test_servers, connect) = TestServers::start();
+        test_servers.broken.store(true, Ordering::SeqCst);
+        let mut transport = ReconnectingTransport::new(connect().unwrap(), connect);
+        transport.set_retry_interval(Duration::ZERO);
+
+        // the connection looks fine until written to
+        assert!(!transport.is_closed());
+        transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+        assert!(transport.is_closed());
+        assert_eq!(transport.pending.len(), 1);
+
+        // the pending message is kept when it can't be replayed
+        test_servers.broken.store(true, Ordering::SeqCst);
+        transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+        assert_eq!(transport.pending.len(), 2);
+        assert_eq!(test_servers.connections(), 2);
+
+        let resent = Arc::new(Mutex::new(vec![]));
+        {
+            let resent = resent.clone();
+            transport
+                .send_state("ping".into(), move |sent| {
+                    resent.lock().unwrap().push(sent);
+                    ExampleInterfaceRequest::Ping {}
+                })
+                .unwrap();
+        }
+        assert!(transport.pending.is_empty());
+        assert_eq!(*resent.lock().unwrap(), vec![false]);
+        assert_eq!(req_cnt(&mut transport), 3);
+
+        stop_server(&mut transport, &test_servers.servers);
+        transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+        assert_eq!(*resent.lock().unwrap(), vec![false, true]);
+        assert_eq!(req_cnt(&mut transport), 6);
+        assert_eq!(test_servers.connections(), 4);
+    }
+}
@@ -382,6 +382,13 @@ pub struct ServerError {
     pub detail: String,
 }
 
+impl ServerError {
+    /// Returns a new server error with `kind` and `detail`.
+    pub fn new(kind: io::ErrorKind, detail: String) -> ServerError {
+        ServerError { kind, detail }
+    }
+}
+
 impl<T> Request<T> {
     /// Returns the deadline for this request.
     pub fn deadline(&self) -> &SystemTime {
@@ -11,7 +11,7 @@ use crate::context::SpanExt;
 use crate::{
     cancellations::{cancellations, CanceledRequests, RequestCancellation},
     context::{self},
-    trace, ClientMessage, Request, Response, Transport,
+    trace, ClientMessage, Request, Response, ServerError, Transport,
 };
 use ::tokio::sync::mpsc;
 use futures::{
@@ -790,6 +790,24 @@ impl<Req, Res> InFlightRequest<Req, Res> {
         // request data, so the request does not need to be canceled.
         response_guard.cancel = false;
     }
+
+    /// Answers the request with `error` without executing it, also when the client asked to
+    /// discard the response. Fails, cancelling the request, if the responses buffer is full.
+    pub fn reject(self, error: ServerError) -> Result<(), ServerError> {
+        let Self {
+            response_tx,
+            mut response_guard,
+            request,
+            ..
+        } = self;
+        let response = RequestResponse::Response(Response {
+            request_id: request.id,
+            message: Err(error.clone()),
+        });
+        response_tx.try_send(response).map_err(|_| error)?;
+        response_guard.cancel = false;
+        Ok(())
+    }
 }
 
 impl<C> Stream for Requests<C>
@@ -12,9 +12,20 @@ use tokio::{net::UnixStream, runtime};
 use datadog_ipc::example_interface::{
     ExampleInterfaceRequest, ExampleInterfaceResponse, ExampleServer, ExampleTransport,
 };
+use datadog_ipc::platform::ring_buffer::{OverflowPolicy, RingBufferHandle};
+use datadog_ipc::transport::Codec;
 
 #[test]
 fn test_blocking_client() {
+    run_blocking_client(None);
+}
+
+#[test]
+fn test_blocking_client_message_pack() {
+    run_blocking_client(Some(Codec::MessagePack));
+}
+
+fn run_blocking_client(codec: Option<Codec>) {
     let (sock_a, sock_b) = StdUnixStream::pair().unwrap();
     // Setup async server
     let rt = runtime::Builder::new_multi_thread()
@@ -35,6 +46,9 @@ fn test_blocking_client() {
 
     // Test blocking sync code
     let mut transport = ExampleTransport::from(sock_b);
+    if let Some(codec) = codec {
+        assert_eq!(codec, transport.negotiate_codec(codec).unwrap());
+    }
     transport.set_nonblocking(true).unwrap(); // sending one-way messages should be instantaineous, even if the RPC worker is not fully up
     transport.send(ExampleInterfaceRequest::Ping {}).unwrap();
     transport.set_nonblocking(false).unwrap(); // write should still be quick, but we'll have to block waiting for RPC worker to come up
@@ -58,6 +72,16 @@ fn test_blocking_client() {
         _ => panic!("shouldn't happen"),
     }
 
+    match transport
+        .call(ExampleInterfaceRequest::DataLen {
+            data: vec![0xff; 1000],
+        })
+        .unwrap()
+    {
+        ExampleInterfaceResponse::DataLen(len) => assert_eq!(1000, len),
+        _ => panic!("shouldn't happen"),
+    }
+
     let f = tempfile::tempfile().unwrap();
     transport
         .call(ExampleInterfaceRequest::StoreFile { file: f.into() })
@@ -72,4 +96,20 @@ fn test_blocking_client() {
     };
     let mut f = f.into_instance().unwrap();
     writeln!(f, "test").unwrap(); // file should still be writeable
+
+    let ring_buffer = RingBufferHandle::new(0x10000).unwrap();
+    let sender = ring_buffer.sender(OverflowPolicy::Reject).unwrap();
+    for i in 0..100 {
+        sender.push(&vec![i; i as usize]).unwrap();
+    }
+    match transport
+        .call(ExampleInterfaceRequest::ReadRingBuffer {
+            ring_buffer,
+            messages: 100,
+        })
+        .unwrap()
+    {
+        ExampleInterfaceResponse::ReadRingBuffer(len) => assert_eq!((0..100).sum::<usize>(), len),
+        _ => panic!("shouldn't happen"),
+    }
 }
@@ -0,0 +1,115 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+#![cfg(unix)]
+use std::{io, os::unix::net::UnixStream as StdUnixStream, sync::mpsc, time::Duration};
+
+use tarpc::server::{BaseChannel, Channel, Config};
+use tokio::{net::UnixStream, runtime};
+
+use datadog_ipc::example_interface::{
+    ExampleInterface, ExampleInterfaceRequest, ExampleInterfaceResponse, ExampleServer,
+    ExampleTransport,
+};
+use datadog_ipc::platform::{ring_buffer::RingBufferHandle, AsyncChannel};
+use datadog_ipc::transport::{Codec, Protocol, Transport};
+
+/// Serves a connection speaking `protocol`, reporting the outcome of the handshake
+fn start_server(protocol: Protocol) -> (ExampleTransport, mpsc::Receiver<io::Result<Protocol>>) {
+    let (sock_a, sock_b) = StdUnixStream::pair().unwrap();
+    let rt = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    sock_a.set_nonblocking(true).unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        rt.block_on(async move {
+            let socket = UnixStream::from_std(sock_a).unwrap();
+            let channel = AsyncChannel::from(socket);
+            let transport = match Transport::accept_versioned(channel, &protocol).await {
+                Ok(transport) => transport,
+                Err(e) => return tx.send(Err(e)).unwrap(),
+            };
+            tx.send(Ok(transport.peer_protocol().unwrap().clone()))
+                .unwrap();
+            let channel = BaseChannel::new(Config::default(), transport);
+            channel.execute(ExampleServer::default().serve()).await
+        })
+    });
+
+    let mut transport = ExampleTransport::from(sock_b);
+    transport
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    (transport, rx)
+}
+
+fn ring_buffer_request() -> ExampleInterfaceRequest {
+    ExampleInterfaceRequest::ReadRingBuffer {
+        ring_buffer: RingBufferHandle::new(0x1000).unwrap(),
+        messages: 0,
+    }
+}
+
+#[test]
+fn test_negotiate_protocol() {
+    let protocol = Protocol::of::<ExampleInterfaceRequest>();
+    let (mut transport, server) = start_server(protocol.clone());
+
+    assert_eq!(
+        Codec::MessagePack,
+        transport.negotiate_protocol(Codec::MessagePack).unwrap()
+    );
+    assert_eq!(Some(&protocol), transport.peer_protocol());
+    assert_eq!(protocol, server.recv().unwrap().unwrap());
+
+    assert!(transport.supports(&ring_buffer_request()));
+    match transport.call(ring_buffer_request()).unwrap() {
+        ExampleInterfaceResponse::ReadRingBuffer(len) => assert_eq!(0, len),
+        _ => panic!("shouldn't happen"),
+    }
+}
+
+#[test]
+fn test_reject_incompatible_client() {
+    let protocol = Protocol {
+        version: 2,
+        ..Protocol::of::<ExampleInterfaceRequest>()
+    };
+    let (mut transport, server) = start_server(protocol);
+
+    let err = transport.negotiate_protocol(Codec::Json).unwrap_err();
+    assert_eq!(io::ErrorKind::Unsupported, err.kind());
+    assert_eq!(
+        "Incompatible IPC protocol: the client speaks ExampleInterface version 1, the server ExampleInterface version 2",
+        err.to_string()
+    );
+    assert_eq!(
+        io::ErrorKind::Unsupported,
+        server.recv().unwrap().unwrap_err().kind()
+    );
+}
+
+#[test]
+fn test_missing_capability() {
+    let protocol = Protocol {
+        capabilities: vec![],
+        ..Protocol::of::<ExampleInterfaceRequest>()
+    };
+    let (mut transport, server) = start_server(protocol);
+
+    transport.negotiate_protocol(Codec::Json).unwrap();
+    server.recv().unwrap().unwrap();
+
+    // refused without being sent, the connection is still usable
+    assert!(!transport.supports(&ring_buffer_request()));
+    let err = transport.call(ring_buffer_request()).unwrap_err();
+    assert_eq!(io::ErrorKind::Unsupported, err.kind());
+    let err = transport.send(ring_buffer_request()).unwrap_err();
+    assert_eq!(io::ErrorKind::Unsupported, err.kind());
+    match transport.call(ExampleInterfaceRequest::ReqCnt {}).unwrap() {
+        ExampleInterfaceResponse::ReqCnt(cnt) => assert_eq!(0, cnt),
+        _ => panic!("shouldn't happen"),
+    }
+    assert!(!transport.is_closed());
+}
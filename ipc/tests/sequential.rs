@@ -0,0 +1,98 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+#![cfg(unix)]
+use std::{
+    io,
+    os::unix::{io::AsRawFd, net::UnixStream as StdUnixStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use nix::sys::socket::{recv, MsgFlags};
+use tarpc::server::{BaseChannel, Channel, Config, Serve};
+use tokio::{net::UnixStream, runtime};
+
+use datadog_ipc::example_interface::{
+    ExampleInterface, ExampleInterfaceRequest, ExampleServer, ExampleTransport,
+};
+use datadog_ipc::platform::AsyncChannel;
+use datadog_ipc::sequential::{execute_sequential, QueueFullPolicy};
+use datadog_ipc::transport::Transport;
+
+/// Serves a connection handling one request at a time, with room for one more in the queue. The
+/// client socket is returned as well, to wait for the responses without reading them.
+fn start_server(policy: QueueFullPolicy) -> (ExampleTransport, StdUnixStream, Arc<AtomicU64>) {
+    let (sock_a, sock_b) = StdUnixStream::pair().unwrap();
+    let client_socket = sock_b.try_clone().unwrap();
+    let rt = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    sock_a.set_nonblocking(true).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let (handling_tx, handling_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        rt.block_on(async move {
+            let socket = UnixStream::from_std(sock_a).unwrap();
+            let transport = Transport::accept(AsyncChannel::from(socket)).await.unwrap();
+            let channel = BaseChannel::new(Config::default(), transport);
+            let server = ExampleServer::default().serve();
+            let serve = move |ctx, req| {
+                handling_tx.send(()).ok();
+                server.serve(ctx, req)
+            };
+            let mut executor = execute_sequential(channel.requests(), serve, 1);
+            executor.set_queue_full_policy(policy);
+            tx.send(executor.dropped_requests()).unwrap();
+            executor.await
+        })
+    });
+
+    let mut transport = ExampleTransport::from(sock_b);
+    transport
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    // the worker is blocked by its first request, never answered
+    transport.send(ExampleInterfaceRequest::Notify {}).unwrap();
+    let dropped = rx.recv().unwrap();
+    handling_rx.recv().unwrap();
+    // the requests are read in order, the next ones find the queue full
+    transport.send(ExampleInterfaceRequest::Ping {}).unwrap();
+    (transport, client_socket, dropped)
+}
+
+#[test]
+fn test_drop_and_notify_client() {
+    let (mut transport, client_socket, dropped) = start_server(QueueFullPolicy::Drop);
+
+    let err = transport
+        .call(ExampleInterfaceRequest::ReqCnt {})
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+    assert_eq!(1, transport.dropped_requests());
+
+    // the rejection of a one-way message is received before sending the next one
+    transport.send(ExampleInterfaceRequest::Ping {}).unwrap();
+    // wait for the rejection without reading it
+    recv(client_socket.as_raw_fd(), &mut [0], MsgFlags::MSG_PEEK).unwrap();
+    assert!(!transport.is_closed());
+    transport.send(ExampleInterfaceRequest::Ping {}).unwrap();
+    assert_eq!(2, transport.dropped_requests());
+    assert!(!transport.is_closed());
+    assert!(dropped.load(Ordering::Relaxed) >= 2);
+}
+
+#[test]
+fn test_block_reading() {
+    let (mut transport, _, dropped) = start_server(QueueFullPolicy::Block);
+
+    transport.send(ExampleInterfaceRequest::Ping {}).unwrap();
+    // neither read nor rejected while the queue is full
+    assert!(transport.call(ExampleInterfaceRequest::ReqCnt {}).is_err());
+    assert_eq!(0, transport.dropped_requests());
+    assert_eq!(0, dropped.load(Ordering::Relaxed));
+    assert!(!transport.is_closed());
+}
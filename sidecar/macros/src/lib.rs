@@ -2,7 +2,7 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, ToTokens};
 use syn::FnArg::Typed;
 use syn::__private::Span;
 use syn::{parse_quote, Arm, Ident, ItemTrait, Pat, TraitItem};
@@ -26,16 +26,31 @@ fn snake_to_camel(ident_str: &str) -> String {
     camel_ty
 }
 
+/// Implements RequestIdentification for the requests of the interface. The methods marked
+/// `#[SidecarOwner]` are identified as reserved to the user running the sidecar.
 #[proc_macro_attribute]
-pub fn extract_request_id(_attr: TokenStream, mut input: TokenStream) -> TokenStream {
-    let item: ItemTrait = syn::parse(input.clone()).unwrap();
+pub fn extract_request_id(_attr: TokenStream, input: TokenStream) -> TokenStream {
+    let mut item: ItemTrait = syn::parse(input).unwrap();
     let name = &format_ident!("{}Request", item.ident);
     let mut arms: Vec<Arm> = vec![];
-    for inner in item.items {
+    for inner in item.items.iter_mut() {
         if let TraitItem::Fn(func) = inner {
-            for any_arg in func.sig.inputs {
+            let orig_attr_num = func.attrs.len();
+            func.attrs
+                .retain(|attr| attr.meta.path().to_token_stream().to_string() != "SidecarOwner");
+            if orig_attr_num != func.attrs.len() {
+                let method = Ident::new(
+                    &snake_to_camel(&func.sig.ident.to_string()),
+                    Span::mixed_site(),
+                );
+                arms.push(parse_quote! {
+                    #name::#method { .. } => RequestIdentifier::SidecarOwner
+                });
+                continue;
+            }
+            for any_arg in func.sig.inputs.iter() {
                 if let Typed(arg) = any_arg {
-                    if let Pat::Ident(ident) = *arg.pat {
+                    if let Pat::Ident(ident) = &*arg.pat {
                         let matched_enum_type = match ident.ident.to_string().as_str() {
                             "session_id" => Some(format_ident!("SessionId")),
                             "instance_id" => Some(format_ident!("InstanceId")),
@@ -55,7 +70,8 @@ pub fn extract_request_id(_attr: TokenStream, mut input: TokenStream) -> TokenSt
             }
         }
     }
-    input.extend(TokenStream::from(quote! {
+    let mut output = item.into_token_stream();
+    output.extend(quote! {
         impl RequestIdentification for tarpc::Request<#name> {
             fn extract_identifier(&self) -> RequestIdentifier {
                 match &self.message {
@@ -66,6 +82,6 @@ pub fn extract_request_id(_attr: TokenStream, mut input: TokenStream) -> TokenSt
                 }
             }
         }
-    }));
-    input
+    });
+    output.into()
 }
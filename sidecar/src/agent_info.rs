@@ -0,0 +1,336 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! Features of the agents the sessions send data to, as advertised on their `/info` endpoint.
+//! The raw `/info` response is shared with the libraries through shared memory, the same way the
+//! remote configuration of the agent is.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use datadog_ipc::platform::NamedShmHandle;
+use ddcommon::{connector::Connector, Endpoint};
+use http::uri::PathAndQuery;
+use hyper::{Body, Client, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::agent_remote_config::{
+    self, shm_path_for_endpoint, AgentRemoteConfigReader, AgentRemoteConfigWriter,
+};
+
+const INFO_PATH: &str = "/info";
+const INFO_SHM_KIND: &str = "agent-info";
+const TRACES_V07_PATH: &str = "/v0.7/traces";
+const TRACES_V04_PATH: &str = "/v0.4/traces";
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentInfo {
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Paths of the endpoints the agent accepts data on
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub endpoints: Vec<String>,
+    /// Whether the agent accepts traces from which the unsampled chunks were dropped
+    #[serde(default)]
+    pub client_drop_p0s: bool,
+    #[serde(default)]
+    pub span_events: bool,
+    /// Tags to add to the stats computed for client spans
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub peer_tags: Vec<String>,
+}
+
+pub(crate) fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl AgentInfo {
+    pub fn supports(&self, path: &str) -> bool {
+        self.endpoints.iter().any(|endpoint| endpoint == path)
+    }
+}
+
+/// Reads the `/info` response of the agent at `endpoint`, as fetched by the sidecar
+pub fn new_reader(endpoint: &Endpoint) -> AgentRemoteConfigReader<NamedShmHandle> {
+    agent_remote_config::new_named_reader(shm_path_for_endpoint(INFO_SHM_KIND, endpoint))
+}
+
+/// The `/info` endpoint of the agent, None when sending to the intake
+fn info_endpoint(endpoint: &Endpoint) -> Option<Endpoint> {
+    if endpoint.api_key.is_some() || endpoint.url.authority().is_none() {
+        return None;
+    }
+    let mut parts = endpoint.url.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::from_static(INFO_PATH));
+    Some(Endpoint {
+        url: hyper::Uri::from_parts(parts).ok()?,
+        api_key: None,
+    })
+}
+
+/// The body of the `/info` response, None if the agent predates that endpoint
+async fn fetch_info(endpoint: &Endpoint) -> anyhow::Result<Option<Vec<u8>>> {
+    let req = hyper::Request::get(endpoint.url.clone())
+        .header(
+            hyper::header::USER_AGENT,
+            concat!("Sidecar/", env!("CARGO_PKG_VERSION")),
+        )
+        .body(Body::empty())?;
+    let response = tokio::time::timeout(
+        FETCH_TIMEOUT,
+        Client::builder().build(Connector::default()).request(req),
+    )
+    .await??;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => Ok(Some(
+            hyper::body::to_bytes(response.into_body()).await?.to_vec(),
+        )),
+        status => anyhow::bail!("Unexpected status {status}"),
+    }
+}
+
+struct AgentInfoFetcher {
+    sessions: HashSet<String>,
+    info: Arc<Mutex<Option<AgentInfo>>>,
+    task: JoinHandle<()>,
+}
+
+impl AgentInfoFetcher {
+    fn start(endpoint: Endpoint) -> Self {
+        let info = Arc::new(Mutex::new(None));
+        let task = tokio::spawn(Self::refresh(endpoint, info.clone()));
+        AgentInfoFetcher {
+            sessions: HashSet::new(),
+            info,
+            task,
+        }
+    }
+
+    /// Keeps the last known info if the agent can't be reached
+    async fn refresh(endpoint: Endpoint, info: Arc<Mutex<Option<AgentInfo>>>) {
+        let shm_path = shm_path_for_endpoint(INFO_SHM_KIND, &endpoint);
+        let writer: Option<AgentRemoteConfigWriter<NamedShmHandle>> =
+            match agent_remote_config::new_named_writer(shm_path) {
+                Ok(writer) => Some(writer),
+                Err(e) => {
+                    warn!("Can't share the info of the agent at {}: {e}", endpoint.url);
+                    None
+                }
+            };
+        let mut last_body = None;
+        loop {
+            match fetch_info(&endpoint).await {
+                Ok(Some(body)) => {
+                    if last_body.as_ref() != Some(&body) {
+                        match serde_json::from_slice::<AgentInfo>(&body) {
+                            Ok(new_info) => {
+                                info!(
+                                    "Agent at {} has version {:?}",
+                                    endpoint.url, new_info.version
+                                );
+                                *info.lock().unwrap() = Some(new_info);
+                                if let Some(ref writer) = writer {
+                                    writer.write(&body);
+                                }
+                                last_body = Some(body);
+                            }
+                            Err(e) => warn!("Invalid info of the agent at {}: {e}", endpoint.url),
+                        }
+                    }
+                }
+                Ok(None) => {
+                    debug!("Agent at {} has no info endpoint", endpoint.url);
+                    *info.lock().unwrap() = Some(AgentInfo::default());
+                }
+                Err(e) => debug!(
+                    "Error fetching the info of the agent at {}: {e}",
+                    endpoint.url
+                ),
+            }
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    }
+}
+
+/// The info of the agents used by the sessions, refreshed as long as a session uses the agent
+#[derive(Default, Clone)]
+pub struct AgentInfos {
+    fetchers: Arc<Mutex<HashMap<Endpoint, AgentInfoFetcher>>>,
+}
+
+impl AgentInfos {
+    /// Starts fetching the info of the agent the session sends data to, if not done yet
+    pub fn watch(&self, session_id: &str, endpoint: &Endpoint) {
+        let info_endpoint = match info_endpoint(endpoint) {
+            Some(info_endpoint) => info_endpoint,
+            None => return self.unwatch(session_id),
+        };
+        let mut fetchers = self.fetchers.lock().unwrap();
+        Self::remove_session(&mut fetchers, session_id, Some(&info_endpoint));
+        fetchers
+            .entry(info_endpoint.clone())
+            .or_insert_with(|| AgentInfoFetcher::start(info_endpoint))
+            .sessions
+            .insert(session_id.to_owned());
+    }
+
+    pub fn unwatch(&self, session_id: &str) {
+        Self::remove_session(&mut self.fetchers.lock().unwrap(), session_id, None);
+    }
+
+    fn remove_session(
+        fetchers: &mut HashMap<Endpoint, AgentInfoFetcher>,
+        session_id: &str,
+        except: Option<&Endpoint>,
+    ) {
+        fetchers.retain(|endpoint, fetcher| {
+            if Some(endpoint) != except {
+                fetcher.sessions.remove(session_id);
+            }
+            if fetcher.sessions.is_empty() && Some(endpoint) != except {
+                fetcher.task.abort();
+                return false;
+            }
+            true
+        });
+    }
+
+    /// None until the info was fetched once, or when `endpoint` is not an agent
+    pub fn get(&self, endpoint: &Endpoint) -> Option<AgentInfo> {
+        let info_endpoint = info_endpoint(endpoint)?;
+        let fetchers = self.fetchers.lock().unwrap();
+        let info = fetchers.get(&info_endpoint)?.info.lock().unwrap().clone();
+        info
+    }
+
+    /// Whether data may be sent to the endpoint. Assumed as long as the agent is unknown.
+    pub fn supports(&self, endpoint: &Endpoint) -> bool {
+        match self.get(endpoint) {
+            Some(info) => info.supports(endpoint.url.path()),
+            None => true,
+        }
+    }
+
+    /// The endpoint to send traces meant for `target` to: agents which do not support the v0.7
+    /// format get the v0.4 one
+    pub fn trace_endpoint(&self, target: &Endpoint) -> Endpoint {
+        if target.url.path() != TRACES_V07_PATH || self.supports(target) {
+            return target.clone();
+        }
+        let mut parts = target.url.clone().into_parts();
+        parts.path_and_query = Some(PathAndQuery::from_static(TRACES_V04_PATH));
+        match hyper::Uri::from_parts(parts) {
+            Ok(url) => Endpoint { url, api_key: None },
+            Err(_) => target.clone(),
+        }
+    }
+
+    pub fn state(&self) -> BTreeMap<String, Option<AgentInfo>> {
+        self.fetchers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(endpoint, fetcher)| {
+                (
+                    endpoint.url.to_string(),
+                    fetcher.info.lock().unwrap().clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(path: &'static str) -> Endpoint {
+        Endpoint {
+            url: hyper::Uri::from_static(path),
+            api_key: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let info: AgentInfo = serde_json::from_str(
+            r#"{
+                "version": "7.49.0",
+                "git_commit": "1a2b3c4",
+                "endpoints": ["/v0.4/traces", "/v0.6/stats", "/v0.7/traces", "/info"],
+                "client_drop_p0s": true,
+                "span_events": false,
+                "peer_tags": null,
+                "config": {"receiver_port": 8126}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(info.version.as_deref(), Some("7.49.0"));
+        assert!(info.client_drop_p0s);
+        assert!(!info.span_events);
+        assert!(info.peer_tags.is_empty());
+        assert!(info.supports("/v0.7/traces"));
+        assert!(!info.supports("/v0.5/traces"));
+
+        let info: AgentInfo = serde_json::from_str("{}").unwrap();
+        assert_eq!(info, AgentInfo::default());
+    }
+
+    #[tokio::test]
+    async fn test_trace_endpoint_fallback() {
+        let infos = AgentInfos::default();
+        let v07 = agent("http://localhost:8126/v0.7/traces");
+        let stats = agent("http://localhost:8126/v0.6/stats");
+
+        // unknown agents are expected to support everything
+        assert_eq!(infos.trace_endpoint(&v07), v07);
+        assert!(infos.supports(&stats));
+
+        let info = Arc::new(Mutex::new(Some(AgentInfo {
+            endpoints: vec![TRACES_V04_PATH.to_string()],
+            ..Default::default()
+        })));
+        infos.fetchers.lock().unwrap().insert(
+            info_endpoint(&v07).unwrap(),
+            AgentInfoFetcher {
+                sessions: HashSet::from(["session".to_string()]),
+                info: info.clone(),
+                task: tokio::spawn(async {}),
+            },
+        );
+        assert_eq!(
+            infos.trace_endpoint(&v07),
+            agent("http://localhost:8126/v0.4/traces")
+        );
+        assert!(!infos.supports(&stats));
+        // other agents are not affected
+        let other = agent("http://localhost:8127/v0.7/traces");
+        assert_eq!(infos.trace_endpoint(&other), other);
+
+        info.lock().unwrap().as_mut().unwrap().endpoints =
+            vec![TRACES_V07_PATH.to_string(), "/v0.6/stats".to_string()];
+        assert_eq!(infos.trace_endpoint(&v07), v07);
+        assert!(infos.supports(&stats));
+
+        infos.unwatch("session");
+        assert!(infos.get(&v07).is_none());
+
+        let intake = Endpoint {
+            url: hyper::Uri::from_static("https://trace.agent.datadoghq.com/api/v0.2/traces"),
+            api_key: Some("key".into()),
+        };
+        infos.watch("session", &intake);
+        assert!(infos.fetchers.lock().unwrap().is_empty());
+    }
+}
@@ -22,7 +22,7 @@ where
     T: FileBackedHandle + From<MappedMem<T>>,
 {
     handle: Option<MappedMem<T>>,
-    endpoint: Option<Endpoint>,
+    path: Option<CString>,
     current_config: Option<Vec<u64>>,
 }
 
@@ -67,11 +67,16 @@ unsafe fn reinterpret_u8_as_u64_slice(slice: &[u8]) -> &[u64] {
     std::slice::from_raw_parts(slice.as_ptr() as *const u64, slice.len() / 8)
 }
 
-fn path_for_endpoint(endpoint: &Endpoint) -> CString {
+/// Name of the shared memory holding the `kind` of data of the agent at `endpoint`
+pub(crate) fn shm_path_for_endpoint(kind: &str, endpoint: &Endpoint) -> CString {
     // We need a stable hash so that the outcome is independent of the process
     let mut hasher = ZwoHasher::default();
     endpoint.url.authority().unwrap().hash(&mut hasher);
-    CString::new(format!("/libdatadog-agent-config-{}", hasher.finish())).unwrap()
+    CString::new(format!("/libdatadog-{kind}-{}", hasher.finish())).unwrap()
+}
+
+fn path_for_endpoint(endpoint: &Endpoint) -> CString {
+    shm_path_for_endpoint("agent-config", endpoint)
 }
 
 pub fn create_anon_pair() -> anyhow::Result<(AgentRemoteConfigWriter<ShmHandle>, ShmHandle)> {
@@ -85,9 +90,14 @@ pub fn create_anon_pair() -> anyhow::Result<(AgentRemoteConfigWriter<ShmHandle>,
 }
 
 pub fn new_reader(endpoint: &Endpoint) -> AgentRemoteConfigReader<NamedShmHandle> {
+    new_named_reader(path_for_endpoint(endpoint))
+}
+
+/// The shared memory is opened once it exists
+pub fn new_named_reader(path: CString) -> AgentRemoteConfigReader<NamedShmHandle> {
     AgentRemoteConfigReader {
-        handle: open_named_shm(endpoint).ok(),
-        endpoint: Some(endpoint.clone()),
+        handle: open_named_shm(&path).ok(),
+        path: Some(path),
         current_config: None,
     }
 }
@@ -95,16 +105,18 @@ pub fn new_reader(endpoint: &Endpoint) -> AgentRemoteConfigReader<NamedShmHandle
 pub fn reader_from_shm(handle: ShmHandle) -> io::Result<AgentRemoteConfigReader<ShmHandle>> {
     Ok(AgentRemoteConfigReader {
         handle: Some(handle.map()?),
-        endpoint: None,
+        path: None,
         current_config: None,
     })
 }
 
 pub fn new_writer(endpoint: &Endpoint) -> io::Result<AgentRemoteConfigWriter<NamedShmHandle>> {
+    new_named_writer(path_for_endpoint(endpoint))
+}
+
+pub fn new_named_writer(path: CString) -> io::Result<AgentRemoteConfigWriter<NamedShmHandle>> {
     Ok(AgentRemoteConfigWriter {
-        handle: Mutex::new(Some(
-            NamedShmHandle::create(path_for_endpoint(endpoint), 0x1000)?.map()?,
-        )),
+        handle: Mutex::new(Some(NamedShmHandle::create(path, 0x1000)?.map()?)),
     })
 }
 
@@ -112,21 +124,21 @@ pub trait ReaderOpener<T>
 where
     T: FileBackedHandle,
 {
-    fn open(endpoint: &Endpoint) -> Option<MappedMem<T>>;
+    fn open(path: &CString) -> Option<MappedMem<T>>;
 }
 
-fn open_named_shm(endpoint: &Endpoint) -> io::Result<MappedMem<NamedShmHandle>> {
-    NamedShmHandle::open(path_for_endpoint(endpoint))?.map()
+fn open_named_shm(path: &CString) -> io::Result<MappedMem<NamedShmHandle>> {
+    NamedShmHandle::open(path.clone())?.map()
 }
 
 impl ReaderOpener<NamedShmHandle> for AgentRemoteConfigReader<NamedShmHandle> {
-    fn open(endpoint: &Endpoint) -> Option<MappedMem<NamedShmHandle>> {
-        open_named_shm(endpoint).ok()
+    fn open(path: &CString) -> Option<MappedMem<NamedShmHandle>> {
+        open_named_shm(path).ok()
     }
 }
 
 impl ReaderOpener<ShmHandle> for AgentRemoteConfigReader<ShmHandle> {
-    fn open(_: &Endpoint) -> Option<MappedMem<ShmHandle>> {
+    fn open(_: &CString) -> Option<MappedMem<ShmHandle>> {
         None
     }
 }
@@ -186,8 +198,8 @@ where
                     return success;
                 }
             }
-        } else if let Some(ref endpoint) = self.endpoint {
-            if let Some(handle) = Self::open(endpoint) {
+        } else if let Some(ref path) = self.path {
+            if let Some(handle) = Self::open(path) {
                 self.handle.replace(handle);
                 return self.read();
             }
@@ -0,0 +1,18 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! Usage:
+//! ./dump_sidecar_state
+//!
+//! Prints the state of the running sidecar as json. The sidecar is found the same way the
+//! library finds it, so _DD_DEBUG_SIDECAR_IPC_MODE must match the mode of the library. The
+//! sidecar of another process can't be reached in instance_per_process mode.
+
+use datadog_sidecar::{config::Config, connect_to_sidecar, interface::blocking};
+
+fn main() -> anyhow::Result<()> {
+    let mut transport = connect_to_sidecar(&Config::get())?;
+    let state = blocking::dump_state(&mut transport)?;
+    println!("{}", serde_json::to_string_pretty(&state)?);
+    Ok(())
+}
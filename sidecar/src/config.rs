@@ -7,9 +7,12 @@ use std::{collections::HashMap, path::PathBuf, time::Duration};
 use ddcommon::{parse_uri, Endpoint};
 use spawn_worker::LibDependency;
 
+use crate::trace_spool::SpoolConfig;
+
 const ENV_SIDECAR_IPC_MODE: &str = "_DD_DEBUG_SIDECAR_IPC_MODE";
 const SIDECAR_IPC_MODE_SHARED: &str = "shared";
 const SIDECAR_IPC_MODE_PER_PROCESS: &str = "instance_per_process";
+const SIDECAR_IPC_MODE_SHARED_PER_UID: &str = "shared_per_uid";
 
 const ENV_SIDECAR_LOG_METHOD: &str = "_DD_DEBUG_SIDECAR_LOG_METHOD";
 const SIDECAR_LOG_METHOD_DISABLED: &str = "disabled";
@@ -23,10 +26,20 @@ const DEFAULT_IDLE_LINGER_TIME: Duration = Duration::from_secs(60);
 
 const ENV_SIDECAR_SELF_TELEMETRY: &str = "_DD_SIDECAR_SELF_TELEMETRY";
 
+const ENV_SIDECAR_CLIENT_COMPUTED_STATS: &str = "_DD_SIDECAR_CLIENT_COMPUTED_STATS";
+
+const ENV_SIDECAR_TRACE_SPOOL_DIR: &str = "_DD_SIDECAR_TRACE_SPOOL_DIR";
+const ENV_SIDECAR_TRACE_SPOOL_MAX_BYTES: &str = "_DD_SIDECAR_TRACE_SPOOL_MAX_BYTES";
+const ENV_SIDECAR_TRACE_SPOOL_MAX_AGE_SECS: &str = "_DD_SIDECAR_TRACE_SPOOL_MAX_AGE_SECS";
+const DEFAULT_TRACE_SPOOL_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_TRACE_SPOOL_MAX_AGE: Duration = Duration::from_secs(3600);
+
 #[derive(Debug, Copy, Clone)]
 pub enum IpcMode {
     Shared,
     InstancePerProcess,
+    /// One sidecar per user, reachable by the processes of that user only
+    SharedPerUid,
 }
 
 impl Default for IpcMode {
@@ -40,6 +53,7 @@ impl ToString for IpcMode {
         match self {
             IpcMode::Shared => SIDECAR_IPC_MODE_SHARED,
             IpcMode::InstancePerProcess => SIDECAR_IPC_MODE_PER_PROCESS,
+            IpcMode::SharedPerUid => SIDECAR_IPC_MODE_SHARED_PER_UID,
         }
         .into()
     }
@@ -70,12 +84,16 @@ impl ToString for LogMethod {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub ipc_mode: IpcMode,
     pub log_method: LogMethod,
     pub idle_linger_time: Duration,
     pub self_telemetry: bool,
+    /// Computes the stats of the traces sent to the agents supporting it
+    pub client_computed_stats: bool,
+    /// Traces which can't be sent are dropped when unset
+    pub trace_spool: Option<SpoolConfig>,
     pub library_dependencies: Vec<LibDependency>,
     pub child_env: HashMap<std::ffi::OsString, std::ffi::OsString>,
 }
@@ -86,7 +104,7 @@ impl Config {
     }
 
     pub fn to_env(&self) -> HashMap<&'static str, String> {
-        HashMap::from([
+        let mut env = HashMap::from([
             (ENV_SIDECAR_IPC_MODE, self.ipc_mode.to_string()),
             (ENV_SIDECAR_LOG_METHOD, self.log_method.to_string()),
             (
@@ -94,7 +112,26 @@ impl Config {
                 self.idle_linger_time.as_secs().to_string(),
             ),
             (ENV_SIDECAR_SELF_TELEMETRY, self.self_telemetry.to_string()),
-        ])
+            (
+                ENV_SIDECAR_CLIENT_COMPUTED_STATS,
+                self.client_computed_stats.to_string(),
+            ),
+        ]);
+        if let Some(spool) = &self.trace_spool {
+            env.insert(
+                ENV_SIDECAR_TRACE_SPOOL_DIR,
+                spool.dir.to_string_lossy().into_owned(),
+            );
+            env.insert(
+                ENV_SIDECAR_TRACE_SPOOL_MAX_BYTES,
+                spool.max_bytes.to_string(),
+            );
+            env.insert(
+                ENV_SIDECAR_TRACE_SPOOL_MAX_AGE_SECS,
+                spool.max_age.as_secs().to_string(),
+            );
+        }
+        env
     }
 }
 
@@ -107,8 +144,9 @@ impl FromEnv {
         match mode.as_str() {
             SIDECAR_IPC_MODE_SHARED => IpcMode::Shared,
             SIDECAR_IPC_MODE_PER_PROCESS => IpcMode::InstancePerProcess,
+            SIDECAR_IPC_MODE_SHARED_PER_UID => IpcMode::SharedPerUid,
             SIDECAR_HELP => {
-                println!("help: {ENV_SIDECAR_IPC_MODE}: {SIDECAR_IPC_MODE_SHARED}|{SIDECAR_IPC_MODE_PER_PROCESS}");
+                println!("help: {ENV_SIDECAR_IPC_MODE}: {SIDECAR_IPC_MODE_SHARED}|{SIDECAR_IPC_MODE_PER_PROCESS}|{SIDECAR_IPC_MODE_SHARED_PER_UID}");
                 IpcMode::default()
             }
             _ => IpcMode::default(),
@@ -155,12 +193,38 @@ impl FromEnv {
         )
     }
 
+    fn client_computed_stats() -> bool {
+        matches!(
+            std::env::var(ENV_SIDECAR_CLIENT_COMPUTED_STATS).as_deref(),
+            Ok("true" | "1")
+        )
+    }
+
+    fn trace_spool() -> Option<SpoolConfig> {
+        let dir = std::env::var_os(ENV_SIDECAR_TRACE_SPOOL_DIR).filter(|dir| !dir.is_empty())?;
+        Some(SpoolConfig {
+            dir: PathBuf::from(dir),
+            max_bytes: std::env::var(ENV_SIDECAR_TRACE_SPOOL_MAX_BYTES)
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or(DEFAULT_TRACE_SPOOL_MAX_BYTES),
+            max_age: std::env::var(ENV_SIDECAR_TRACE_SPOOL_MAX_AGE_SECS)
+                .unwrap_or_default()
+                .parse()
+                .ok()
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TRACE_SPOOL_MAX_AGE),
+        })
+    }
+
     pub fn config() -> Config {
         Config {
             ipc_mode: Self::ipc_mode(),
             log_method: Self::log_method(),
             idle_linger_time: Self::idle_linger_time(),
             self_telemetry: Self::self_telemetry(),
+            client_computed_stats: Self::client_computed_stats(),
+            trace_spool: Self::trace_spool(),
             library_dependencies: vec![],
             child_env: std::env::vars_os().collect(),
         }
@@ -0,0 +1,238 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::io;
+use std::sync::{Arc, Mutex};
+
+#[cfg(unix)]
+use crate::setup::IpcConnection;
+
+/// The user a process runs as: its uid on unix, the string form of its SID on windows
+#[cfg(unix)]
+pub type UserId = u32;
+#[cfg(windows)]
+pub type UserId = String;
+
+/// Identity of the process on the other end of a connection, as of when it connected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: UserId,
+    #[cfg(unix)]
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+#[cfg(unix)]
+impl PeerCredentials {
+    pub fn of(socket: &IpcConnection) -> io::Result<Self> {
+        let cred = socket.peer_cred()?;
+        Ok(PeerCredentials {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        })
+    }
+}
+
+/// The effective user of the current process, the one running the sidecar
+#[cfg(unix)]
+pub fn current_user() -> UserId {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(windows)]
+pub use windows::{current_user, process_user};
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        io,
+        os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle},
+        ptr, slice,
+    };
+
+    use lazy_static::lazy_static;
+
+    use windows_sys::Win32::{
+        Foundation::HANDLE,
+        Security::{
+            Authorization::ConvertSidToStringSidW, GetTokenInformation, RevertToSelf, TokenUser,
+            TOKEN_QUERY, TOKEN_USER,
+        },
+        System::{
+            Memory::LocalFree,
+            Pipes::{GetNamedPipeClientProcessId, ImpersonateNamedPipeClient},
+            Threading::{
+                GetCurrentProcess, GetCurrentThread, OpenProcess, OpenProcessToken,
+                OpenThreadToken, PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+        },
+    };
+
+    use super::{PeerCredentials, UserId};
+    use crate::setup::IpcConnection;
+
+    impl PeerCredentials {
+        /// The user is the one the client impersonates the sidecar as, which it allows by default
+        pub fn of(pipe: &IpcConnection) -> io::Result<Self> {
+            let pipe = pipe.as_raw_handle() as HANDLE;
+            let mut pid = 0;
+            if unsafe { GetNamedPipeClientProcessId(pipe, &mut pid) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if unsafe { ImpersonateNamedPipeClient(pipe) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut token = 0;
+            let opened = unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, 1, &mut token) };
+            let error = io::Error::last_os_error();
+            // the thread must not keep running as the client, whatever happens
+            if unsafe { RevertToSelf() } == 0 {
+                panic!(
+                    "Failed reverting the impersonation of a client: {}",
+                    io::Error::last_os_error()
+                );
+            }
+            if opened == 0 {
+                return Err(error);
+            }
+            let token = unsafe { OwnedHandle::from_raw_handle(token as RawHandle) };
+
+            Ok(PeerCredentials {
+                uid: token_user(&token)?,
+                pid: Some(pid as i32),
+            })
+        }
+    }
+
+    /// The user running the process `pid`, which the current user must be allowed to query
+    pub fn process_user(pid: u32) -> io::Result<UserId> {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+        if process == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let process = unsafe { OwnedHandle::from_raw_handle(process as RawHandle) };
+        let mut token = 0;
+        if unsafe { OpenProcessToken(process.as_raw_handle() as HANDLE, TOKEN_QUERY, &mut token) }
+            == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let token = unsafe { OwnedHandle::from_raw_handle(token as RawHandle) };
+        token_user(&token)
+    }
+
+    /// The user running the current process, the one running the sidecar
+    pub fn current_user() -> UserId {
+        lazy_static! {
+            static ref USER: UserId = {
+                let mut token = 0;
+                // querying its own token is always allowed
+                if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+                    panic!(
+                        "Failed opening the token of the current process: {}",
+                        io::Error::last_os_error()
+                    );
+                }
+                let token = unsafe { OwnedHandle::from_raw_handle(token as RawHandle) };
+                token_user(&token).expect("Failed reading the user of the current process")
+            };
+        }
+        USER.clone()
+    }
+
+    fn token_user(token: &OwnedHandle) -> io::Result<UserId> {
+        let token = token.as_raw_handle() as HANDLE;
+        let mut len = 0;
+        // fails with the size to allocate
+        unsafe { GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len) };
+        // u64s for the alignment of TOKEN_USER
+        let mut buf = vec![0u64; len as usize / 8 + 1];
+        if unsafe { GetTokenInformation(token, TokenUser, buf.as_mut_ptr().cast(), len, &mut len) }
+            == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let user = unsafe { &*(buf.as_ptr() as *const TOKEN_USER) };
+
+        let mut sid = ptr::null_mut();
+        if unsafe { ConvertSidToStringSidW(user.User.Sid, &mut sid) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let sid_string = unsafe {
+            let len = (0..).take_while(|&i| *sid.add(i) != 0).count();
+            String::from_utf16_lossy(slice::from_raw_parts(sid, len))
+        };
+        unsafe { LocalFree(sid as _) };
+        Ok(sid_string)
+    }
+}
+
+/// Sessions belong to the user who first used them, so that on shared hosts the processes of one
+/// user can't inject data into, or shut down, the sessions of another one
+#[derive(Default, Clone)]
+pub struct SessionOwners {
+    owners: Arc<Mutex<HashMap<String, UserId>>>,
+}
+
+impl SessionOwners {
+    /// Whether the peer may use the session, which it then owns if nobody did yet
+    pub fn authorize(&self, session_id: &str, peer: &PeerCredentials) -> bool {
+        *self
+            .owners
+            .lock()
+            .unwrap()
+            .entry(session_id.to_owned())
+            .or_insert_with(|| peer.uid.to_owned())
+            == peer.uid
+    }
+
+    pub fn owner(&self, session_id: &str) -> Option<UserId> {
+        self.owners.lock().unwrap().get(session_id).cloned()
+    }
+
+    pub fn remove(&self, session_id: &str) {
+        self.owners.lock().unwrap().remove(session_id);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn test_socketpair_credentials() {
+        let (client, server) = UnixStream::pair().unwrap();
+        for socket in [&client, &server] {
+            let cred = PeerCredentials::of(socket).unwrap();
+            assert_eq!(cred.uid, unsafe { libc::getuid() });
+            assert_eq!(cred.gid, unsafe { libc::getgid() });
+            #[cfg(target_os = "linux")]
+            assert_eq!(cred.pid, Some(std::process::id() as i32));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_owners() {
+        let (_client, server) = UnixStream::pair().unwrap();
+        let peer = PeerCredentials::of(&server).unwrap();
+        let other_user = PeerCredentials {
+            uid: peer.uid + 1,
+            ..peer
+        };
+
+        let owners = SessionOwners::default();
+        assert!(owners.authorize("session", &peer));
+        assert!(owners.authorize("session", &peer));
+        assert!(!owners.authorize("session", &other_user));
+        assert!(owners.authorize("other session", &other_user));
+
+        owners.remove("session");
+        assert!(owners.authorize("session", &other_user));
+        assert!(!owners.authorize("session", &peer));
+    }
+}
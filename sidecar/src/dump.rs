@@ -0,0 +1,73 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! Snapshot of the internals of a running sidecar, for debugging
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent_info::AgentInfo;
+use crate::interface::SessionConfig;
+use crate::trace_spool::SpoolStats;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SidecarState {
+    /// Only the sessions owned by the user requesting the state
+    pub sessions: BTreeMap<String, SessionState>,
+    /// Since the sidecar started
+    pub submitted_payloads: u64,
+    /// Since the sidecar started, by the closed connections whose queue was full
+    pub dropped_requests: u64,
+    pub traces: TraceFlusherState,
+    pub profiles: ProfileFlusherState,
+    /// Agents the remote configuration received from is written for
+    pub remote_config_writers: Vec<String>,
+    /// By `/info` endpoint, None until fetched
+    pub agent_infos: BTreeMap<String, Option<AgentInfo>>,
+    /// Agents and services remote configurations are polled for
+    pub remote_config_services: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    /// With the api key redacted
+    pub config: Option<SessionConfig>,
+    pub runtimes: BTreeMap<String, RuntimeState>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RuntimeState {
+    /// Services with a started telemetry worker
+    pub apps: Vec<String>,
+    pub queues: BTreeMap<u64, QueueState>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QueueState {
+    /// Set once the queue was flushed to the app of the service
+    pub service: Option<String>,
+    pub queued_actions: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TraceFlusherState {
+    pub pending_payloads: usize,
+    pub pending_bytes: usize,
+    pub flushing: bool,
+    /// Number of distinct payloads stats are being computed for
+    pub stats_concentrators: usize,
+    pub last_error: Option<String>,
+    /// Dropped because of the memory limit or failing sends, and not spooled
+    pub dropped_payloads: u64,
+    pub spool: Option<SpoolStats>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ProfileFlusherState {
+    pub pending_profiles: usize,
+    pub flushing: bool,
+    pub last_error: Option<String>,
+    /// Since the sidecar started, because of rate limits or of missing endpoints
+    pub dropped_profiles: u64,
+}
@@ -0,0 +1,420 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::future::Future;
+use std::{
+    fs::File,
+    io,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
+    time::{self, Duration},
+};
+
+use futures::future;
+use manual_future::ManualFuture;
+use spawn_worker::{entrypoint, SpawnWorker, Stdio};
+use tokio::select;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::task::JoinHandle;
+
+use crate::config::{self, Config};
+use crate::interface::blocking::{self, SidecarConnection, SidecarTransport};
+use crate::interface::SidecarServer;
+use crate::setup::{self, IpcConnection, Liaison};
+use crate::trace_spool::TraceSpool;
+use crate::{daemonize, ddog_daemon_entry_point};
+use datadog_ipc::platform::Channel as IpcChannel;
+use datadog_ipc::transport::Codec;
+use ddtelemetry::data::metrics::{MetricNamespace, MetricType};
+use ddtelemetry::metrics::ContextKey;
+use ddtelemetry::worker::{
+    LifecycleAction, TelemetryActions, TelemetryWorkerBuilder, TelemetryWorkerHandle,
+};
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+// the timeout doubles with each attempt
+const HANDSHAKE_ATTEMPTS: u32 = 3;
+
+struct MetricData<'a> {
+    worker: &'a TelemetryWorkerHandle,
+    server: &'a SidecarServer,
+    submitted_payloads: ContextKey,
+    dropped_requests: ContextKey,
+    active_sessions: ContextKey,
+    // the server counters are cumulative, only their growth is reported
+    reported_submitted_payloads: u64,
+    reported_dropped_requests: u64,
+}
+impl<'a> MetricData<'a> {
+    async fn send(&self, key: ContextKey, value: f64) {
+        let _ = self
+            .worker
+            .send_msg(TelemetryActions::AddPoint((value, key, vec![])))
+            .await;
+    }
+
+    async fn collect_and_send(&mut self) {
+        let submitted_payloads = self.server.submitted_payloads.load(Ordering::Relaxed);
+        let dropped_requests = self.server.dropped_requests.load(Ordering::Relaxed);
+        let new_submitted_payloads = submitted_payloads - self.reported_submitted_payloads;
+        let new_dropped_requests = dropped_requests - self.reported_dropped_requests;
+        self.reported_submitted_payloads = submitted_payloads;
+        self.reported_dropped_requests = dropped_requests;
+        future::join_all(vec![
+            self.send(self.submitted_payloads, new_submitted_payloads as f64),
+            self.send(self.dropped_requests, new_dropped_requests as f64),
+            self.send(
+                self.active_sessions,
+                self.server.active_session_count() as f64,
+            ),
+        ])
+        .await;
+    }
+}
+
+fn self_telemetry(server: SidecarServer, mut shutdown_receiver: Receiver<()>) -> JoinHandle<()> {
+    if !Config::get().self_telemetry {
+        return tokio::spawn(async move {
+            shutdown_receiver.recv().await;
+        });
+    }
+
+    let (future, completer) = ManualFuture::new();
+    server
+        .self_telemetry_config
+        .lock()
+        .unwrap()
+        .replace(completer);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+        select! {
+            _ = shutdown_receiver.recv() => { },
+            config = future => {
+                if let Ok((worker, join_handle)) = TelemetryWorkerBuilder::new_fetch_host(
+                    "datadog-ipc-helper".to_string(),
+                    "php".to_string(),
+                    "SIDECAR".to_string(),
+                    env!("CARGO_PKG_VERSION").to_string(),
+                )
+                .spawn_with_config(config)
+                .await
+                {
+                    let mut metrics = MetricData {
+                        worker: &worker,
+                        server: &server,
+                        submitted_payloads: worker.register_metric_context(
+                            "sidecar.submitted_payloads".to_string(),
+                            vec![],
+                            MetricType::Count,
+                            true,
+                            MetricNamespace::Trace,
+                        ),
+                        dropped_requests: worker.register_metric_context(
+                            "sidecar.dropped_requests".to_string(),
+                            vec![],
+                            MetricType::Count,
+                            true,
+                            MetricNamespace::Trace,
+                        ),
+                        active_sessions: worker.register_metric_context(
+                            "sidecar.active_sessions".to_string(),
+                            vec![],
+                            MetricType::Gauge,
+                            true,
+                            MetricNamespace::Trace,
+                        ),
+                        reported_submitted_payloads: 0,
+                        reported_dropped_requests: 0,
+                    };
+
+                    let _ = worker
+                        .send_msg(TelemetryActions::Lifecycle(LifecycleAction::Start))
+                        .await;
+                    loop {
+                        select! {
+                            _ = interval.tick() => {
+                                metrics.collect_and_send().await;
+                                let _ = worker.send_msg(TelemetryActions::Lifecycle(LifecycleAction::FlushMetricAggr)).await;
+                                let _ = worker.send_msg(TelemetryActions::Lifecycle(LifecycleAction::FlushData)).await;
+                            },
+                            _ = shutdown_receiver.recv() => {
+                                metrics.collect_and_send().await;
+                                let _ = worker.send_msg(TelemetryActions::Lifecycle(LifecycleAction::Stop)).await;
+                                let _ = join_handle.await;
+                                return
+                            },
+                        }
+                    }
+                } else {
+                    shutdown_receiver.recv().await;
+                }
+            },
+        }
+    })
+}
+
+/// Serves the connections the accept loop passes to its handler, until the loop ends once `cancel`
+/// is called, which happens when the sidecar has been idle for too long or is told to stop
+pub(crate) async fn main_loop<L, Fut, C>(accept_loop: L, cancel: C) -> io::Result<()>
+where
+    L: FnOnce(Box<dyn Fn(IpcConnection)>) -> Fut,
+    Fut: Future<Output = ()>,
+    C: Fn() + Clone + Send + 'static,
+{
+    let counter = Arc::new(AtomicI32::new(0));
+    let cloned_counter = Arc::clone(&counter);
+
+    let cloned_cancel = cancel.clone();
+    tokio::spawn(async move {
+        let mut last_seen_connection_time = time::Instant::now();
+        let max_idle_linger_time = config::Config::get().idle_linger_time;
+
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            if cloned_counter.load(Ordering::Acquire) > 0 {
+                last_seen_connection_time = time::Instant::now();
+            }
+
+            if last_seen_connection_time.elapsed() > max_idle_linger_time {
+                cloned_cancel();
+                tracing::info!("No active connections - shutting down");
+                break;
+            }
+        }
+    });
+
+    let cloned_cancel = cancel.clone();
+    tokio::spawn(async move {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Error setting up signal handler {}", err);
+        }
+        tracing::info!("Received Ctrl-C Signal, shutting down");
+        cloned_cancel();
+    });
+
+    let server = SidecarServer::default();
+
+    let drain_requested = server.drain_requested.clone();
+    tokio::spawn(async move {
+        drain_requested.notified().await;
+        tracing::info!("Handing over to a newer sidecar, shutting down");
+        cancel();
+    });
+
+    let cfg = config::Config::get();
+    server
+        .trace_flusher
+        .client_computed_stats
+        .store(cfg.client_computed_stats, Ordering::Relaxed);
+    if let config::IpcMode::Shared = cfg.ipc_mode {
+        tokio::task::spawn_blocking(drain_older_sidecars);
+    }
+    if let Some(spool_config) = cfg.trace_spool {
+        match tokio::task::spawn_blocking(move || TraceSpool::open(spool_config)).await? {
+            Ok(spool) => server.trace_flusher.enable_spool(spool),
+            Err(e) => tracing::error!("Failed opening the trace spool: {e}"),
+        }
+    }
+
+    let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel::<()>(1);
+    let telemetry_handle = self_telemetry(server.clone(), shutdown_complete_rx);
+
+    let cloned_server = server.clone();
+    accept_loop(Box::new(move |socket| {
+        tracing::info!("connection accepted");
+        counter.fetch_add(1, Ordering::AcqRel);
+
+        let cloned_counter = Arc::clone(&counter);
+        let server = cloned_server.clone();
+        let shutdown_complete_tx = shutdown_complete_tx.clone();
+        tokio::spawn(async move {
+            server.accept_connection(socket).await;
+            cloned_counter.fetch_add(-1, Ordering::AcqRel);
+            tracing::info!("connection closed");
+
+            // Once all tx/senders are dropped the receiver will complete
+            drop(shutdown_complete_tx);
+        });
+    }))
+    // Dropping the handler with the final sender lets the receiver complete
+    .await;
+    let _ = telemetry_handle.await;
+    _ = server.trace_flusher.join().await;
+    _ = server.profile_flusher.join().await;
+    Ok(())
+}
+
+/// Takes over from the sidecars of older library versions, which would otherwise keep running
+/// until they are idle, holding data of their clients
+fn drain_older_sidecars() {
+    for liaison in setup::DefaultLiason::ipc_shared().older_versions() {
+        let mut connection: SidecarConnection = match connect_channel(&liaison) {
+            Ok(channel) => channel.into(),
+            Err(_) => continue,
+        };
+        _ = connection.set_read_timeout(Some(DRAIN_TIMEOUT));
+        // an older sidecar going away is not to be replaced
+        let mut transport =
+            SidecarTransport::new(connection, || Err(io::ErrorKind::NotConnected.into()));
+        match blocking::drain(&mut transport) {
+            Ok(()) => tracing::info!("Drained sidecar of an older version"),
+            // sidecars predating the handover don't understand the request
+            Err(e) => tracing::warn!("Failed draining sidecar of an older version: {e}"),
+        }
+    }
+}
+
+/// The worker running the sidecar, which is passed the listener it serves by the platform
+/// specific daemonize()
+pub(crate) fn sidecar_worker(cfg: &Config) -> io::Result<SpawnWorker> {
+    let mut spawn_cfg = unsafe { SpawnWorker::new() };
+    spawn_cfg
+        .stdin(Stdio::Null)
+        .daemonize(true)
+        .process_name("datadog-ipc-helper")
+        .shared_lib_dependencies(cfg.library_dependencies.clone())
+        .target(entrypoint!(ddog_daemon_entry_point));
+    for (env, val) in cfg.to_env().into_iter() {
+        spawn_cfg.append_env(env, val);
+    }
+    match &cfg.log_method {
+        config::LogMethod::File(path) => {
+            let file = File::options()
+                .write(true)
+                .append(true)
+                .truncate(false)
+                .create(true)
+                .open(path)?;
+            spawn_cfg.stdout(file.try_clone()?);
+            spawn_cfg.stderr(file);
+        }
+        config::LogMethod::Disabled => {
+            spawn_cfg.stdout(Stdio::Null);
+            spawn_cfg.stdout(Stdio::Null);
+        }
+        _ => {}
+    }
+    Ok(spawn_cfg)
+}
+
+fn liaison(ipc_mode: config::IpcMode) -> setup::DefaultLiason {
+    match ipc_mode {
+        config::IpcMode::Shared => setup::DefaultLiason::ipc_shared(),
+        config::IpcMode::InstancePerProcess => setup::DefaultLiason::ipc_per_process(),
+        config::IpcMode::SharedPerUid => setup::DefaultLiason::ipc_per_uid(),
+    }
+}
+
+/// The transport connects again to a sidecar taking over once the connection to it is lost. A
+/// sidecar which stopped is only started again by reconnect_to_sidecar.
+pub fn start_or_connect_to_sidecar(cfg: config::Config) -> io::Result<SidecarTransport> {
+    let connection = start_or_connect(&cfg)?;
+    let ipc_mode = cfg.ipc_mode;
+    Ok(SidecarTransport::new(connection, move || {
+        connect(&liaison(ipc_mode))
+    }))
+}
+
+/// Starts the sidecar again if the connection to it is lost and no other sidecar took over
+pub fn reconnect_to_sidecar(
+    transport: &mut SidecarTransport,
+    cfg: &config::Config,
+) -> io::Result<()> {
+    transport.reconnect_with(|| start_or_connect(cfg))
+}
+
+fn start_or_connect(cfg: &config::Config) -> io::Result<SidecarConnection> {
+    let liaison = liaison(cfg.ipc_mode);
+
+    match liaison.attempt_listen() {
+        Ok(Some(listener)) => daemonize(listener, cfg.clone())?,
+        Ok(None) => {}
+        Err(err) => tracing::error!("Error starting sidecar {}", err),
+    }
+
+    connect(&liaison)
+}
+
+/// Connects to an already running sidecar, without starting one
+pub fn connect_to_sidecar(cfg: &config::Config) -> io::Result<SidecarTransport> {
+    let ipc_mode = cfg.ipc_mode;
+    let connection = connect(&liaison(ipc_mode))?;
+    Ok(SidecarTransport::new(connection, move || {
+        connect(&liaison(ipc_mode))
+    }))
+}
+
+/// Uses MessagePack with the sidecars supporting it, Json otherwise. Fails if the sidecar speaks
+/// another version of the protocol.
+fn connect(liaison: &setup::DefaultLiason) -> io::Result<SidecarConnection> {
+    let mut timeout = HANDSHAKE_TIMEOUT;
+    for attempt in 1..=HANDSHAKE_ATTEMPTS {
+        let mut transport: SidecarConnection = connect_channel(liaison)?.into();
+        transport.set_read_timeout(Some(timeout))?;
+        match transport.negotiate_protocol(Codec::MessagePack) {
+            Ok(_) => {
+                transport.set_read_timeout(None)?;
+                return Ok(transport);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                tracing::error!("Cannot use the sidecar: {e}");
+                return Err(e);
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if attempt < HANDSHAKE_ATTEMPTS {
+                    tracing::debug!("The sidecar did not answer the handshake in {timeout:?}");
+                    timeout *= 2;
+                } else {
+                    tracing::warn!(
+                        "The sidecar did not answer the handshake, falling back to json: {e}"
+                    );
+                }
+            }
+            Err(e) => {
+                // sidecars predating the handshake close the connection
+                tracing::debug!("Falling back to json for the sidecar connection: {e}");
+                break;
+            }
+        }
+    }
+    Ok(connect_channel(liaison)?.into())
+}
+
+#[allow(clippy::useless_conversion)] // the liaisons connect with a Channel on windows
+fn connect_channel<L: Liaison>(liaison: &L) -> io::Result<IpcChannel> {
+    Ok(liaison.connect_to_server()?.into())
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn enable_tracing() -> anyhow::Result<()> {
+    let subscriber = tracing_subscriber::fmt();
+
+    match config::Config::get().log_method {
+        config::LogMethod::Stdout => subscriber.with_writer(io::stdout).init(),
+        config::LogMethod::Stderr => subscriber.with_writer(io::stderr).init(),
+        config::LogMethod::File(path) => {
+            let log_file = std::fs::File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .append(true)
+                .open(path)?;
+            tracing_subscriber::fmt()
+                .with_writer(std::sync::Mutex::new(log_file))
+                .init()
+        }
+        config::LogMethod::Disabled => return Ok(()),
+    };
+
+    Ok(())
+}
@@ -5,11 +5,10 @@
 #![allow(clippy::needless_collect)]
 use std::collections::hash_map::Entry;
 use std::collections::{BTreeMap, HashSet};
-use std::iter::zip;
 use std::ops::DerefMut;
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::time;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use std::{
     collections::HashMap,
     pin::Pin,
@@ -18,40 +17,53 @@ use std::{
 
 use anyhow::Result;
 
-use datadog_ipc::{platform::AsyncChannel, transport::Transport};
+use datadog_ipc::{
+    platform::AsyncChannel,
+    transport::{Protocol, Transport},
+};
 use futures::{
     future::{self, join_all, BoxFuture, Ready, Shared},
     FutureExt,
 };
 use manual_future::{ManualFuture, ManualFutureCompleter};
 
+use datadog_ipc::platform::ring_buffer::RingBufferHandle;
 use datadog_ipc::platform::{FileBackedHandle, NamedShmHandle, ShmHandle};
 use datadog_ipc::tarpc::{context::Context, server::Channel};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use tokio::net::UnixStream;
+use serde_bytes::ByteBuf;
 use tokio::select;
 use tokio::task::{JoinError, JoinHandle};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
+use crate::agent_info::AgentInfos;
 use crate::agent_remote_config::AgentRemoteConfigWriter;
 use crate::config::get_product_endpoint;
+use crate::credentials::{current_user, PeerCredentials, SessionOwners};
+use crate::dump::{QueueState, RuntimeState, SessionState, SidecarState, TraceFlusherState};
+use crate::profiling::{self, ProfileAttachment, ProfileFlusher, ProfileMetadata};
+use crate::remote_config::{RemoteConfigAck, RemoteConfigTarget, RemoteConfigs};
+use crate::setup::IpcConnection;
 use datadog_ipc::tarpc;
 use datadog_trace_protobuf::pb;
-use datadog_trace_utils::trace_utils;
+use datadog_trace_utils::concentrator::{SpanConcentrator, DEFAULT_BUCKET_DURATION};
 use datadog_trace_utils::trace_utils::{SendData, TracerHeaderTags};
+use datadog_trace_utils::{stats_utils, trace_utils};
 use ddcommon::Endpoint;
 use ddtelemetry::{
     data,
     worker::{
-        store::Store, LifecycleAction, TelemetryActions, TelemetryWorkerBuilder,
-        TelemetryWorkerHandle, MAX_ITEMS,
+        stats::TelemetryWorkerStats, store::Store, LifecycleAction, RestoredState,
+        TelemetryActions, TelemetryWorkerBuilder, TelemetryWorkerHandle, MAX_ITEMS,
     },
 };
 
+use crate::trace_spool::TraceSpool;
 use crate::tracer;
 
 #[datadog_sidecar_macros::extract_request_id]
+#[datadog_ipc_macros::protocol(version = 2)]
 #[datadog_ipc_macros::impl_transfer_handles]
 #[tarpc::service]
 pub trait SidecarInterface {
@@ -65,6 +77,7 @@ pub trait SidecarInterface {
         queue_id: QueueId,
         meta: RuntimeMeta,
         service_name: String,
+        already_started: bool,
     );
     async fn set_session_config(session_id: String, config: SessionConfig);
     async fn shutdown_runtime(instance_id: InstanceId);
@@ -76,9 +89,37 @@ pub trait SidecarInterface {
     );
     async fn send_trace_v04_bytes(
         instance_id: InstanceId,
-        data: Vec<u8>,
+        data: ByteBuf,
+        headers: SerializedTracerHeaderTags,
+    );
+    #[Optional]
+    async fn register_trace_ring_buffer(
+        instance_id: InstanceId,
+        #[SerializedHandle] ring_buffer: RingBufferHandle,
         headers: SerializedTracerHeaderTags,
     );
+    async fn send_profile_shm(
+        instance_id: InstanceId,
+        #[SerializedHandle] handle: ShmHandle,
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    );
+    async fn send_profile_bytes(
+        instance_id: InstanceId,
+        data: ByteBuf,
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    );
+    async fn subscribe_remote_config(instance_id: InstanceId, target: RemoteConfigTarget);
+    async fn acknowledge_remote_config(
+        instance_id: InstanceId,
+        target: RemoteConfigTarget,
+        acks: Vec<RemoteConfigAck>,
+    );
+    async fn telemetry_stats(instance_id: InstanceId) -> HashMap<String, TelemetryWorkerStats>;
+    #[SidecarOwner]
+    async fn drain();
+    async fn dump_state() -> SidecarState;
     async fn ping();
 }
 
@@ -89,18 +130,41 @@ pub trait RequestIdentification {
 pub enum RequestIdentifier {
     InstanceId(InstanceId),
     SessionId(String),
+    /// Requests acting on the whole sidecar, reserved to the user running it
+    SidecarOwner,
     None,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Whether the peer may send the request: the requests of a session are reserved to the owner of
+/// the session, and the ones acting on the whole sidecar to the user running it
+fn authorize_request(
+    session_owners: &SessionOwners,
+    request: &RequestIdentifier,
+    peer: &PeerCredentials,
+) -> bool {
+    match request {
+        RequestIdentifier::SessionId(session)
+        | RequestIdentifier::InstanceId(InstanceId {
+            session_id: session,
+            ..
+        }) => session_owners.authorize(session, peer),
+        RequestIdentifier::SidecarOwner => peer.uid == current_user(),
+        RequestIdentifier::None => true,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SerializedTracerHeaderTags {
     data: String,
 }
 
-impl<'a> From<&'a SerializedTracerHeaderTags> for TracerHeaderTags<'a> {
-    fn from(serialized: &'a SerializedTracerHeaderTags) -> Self {
-        // Panics if deserialization fails due (e.g. input containing double quote or backslash)
-        serde_json::from_str(&serialized.data).unwrap()
+impl<'a> TryFrom<&'a SerializedTracerHeaderTags> for TracerHeaderTags<'a> {
+    type Error = serde_json::Error;
+
+    /// Fails on malformed data, or on values which can't be borrowed as they were escaped (e.g.
+    /// containing double quotes or backslashes)
+    fn try_from(serialized: &'a SerializedTracerHeaderTags) -> Result<Self, Self::Error> {
+        serde_json::from_str(&serialized.data)
     }
 }
 
@@ -169,6 +233,8 @@ struct SessionInfo {
     runtimes: Arc<Mutex<HashMap<String, RuntimeInfo>>>,
     session_config: Arc<Mutex<Option<ddtelemetry::config::Config>>>,
     tracer_config: Arc<Mutex<tracer::Config>>,
+    profiling_endpoint: Arc<Mutex<Option<Endpoint>>>,
+    config: Arc<Mutex<Option<SessionConfig>>>,
 }
 
 impl SessionInfo {
@@ -185,6 +251,16 @@ impl SessionInfo {
     }
 
     async fn shutdown(&self) {
+        self.stop(LifecycleAction::Stop).await
+    }
+
+    /// Flushes the data of the runtimes without closing their apps, which the sidecar taking
+    /// over continues
+    async fn detach(&self) {
+        self.stop(LifecycleAction::Detach).await
+    }
+
+    async fn stop(&self, action: LifecycleAction) {
         let runtimes: Vec<RuntimeInfo> = self
             .runtimes
             .lock()
@@ -195,7 +271,7 @@ impl SessionInfo {
 
         let runtimes_shutting_down: Vec<_> = runtimes
             .into_iter()
-            .map(|rt| tokio::spawn(async move { rt.shutdown().await }))
+            .map(|rt| tokio::spawn(async move { rt.stop(action).await }))
             .collect();
 
         future::join_all(runtimes_shutting_down).await;
@@ -256,6 +332,31 @@ impl SessionInfo {
     {
         f(&mut self.get_trace_config());
     }
+
+    fn state(&self) -> SessionState {
+        let mut config = self.config.lock().unwrap().clone();
+        if let Some(SessionConfig {
+            endpoint:
+                Endpoint {
+                    api_key: Some(api_key),
+                    ..
+                },
+            ..
+        }) = &mut config
+        {
+            *api_key = "<redacted>".into();
+        }
+        SessionState {
+            config,
+            runtimes: self
+                .runtimes
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(runtime_id, runtime)| (runtime_id.clone(), runtime.state()))
+                .collect(),
+        }
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -269,6 +370,13 @@ enum AppOrQueue {
 struct RuntimeInfo {
     apps: Arc<Mutex<HashMap<String, Shared<ManualFuture<Option<AppInstance>>>>>>,
     app_or_actions: Arc<Mutex<HashMap<QueueId, AppOrQueue>>>,
+    trace_ring_buffers: Arc<Mutex<Vec<TraceRingBufferReader>>>,
+}
+
+struct TraceRingBufferReader {
+    // makes the reader forward what is left in the ring buffer and return
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: JoinHandle<()>,
 }
 
 impl RuntimeInfo {
@@ -291,7 +399,63 @@ impl RuntimeInfo {
         }
     }
 
+    fn state(&self) -> RuntimeState {
+        let mut apps: Vec<String> = self.apps.lock().unwrap().keys().cloned().collect();
+        apps.sort();
+        let queues = self
+            .app_or_actions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(queue_id, app_or_queue)| {
+                let state = match app_or_queue {
+                    AppOrQueue::App(service) => QueueState {
+                        service: service.peek().cloned(),
+                        queued_actions: 0,
+                    },
+                    AppOrQueue::Queue(data) => QueueState {
+                        service: None,
+                        queued_actions: data.len(),
+                    },
+                };
+                (queue_id.inner, state)
+            })
+            .collect();
+        RuntimeState { apps, queues }
+    }
+
+    /// Stats of the telemetry workers of the started apps, by service name, without waiting for
+    /// the apps still starting
+    fn telemetry_stats(&self) -> HashMap<String, TelemetryWorkerStats> {
+        self.apps
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(service, instance)| {
+                let instance = instance.clone().now_or_never().flatten()?;
+                Some((service.clone(), instance.telemetry.stats()))
+            })
+            .collect()
+    }
+
     async fn shutdown(self) {
+        self.stop(LifecycleAction::Stop).await
+    }
+
+    /// Stops the telemetry workers of the apps with `action`, either Stop or Detach
+    async fn stop(self, action: LifecycleAction) {
+        let readers: Vec<_> = self.trace_ring_buffers.lock().unwrap().drain(..).collect();
+        for reader in readers {
+            _ = reader.stop.send(());
+            let mut task = reader.task;
+            if tokio::time::timeout(RING_BUFFER_DRAIN_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                warn!("Timed out forwarding the traces left in a ring buffer");
+                task.abort();
+            }
+        }
         let instance_futures: Vec<_> = self
             .apps
             .lock()
@@ -307,7 +471,7 @@ impl RuntimeInfo {
                     if let Some(instance) = instance {
                         instance
                             .telemetry
-                            .send_msg(TelemetryActions::Lifecycle(LifecycleAction::Stop))
+                            .send_msg(TelemetryActions::Lifecycle(action))
                             .await
                             .ok();
                         instance.telemetry_worker_shutdown.await;
@@ -361,6 +525,14 @@ impl EnqueuedTelemetryData {
         data
     }
 
+    /// Number of actions waiting for the app to be started
+    fn len(&self) -> usize {
+        self.dependencies.unflushed().count()
+            + self.configurations.unflushed().count()
+            + self.integrations.unflushed().count()
+            + self.actions.len()
+    }
+
     fn extract_telemetry_actions(&mut self, actions: &mut Vec<TelemetryActions>) {
         for d in self.dependencies.unflushed() {
             actions.push(TelemetryActions::AddDependecy(d.clone()));
@@ -408,13 +580,99 @@ struct AgentRemoteConfigs {
     pub last_used: BTreeMap<Instant, Endpoint>,
 }
 
+/// Identifies the payload the stats computed from a tracer payload are sent with
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TraceStatsKey {
+    endpoint: Endpoint,
+    hostname: String,
+    env: String,
+    version: String,
+    lang: String,
+    tracer_version: String,
+    runtime_id: String,
+    container_id: String,
+}
+
+impl TraceStatsKey {
+    fn new(endpoint: &Endpoint, payload: &pb::TracerPayload) -> Self {
+        TraceStatsKey {
+            endpoint: endpoint.clone(),
+            hostname: payload.hostname.clone(),
+            env: payload.env.clone(),
+            version: payload.app_version.clone(),
+            lang: payload.language_name.clone(),
+            tracer_version: payload.tracer_version.clone(),
+            runtime_id: payload.runtime_id.clone(),
+            container_id: payload.container_id.clone(),
+        }
+    }
+
+    fn into_payload(
+        self,
+        stats: Vec<pb::ClientStatsBucket>,
+        sequence: u64,
+    ) -> pb::ClientStatsPayload {
+        pb::ClientStatsPayload {
+            hostname: self.hostname,
+            env: self.env,
+            version: self.version,
+            stats,
+            lang: self.lang,
+            tracer_version: self.tracer_version,
+            runtime_id: self.runtime_id,
+            sequence,
+            agent_aggregation: String::new(),
+            service: String::new(),
+            container_id: self.container_id,
+            tags: vec![],
+        }
+    }
+}
+
+/// Whether an endpoint accepts the stats computed by the sidecar
+enum StatsEndpointState {
+    /// Waiting for an empty stats payload to be accepted
+    Probing,
+    Accepting,
+    Failing(Instant),
+}
+
+/// Delay before probing again an endpoint which failed to accept stats
+const STATS_ENDPOINT_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const SPOOL_REPLAY_INTERVAL: Duration = Duration::from_secs(10);
+const RING_BUFFER_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether sending the traces again may succeed
+fn is_retryable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<trace_utils::SendError>()
+        .is_some_and(trace_utils::SendError::is_retryable)
+}
+
+#[derive(Default)]
+struct TraceStatsData {
+    concentrators: HashMap<TraceStatsKey, SpanConcentrator>,
+    endpoints: HashMap<Endpoint, StatsEndpointState>,
+    sequence: u64,
+    flusher: Option<JoinHandle<()>>,
+}
+
 #[derive(Default)]
 pub struct TraceFlusher {
     inner: Mutex<TraceFlusherData>,
+    stats: Mutex<TraceStatsData>,
     pub interval: AtomicU64,
     pub min_force_flush_size: AtomicU32,
     pub min_force_drop_size: AtomicU32, // put a limit on memory usage
     remote_config: Mutex<AgentRemoteConfigs>,
+    last_error: Mutex<Option<String>>,
+    // only accessed from blocking tasks, as it does disk I/O
+    spool: Arc<Mutex<Option<TraceSpool>>>,
+    // held while replaying the spool of an endpoint, to keep its payloads in order
+    replaying: Mutex<HashMap<Endpoint, Arc<tokio::sync::Mutex<()>>>>,
+    // without a spool, when spooling failed, or refused by the endpoint
+    dropped_payloads: AtomicU64,
+    /// Compute the stats of the traces, instead of the agent, when the stats endpoint accepts them
+    pub client_computed_stats: AtomicBool,
 }
 
 impl TraceFlusher {
@@ -476,13 +734,11 @@ impl TraceFlusher {
                     },
                 )
                 .send_data;
-                let mut futures: Vec<_> = Vec::new();
-                let mut intake_target: Vec<_> = Vec::new();
-                for send_data in trace_utils::coalesce_send_data(trace_buffer).into_iter() {
-                    intake_target.push(send_data.target.clone());
-                    futures.push(send_data.send());
-                }
-                for (endpoint, response) in zip(intake_target, join_all(futures).await) {
+                let futures: Vec<_> = trace_utils::coalesce_send_data(trace_buffer)
+                    .into_iter()
+                    .map(|send_data| self.send_traces(send_data))
+                    .collect();
+                for (endpoint, response) in join_all(futures).await {
                     match response {
                         Ok(response) => {
                             if endpoint.api_key.is_none() {
@@ -499,9 +755,8 @@ impl TraceFlusher {
                         }
                         Err(e) => {
                             error!("Error sending trace: {e:?}");
-                            if endpoint.api_key.is_some() {
-                                // TODO: Retries when sending to intake
-                            }
+                            *self.last_error.lock().unwrap() =
+                                Some(format!("sending traces to {}: {e}", endpoint.url));
                         }
                     }
                 }
@@ -525,6 +780,7 @@ impl TraceFlusher {
         if flush_data.traces.send_data_size
             > self.min_force_drop_size.load(Ordering::Relaxed) as usize
         {
+            tokio::spawn(self.clone().spool(data));
             return;
         }
 
@@ -541,6 +797,262 @@ impl TraceFlusher {
         }
     }
 
+    /// Starts replaying the spooled payloads, also periodically, so that they don't wait for new
+    /// traces to be sent
+    pub fn enable_spool(self: &Arc<Self>, spool: TraceSpool) {
+        *self.spool.lock().unwrap() = Some(spool);
+
+        let flusher = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let targets = match flusher.upgrade() {
+                    Some(flusher) => flusher.with_spool(TraceSpool::pending_targets).await,
+                    None => return,
+                };
+                for endpoint in targets.unwrap_or_default() {
+                    let Some(flusher) = flusher.upgrade() else {
+                        return;
+                    };
+                    let lock = flusher.replay_lock(&endpoint);
+                    let _replaying = lock.lock().await;
+                    if let Err(e) = flusher.replay_spool(&endpoint).await {
+                        debug!("Could not replay spooled traces to {}: {e}", endpoint.url);
+                    }
+                }
+                tokio::time::sleep(SPOOL_REPLAY_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Runs `f` on the spool, if enabled, in a blocking task
+    async fn with_spool<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut TraceSpool) -> R + Send + 'static,
+    ) -> Option<R> {
+        let spool = self.spool.clone();
+        tokio::task::spawn_blocking(move || spool.lock().unwrap().as_mut().map(f))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    fn replay_lock(&self, endpoint: &Endpoint) -> Arc<tokio::sync::Mutex<()>> {
+        self.replaying
+            .lock()
+            .unwrap()
+            .entry(endpoint.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Keeps the data on disk until it can be sent, if a spool is enabled
+    async fn spool(self: Arc<Self>, data: SendData) {
+        let target = data.target.url.clone();
+        match self.with_spool(move |spool| spool.push(&data)).await {
+            Some(Ok(())) => return,
+            Some(Err(e)) => error!("Error spooling traces for {target}: {e}"),
+            None => {}
+        }
+        self.dropped_payloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sends the data spooled for the endpoint first, so that traces arrive in order, and spools
+    /// the new data if the endpoint is unreachable
+    async fn send_traces(
+        self: &Arc<Self>,
+        send_data: SendData,
+    ) -> (Endpoint, anyhow::Result<hyper::Response<hyper::Body>>) {
+        let endpoint = send_data.target.clone();
+        let lock = self.replay_lock(&endpoint);
+        let _replaying = lock.lock().await;
+        if let Err(e) = self.replay_spool(&endpoint).await {
+            self.clone().spool(send_data).await;
+            return (endpoint, Err(e));
+        }
+
+        let spooled = self
+            .spool
+            .lock()
+            .unwrap()
+            .is_some()
+            .then(|| send_data.clone());
+        let result = send_data.send().await;
+        if let Err(e) = &result {
+            match spooled {
+                Some(send_data) if is_retryable(e) => self.clone().spool(send_data).await,
+                _ => _ = self.dropped_payloads.fetch_add(1, Ordering::Relaxed),
+            }
+        }
+        (endpoint, result)
+    }
+
+    /// Sends the spooled payloads of the endpoint, until it is unreachable. The payloads it
+    /// refuses are dropped, as they would be refused again.
+    async fn replay_spool(&self, endpoint: &Endpoint) -> anyhow::Result<()> {
+        loop {
+            let target = endpoint.clone();
+            let send_data = match self.with_spool(move |spool| spool.front(&target)).await {
+                Some(Some(send_data)) => send_data,
+                _ => return Ok(()),
+            };
+            let target = endpoint.clone();
+            match send_data.send().await {
+                Ok(_) => {
+                    self.with_spool(move |spool| spool.remove_front(&target))
+                        .await;
+                    info!("Successfully replayed spooled traces to {}", endpoint.url);
+                }
+                Err(e) if is_retryable(&e) => return Err(e),
+                Err(e) => {
+                    self.with_spool(move |spool| spool.reject_front(&target))
+                        .await;
+                    warn!("Dropping spooled traces refused by {}: {e}", endpoint.url);
+                }
+            }
+        }
+    }
+
+    /// Whether the stats of the traces are computed and sent to `endpoint`. Until the endpoint
+    /// accepted a stats payload, and after it failed to, the agent computes them.
+    pub fn computes_stats_for(self: &Arc<Self>, endpoint: &Endpoint) -> bool {
+        if !self.client_computed_stats.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        match stats.endpoints.get(endpoint) {
+            Some(StatsEndpointState::Accepting) => return true,
+            Some(StatsEndpointState::Probing) => return false,
+            Some(StatsEndpointState::Failing(since))
+                if since.elapsed() < STATS_ENDPOINT_RETRY_INTERVAL =>
+            {
+                return false
+            }
+            _ => {}
+        }
+        stats
+            .endpoints
+            .insert(endpoint.clone(), StatsEndpointState::Probing);
+
+        let flusher = self.clone();
+        let endpoint = endpoint.clone();
+        tokio::spawn(async move {
+            flusher
+                .send_stats(endpoint, pb::ClientStatsPayload::default())
+                .await
+        });
+        false
+    }
+
+    /// Aggregates the spans of the payload into the stats to send to `endpoint`
+    pub fn add_stats(self: &Arc<Self>, endpoint: &Endpoint, payload: &pb::TracerPayload) {
+        let mut stats = self.stats.lock().unwrap();
+        let concentrator = stats
+            .concentrators
+            .entry(TraceStatsKey::new(endpoint, payload))
+            .or_default();
+        for chunk in payload.chunks.iter() {
+            concentrator.add_chunk(chunk);
+        }
+        if stats.flusher.is_none() {
+            stats.flusher = Some(self.clone().start_stats_flusher());
+        }
+    }
+
+    fn start_stats_flusher(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(DEFAULT_BUCKET_DURATION).await;
+                self.flush_stats(false).await;
+
+                let mut stats = self.stats.lock().unwrap();
+                if stats.concentrators.is_empty() {
+                    stats.flusher = None;
+                    break;
+                }
+            }
+        })
+    }
+
+    async fn flush_stats(&self, force: bool) {
+        let payloads: Vec<_> = {
+            let mut stats = self.stats.lock().unwrap();
+            let stats = stats.deref_mut();
+            let now = SystemTime::now();
+            let mut payloads = vec![];
+            for (key, concentrator) in stats.concentrators.iter_mut() {
+                let buckets = concentrator.flush(now, force);
+                if !buckets.is_empty() {
+                    stats.sequence += 1;
+                    payloads.push((
+                        key.endpoint.clone(),
+                        key.clone().into_payload(buckets, stats.sequence),
+                    ));
+                }
+            }
+            stats
+                .concentrators
+                .retain(|_, concentrator| !concentrator.is_empty());
+            payloads
+        };
+
+        for (endpoint, payload) in payloads {
+            self.send_stats(endpoint, payload).await;
+        }
+    }
+
+    /// The traces sent once the endpoint failed have their stats computed by the agent
+    async fn send_stats(&self, endpoint: Endpoint, payload: pb::ClientStatsPayload) {
+        let result = if let Some(api_key) = &endpoint.api_key {
+            match stats_utils::serialize_stats_payload(stats_utils::construct_stats_payload(vec![
+                payload,
+            ])) {
+                Ok(data) => stats_utils::send_stats_payload(data, &endpoint, api_key).await,
+                Err(e) => Err(e),
+            }
+        } else {
+            stats_utils::send_client_stats_payload(&payload, &endpoint).await
+        };
+        let state = match result {
+            Ok(()) => {
+                info!("Successfully flushed trace stats to {}", endpoint.url);
+                StatsEndpointState::Accepting
+            }
+            Err(e) => {
+                error!("Error sending trace stats: {e:?}");
+                *self.last_error.lock().unwrap() =
+                    Some(format!("sending trace stats to {}: {e}", endpoint.url));
+                StatsEndpointState::Failing(Instant::now())
+            }
+        };
+        self.stats.lock().unwrap().endpoints.insert(endpoint, state);
+    }
+
+    pub fn state(&self) -> TraceFlusherState {
+        let data = self.inner.lock().unwrap();
+        TraceFlusherState {
+            pending_payloads: data.traces.send_data.len(),
+            pending_bytes: data.traces.send_data_size,
+            flushing: data.flusher.is_some(),
+            stats_concentrators: self.stats.lock().unwrap().concentrators.len(),
+            last_error: self.last_error.lock().unwrap().clone(),
+            dropped_payloads: self.dropped_payloads.load(Ordering::Relaxed),
+            spool: self.spool.lock().unwrap().as_ref().map(TraceSpool::stats),
+        }
+    }
+
+    pub fn remote_config_writers(&self) -> Vec<String> {
+        let mut writers: Vec<String> = self
+            .remote_config
+            .lock()
+            .unwrap()
+            .writers
+            .keys()
+            .map(|endpoint| endpoint.url.to_string())
+            .collect();
+        writers.sort();
+        writers
+    }
+
     pub async fn join(&self) -> Result<(), JoinError> {
         let flusher = {
             let mut flush_data = self.inner.lock().unwrap();
@@ -548,43 +1060,86 @@ impl TraceFlusher {
             flush_data.traces.flush();
             flush_data.deref_mut().flusher.take()
         };
-        if let Some(flusher) = flusher {
+        let result = if let Some(flusher) = flusher {
             flusher.await
         } else {
             Ok(())
-        }
+        };
+        self.flush_stats(true).await;
+        result
     }
 }
 
 #[derive(Default, Clone)]
 pub struct SidecarServer {
     pub trace_flusher: Arc<TraceFlusher>,
+    pub profile_flusher: Arc<ProfileFlusher>,
     sessions: Arc<Mutex<HashMap<String, SessionInfo>>>,
     session_counter: Arc<Mutex<HashMap<String, u32>>>,
+    session_owners: SessionOwners,
+    agent_infos: AgentInfos,
+    remote_configs: RemoteConfigs,
+    // the peer of the connection this instance serves
+    peer: Option<PeerCredentials>,
     pub self_telemetry_config:
         Arc<Mutex<Option<ManualFutureCompleter<ddtelemetry::config::Config>>>>,
+    /// Payloads submitted since the sidecar started
     pub submitted_payloads: Arc<AtomicU64>,
+    /// Requests of the closed connections dropped while their queue was full, since the sidecar
+    /// started
+    pub dropped_requests: Arc<AtomicU64>,
+    /// Notified once the sidecar must stop accepting connections
+    pub drain_requested: Arc<tokio::sync::Notify>,
 }
 
 impl SidecarServer {
-    pub async fn accept_connection(self, socket: UnixStream) {
+    pub async fn accept_connection(self, socket: IpcConnection) {
+        let peer = match PeerCredentials::of(&socket) {
+            Ok(peer) => peer,
+            Err(e) => {
+                error!("Refusing connection of unidentifiable peer: {e}");
+                return;
+            }
+        };
+        debug!("Accepted connection of {peer:?}");
+
+        let protocol = Protocol::of::<SidecarInterfaceRequest>();
+        let channel = AsyncChannel::from(socket);
+        let transport = match Transport::accept_versioned(channel, &protocol).await {
+            Ok(transport) => transport,
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                warn!("Refusing connection of pid {:?}: {e}", peer.pid);
+                return;
+            }
+            Err(e) => {
+                debug!("Connection closed before its first message: {e}");
+                return;
+            }
+        };
         let server = datadog_ipc::tarpc::server::BaseChannel::new(
             datadog_ipc::tarpc::server::Config {
                 pending_response_buffer: 10000,
             },
-            Transport::try_from(AsyncChannel::from(socket)).unwrap(),
+            transport,
         );
 
+        let connection_server = SidecarServer {
+            peer: Some(peer.clone()),
+            ..self.clone()
+        };
         let mut executor = datadog_ipc::sequential::execute_sequential(
             server.requests(),
-            self.clone().serve(),
+            connection_server.serve(),
             100,
         );
         let (tx, mut rx) = tokio::sync::mpsc::channel::<_>(100);
         let tx = executor.swap_sender(tx);
+        let dropped_requests = executor.dropped_requests();
 
         let session_counter = self.session_counter.clone();
         let submitted_payloads = self.submitted_payloads.clone();
+        let session_owners = self.session_owners.clone();
+        let pid = peer.pid;
         let session_interceptor = tokio::spawn(async move {
             let mut sessions = HashSet::new();
             let mut instances = HashSet::new();
@@ -597,6 +1152,23 @@ impl SidecarServer {
                 submitted_payloads.fetch_add(1, Ordering::Relaxed);
 
                 let instance: RequestIdentifier = req.get().extract_identifier();
+                if !authorize_request(&session_owners, &instance, &peer) {
+                    match &instance {
+                        RequestIdentifier::SessionId(session)
+                        | RequestIdentifier::InstanceId(InstanceId {
+                            session_id: session,
+                            ..
+                        }) => warn!(
+                            "Dropping request of uid {} for session {session}, owned by another user",
+                            peer.uid
+                        ),
+                        _ => warn!(
+                            "Dropping request of uid {}, reserved to the user running the sidecar",
+                            peer.uid
+                        ),
+                    }
+                    continue;
+                }
                 if tx.send((serve, req)).await.is_ok() {
                     if let RequestIdentifier::InstanceId(ref instance_id) = instance {
                         instances.insert(instance_id.clone());
@@ -619,6 +1191,14 @@ impl SidecarServer {
         });
 
         executor.await;
+        let dropped_requests = dropped_requests.load(Ordering::Relaxed);
+        if dropped_requests > 0 {
+            warn!(
+                "Dropped {dropped_requests} requests of pid {pid:?} as the sidecar could not keep up"
+            );
+            self.dropped_requests
+                .fetch_add(dropped_requests, Ordering::Relaxed);
+        }
         if let Ok((sessions, instances)) = session_interceptor.await {
             for session in sessions {
                 let stop = {
@@ -645,6 +1225,8 @@ impl SidecarServer {
                     .unwrap()
                     .get(&instance_id.session_id)
                     .cloned();
+                self.remote_configs
+                    .unsubscribe_runtime(&instance_id.session_id, &instance_id.runtime_id);
                 if let Some(session) = maybe_session {
                     session.shutdown_runtime(&instance_id.runtime_id).await;
                 }
@@ -652,6 +1234,64 @@ impl SidecarServer {
         }
     }
 
+    /// Hands over to a newer sidecar: stops accepting connections and flushes the buffered data.
+    /// The apps are not closed, the tracers reconnecting to the newer sidecar continue them
+    pub async fn drain(&self) {
+        info!("Draining sidecar for a newer version");
+        self.drain_requested.notify_one();
+
+        let sessions: Vec<SessionInfo> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, session)| session)
+            .collect();
+        join_all(sessions.iter().map(SessionInfo::detach)).await;
+
+        _ = self.trace_flusher.join().await;
+        _ = self.profile_flusher.join().await;
+    }
+
+    /// The sessions shown are the ones owned by the peer, if known. The state shared by all
+    /// sessions is only shown to the user running the sidecar
+    pub fn dump_state(&self) -> SidecarState {
+        let sessions: Vec<(String, SessionInfo)> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(session_id, _)| match &self.peer {
+                Some(peer) => self.session_owners.owner(session_id).as_ref() == Some(&peer.uid),
+                None => true,
+            })
+            .map(|(session_id, session)| (session_id.clone(), session.clone()))
+            .collect();
+        let state = SidecarState {
+            sessions: sessions
+                .into_iter()
+                .map(|(session_id, session)| (session_id, session.state()))
+                .collect(),
+            submitted_payloads: self.submitted_payloads.load(Ordering::Relaxed),
+            dropped_requests: self.dropped_requests.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        // the state shared by all sessions reveals the endpoints and services of other users
+        if let Some(peer) = &self.peer {
+            if peer.uid != current_user() {
+                return state;
+            }
+        }
+        SidecarState {
+            traces: self.trace_flusher.state(),
+            profiles: self.profile_flusher.state(),
+            remote_config_writers: self.trace_flusher.remote_config_writers(),
+            agent_infos: self.agent_infos.state(),
+            remote_config_services: self.remote_configs.services(),
+            ..state
+        }
+    }
+
     pub fn active_session_count(&self) -> usize {
         self.session_counter.lock().unwrap().len()
     }
@@ -673,11 +1313,32 @@ impl SidecarServer {
         session.get_runtime(&instance_id.runtime_id)
     }
 
+    /// Unlike get_runtime, does not create the runtime nor its session when unknown
+    fn find_runtime(&self, instance_id: &InstanceId) -> Option<RuntimeInfo> {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(&instance_id.session_id)?
+            .clone();
+        let runtime = session
+            .runtimes
+            .lock()
+            .unwrap()
+            .get(&instance_id.runtime_id)
+            .cloned();
+        runtime
+    }
+
     async fn stop_session(&self, session_id: &String) {
+        self.session_owners.remove(session_id);
+        self.agent_infos.unwatch(session_id);
+        self.remote_configs.unsubscribe_session(session_id);
         let session = match self.sessions.lock().unwrap().remove(session_id) {
             Some(session) => session,
             None => return,
         };
+        self.profile_flusher.remove_session(session_id);
 
         session.shutdown().await
     }
@@ -687,6 +1348,7 @@ impl SidecarServer {
         instance_id: &InstanceId,
         runtime_meta: &RuntimeMeta,
         service_name: &String,
+        already_started: bool,
         inital_actions: Vec<TelemetryActions>,
     ) -> Option<AppInstance> {
         let rt_info = self.get_runtime(instance_id);
@@ -703,6 +1365,16 @@ impl SidecarServer {
             runtime_meta.tracer_version.clone(),
         );
         builder.runtime_id = Some(instance_id.runtime_id.clone());
+        // The libraries loaded by the sidecar are not the ones of the application
+        builder.native_deps = false;
+        if already_started {
+            // continues the telemetry of the app, without starting it again
+            builder.restored_state = Some(RestoredState {
+                seq_id: 0,
+                started: true,
+                metric_contexts: vec![],
+            });
+        }
 
         let session_info = self.get_session(&instance_id.session_id);
         let config = session_info
@@ -741,8 +1413,20 @@ impl SidecarServer {
         app_future.await
     }
 
-    fn send_trace_v04(&self, headers: &SerializedTracerHeaderTags, data: &[u8], target: &Endpoint) {
-        let headers: TracerHeaderTags = headers.into();
+    fn send_trace_v04(
+        &self,
+        headers: &SerializedTracerHeaderTags,
+        data: &[u8],
+        target: &Endpoint,
+        stats_target: Option<&Endpoint>,
+    ) {
+        let mut headers: TracerHeaderTags = match headers.try_into() {
+            Ok(headers) => headers,
+            Err(err) => {
+                error!("Error deserializing the tracer header tags: {err}");
+                return;
+            }
+        };
 
         let size = data.len();
         let traces: Vec<Vec<pb::Span>> = match rmp_serde::from_slice(data) {
@@ -761,10 +1445,53 @@ impl SidecarServer {
         let payload =
             trace_utils::collect_trace_chunks(traces, &headers, |_chunk, _root_span_index| {});
 
+        let target = &self.agent_infos.trace_endpoint(target);
+        let stats_target =
+            stats_target.filter(|stats_target| self.agent_infos.supports(stats_target));
+        if !headers.client_computed_stats {
+            if let Some(stats_target) =
+                stats_target.filter(|target| self.trace_flusher.computes_stats_for(target))
+            {
+                self.trace_flusher.add_stats(stats_target, &payload);
+                // the agent must not count these spans again
+                headers.client_computed_stats = true;
+            }
+        }
+
         // send trace payload to our trace flusher
         let data = SendData::new(size, payload, headers, target);
         self.trace_flusher.enqueue(data);
     }
+
+    fn send_profile(
+        &self,
+        instance_id: &InstanceId,
+        data: &[u8],
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    ) {
+        let session = self.get_session(&instance_id.session_id);
+        let endpoint = match session.profiling_endpoint.lock().unwrap().clone() {
+            Some(endpoint) => endpoint,
+            None => {
+                self.profile_flusher.drop_profile(
+                    &instance_id.session_id,
+                    "no profiling endpoint is configured",
+                );
+                return;
+            }
+        };
+        match profiling::split_attachments(data, attachments) {
+            Ok(files) => {
+                self.profile_flusher
+                    .enqueue(&instance_id.session_id, endpoint, meta, files)
+            }
+            Err(e) => self.profile_flusher.drop_profile(
+                &instance_id.session_id,
+                &format!("invalid attachments: {e}"),
+            ),
+        }
+    }
 }
 
 type NoResponse = Ready<()>;
@@ -791,6 +1518,8 @@ impl SidecarInterface for SidecarServer {
     type ShutdownRuntimeFut = NoResponse;
     fn shutdown_runtime(self, _: Context, instance_id: InstanceId) -> Self::ShutdownRuntimeFut {
         let session = self.get_session(&instance_id.session_id);
+        self.remote_configs
+            .unsubscribe_runtime(&instance_id.session_id, &instance_id.runtime_id);
         tokio::spawn(async move { session.shutdown_runtime(&instance_id.runtime_id).await });
 
         no_response()
@@ -858,6 +1587,7 @@ impl SidecarInterface for SidecarServer {
         queue_id: QueueId,
         runtime_meta: RuntimeMeta,
         service_name: String,
+        already_started: bool,
     ) -> Self::RegisterServiceAndFlushQueuedActionsFut {
         // We need a channel to have enqueuing code await
         let (future, completer) = ManualFuture::new();
@@ -878,7 +1608,13 @@ impl SidecarInterface for SidecarServer {
 
             tokio::spawn(async move {
                 if let Some(app) = self
-                    .get_app(&instance_id, &runtime_meta, &service_name, actions)
+                    .get_app(
+                        &instance_id,
+                        &runtime_meta,
+                        &service_name,
+                        already_started,
+                        actions,
+                    )
                     .await
                 {
                     let actions: Vec<_> = std::mem::take(&mut enqueued_data.actions);
@@ -912,6 +1648,8 @@ impl SidecarInterface for SidecarServer {
         config: SessionConfig,
     ) -> Self::SetSessionConfigFut {
         let session = self.get_session(&session_id);
+        *session.config.lock().unwrap() = Some(config.clone());
+        self.agent_infos.watch(&session_id, &config.endpoint);
         session.modify_telemetry_config(|cfg| {
             let endpoint =
                 get_product_endpoint(ddtelemetry::config::PROD_INTAKE_SUBDOMAIN, &config.endpoint);
@@ -924,6 +1662,10 @@ impl SidecarInterface for SidecarServer {
             );
             cfg.set_endpoint(endpoint).ok();
         });
+        match profiling::profiling_endpoint(&config.endpoint) {
+            Ok(endpoint) => *session.profiling_endpoint.lock().unwrap() = Some(endpoint),
+            Err(e) => error!("Invalid profiling endpoint: {e:?}"),
+        }
         self.trace_flusher
             .interval
             .store(config.flush_interval.as_millis() as u64, Ordering::Relaxed);
@@ -962,16 +1704,21 @@ impl SidecarInterface for SidecarServer {
         handle: ShmHandle,
         headers: SerializedTracerHeaderTags,
     ) -> Self::SendTraceV04ShmFut {
-        if let Some(endpoint) = self
-            .get_session(&instance_id.session_id)
-            .get_trace_config()
-            .endpoint
-            .clone()
-        {
+        let (endpoint, stats_endpoint) = {
+            let config = self.get_session(&instance_id.session_id);
+            let config = config.get_trace_config();
+            (config.endpoint.clone(), config.stats_endpoint.clone())
+        };
+        if let Some(endpoint) = endpoint {
             tokio::spawn(async move {
                 match handle.map() {
                     Ok(mapped) => {
-                        self.send_trace_v04(&headers, mapped.as_slice(), &endpoint);
+                        self.send_trace_v04(
+                            &headers,
+                            mapped.as_slice(),
+                            &endpoint,
+                            stats_endpoint.as_ref(),
+                        );
                     }
                     Err(e) => error!("Failed mapping shared trace data memory: {}", e),
                 }
@@ -987,48 +1734,258 @@ impl SidecarInterface for SidecarServer {
         self,
         _: Context,
         instance_id: InstanceId,
-        data: Vec<u8>,
+        data: ByteBuf,
         headers: SerializedTracerHeaderTags,
     ) -> Self::SendTraceV04BytesFut {
-        if let Some(endpoint) = self
-            .get_session(&instance_id.session_id)
-            .get_trace_config()
-            .endpoint
-            .clone()
-        {
+        let (endpoint, stats_endpoint) = {
+            let config = self.get_session(&instance_id.session_id);
+            let config = config.get_trace_config();
+            (config.endpoint.clone(), config.stats_endpoint.clone())
+        };
+        if let Some(endpoint) = endpoint {
             tokio::spawn(async move {
-                self.send_trace_v04(&headers, data.as_slice(), &endpoint);
+                self.send_trace_v04(
+                    &headers,
+                    data.as_slice(),
+                    &endpoint,
+                    stats_endpoint.as_ref(),
+                );
             });
         }
 
         no_response()
     }
+
+    type RegisterTraceRingBufferFut = NoResponse;
+
+    fn register_trace_ring_buffer(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        ring_buffer: RingBufferHandle,
+        headers: SerializedTracerHeaderTags,
+    ) -> Self::RegisterTraceRingBufferFut {
+        let session = self.get_session(&instance_id.session_id);
+        let runtime = session.get_runtime(&instance_id.runtime_id);
+        let (stop, mut stopped) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut receiver = match ring_buffer.into_receiver() {
+                Ok(receiver) => receiver,
+                Err(e) => return error!("Failed mapping trace ring buffer: {}", e),
+            };
+            let forward = |data: Vec<u8>| {
+                let (endpoint, stats_endpoint) = {
+                    let config = session.get_trace_config();
+                    (config.endpoint.clone(), config.stats_endpoint.clone())
+                };
+                if let Some(endpoint) = endpoint {
+                    self.send_trace_v04(&headers, &data, &endpoint, stats_endpoint.as_ref());
+                }
+            };
+            loop {
+                let data = select! {
+                    data = receiver.recv() => data,
+                    _ = &mut stopped => break,
+                };
+                match data {
+                    Ok(data) => forward(data),
+                    Err(e) => return error!("Failed reading trace ring buffer: {}", e),
+                }
+            }
+            // the traces pushed before the runtime was shut down
+            loop {
+                match receiver.try_recv() {
+                    Ok(Some(data)) => forward(data),
+                    Ok(None) => return,
+                    Err(e) => return error!("Failed reading trace ring buffer: {}", e),
+                }
+            }
+        });
+        runtime
+            .trace_ring_buffers
+            .lock()
+            .unwrap()
+            .push(TraceRingBufferReader { stop, task });
+
+        no_response()
+    }
+
+    type SendProfileShmFut = NoResponse;
+
+    fn send_profile_shm(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        handle: ShmHandle,
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    ) -> Self::SendProfileShmFut {
+        tokio::spawn(async move {
+            match handle.map() {
+                Ok(mapped) => {
+                    self.send_profile(&instance_id, mapped.as_slice(), attachments, meta);
+                }
+                Err(e) => error!("Failed mapping shared profile data memory: {}", e),
+            }
+        });
+
+        no_response()
+    }
+
+    type SendProfileBytesFut = NoResponse;
+
+    fn send_profile_bytes(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        data: ByteBuf,
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    ) -> Self::SendProfileBytesFut {
+        self.send_profile(&instance_id, data.as_slice(), attachments, meta);
+
+        no_response()
+    }
+
+    type DrainFut = BoxFuture<'static, ()>;
+
+    fn drain(self, _: Context) -> Self::DrainFut {
+        Box::pin(async move { SidecarServer::drain(&self).await })
+    }
+
+    type DumpStateFut = Ready<SidecarState>;
+
+    fn dump_state(self, _: Context) -> Self::DumpStateFut {
+        future::ready(SidecarServer::dump_state(&self))
+    }
+
+    type SubscribeRemoteConfigFut = NoResponse;
+
+    fn subscribe_remote_config(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        target: RemoteConfigTarget,
+    ) -> Self::SubscribeRemoteConfigFut {
+        let session = self.get_session(&instance_id.session_id);
+        let endpoint = session
+            .config
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|config| config.endpoint.clone());
+        match endpoint {
+            Some(endpoint) => {
+                if let Err(e) = self.remote_configs.subscribe(
+                    &instance_id.session_id,
+                    &instance_id.runtime_id,
+                    &endpoint,
+                    target,
+                ) {
+                    warn!("Can't subscribe to the remote configuration: {e:?}");
+                }
+            }
+            None => warn!(
+                "Runtime {} subscribed to the remote configuration before the session was configured",
+                instance_id.runtime_id
+            ),
+        }
+
+        no_response()
+    }
+
+    type AcknowledgeRemoteConfigFut = NoResponse;
+
+    fn acknowledge_remote_config(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        target: RemoteConfigTarget,
+        acks: Vec<RemoteConfigAck>,
+    ) -> Self::AcknowledgeRemoteConfigFut {
+        let session = self.get_session(&instance_id.session_id);
+        let endpoint = session
+            .config
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|config| config.endpoint.clone());
+        if let Some(endpoint) = endpoint {
+            if let Err(e) = self.remote_configs.acknowledge(
+                &instance_id.session_id,
+                &instance_id.runtime_id,
+                &endpoint,
+                target,
+                acks,
+            ) {
+                warn!("Can't acknowledge the remote configuration: {e:?}");
+            }
+        }
+
+        no_response()
+    }
+
+    type TelemetryStatsFut = Ready<HashMap<String, TelemetryWorkerStats>>;
+
+    fn telemetry_stats(self, _: Context, instance_id: InstanceId) -> Self::TelemetryStatsFut {
+        let stats = self
+            .find_runtime(&instance_id)
+            .map(|runtime| runtime.telemetry_stats())
+            .unwrap_or_default();
+        future::ready(stats)
+    }
 }
 
 pub mod blocking {
-    use datadog_ipc::platform::ShmHandle;
+    use datadog_ipc::platform::{ring_buffer::RingBufferHandle, ShmHandle};
     use std::{
         borrow::Cow,
+        collections::HashMap,
         io,
+        sync::atomic::{AtomicU64, Ordering},
         time::{Duration, Instant},
     };
 
-    use datadog_ipc::transport::blocking::BlockingTransport;
+    use datadog_ipc::transport::{
+        blocking::BlockingTransport, reconnecting::ReconnectingTransport,
+    };
 
+    use crate::dump::SidecarState;
     use crate::interface::{SerializedTracerHeaderTags, SessionConfig};
-    use ddtelemetry::worker::TelemetryActions;
+    use crate::profiling::{ProfileAttachment, ProfileMetadata};
+    use crate::remote_config::{RemoteConfigAck, RemoteConfigTarget};
+    use ddtelemetry::worker::{stats::TelemetryWorkerStats, TelemetryActions};
 
     use super::{
         InstanceId, QueueId, RuntimeMeta, SidecarInterfaceRequest, SidecarInterfaceResponse,
     };
+    use serde_bytes::ByteBuf;
 
-    pub type SidecarTransport =
+    /// A single connection to the sidecar
+    pub type SidecarConnection =
         BlockingTransport<SidecarInterfaceResponse, SidecarInterfaceRequest>;
 
+    /// Connects again to a restarted sidecar, which is then sent the configuration of the
+    /// sessions and the services and subscriptions of the runtimes again
+    pub type SidecarTransport =
+        ReconnectingTransport<SidecarInterfaceResponse, SidecarInterfaceRequest>;
+
+    static RING_BUFFER_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    /// Prefix of the keys of the replayed messages of a session
+    fn session_key(session_id: &str) -> String {
+        format!("{session_id}/")
+    }
+
+    fn runtime_key(instance_id: &InstanceId) -> String {
+        format!("{}/{}/", instance_id.session_id, instance_id.runtime_id)
+    }
+
     pub fn shutdown_runtime(
         transport: &mut SidecarTransport,
         instance_id: &InstanceId,
     ) -> io::Result<()> {
+        transport.forget_state(&runtime_key(instance_id));
         transport.send(SidecarInterfaceRequest::ShutdownRuntime {
             instance_id: instance_id.clone(),
         })
@@ -1038,6 +1995,7 @@ pub mod blocking {
         transport: &mut SidecarTransport,
         session_id: String,
     ) -> io::Result<()> {
+        transport.forget_state(&session_key(&session_id));
         transport.send(SidecarInterfaceRequest::ShutdownSession { session_id })
     }
 
@@ -1061,14 +2019,21 @@ pub mod blocking {
         runtime_metadata: &RuntimeMeta,
         service_name: Cow<str>,
     ) -> io::Result<()> {
-        transport.send(
+        let key = format!("{}service/{}", runtime_key(instance_id), queue_id.inner);
+        let instance_id = instance_id.clone();
+        let queue_id = *queue_id;
+        let meta = runtime_metadata.clone();
+        let service_name = service_name.into_owned();
+        transport.send_state(key, move |resent| {
             SidecarInterfaceRequest::RegisterServiceAndFlushQueuedActions {
                 instance_id: instance_id.clone(),
-                queue_id: *queue_id,
-                meta: runtime_metadata.clone(),
-                service_name: service_name.into_owned(),
-            },
-        )
+                queue_id,
+                meta: meta.clone(),
+                service_name: service_name.clone(),
+                // a previous sidecar already told the app started
+                already_started: resent,
+            }
+        })
     }
 
     pub fn set_session_config(
@@ -1076,9 +2041,12 @@ pub mod blocking {
         session_id: String,
         config: &SessionConfig,
     ) -> io::Result<()> {
-        transport.send(SidecarInterfaceRequest::SetSessionConfig {
-            session_id,
-            config: config.clone(),
+        let config = config.clone();
+        transport.send_state(session_key(&session_id), move |_| {
+            SidecarInterfaceRequest::SetSessionConfig {
+                session_id: session_id.clone(),
+                config: config.clone(),
+            }
         })
     }
 
@@ -1090,7 +2058,7 @@ pub mod blocking {
     ) -> io::Result<()> {
         transport.send(SidecarInterfaceRequest::SendTraceV04Bytes {
             instance_id: instance_id.clone(),
-            data,
+            data: ByteBuf::from(data),
             headers,
         })
     }
@@ -1108,6 +2076,119 @@ pub mod blocking {
         })
     }
 
+    /// The traces pushed to the ring buffer are then sent as with send_trace_v04_bytes, until the
+    /// runtime is shut down. Fails with an Unsupported error if the sidecar can't read it.
+    pub fn register_trace_ring_buffer(
+        transport: &mut SidecarTransport,
+        instance_id: &InstanceId,
+        ring_buffer: RingBufferHandle,
+        headers: SerializedTracerHeaderTags,
+    ) -> io::Result<()> {
+        let key = format!(
+            "{}trace-ring-buffer/{}",
+            runtime_key(instance_id),
+            RING_BUFFER_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let instance_id = instance_id.clone();
+        transport.send_state(key, move |_| {
+            SidecarInterfaceRequest::RegisterTraceRingBuffer {
+                instance_id: instance_id.clone(),
+                ring_buffer: ring_buffer.clone(),
+                headers: headers.clone(),
+            }
+        })
+    }
+
+    pub fn send_profile_bytes(
+        transport: &mut SidecarTransport,
+        instance_id: &InstanceId,
+        data: Vec<u8>,
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    ) -> io::Result<()> {
+        transport.send(SidecarInterfaceRequest::SendProfileBytes {
+            instance_id: instance_id.clone(),
+            data: ByteBuf::from(data),
+            attachments,
+            meta,
+        })
+    }
+
+    pub fn send_profile_shm(
+        transport: &mut SidecarTransport,
+        instance_id: &InstanceId,
+        handle: ShmHandle,
+        attachments: Vec<ProfileAttachment>,
+        meta: ProfileMetadata,
+    ) -> io::Result<()> {
+        transport.send(SidecarInterfaceRequest::SendProfileShm {
+            instance_id: instance_id.clone(),
+            handle,
+            attachments,
+            meta,
+        })
+    }
+
+    /// The configurations are then read with remote_config::new_reader, once received
+    pub fn subscribe_remote_config(
+        transport: &mut SidecarTransport,
+        instance_id: &InstanceId,
+        target: RemoteConfigTarget,
+    ) -> io::Result<()> {
+        let key = format!("{}remote-config", runtime_key(instance_id));
+        let instance_id = instance_id.clone();
+        transport.send_state(key, move |_| {
+            SidecarInterfaceRequest::SubscribeRemoteConfig {
+                instance_id: instance_id.clone(),
+                target: target.clone(),
+            }
+        })
+    }
+
+    /// Reports how the runtime applied the configurations of a target it subscribed to
+    pub fn acknowledge_remote_config(
+        transport: &mut SidecarTransport,
+        instance_id: &InstanceId,
+        target: RemoteConfigTarget,
+        acks: Vec<RemoteConfigAck>,
+    ) -> io::Result<()> {
+        transport.send(SidecarInterfaceRequest::AcknowledgeRemoteConfig {
+            instance_id: instance_id.clone(),
+            target,
+            acks,
+        })
+    }
+
+    /// Stats of the telemetry workers of the runtime, by service name
+    pub fn telemetry_stats(
+        transport: &mut SidecarTransport,
+        instance_id: &InstanceId,
+    ) -> io::Result<HashMap<String, TelemetryWorkerStats>> {
+        let res = transport.call(SidecarInterfaceRequest::TelemetryStats {
+            instance_id: instance_id.clone(),
+        })?;
+        if let SidecarInterfaceResponse::TelemetryStats(stats) = res {
+            Ok(stats)
+        } else {
+            Err(unexpected_response("telemetry_stats"))
+        }
+    }
+
+    /// Returns once the sidecar flushed its data and stopped accepting connections
+    pub fn drain(transport: &mut SidecarTransport) -> io::Result<()> {
+        transport.call(SidecarInterfaceRequest::Drain {})?;
+        Ok(())
+    }
+
+    pub fn dump_state(transport: &mut SidecarTransport) -> io::Result<SidecarState> {
+        let res = transport.call(SidecarInterfaceRequest::DumpState {})?;
+        if let SidecarInterfaceResponse::DumpState(state) = res {
+            Ok(state)
+        } else {
+            Err(unexpected_response("dump_state"))
+        }
+    }
+
     pub fn ping(transport: &mut SidecarTransport) -> io::Result<Duration> {
         let start = Instant::now();
         transport.call(SidecarInterfaceRequest::Ping {})?;
@@ -1116,4 +2197,402 @@ pub mod blocking {
             .checked_duration_since(start)
             .unwrap_or_default())
     }
+
+    fn unexpected_response(request: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected response of the sidecar to {request}"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)] // the users are made up from uids
+    fn test_dump_state() {
+        let server = SidecarServer::default();
+        *server.trace_flusher.last_error.lock().unwrap() =
+            Some("sending traces to http://agent:8126".into());
+        let sidecar_uid = current_user();
+        let peer = PeerCredentials {
+            uid: sidecar_uid.wrapping_add(1),
+            gid: 1000,
+            pid: None,
+        };
+        let other_user = PeerCredentials {
+            uid: sidecar_uid.wrapping_add(2),
+            ..peer
+        };
+        for (session_id, owner) in [("mine", &peer), ("theirs", &other_user)] {
+            let session = server.get_session(&session_id.to_owned());
+            *session.config.lock().unwrap() = Some(SessionConfig {
+                endpoint: Endpoint {
+                    url: hyper::Uri::from_static("https://datadoghq.com"),
+                    api_key: Some("secret".into()),
+                },
+                flush_interval: Duration::from_secs(1),
+                force_flush_size: 0,
+                force_drop_size: 0,
+            });
+            session.get_runtime(&"runtime".to_owned());
+            assert!(server.session_owners.authorize(session_id, owner));
+        }
+
+        let state = SidecarServer::dump_state(&SidecarServer {
+            peer: Some(peer.clone()),
+            ..server.clone()
+        });
+        assert_eq!(vec!["mine"], state.sessions.keys().collect::<Vec<_>>());
+        let session = &state.sessions["mine"];
+        assert!(session.runtimes.contains_key("runtime"));
+        let api_key = session.config.as_ref().unwrap().endpoint.api_key.as_deref();
+        assert_eq!(Some("<redacted>"), api_key);
+        assert_eq!(None, state.traces.last_error);
+
+        let state = SidecarServer::dump_state(&SidecarServer {
+            peer: Some(PeerCredentials {
+                uid: sidecar_uid,
+                ..peer
+            }),
+            ..server.clone()
+        });
+        assert!(state.sessions.is_empty());
+        assert!(state.traces.last_error.is_some());
+
+        assert_eq!(2, SidecarServer::dump_state(&server).sessions.len());
+    }
+
+    #[test]
+    fn test_malformed_tracer_header_tags() {
+        let serialized: SerializedTracerHeaderTags = TracerHeaderTags {
+            lang: "php",
+            ..Default::default()
+        }
+        .into();
+        assert_eq!("php", TracerHeaderTags::try_from(&serialized).unwrap().lang);
+
+        // escaped values can't be borrowed
+        let serialized: SerializedTracerHeaderTags = TracerHeaderTags {
+            lang_version: "8.2 \"zts\"",
+            ..Default::default()
+        }
+        .into();
+        assert!(TracerHeaderTags::try_from(&serialized).is_err());
+        // the traces are dropped rather than bringing the sidecar down
+        SidecarServer::default().send_trace_v04(
+            &serialized,
+            &[0x91, 0x90],
+            &Endpoint {
+                url: hyper::Uri::from_static("http://localhost:8126/v0.4/traces"),
+                api_key: None,
+            },
+            None,
+        );
+    }
+
+    #[test]
+    fn test_telemetry_stats_of_unknown_runtimes() {
+        let server = SidecarServer::default();
+        let instance_id = InstanceId::new("session", "runtime");
+        assert!(server
+            .clone()
+            .telemetry_stats(tarpc::context::current(), instance_id.clone())
+            .now_or_never()
+            .unwrap()
+            .is_empty());
+        assert_eq!(0, server.sessions.lock().unwrap().len());
+
+        // apps still starting are skipped rather than waited for
+        let runtime = server.get_runtime(&instance_id);
+        let (_, _completer) = runtime.get_app(&"service".to_string());
+        assert!(server
+            .telemetry_stats(tarpc::context::current(), instance_id)
+            .now_or_never()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stats_fall_back_to_the_agent() {
+        let flusher = Arc::new(TraceFlusher::default());
+        // nothing listens there
+        let endpoint = Endpoint {
+            url: hyper::Uri::from_static("http://127.0.0.1:1/v0.6/stats"),
+            api_key: None,
+        };
+        assert!(!flusher.computes_stats_for(&endpoint));
+        assert!(flusher.stats.lock().unwrap().endpoints.is_empty());
+
+        flusher.client_computed_stats.store(true, Ordering::Relaxed);
+        assert!(!flusher.computes_stats_for(&endpoint));
+        flusher
+            .send_stats(endpoint.clone(), pb::ClientStatsPayload::default())
+            .await;
+        assert!(matches!(
+            flusher.stats.lock().unwrap().endpoints.get(&endpoint),
+            Some(StatsEndpointState::Failing(_))
+        ));
+        assert!(!flusher.computes_stats_for(&endpoint));
+        assert!(flusher.last_error.lock().unwrap().is_some());
+
+        flusher
+            .stats
+            .lock()
+            .unwrap()
+            .endpoints
+            .insert(endpoint.clone(), StatsEndpointState::Accepting);
+        assert!(flusher.computes_stats_for(&endpoint));
+    }
+
+    #[tokio::test]
+    async fn test_replay_drops_refused_payloads() {
+        use crate::trace_spool::SpoolConfig;
+        use datadog_trace_protobuf::pb::TracerPayload;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = vec![0; 65536];
+                _ = socket.read(&mut buf).await;
+                _ = socket
+                    .write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+        let refusing = Endpoint {
+            url: format!("http://127.0.0.1:{port}/v0.4/traces")
+                .parse()
+                .unwrap(),
+            api_key: None,
+        };
+        // nothing listens there
+        let unreachable = Endpoint {
+            url: hyper::Uri::from_static("http://127.0.0.1:1/v0.4/traces"),
+            api_key: None,
+        };
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut spool = TraceSpool::open(SpoolConfig {
+            dir: tmpdir.path().to_path_buf(),
+            max_bytes: u64::MAX,
+            max_age: Duration::from_secs(3600),
+        })
+        .unwrap();
+        for endpoint in [&refusing, &refusing, &unreachable] {
+            let payload = TracerPayload::default();
+            let data = SendData::new(10, payload, TracerHeaderTags::default(), endpoint);
+            spool.push(&data).unwrap();
+        }
+        let flusher = Arc::new(TraceFlusher::default());
+        *flusher.spool.lock().unwrap() = Some(spool);
+
+        flusher.replay_spool(&refusing).await.unwrap();
+        assert!(flusher.replay_spool(&unreachable).await.is_err());
+        let stats = flusher.state().spool.unwrap();
+        assert_eq!(2, stats.rejected_payloads);
+        assert_eq!(1, stats.pending_payloads);
+    }
+
+    #[tokio::test]
+    async fn test_ring_buffer_is_drained_on_shutdown() {
+        use datadog_ipc::platform::ring_buffer::{OverflowPolicy, RingBufferHandle};
+
+        let server = SidecarServer::default();
+        server
+            .trace_flusher
+            .interval
+            .store(3_600_000, Ordering::Relaxed);
+        for size in [
+            &server.trace_flusher.min_force_flush_size,
+            &server.trace_flusher.min_force_drop_size,
+        ] {
+            size.store(u32::MAX, Ordering::Relaxed);
+        }
+        let session = server.get_session(&"session".to_owned());
+        session.modify_trace_config(|cfg| {
+            cfg.endpoint = Some(Endpoint {
+                url: hyper::Uri::from_static("http://127.0.0.1:1/v0.4/traces"),
+                api_key: None,
+            })
+        });
+
+        let ring_buffer = RingBufferHandle::new(4096).unwrap();
+        let sender = ring_buffer.sender(OverflowPolicy::Reject).unwrap();
+        _ = server.clone().register_trace_ring_buffer(
+            tarpc::context::current(),
+            InstanceId::new("session", "runtime"),
+            ring_buffer,
+            TracerHeaderTags::default().into(),
+        );
+        let traces = vec![vec![pb::Span {
+            name: "span".to_string(),
+            ..Default::default()
+        }]];
+        sender
+            .push(&rmp_serde::to_vec_named(&traces).unwrap())
+            .unwrap();
+
+        // the reader did not run yet
+        session.shutdown_runtime(&"runtime".to_owned()).await;
+        assert_eq!(1, server.trace_flusher.state().pending_payloads);
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_drain_reserved_to_the_sidecar_owner() {
+        let (_client, socket) = tokio::net::UnixStream::pair().unwrap();
+        let peer = PeerCredentials::of(&socket).unwrap();
+        let foreign = PeerCredentials {
+            uid: peer.uid + 1,
+            ..peer
+        };
+        let owners = SessionOwners::default();
+        let request = |message| tarpc::Request {
+            context: tarpc::context::current(),
+            id: 0,
+            message,
+        };
+
+        let drain = request(SidecarInterfaceRequest::Drain {}).extract_identifier();
+        assert!(matches!(drain, RequestIdentifier::SidecarOwner));
+        assert!(authorize_request(&owners, &drain, &peer));
+        assert!(!authorize_request(&owners, &drain, &foreign));
+
+        let ping = request(SidecarInterfaceRequest::Ping {}).extract_identifier();
+        assert!(authorize_request(&owners, &ping, &foreign));
+
+        let shutdown = request(SidecarInterfaceRequest::ShutdownSession {
+            session_id: "session".into(),
+        })
+        .extract_identifier();
+        assert!(authorize_request(&owners, &shutdown, &peer));
+        assert!(!authorize_request(&owners, &shutdown, &foreign));
+    }
+
+    #[test]
+    fn test_protocol_version_is_bumped_with_the_interface() {
+        use datadog_ipc::transport::Versioned;
+
+        // when the interface changes, bump its protocol version, then update its signature
+        assert_eq!(
+            (2, 0x6bf9ab0420eabc8e),
+            (
+                SidecarInterfaceRequest::PROTOCOL_VERSION,
+                SidecarInterfaceRequest::PROTOCOL_SIGNATURE
+            )
+        );
+    }
+
+    #[test]
+    fn test_requests_roundtrip_through_codecs() {
+        use datadog_ipc::transport::Codec;
+
+        let instance_id = InstanceId::new("session", "runtime");
+        let requests = vec![
+            SidecarInterfaceRequest::EqueueActions {
+                instance_id: instance_id.clone(),
+                queue_id: QueueId::new_unique(),
+                actions: vec![
+                    TelemetryActions::AddConfig(data::Configuration {
+                        name: "DD_TRACE_ENABLED".to_string(),
+                        value: "true".to_string(),
+                        origin: data::ConfigurationOrigin::EnvVar,
+                        error: None,
+                        seq_id: Some(1),
+                    }),
+                    TelemetryActions::AddDependecy(data::Dependency {
+                        name: "dependency".to_string(),
+                        version: None,
+                    }),
+                    TelemetryActions::Lifecycle(LifecycleAction::Start),
+                ],
+            },
+            SidecarInterfaceRequest::SetSessionConfig {
+                session_id: "session".to_string(),
+                config: SessionConfig {
+                    endpoint: Endpoint {
+                        url: hyper::Uri::from_static("http://localhost:8126"),
+                        api_key: None,
+                    },
+                    flush_interval: Duration::from_millis(1500),
+                    force_flush_size: 1000,
+                    force_drop_size: 2000,
+                },
+            },
+            SidecarInterfaceRequest::SendTraceV04Bytes {
+                instance_id: instance_id.clone(),
+                data: ByteBuf::from(vec![0x91, 0x90, 0xff]),
+                headers: TracerHeaderTags {
+                    lang: "php",
+                    ..Default::default()
+                }
+                .into(),
+            },
+            SidecarInterfaceRequest::SendProfileBytes {
+                instance_id: instance_id.clone(),
+                data: ByteBuf::from(vec![1, 2, 3]),
+                attachments: vec![ProfileAttachment {
+                    name: "profile.pprof".to_string(),
+                    len: 3,
+                }],
+                meta: ProfileMetadata {
+                    profiling_library_name: "library".to_string(),
+                    profiling_library_version: "1.0".to_string(),
+                    family: "php".to_string(),
+                    start: SystemTime::UNIX_EPOCH,
+                    end: SystemTime::now(),
+                    tags: vec![],
+                    endpoint_counts: HashMap::from([("/".to_string(), 2)]),
+                    internal_metadata: None,
+                },
+            },
+            SidecarInterfaceRequest::SubscribeRemoteConfig {
+                instance_id: instance_id.clone(),
+                target: RemoteConfigTarget {
+                    service: "service".to_string(),
+                    products: vec![crate::remote_config::RemoteConfigProduct::ApmTracing],
+                    ..Default::default()
+                },
+            },
+            SidecarInterfaceRequest::AcknowledgeRemoteConfig {
+                instance_id,
+                target: RemoteConfigTarget::default(),
+                acks: vec![crate::remote_config::RemoteConfigAck {
+                    product: crate::remote_config::RemoteConfigProduct::ApmTracing,
+                    id: "1234".to_string(),
+                    version: 3,
+                    error: Some("unsupported".to_string()),
+                }],
+            },
+        ];
+
+        for codec in [Codec::Json, Codec::MessagePack] {
+            for request in &requests {
+                let decoded: SidecarInterfaceRequest = codec
+                    .deserialize(&codec.serialize(request).unwrap())
+                    .unwrap();
+                assert_eq!(
+                    serde_json::to_value(request).unwrap(),
+                    serde_json::to_value(&decoded).unwrap()
+                );
+            }
+        }
+
+        // the payloads are sent as binary, not as arrays of numbers
+        let serialized_len = |data: Vec<u8>| {
+            let request = SidecarInterfaceRequest::SendTraceV04Bytes {
+                instance_id: InstanceId::new("session", "runtime"),
+                data: ByteBuf::from(data),
+                headers: TracerHeaderTags::default().into(),
+            };
+            Codec::MessagePack.serialize(&request).unwrap().len()
+        };
+        let overhead = serialized_len(vec![0xff; 1000]) - serialized_len(vec![]);
+        assert!(overhead <= 1003, "{overhead} bytes");
+    }
 }
@@ -1,18 +1,28 @@
 // Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
-#[cfg(not(windows))]
+pub mod agent_info;
 pub mod agent_remote_config;
-#[cfg(not(windows))]
 pub mod config;
-#[cfg(not(windows))]
+pub mod credentials;
+pub mod dump;
+mod entry;
 pub mod interface;
-#[cfg(not(windows))]
+pub mod profiling;
+pub mod remote_config;
 pub mod setup;
-#[cfg(not(windows))]
+pub mod trace_spool;
 mod tracer;
 
+pub use entry::*;
+
 #[cfg(unix)]
 mod unix;
 
 #[cfg(unix)]
 pub use unix::*;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub use windows::*;
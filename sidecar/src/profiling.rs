@@ -0,0 +1,489 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context;
+use datadog_profiling::exporter::{self, DateTime, File, ProfileExporter, Tag, Utc};
+use datadog_profiling::profile::profiled_endpoints::ProfiledEndpointsStats;
+use ddcommon::Endpoint;
+use http::uri::PathAndQuery;
+use serde::{Deserialize, Serialize};
+use tokio::task::{JoinError, JoinHandle};
+use tracing::{error, info, warn};
+
+use crate::config::get_product_endpoint;
+use crate::dump::ProfileFlusherState;
+
+pub const PROD_INTAKE_SUBDOMAIN: &str = "intake.profile";
+const AGENTLESS_PATH: &str = "/api/v2/profile";
+
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_PROFILES_PER_MINUTE: u32 = 120;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Everything describing a profile, apart from its files
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfileMetadata {
+    pub profiling_library_name: String,
+    pub profiling_library_version: String,
+    pub family: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub tags: Vec<Tag>,
+    pub endpoint_counts: HashMap<String, i64>,
+    /// Serialized json object
+    pub internal_metadata: Option<String>,
+}
+
+/// A file attached to a profile. The files are transmitted back to back, in order, in a single
+/// buffer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfileAttachment {
+    pub name: String,
+    pub len: usize,
+}
+
+pub fn split_attachments(
+    data: &[u8],
+    attachments: Vec<ProfileAttachment>,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut offset = 0;
+    attachments
+        .into_iter()
+        .map(|attachment| {
+            let end = offset + attachment.len;
+            let bytes = data.get(offset..end).with_context(|| {
+                format!(
+                    "attachment {} ends at {end}, past the {} bytes of data",
+                    attachment.name,
+                    data.len()
+                )
+            })?;
+            offset = end;
+            Ok((attachment.name, bytes.to_vec()))
+        })
+        .collect()
+}
+
+/// The profiling intake endpoint, for a session sending data to `endpoint`
+pub fn profiling_endpoint(endpoint: &Endpoint) -> anyhow::Result<Endpoint> {
+    if endpoint.api_key.is_some() {
+        let endpoint = get_product_endpoint(PROD_INTAKE_SUBDOMAIN, endpoint);
+        let mut parts = endpoint.url.into_parts();
+        parts.path_and_query = Some(PathAndQuery::from_static(AGENTLESS_PATH));
+        Ok(Endpoint {
+            url: hyper::Uri::from_parts(parts)?,
+            api_key: endpoint.api_key,
+        })
+    } else {
+        exporter::config::agent(endpoint.url.clone())
+    }
+}
+
+struct Profile {
+    endpoint: Endpoint,
+    meta: ProfileMetadata,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+type ExporterKey = (String, String, String, Endpoint);
+
+impl Profile {
+    fn exporter_key(&self) -> ExporterKey {
+        (
+            self.meta.profiling_library_name.clone(),
+            self.meta.profiling_library_version.clone(),
+            self.meta.family.clone(),
+            self.endpoint.clone(),
+        )
+    }
+
+    /// Blocking: ProfileExporter drives its own runtime
+    fn upload(&self, exporter: &ProfileExporter) -> anyhow::Result<()> {
+        let files: Vec<File> = self
+            .files
+            .iter()
+            .map(|(name, bytes)| File { name, bytes })
+            .collect();
+        let endpoint_counts: ProfiledEndpointsStats = self.meta.endpoint_counts.clone().into();
+        let internal_metadata: Option<serde_json::Value> = self
+            .meta
+            .internal_metadata
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let request = exporter.build(
+                DateTime::<Utc>::from(self.meta.start),
+                DateTime::<Utc>::from(self.meta.end),
+                &files,
+                Some(&self.meta.tags),
+                (!endpoint_counts.is_empty()).then_some(&endpoint_counts),
+                internal_metadata.clone(),
+                UPLOAD_TIMEOUT,
+            )?;
+            let error = match exporter.send(request, None) {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    // other client errors won't go away by sending the same profile again
+                    if status.is_client_error()
+                        && status != http::StatusCode::REQUEST_TIMEOUT
+                        && status != http::StatusCode::TOO_MANY_REQUESTS
+                    {
+                        anyhow::bail!("profile rejected with status {status}");
+                    }
+                    anyhow::anyhow!("profile upload failed with status {status}")
+                }
+                Err(e) => e,
+            };
+            if attempt == MAX_ATTEMPTS {
+                return Err(error);
+            }
+            warn!(
+                "Retrying profile upload to {}: {error:?}",
+                self.endpoint.url
+            );
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// Returns how many profiles could not be uploaded and were dropped, along with the last error.
+/// The exporters are reused from one flush to the next
+fn upload_profiles(
+    profiles: Vec<Profile>,
+    exporters: &mut HashMap<ExporterKey, ProfileExporter>,
+) -> (u64, Option<String>) {
+    let mut dropped = 0;
+    let mut last_error = None;
+    for profile in profiles {
+        let exporter = match exporters.entry(profile.exporter_key()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (name, version, family, endpoint) = entry.key().clone();
+                match ProfileExporter::new(name, version, family, None, endpoint) {
+                    Ok(exporter) => entry.insert(exporter),
+                    Err(e) => {
+                        error!("Error creating profile exporter: {e:?}");
+                        last_error = Some(format!("creating profile exporter: {e}"));
+                        dropped += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+        match profile.upload(exporter) {
+            Ok(()) => info!("Successfully sent profile to {}", profile.endpoint.url),
+            Err(e) => {
+                error!("Error sending profile: {e:?}");
+                last_error = Some(format!("sending profile to {}: {e}", profile.endpoint.url));
+                dropped += 1;
+            }
+        }
+    }
+    (dropped, last_error)
+}
+
+struct RateLimit {
+    window_start: Instant,
+    count: u32,
+}
+
+impl RateLimit {
+    fn new() -> Self {
+        RateLimit {
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    fn allow(&mut self, max: u32) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= RATE_LIMIT_WINDOW {
+            self.window_start = now;
+            self.count = 0;
+        }
+        if self.count >= max {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+#[derive(Default)]
+struct ProfileFlusherData {
+    profiles: Vec<Profile>,
+    rate_limits: HashMap<String, RateLimit>,
+    exporters: HashMap<ExporterKey, ProfileExporter>,
+    flusher: Option<JoinHandle<()>>,
+    last_error: Option<String>,
+    dropped_profiles: u64,
+}
+
+/// Batches the profiles received from all sessions and uploads them in the background
+pub struct ProfileFlusher {
+    inner: Mutex<ProfileFlusherData>,
+    pub interval: AtomicU64,
+    pub max_profiles_per_minute: AtomicU32, // per session
+}
+
+impl Default for ProfileFlusher {
+    fn default() -> Self {
+        ProfileFlusher {
+            inner: Mutex::default(),
+            interval: AtomicU64::new(DEFAULT_FLUSH_INTERVAL.as_millis() as u64),
+            max_profiles_per_minute: AtomicU32::new(DEFAULT_MAX_PROFILES_PER_MINUTE),
+        }
+    }
+}
+
+impl ProfileFlusher {
+    fn start_profile_flusher(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(self.interval.load(Ordering::Relaxed)))
+                    .await;
+
+                let (profiles, mut exporters) = {
+                    let mut data = self.inner.lock().unwrap();
+                    (
+                        std::mem::take(&mut data.profiles),
+                        std::mem::take(&mut data.exporters),
+                    )
+                };
+                let count = profiles.len() as u64;
+                let upload = tokio::task::spawn_blocking(move || {
+                    let (dropped, last_error) = upload_profiles(profiles, &mut exporters);
+                    (dropped, last_error, exporters)
+                });
+                let (dropped, last_error, exporters) = match upload.await {
+                    Ok(uploaded) => uploaded,
+                    Err(e) => {
+                        error!("Error uploading profiles: {e:?}");
+                        let error = Some(format!("uploading profiles: {e}"));
+                        (count, error, HashMap::new())
+                    }
+                };
+
+                let mut data = self.inner.lock().unwrap();
+                data.exporters = exporters;
+                data.dropped_profiles += dropped;
+                if last_error.is_some() {
+                    data.last_error = last_error;
+                }
+                if data.profiles.is_empty() {
+                    data.flusher = None;
+                    break;
+                }
+            }
+        })
+    }
+
+    pub fn enqueue(
+        self: &Arc<Self>,
+        session_id: &str,
+        endpoint: Endpoint,
+        meta: ProfileMetadata,
+        files: Vec<(String, Vec<u8>)>,
+    ) {
+        let mut data = self.inner.lock().unwrap();
+        let data = data.deref_mut();
+
+        let max = self.max_profiles_per_minute.load(Ordering::Relaxed);
+        if !data
+            .rate_limits
+            .entry(session_id.to_owned())
+            .or_insert_with(RateLimit::new)
+            .allow(max)
+        {
+            warn!("Dropping profile of session {session_id}, which sent more than {max} profiles in the last minute");
+            data.dropped_profiles += 1;
+            return;
+        }
+
+        data.profiles.push(Profile {
+            endpoint,
+            meta,
+            files,
+        });
+        if data.flusher.is_none() {
+            data.flusher = Some(self.clone().start_profile_flusher());
+        }
+    }
+
+    /// Counts a profile which could not be enqueued
+    pub fn drop_profile(&self, session_id: &str, reason: &str) {
+        warn!("Dropping profile of session {session_id}: {reason}");
+        self.inner.lock().unwrap().dropped_profiles += 1;
+    }
+
+    pub fn remove_session(&self, session_id: &str) {
+        self.inner.lock().unwrap().rate_limits.remove(session_id);
+    }
+
+    pub fn state(&self) -> ProfileFlusherState {
+        let data = self.inner.lock().unwrap();
+        ProfileFlusherState {
+            pending_profiles: data.profiles.len(),
+            flushing: data.flusher.is_some(),
+            last_error: data.last_error.clone(),
+            dropped_profiles: data.dropped_profiles,
+        }
+    }
+
+    pub async fn join(&self) -> Result<(), JoinError> {
+        let flusher = {
+            let mut data = self.inner.lock().unwrap();
+            self.interval.store(0, Ordering::SeqCst);
+            data.flusher.take()
+        };
+        if let Some(flusher) = flusher {
+            flusher.await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_attachments() {
+        let attachments = vec![
+            ProfileAttachment {
+                name: "profile.pprof".into(),
+                len: 3,
+            },
+            ProfileAttachment {
+                name: "metrics.json".into(),
+                len: 2,
+            },
+        ];
+        let files = split_attachments(b"abcdef", attachments.clone()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("profile.pprof".to_owned(), b"abc".to_vec()),
+                ("metrics.json".to_owned(), b"de".to_vec())
+            ]
+        );
+
+        assert!(split_attachments(b"abcd", attachments).is_err());
+    }
+
+    #[test]
+    fn test_profiling_endpoint() {
+        let agent = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8126/"),
+            api_key: None,
+        };
+        assert_eq!(
+            profiling_endpoint(&agent).unwrap().url,
+            "http://localhost:8126/profiling/v1/input"
+        );
+
+        let agentless = Endpoint {
+            url: hyper::Uri::from_static("datadoghq.com"),
+            api_key: Some("key".into()),
+        };
+        assert_eq!(
+            profiling_endpoint(&agentless).unwrap().url,
+            "https://intake.profile.datadoghq.com/api/v2/profile"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropped_profiles() {
+        let flusher = Arc::new(ProfileFlusher::default());
+        flusher.max_profiles_per_minute.store(0, Ordering::Relaxed);
+        let meta = ProfileMetadata {
+            profiling_library_name: "library".into(),
+            profiling_library_version: "1.0".into(),
+            family: "php".into(),
+            start: SystemTime::now(),
+            end: SystemTime::now(),
+            tags: vec![],
+            endpoint_counts: HashMap::new(),
+            internal_metadata: None,
+        };
+        let endpoint = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8126/profiling/v1/input"),
+            api_key: None,
+        };
+        flusher.enqueue("session", endpoint, meta, vec![]);
+        flusher.drop_profile("session", "no profiling endpoint is configured");
+
+        let state = flusher.state();
+        assert_eq!(state.dropped_profiles, 2);
+        assert_eq!(state.pending_profiles, 0);
+        assert!(!state.flushing);
+    }
+
+    #[test]
+    fn test_failed_uploads_are_dropped() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut socket in listener.incoming().flatten() {
+                let mut buf = vec![0; 65536];
+                _ = socket.read(&mut buf);
+                _ = socket.write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\n\r\n");
+            }
+        });
+        let profile = Profile {
+            endpoint: Endpoint {
+                url: format!("http://127.0.0.1:{port}/profiling/v1/input")
+                    .parse()
+                    .unwrap(),
+                api_key: None,
+            },
+            meta: ProfileMetadata {
+                profiling_library_name: "library".into(),
+                profiling_library_version: "1.0".into(),
+                family: "php".into(),
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                tags: vec![],
+                endpoint_counts: HashMap::new(),
+                internal_metadata: None,
+            },
+            files: vec![("profile.pprof".into(), b"abc".to_vec())],
+        };
+
+        let (dropped, last_error) = upload_profiles(vec![profile], &mut HashMap::new());
+        assert_eq!(dropped, 1);
+        assert!(last_error.unwrap().contains("400"));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::new();
+        assert!(limit.allow(2));
+        assert!(limit.allow(2));
+        assert!(!limit.allow(2));
+
+        limit.window_start -= RATE_LIMIT_WINDOW;
+        assert!(limit.allow(2));
+    }
+}
@@ -0,0 +1,979 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! Remote Configuration client, polling the agent on behalf of all the tracers interested in the
+//! configuration of a service. The configurations applying to the tracers are delivered in a
+//! shared memory, whose generation changes with every update.
+//!
+//! The agent verifies the TUF signatures of the metadata it relays; the client checks that the
+//! target files match the hashes in the targets metadata, and keeps the files it was given for as
+//! long as they apply, so that the agent only sends the changes.
+//!
+//! The configurations are reported to the agent as unacknowledged until a tracer acknowledges
+//! them, or reports an error applying them. With several tracers sharing a client, the latest
+//! report of a configuration is the one sent to the agent.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::Engine;
+use datadog_ipc::platform::NamedShmHandle;
+use ddcommon::{connector::Connector, Endpoint};
+use http::uri::PathAndQuery;
+use hyper::{Body, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use zwohash::ZwoHasher;
+
+use crate::agent_info::null_as_default;
+use crate::agent_remote_config::{self, AgentRemoteConfigReader, AgentRemoteConfigWriter};
+
+const CONFIG_PATH: &str = "/v0.7/config";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+const APPLY_STATE_UNACKNOWLEDGED: u64 = 1;
+const APPLY_STATE_ACKNOWLEDGED: u64 = 2;
+const APPLY_STATE_ERROR: u64 = 3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RemoteConfigProduct {
+    ApmTracing,
+    Asm,
+    AsmData,
+    AsmDd,
+    AsmFeatures,
+    LiveDebugging,
+}
+
+impl RemoteConfigProduct {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RemoteConfigProduct::ApmTracing => "APM_TRACING",
+            RemoteConfigProduct::Asm => "ASM",
+            RemoteConfigProduct::AsmData => "ASM_DATA",
+            RemoteConfigProduct::AsmDd => "ASM_DD",
+            RemoteConfigProduct::AsmFeatures => "ASM_FEATURES",
+            RemoteConfigProduct::LiveDebugging => "LIVE_DEBUGGING",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "APM_TRACING" => RemoteConfigProduct::ApmTracing,
+            "ASM" => RemoteConfigProduct::Asm,
+            "ASM_DATA" => RemoteConfigProduct::AsmData,
+            "ASM_DD" => RemoteConfigProduct::AsmDd,
+            "ASM_FEATURES" => RemoteConfigProduct::AsmFeatures,
+            "LIVE_DEBUGGING" => RemoteConfigProduct::LiveDebugging,
+            _ => return None,
+        })
+    }
+}
+
+/// What the tracers receive configurations for. Tracers with the same target share a client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RemoteConfigTarget {
+    pub service: String,
+    pub env: String,
+    pub app_version: String,
+    pub language: String,
+    pub tracer_version: String,
+    pub products: Vec<RemoteConfigProduct>,
+    /// Bitmask of the remote configuration capabilities of the tracer
+    pub capabilities: u64,
+}
+
+/// A configuration applying to the target, as delivered to the tracers: the shared memory holds
+/// a json array of these.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteConfigFile {
+    pub path: String,
+    pub product: RemoteConfigProduct,
+    pub id: String,
+    pub version: u64,
+    pub contents: serde_json::Value,
+}
+
+/// How a tracer applied a configuration it was delivered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteConfigAck {
+    pub product: RemoteConfigProduct,
+    pub id: String,
+    pub version: u64,
+    /// Why the configuration could not be applied, if it could not
+    pub error: Option<String>,
+}
+
+/// Name of the shared memory with the configurations of the target, from the agent at `endpoint`
+pub fn shm_path(endpoint: &Endpoint, target: &RemoteConfigTarget) -> CString {
+    // We need a stable hash so that the outcome is independent of the process
+    let mut hasher = ZwoHasher::default();
+    endpoint.url.authority().unwrap().hash(&mut hasher);
+    for field in [
+        &target.service,
+        &target.env,
+        &target.app_version,
+        &target.language,
+        &target.tracer_version,
+    ] {
+        field.hash(&mut hasher);
+    }
+    for product in &target.products {
+        product.name().hash(&mut hasher);
+    }
+    target.capabilities.hash(&mut hasher);
+    CString::new(format!("/libdatadog-remote-config-{}", hasher.finish())).unwrap()
+}
+
+/// Reads the configurations of the target, as received by the sidecar. Only available once a
+/// tracer subscribed.
+pub fn new_reader(
+    endpoint: &Endpoint,
+    target: &RemoteConfigTarget,
+) -> AgentRemoteConfigReader<NamedShmHandle> {
+    agent_remote_config::new_named_reader(shm_path(endpoint, target))
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct ClientState {
+    root_version: u64,
+    targets_version: u64,
+    config_states: Vec<ConfigState>,
+    has_error: bool,
+    error: String,
+    backend_client_state: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ConfigState {
+    id: String,
+    version: u64,
+    product: String,
+    apply_state: u64,
+    apply_error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TargetFileMeta {
+    path: String,
+    length: u64,
+    hashes: Vec<TargetFileHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TargetFileHash {
+    algorithm: &'static str,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct ClientTracer<'a> {
+    runtime_id: &'a str,
+    language: &'a str,
+    tracer_version: &'a str,
+    service: &'a str,
+    env: &'a str,
+    app_version: &'a str,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ClientInfo<'a> {
+    state: &'a ClientState,
+    id: &'a str,
+    products: Vec<&'static str>,
+    is_tracer: bool,
+    client_tracer: ClientTracer<'a>,
+    /// base64 encoded big endian bitmask
+    capabilities: String,
+}
+
+#[derive(Serialize)]
+struct ClientGetConfigsRequest<'a> {
+    client: ClientInfo<'a>,
+    cached_target_files: Vec<&'a TargetFileMeta>,
+}
+
+/// The binary fields are base64 encoded
+#[derive(Debug, Default, Deserialize)]
+struct ClientGetConfigsResponse {
+    #[serde(default, deserialize_with = "null_as_default")]
+    roots: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    targets: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    target_files: Vec<ResponseFile>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    client_configs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseFile {
+    path: String,
+    raw: String,
+}
+
+#[derive(Deserialize)]
+struct Signed<T> {
+    signed: T,
+}
+
+#[derive(Deserialize)]
+struct Root {
+    version: u64,
+}
+
+#[derive(Deserialize)]
+struct Targets {
+    version: u64,
+    #[serde(default)]
+    custom: TargetsCustom,
+    #[serde(default)]
+    targets: HashMap<String, TargetDescription>,
+}
+
+#[derive(Default, Deserialize)]
+struct TargetsCustom {
+    #[serde(default)]
+    opaque_backend_state: String,
+}
+
+#[derive(Deserialize)]
+struct TargetDescription {
+    length: u64,
+    hashes: HashMap<String, String>,
+    #[serde(default)]
+    custom: TargetDescriptionCustom,
+}
+
+#[derive(Default, Deserialize)]
+struct TargetDescriptionCustom {
+    #[serde(default)]
+    v: u64,
+}
+
+struct CachedFile {
+    meta: TargetFileMeta,
+    raw: Vec<u8>,
+}
+
+/// The product and config id of a target path, which is either
+/// `datadog/<org_id>/<product>/<config_id>/<name>` or `employee/<product>/<config_id>/<name>`
+fn parse_path(path: &str) -> Option<(&str, &str)> {
+    let parts: Vec<&str> = path.split('/').collect();
+    match parts.as_slice() {
+        ["datadog", _, product, id, _] | ["employee", product, id, _] => Some((product, id)),
+        _ => None,
+    }
+}
+
+fn decode_base64(data: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(base64::engine::general_purpose::STANDARD.decode(data)?)
+}
+
+/// Shared by a client and the subscriptions to it
+#[derive(Default)]
+struct Subscribers {
+    /// One of the subscribed runtimes, reported to the agent
+    runtime_id: String,
+    /// The latest reports of the tracers, by product and config id
+    acks: HashMap<(RemoteConfigProduct, String), RemoteConfigAck>,
+}
+
+struct RemoteConfigClient {
+    id: String,
+    endpoint: Endpoint,
+    target: RemoteConfigTarget,
+    subscribers: Arc<Mutex<Subscribers>>,
+    runtime_id: String,
+    state: ClientState,
+    files: HashMap<String, CachedFile>,
+    configs: Vec<RemoteConfigFile>,
+}
+
+impl RemoteConfigClient {
+    fn new(
+        endpoint: Endpoint,
+        target: RemoteConfigTarget,
+        subscribers: Arc<Mutex<Subscribers>>,
+    ) -> Self {
+        RemoteConfigClient {
+            id: uuid::Uuid::new_v4().to_string(),
+            endpoint,
+            target,
+            subscribers,
+            runtime_id: String::new(),
+            state: ClientState {
+                root_version: 1,
+                ..Default::default()
+            },
+            files: HashMap::new(),
+            configs: vec![],
+        }
+    }
+
+    /// Takes over the runtime id and the reports of the subscribed tracers
+    fn sync_subscribers(&mut self) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        self.runtime_id.clone_from(&subscribers.runtime_id);
+
+        let configs = &self.configs;
+        subscribers.acks.retain(|(product, id), _| {
+            configs
+                .iter()
+                .any(|config| config.product == *product && config.id == *id)
+        });
+        for config in configs {
+            let ack = match subscribers.acks.get(&(config.product, config.id.clone())) {
+                Some(ack) if ack.version == config.version => ack,
+                _ => continue,
+            };
+            let state = self.state.config_states.iter_mut().find(|state| {
+                state.product == config.product.name()
+                    && state.id == config.id
+                    && state.version == config.version
+            });
+            if let Some(state) = state {
+                match &ack.error {
+                    None => {
+                        state.apply_state = APPLY_STATE_ACKNOWLEDGED;
+                        state.apply_error = String::new();
+                    }
+                    Some(error) => {
+                        state.apply_state = APPLY_STATE_ERROR;
+                        state.apply_error.clone_from(error);
+                    }
+                }
+            }
+        }
+    }
+
+    fn request(&self) -> ClientGetConfigsRequest<'_> {
+        let capabilities = self.target.capabilities.to_be_bytes();
+        let first_set = capabilities
+            .iter()
+            .position(|byte| *byte != 0)
+            .unwrap_or(capabilities.len() - 1);
+        ClientGetConfigsRequest {
+            client: ClientInfo {
+                state: &self.state,
+                id: &self.id,
+                products: self.target.products.iter().map(|p| p.name()).collect(),
+                is_tracer: true,
+                client_tracer: ClientTracer {
+                    runtime_id: &self.runtime_id,
+                    language: &self.target.language,
+                    tracer_version: &self.target.tracer_version,
+                    service: &self.target.service,
+                    env: &self.target.env,
+                    app_version: &self.target.app_version,
+                    tags: vec![],
+                },
+                capabilities: base64::engine::general_purpose::STANDARD
+                    .encode(&capabilities[first_set..]),
+            },
+            cached_target_files: self.files.values().map(|file| &file.meta).collect(),
+        }
+    }
+
+    /// Processes the response of the agent. Returns whether the configurations changed.
+    fn apply(&mut self, response: ClientGetConfigsResponse) -> anyhow::Result<bool> {
+        if response.targets.is_empty() {
+            // nothing changed since the last request
+            return Ok(false);
+        }
+
+        let mut root_version = self.state.root_version;
+        for root in &response.roots {
+            let root: Signed<Root> =
+                serde_json::from_slice(&decode_base64(root)?).context("Invalid root metadata")?;
+            root_version = root_version.max(root.signed.version);
+        }
+        let targets: Signed<Targets> = serde_json::from_slice(&decode_base64(&response.targets)?)
+            .context("Invalid targets metadata")?;
+        let targets = targets.signed;
+
+        let mut received = HashMap::new();
+        for file in response.target_files {
+            received.insert(file.path, decode_base64(&file.raw)?);
+        }
+
+        let mut files = HashMap::new();
+        let mut configs = vec![];
+        let mut config_states = vec![];
+        for path in response.client_configs {
+            let description = targets
+                .targets
+                .get(&path)
+                .with_context(|| format!("{path} is missing from the targets"))?;
+            let hash = description
+                .hashes
+                .get("sha256")
+                .with_context(|| format!("{path} has no sha256 hash"))?;
+            let meta = TargetFileMeta {
+                path: path.clone(),
+                length: description.length,
+                hashes: vec![TargetFileHash {
+                    algorithm: "sha256",
+                    hash: hash.clone(),
+                }],
+            };
+            let raw = match received.remove(&path) {
+                Some(raw) => raw,
+                None => match self.files.get(&path) {
+                    Some(cached) if cached.meta == meta => cached.raw.clone(),
+                    _ => anyhow::bail!("{path} was neither sent nor cached"),
+                },
+            };
+            if raw.len() as u64 != meta.length || hex::encode(Sha256::digest(&raw)) != *hash {
+                anyhow::bail!("{path} does not match the hash of the targets");
+            }
+
+            let (product_name, id) =
+                parse_path(&path).with_context(|| format!("Invalid target path {path}"))?;
+            let mut state = ConfigState {
+                id: id.to_string(),
+                version: description.custom.v,
+                product: product_name.to_string(),
+                apply_state: APPLY_STATE_UNACKNOWLEDGED,
+                apply_error: String::new(),
+            };
+            match (
+                RemoteConfigProduct::from_name(product_name),
+                serde_json::from_slice(&raw),
+            ) {
+                (Some(product), Ok(contents)) => configs.push(RemoteConfigFile {
+                    path: path.clone(),
+                    product,
+                    id: id.to_string(),
+                    version: description.custom.v,
+                    contents,
+                }),
+                (None, _) => {
+                    state.apply_state = APPLY_STATE_ERROR;
+                    state.apply_error = format!("Unknown product {product_name}");
+                }
+                (_, Err(e)) => {
+                    state.apply_state = APPLY_STATE_ERROR;
+                    state.apply_error = format!("Invalid json: {e}");
+                }
+            }
+            config_states.push(state);
+            files.insert(path, CachedFile { meta, raw });
+        }
+        configs.sort_by(|a, b| a.path.cmp(&b.path));
+
+        self.files = files;
+        self.state = ClientState {
+            root_version,
+            targets_version: targets.version,
+            config_states,
+            has_error: false,
+            error: String::new(),
+            backend_client_state: targets.custom.opaque_backend_state,
+        };
+        let changed = self.configs != configs;
+        self.configs = configs;
+        Ok(changed)
+    }
+
+    async fn poll(&mut self) -> anyhow::Result<bool> {
+        self.sync_subscribers();
+        let body = serde_json::to_vec(&self.request())?;
+        let req = hyper::Request::post(self.endpoint.url.clone())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .header(
+                hyper::header::USER_AGENT,
+                concat!("Sidecar/", env!("CARGO_PKG_VERSION")),
+            )
+            .body(Body::from(body))?;
+        let response = tokio::time::timeout(
+            POLL_TIMEOUT,
+            Client::builder().build(Connector::default()).request(req),
+        )
+        .await??;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        match status {
+            // remote configuration is disabled on the agent
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => {
+                let response = if body.is_empty() {
+                    ClientGetConfigsResponse::default()
+                } else {
+                    serde_json::from_slice(&body)?
+                };
+                match self.apply(response) {
+                    Ok(changed) => Ok(changed),
+                    Err(e) => {
+                        // the agent sends everything again as long as the targets version is kept
+                        self.state.has_error = true;
+                        self.state.error = e.to_string();
+                        Err(e)
+                    }
+                }
+            }
+            status => anyhow::bail!(
+                "Unexpected status {status}: {}",
+                String::from_utf8_lossy(&body)
+            ),
+        }
+    }
+
+    async fn run(mut self, writer: AgentRemoteConfigWriter<NamedShmHandle>) {
+        // tracers must be able to tell no configuration from no response yet
+        let mut delivered = false;
+        loop {
+            match self.poll().await {
+                Ok(changed) => {
+                    if changed || !delivered {
+                        match serde_json::to_vec(&self.configs) {
+                            Ok(contents) => {
+                                info!(
+                                    "Delivering {} remote configurations for service {}",
+                                    self.configs.len(),
+                                    self.target.service
+                                );
+                                writer.write(&contents);
+                                delivered = true;
+                            }
+                            Err(e) => warn!("Error serializing remote configurations: {e}"),
+                        }
+                    }
+                }
+                Err(e) => debug!(
+                    "Error polling remote configurations for service {}: {e:?}",
+                    self.target.service
+                ),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+type ClientKey = (Endpoint, RemoteConfigTarget);
+
+fn config_endpoint(agent: &Endpoint) -> anyhow::Result<Endpoint> {
+    if agent.api_key.is_some() {
+        anyhow::bail!("Remote configuration is only available through the agent");
+    }
+    let mut parts = agent.url.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::from_static(CONFIG_PATH));
+    Ok(Endpoint {
+        url: hyper::Uri::from_parts(parts)?,
+        api_key: None,
+    })
+}
+
+struct RemoteConfigPoller {
+    /// Session and runtime ids of the subscribed tracers
+    runtimes: HashSet<(String, String)>,
+    subscribers: Arc<Mutex<Subscribers>>,
+    task: JoinHandle<()>,
+}
+
+/// The remote configuration clients, one per agent and target, polling as long as a tracer is
+/// subscribed
+#[derive(Default, Clone)]
+pub struct RemoteConfigs {
+    pollers: Arc<Mutex<HashMap<ClientKey, RemoteConfigPoller>>>,
+}
+
+impl RemoteConfigs {
+    /// Subscribes the runtime to the configurations of the target, replacing its former target
+    pub fn subscribe(
+        &self,
+        session_id: &str,
+        runtime_id: &str,
+        agent: &Endpoint,
+        target: RemoteConfigTarget,
+    ) -> anyhow::Result<()> {
+        let subscriber = (session_id.to_string(), runtime_id.to_string());
+        let key = (config_endpoint(agent)?, target);
+
+        let mut pollers = self.pollers.lock().unwrap();
+        Self::remove(&mut pollers, |runtime| *runtime == subscriber, Some(&key));
+        let poller = match pollers.entry(key) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let (endpoint, target) = entry.key().clone();
+                let writer = agent_remote_config::new_named_writer(shm_path(&endpoint, &target))?;
+                let subscribers = Arc::new(Mutex::new(Subscribers {
+                    runtime_id: runtime_id.to_string(),
+                    ..Default::default()
+                }));
+                let client = RemoteConfigClient::new(endpoint, target, subscribers.clone());
+                entry.insert(RemoteConfigPoller {
+                    runtimes: HashSet::new(),
+                    subscribers,
+                    task: tokio::spawn(client.run(writer)),
+                })
+            }
+        };
+        poller.runtimes.insert(subscriber);
+        Ok(())
+    }
+
+    /// Reports how the runtime applied the configurations of the target it subscribed to
+    pub fn acknowledge(
+        &self,
+        session_id: &str,
+        runtime_id: &str,
+        agent: &Endpoint,
+        target: RemoteConfigTarget,
+        acks: Vec<RemoteConfigAck>,
+    ) -> anyhow::Result<()> {
+        let subscriber = (session_id.to_string(), runtime_id.to_string());
+        let key = (config_endpoint(agent)?, target);
+        let pollers = self.pollers.lock().unwrap();
+        let poller = pollers
+            .get(&key)
+            .filter(|poller| poller.runtimes.contains(&subscriber))
+            .context("The runtime is not subscribed to the remote configuration")?;
+        let mut subscribers = poller.subscribers.lock().unwrap();
+        for ack in acks {
+            subscribers.acks.insert((ack.product, ack.id.clone()), ack);
+        }
+        Ok(())
+    }
+
+    pub fn unsubscribe_runtime(&self, session_id: &str, runtime_id: &str) {
+        Self::remove(
+            &mut self.pollers.lock().unwrap(),
+            |(session, runtime)| session == session_id && runtime == runtime_id,
+            None,
+        );
+    }
+
+    pub fn unsubscribe_session(&self, session_id: &str) {
+        Self::remove(
+            &mut self.pollers.lock().unwrap(),
+            |(session, _)| session == session_id,
+            None,
+        );
+    }
+
+    fn remove<F>(
+        pollers: &mut HashMap<ClientKey, RemoteConfigPoller>,
+        is_removed: F,
+        except: Option<&ClientKey>,
+    ) where
+        F: Fn(&(String, String)) -> bool,
+    {
+        pollers.retain(|key, poller| {
+            if Some(key) == except {
+                return true;
+            }
+            poller.runtimes.retain(|runtime| !is_removed(runtime));
+            let Some((_, runtime_id)) = poller.runtimes.iter().next() else {
+                poller.task.abort();
+                return false;
+            };
+            // keep reporting a live runtime
+            let mut subscribers = poller.subscribers.lock().unwrap();
+            if !poller
+                .runtimes
+                .iter()
+                .any(|(_, runtime)| *runtime == subscribers.runtime_id)
+            {
+                subscribers.runtime_id.clone_from(runtime_id);
+            }
+            true
+        });
+    }
+
+    /// Services with subscribed tracers, by agent
+    pub fn services(&self) -> Vec<String> {
+        let mut services: Vec<String> = self
+            .pollers
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|(endpoint, target)| format!("{} {}", endpoint.url, target.service))
+            .collect();
+        services.sort();
+        services
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PATH: &str = "datadog/2/APM_TRACING/1234/config";
+
+    fn encode(data: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(data)
+    }
+
+    fn targets(version: u64, files: &[(&str, &[u8])]) -> String {
+        let targets: serde_json::Map<String, serde_json::Value> = files
+            .iter()
+            .map(|(path, raw)| {
+                (
+                    path.to_string(),
+                    json!({
+                        "length": raw.len(),
+                        "hashes": {"sha256": hex::encode(Sha256::digest(raw))},
+                        "custom": {"v": 3},
+                    }),
+                )
+            })
+            .collect();
+        encode(
+            json!({
+                "signatures": [],
+                "signed": {
+                    "_type": "targets",
+                    "version": version,
+                    "custom": {"opaque_backend_state": "state"},
+                    "targets": targets,
+                }
+            })
+            .to_string()
+            .as_bytes(),
+        )
+    }
+
+    fn target() -> RemoteConfigTarget {
+        RemoteConfigTarget {
+            service: "service".to_string(),
+            products: vec![RemoteConfigProduct::ApmTracing],
+            capabilities: 0x1002,
+            ..Default::default()
+        }
+    }
+
+    fn client() -> RemoteConfigClient {
+        let subscribers = Subscribers {
+            runtime_id: "runtime".to_string(),
+            ..Default::default()
+        };
+        let mut client = RemoteConfigClient::new(
+            Endpoint::default(),
+            target(),
+            Arc::new(Mutex::new(subscribers)),
+        );
+        client.sync_subscribers();
+        client
+    }
+
+    fn response(version: u64, raw: &[u8]) -> ClientGetConfigsResponse {
+        ClientGetConfigsResponse {
+            targets: targets(version, &[(PATH, raw)]),
+            target_files: vec![ResponseFile {
+                path: PATH.to_string(),
+                raw: encode(raw),
+            }],
+            client_configs: vec![PATH.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path(PATH), Some(("APM_TRACING", "1234")));
+        assert_eq!(
+            parse_path("employee/ASM_DD/blocking/config"),
+            Some(("ASM_DD", "blocking"))
+        );
+        assert_eq!(parse_path("datadog/2/APM_TRACING/config"), None);
+    }
+
+    #[test]
+    fn test_request() {
+        let client = client();
+        let request = serde_json::to_value(client.request()).unwrap();
+        assert_eq!(request["client"]["products"], json!(["APM_TRACING"]));
+        assert_eq!(
+            request["client"]["capabilities"],
+            json!(encode(&[0x10, 0x02]))
+        );
+        assert_eq!(request["client"]["state"]["root_version"], json!(1));
+        assert_eq!(request["cached_target_files"], json!([]));
+    }
+
+    #[test]
+    fn test_apply_and_cache() {
+        let mut client = client();
+        let raw = br#"{"lib_config": {"tracing_sampling_rate": 0.5}}"#;
+
+        assert!(client
+            .apply(ClientGetConfigsResponse {
+                roots: vec![encode(br#"{"signed": {"version": 2}}"#)],
+                targets: targets(10, &[(PATH, raw)]),
+                target_files: vec![ResponseFile {
+                    path: PATH.to_string(),
+                    raw: encode(raw),
+                }],
+                client_configs: vec![PATH.to_string()],
+            })
+            .unwrap());
+        assert_eq!(client.state.root_version, 2);
+        assert_eq!(client.state.targets_version, 10);
+        assert_eq!(client.state.backend_client_state, "state");
+        assert_eq!(client.configs.len(), 1);
+        assert_eq!(client.configs[0].id, "1234");
+        assert_eq!(client.configs[0].version, 3);
+        assert_eq!(
+            client.configs[0].contents["lib_config"]["tracing_sampling_rate"],
+            json!(0.5)
+        );
+
+        // nothing new
+        assert!(!client.apply(ClientGetConfigsResponse::default()).unwrap());
+
+        // the cached file is not sent again
+        let request = serde_json::to_value(client.request()).unwrap();
+        assert_eq!(request["cached_target_files"][0]["path"], json!(PATH));
+        assert!(!client
+            .apply(ClientGetConfigsResponse {
+                targets: targets(11, &[(PATH, raw)]),
+                client_configs: vec![PATH.to_string()],
+                ..Default::default()
+            })
+            .unwrap());
+        assert_eq!(client.state.targets_version, 11);
+
+        // removed configurations
+        assert!(client
+            .apply(ClientGetConfigsResponse {
+                targets: targets(12, &[]),
+                ..Default::default()
+            })
+            .unwrap());
+        assert!(client.configs.is_empty());
+        assert!(client.files.is_empty());
+    }
+
+    #[test]
+    fn test_apply_rejects_mismatching_hash() {
+        let mut client = client();
+        let result = client.apply(ClientGetConfigsResponse {
+            targets: targets(10, &[(PATH, b"{}")]),
+            target_files: vec![ResponseFile {
+                path: PATH.to_string(),
+                raw: encode(b"[]"),
+            }],
+            client_configs: vec![PATH.to_string()],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+        assert_eq!(client.state.targets_version, 0);
+        assert!(client.configs.is_empty());
+    }
+
+    #[test]
+    fn test_acknowledged_configs() {
+        let mut client = client();
+        client.apply(response(10, b"{}")).unwrap();
+        let apply_state = |client: &RemoteConfigClient| {
+            let state = &client.state.config_states[0];
+            (state.apply_state, state.apply_error.clone())
+        };
+        assert_eq!(
+            apply_state(&client),
+            (APPLY_STATE_UNACKNOWLEDGED, String::new())
+        );
+
+        let mut ack = RemoteConfigAck {
+            product: RemoteConfigProduct::ApmTracing,
+            id: "1234".to_string(),
+            version: 3,
+            error: None,
+        };
+        let subscribers = client.subscribers.clone();
+        let report = |ack: &RemoteConfigAck| {
+            let mut subscribers = subscribers.lock().unwrap();
+            subscribers
+                .acks
+                .insert((ack.product, ack.id.clone()), ack.clone());
+        };
+        report(&ack);
+        client.sync_subscribers();
+        assert_eq!(
+            apply_state(&client),
+            (APPLY_STATE_ACKNOWLEDGED, String::new())
+        );
+
+        ack.error = Some("unsupported".to_string());
+        report(&ack);
+        client.sync_subscribers();
+        assert_eq!(
+            apply_state(&client),
+            (APPLY_STATE_ERROR, "unsupported".to_string())
+        );
+
+        // reports of other versions don't apply
+        ack.version = 2;
+        ack.error = None;
+        report(&ack);
+        client.apply(response(11, b"[]")).unwrap();
+        client.sync_subscribers();
+        assert_eq!(
+            apply_state(&client),
+            (APPLY_STATE_UNACKNOWLEDGED, String::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poller_reports_a_live_runtime() {
+        let configs = RemoteConfigs::default();
+        let agent = Endpoint {
+            url: hyper::Uri::from_static("http://127.0.0.1:1"),
+            api_key: None,
+        };
+        let target = RemoteConfigTarget {
+            service: format!("service-{}", std::process::id()),
+            ..target()
+        };
+        let subscribers = || {
+            let pollers = configs.pollers.lock().unwrap();
+            pollers.values().next().unwrap().subscribers.clone()
+        };
+
+        configs
+            .subscribe("session", "first", &agent, target.clone())
+            .unwrap();
+        configs
+            .subscribe("session", "second", &agent, target.clone())
+            .unwrap();
+        assert_eq!(subscribers().lock().unwrap().runtime_id, "first");
+
+        configs.unsubscribe_runtime("session", "first");
+        assert_eq!(subscribers().lock().unwrap().runtime_id, "second");
+
+        let ack = RemoteConfigAck {
+            product: RemoteConfigProduct::ApmTracing,
+            id: "1234".to_string(),
+            version: 3,
+            error: None,
+        };
+        assert!(configs
+            .acknowledge(
+                "session",
+                "first",
+                &agent,
+                target.clone(),
+                vec![ack.clone()]
+            )
+            .is_err());
+        configs
+            .acknowledge("session", "second", &agent, target, vec![ack])
+            .unwrap();
+        assert_eq!(subscribers().lock().unwrap().acks.len(), 1);
+
+        configs.unsubscribe_session("session");
+        assert!(configs.services().is_empty());
+    }
+}
@@ -1,8 +1,60 @@
 // Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
+use std::io;
+
 #[cfg(unix)]
 mod unix;
 
 #[cfg(unix)]
 pub use unix::*;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub use windows::*;
+
+/// Implementations of this interface must provide behavior repeatable across processes with the same version
+/// of library.
+/// Allowing all instances of the same version of the library to establish a shared connection
+pub trait Liaison: Sized {
+    fn connect_to_server(&self) -> io::Result<IpcClient>;
+    fn attempt_listen(&self) -> io::Result<Option<IpcServer>>;
+    fn ipc_shared() -> Self;
+    fn ipc_per_process() -> Self;
+    /// Shared between the processes of the current user, and not reachable by other users
+    fn ipc_per_uid() -> Self;
+    /// Liaisons to the shared sidecars of older versions of the library which may still be running,
+    /// so that a newer sidecar can take over from them
+    fn older_versions(&self) -> Vec<Self>;
+}
+
+fn parse_version(version: &str) -> Option<[u64; 3]> {
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    let version = [parts.next()??, parts.next()??, parts.next()??];
+    parts.next().is_none().then_some(version)
+}
+
+/// Whether `version` is a release of the library older than the current one
+fn is_older_version(version: &str) -> bool {
+    match (
+        parse_version(version),
+        parse_version(env!("CARGO_PKG_VERSION")),
+    ) {
+        (Some(version), Some(current)) => version < current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_versions() {
+        assert!(super::is_older_version("0.0.0"));
+        assert!(!super::is_older_version(env!("CARGO_PKG_VERSION")));
+        assert!(!super::is_older_version("1000.0.0"));
+        assert!(!super::is_older_version("0.0.0.1000.1"));
+        assert!(!super::is_older_version("latest"));
+    }
+}
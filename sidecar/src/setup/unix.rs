@@ -4,6 +4,7 @@
 use std::{
     env, fs, io,
     os::unix::{
+        fs::MetadataExt,
         net::{UnixListener, UnixStream},
         prelude::PermissionsExt,
     },
@@ -11,16 +12,14 @@ use std::{
 };
 
 use datadog_ipc::platform::{self, locks::FLock};
+use spawn_worker::getpid;
 
-/// Implementations of this interface must provide behavior repeatable across processes with the same version
-/// of library.
-/// Allowing all instances of the same version of the library to establish a shared connection
-pub trait Liaison: Sized {
-    fn connect_to_server(&self) -> io::Result<UnixStream>;
-    fn attempt_listen(&self) -> io::Result<Option<UnixListener>>;
-    fn ipc_shared() -> Self;
-    fn ipc_per_process() -> Self;
-}
+use super::{is_older_version, Liaison};
+
+pub type IpcClient = UnixStream;
+pub type IpcServer = UnixListener;
+/// The server end of an accepted connection
+pub type IpcConnection = tokio::net::UnixStream;
 
 fn ensure_dir_world_writable<P: AsRef<Path>>(path: P) -> io::Result<()> {
     let mut perm = path.as_ref().metadata()?.permissions();
@@ -39,9 +38,55 @@ fn ensure_dir_exists<P: AsRef<Path>>(path: P) -> io::Result<()> {
     Ok(())
 }
 
+/// Creates the directory accessible by the current user only, refusing to use an existing one
+/// that another user could have created or tampered with
+fn ensure_private_dir_exists(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        ensure_dir_exists(parent)?;
+    }
+    match fs::create_dir(path) {
+        Ok(()) => fs::set_permissions(path, fs::Permissions::from_mode(0o700))?,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() || metadata.uid() != getuid() || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not private to the current user", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn getuid() -> libc::uid_t {
+    unsafe { libc::getuid() }
+}
+
+fn is_process_alive(pid: libc::pid_t) -> bool {
+    // signal 0 only checks whether the process exists
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+const SHARED_SOCKET_PREFIX: &str = "libdd.";
+// the double dot is part of the names all versions use for the shared socket
+const SHARED_SOCKET_SUFFIX: &str = "..sock";
+
+fn per_process_socket_prefix() -> String {
+    format!(
+        concat!("libdd.", env!("CARGO_PKG_VERSION"), ".{}."),
+        getuid()
+    )
+}
+
 pub struct SharedDirLiaison {
     socket_path: PathBuf,
     lock_path: PathBuf,
+    // sockets of other processes in the same directory may be left over by dead processes
+    per_process: bool,
+    private_dir: bool,
 }
 
 impl Liaison for SharedDirLiaison {
@@ -51,7 +96,17 @@ impl Liaison for SharedDirLiaison {
 
     fn attempt_listen(&self) -> io::Result<Option<UnixListener>> {
         let dir = self.socket_path.parent().unwrap_or_else(|| Path::new("/"));
-        ensure_dir_exists(dir)?;
+        if self.private_dir {
+            ensure_private_dir_exists(dir)?;
+        } else {
+            ensure_dir_exists(dir)?;
+        }
+
+        if self.per_process {
+            if let Err(e) = self.remove_stale_sockets(dir) {
+                tracing::debug!("failed removing stale sockets: {e}");
+            }
+        }
 
         let _g = match FLock::try_rw_lock(&self.lock_path) {
             Ok(lock) => lock,
@@ -79,31 +134,110 @@ impl Liaison for SharedDirLiaison {
     }
 
     fn ipc_per_process() -> Self {
-        //TODO: implement per pid handling
-        Self::new_tmp_dir()
+        Self::new_for_process(env::temp_dir().join("libdatadog"), getpid())
+    }
+
+    fn ipc_per_uid() -> Self {
+        Self::new_for_uid(env::temp_dir().join("libdatadog"), getuid())
+    }
+
+    fn older_versions(&self) -> Vec<Self> {
+        let dir = match (self.per_process, self.socket_path.parent()) {
+            (false, Some(dir)) => dir,
+            _ => return vec![],
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let version = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix(SHARED_SOCKET_PREFIX)?
+                    .strip_suffix(SHARED_SOCKET_SUFFIX)?;
+                is_older_version(version).then(|| Self::new_versioned(dir, version))
+            })
+            .collect()
     }
 }
 
 impl SharedDirLiaison {
     pub fn new<P: AsRef<Path>>(base_dir: P) -> Self {
-        let versioned_socket_basename = concat!("libdd.", env!("CARGO_PKG_VERSION"), ".sock");
+        Self::new_versioned(base_dir, env!("CARGO_PKG_VERSION"))
+    }
+
+    fn new_versioned<P: AsRef<Path>>(base_dir: P, version: &str) -> Self {
+        let socket_basename = format!("{SHARED_SOCKET_PREFIX}{version}{SHARED_SOCKET_SUFFIX}");
         let base_dir = base_dir.as_ref();
-        let socket_path = base_dir
-            .join(versioned_socket_basename)
-            .with_extension(".sock");
-        let lock_path = base_dir
-            .join(versioned_socket_basename)
-            .with_extension(".sock.lock");
+        let socket_path = base_dir.join(&socket_basename);
+        let lock_path = base_dir.join(socket_basename + ".lock");
 
         Self {
             socket_path,
             lock_path,
+            per_process: false,
+            private_dir: false,
+        }
+    }
+
+    /// Liaison to the sidecar shared by the processes of `uid`, in a directory only accessible
+    /// to that user
+    pub fn new_for_uid<P: AsRef<Path>>(base_dir: P, uid: libc::uid_t) -> Self {
+        Self {
+            private_dir: true,
+            ..Self::new(base_dir.as_ref().join(format!("uid-{uid}")))
+        }
+    }
+
+    /// Liaison to the sidecar dedicated to the process `pid`, owned by the current user
+    pub fn new_for_process<P: AsRef<Path>>(base_dir: P, pid: libc::pid_t) -> Self {
+        let socket_basename = format!("{}{pid}.sock", per_process_socket_prefix());
+        let base_dir = base_dir.as_ref();
+
+        Self {
+            socket_path: base_dir.join(&socket_basename),
+            lock_path: base_dir.join(socket_basename + ".lock"),
+            per_process: true,
+            private_dir: false,
         }
     }
 
     pub fn new_tmp_dir() -> Self {
         Self::new(env::temp_dir().join("libdatadog"))
     }
+
+    /// Removes the per process sockets of dead processes, which aren't served by a sidecar anymore
+    fn remove_stale_sockets(&self, dir: &Path) -> io::Result<()> {
+        let prefix = per_process_socket_prefix();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let pid = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(".sock"))
+                .and_then(|pid| pid.parse().ok());
+            let pid = match pid {
+                Some(pid) => pid,
+                None => continue,
+            };
+            if path == self.socket_path
+                || is_process_alive(pid)
+                || platform::sockets::is_listening(&path)?
+            {
+                continue;
+            }
+            tracing::debug!("removing stale socket {}", path.display());
+            fs::remove_file(&path)?;
+            let mut lock_path = path.into_os_string();
+            lock_path.push(".lock");
+            let _ = fs::remove_file(lock_path);
+        }
+        Ok(())
+    }
 }
 
 impl Default for SharedDirLiaison {
@@ -115,25 +249,45 @@ impl Default for SharedDirLiaison {
 #[cfg(target_os = "linux")]
 mod linux {
     use std::{
-        io,
-        os::unix::net::{UnixListener, UnixStream},
+        collections::HashSet,
+        fs, io,
+        os::unix::{
+            io::AsRawFd,
+            net::{UnixListener, UnixStream},
+        },
         path::PathBuf,
     };
 
+    use nix::sys::socket::{getsockopt, sockopt};
+
     use spawn_worker::getpid;
 
     use datadog_ipc::platform;
 
-    use super::Liaison;
+    use super::{getuid, is_older_version, Liaison};
+
+    const ABSTRACT_SOCKET_PREFIX: &str = "libdatadog/";
 
     pub struct AbstractUnixSocketLiaison {
         path: PathBuf,
+        // anybody can bind any abstract socket, so the server must be checked to be ours
+        owner_uid: Option<libc::uid_t>,
     }
     pub type DefaultLiason = AbstractUnixSocketLiaison;
 
     impl Liaison for AbstractUnixSocketLiaison {
         fn connect_to_server(&self) -> io::Result<UnixStream> {
-            platform::sockets::connect_abstract(&self.path)
+            let stream = platform::sockets::connect_abstract(&self.path)?;
+            if let Some(uid) = self.owner_uid {
+                let cred = getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials)?;
+                if cred.uid() != uid {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("sidecar socket is owned by uid {}", cred.uid()),
+                    ));
+                }
+            }
+            Ok(stream)
         }
 
         fn attempt_listen(&self) -> io::Result<Option<UnixListener>> {
@@ -145,16 +299,74 @@ mod linux {
         }
 
         fn ipc_shared() -> AbstractUnixSocketLiaison {
-            let path = PathBuf::from(concat!("libdatadog/", env!("CARGO_PKG_VERSION"), ".sock"));
-            Self { path }
+            Self::new_versioned(env!("CARGO_PKG_VERSION"))
         }
 
         fn ipc_per_process() -> AbstractUnixSocketLiaison {
+            Self::new_for_process(getpid())
+        }
+
+        fn ipc_per_uid() -> AbstractUnixSocketLiaison {
+            Self::new_for_uid(getuid())
+        }
+
+        fn older_versions(&self) -> Vec<Self> {
+            if self.path != Self::ipc_shared().path {
+                return vec![];
+            }
+            // abstract sockets can't be listed, besides through procfs
+            let sockets = match fs::read_to_string("/proc/net/unix") {
+                Ok(sockets) => sockets,
+                Err(_) => return vec![],
+            };
+            let versions: HashSet<&str> = sockets
+                .lines()
+                .filter_map(|line| {
+                    line.split_whitespace()
+                        .last()?
+                        .strip_prefix('@')?
+                        .strip_prefix(ABSTRACT_SOCKET_PREFIX)?
+                        .strip_suffix(".sock")
+                })
+                .filter(|version| is_older_version(version))
+                .collect();
+            versions.into_iter().map(Self::new_versioned).collect()
+        }
+    }
+
+    impl AbstractUnixSocketLiaison {
+        fn new_versioned(version: &str) -> Self {
+            let path = PathBuf::from(format!("{ABSTRACT_SOCKET_PREFIX}{version}.sock"));
+            Self {
+                path,
+                owner_uid: None,
+            }
+        }
+
+        /// Abstract sockets are released by the kernel with the last process using them, so
+        /// there is nothing left over to clean up
+        pub fn new_for_process(pid: libc::pid_t) -> Self {
             let path = PathBuf::from(format!(
-                concat!("libdatadog/", env!("CARGO_PKG_VERSION"), ".{}.sock"),
-                getpid()
+                concat!("{}", env!("CARGO_PKG_VERSION"), ".{}.{}.sock"),
+                ABSTRACT_SOCKET_PREFIX,
+                getuid(),
+                pid
             ));
-            Self { path }
+            Self {
+                path,
+                owner_uid: Some(getuid()),
+            }
+        }
+
+        pub fn new_for_uid(uid: libc::uid_t) -> Self {
+            let path = PathBuf::from(format!(
+                concat!("{}", env!("CARGO_PKG_VERSION"), ".uid{}.sock"),
+                ABSTRACT_SOCKET_PREFIX, uid
+            ));
+            Self {
+                path,
+                owner_uid: Some(uid),
+            }
         }
     }
 
@@ -169,6 +381,42 @@ mod linux {
         let l = AbstractUnixSocketLiaison::ipc_per_process();
         super::tests::basic_liaison_connection_test(&l).unwrap();
     }
+
+    #[test]
+    fn test_abstract_socket_per_uid() {
+        let liaison = AbstractUnixSocketLiaison::ipc_per_uid();
+        super::tests::basic_liaison_connection_test(&liaison).unwrap();
+
+        // a socket bound by the current user, pretending to be the sidecar of another one
+        let other_user = AbstractUnixSocketLiaison::new_for_uid(getuid() + 1);
+        let _listener = other_user.attempt_listen().unwrap().unwrap();
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            other_user.connect_to_server().unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_abstract_socket_older_versions() {
+        let older = AbstractUnixSocketLiaison::new_versioned("0.0.0");
+        let _listener = older.attempt_listen().unwrap().unwrap();
+        let newer = AbstractUnixSocketLiaison::new_versioned("1000.0.0");
+        let _newer_listener = newer.attempt_listen().unwrap().unwrap();
+
+        let found = AbstractUnixSocketLiaison::ipc_shared().older_versions();
+        assert!(found.iter().any(|liaison| liaison.path == older.path));
+        assert!(!found.iter().any(|liaison| liaison.path == newer.path));
+        assert!(AbstractUnixSocketLiaison::ipc_per_process()
+            .older_versions()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_abstract_socket_per_process_isolation() {
+        let first_liaison = AbstractUnixSocketLiaison::new_for_process(getpid());
+        let second_liaison = AbstractUnixSocketLiaison::new_for_process(super::tests::parent_pid());
+        super::tests::distinct_sidecars_test(&first_liaison, &second_liaison);
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -180,7 +428,10 @@ pub type DefaultLiason = SharedDirLiaison;
 #[cfg(test)]
 mod tests {
     use std::{
+        env, fs,
         io::{self, Read, Write},
+        os::unix::fs::PermissionsExt,
+        process::{Command, Stdio},
         thread,
         time::Duration,
     };
@@ -199,6 +450,101 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_shared_dir_per_process_isolation() {
+        let tmpdir = tempdir().unwrap();
+        let first_liaison =
+            super::SharedDirLiaison::new_for_process(tmpdir.path(), super::getpid());
+        let second_liaison = super::SharedDirLiaison::new_for_process(tmpdir.path(), parent_pid());
+        assert_ne!(first_liaison.socket_path, second_liaison.socket_path);
+        distinct_sidecars_test(&first_liaison, &second_liaison);
+    }
+
+    #[test]
+    fn test_shared_dir_removes_stale_sockets() {
+        let tmpdir = tempdir().unwrap();
+        let stale = super::SharedDirLiaison::new_for_process(tmpdir.path(), exited_pid());
+        drop(stale.attempt_listen().unwrap().unwrap());
+        assert!(stale.socket_path.exists());
+
+        // the sidecar is gone, and so is the process it was started for
+        let liaison = super::SharedDirLiaison::new_for_process(tmpdir.path(), super::getpid());
+        let _listener = liaison.attempt_listen().unwrap().unwrap();
+        assert!(!stale.socket_path.exists());
+        assert!(!stale.lock_path.exists());
+    }
+
+    #[test]
+    fn test_shared_dir_per_uid() {
+        let tmpdir = tempdir().unwrap();
+        let liaison = super::SharedDirLiaison::new_for_uid(tmpdir.path(), super::getuid());
+        basic_liaison_connection_test(&liaison).unwrap();
+        let dir = liaison.socket_path.parent().unwrap();
+        assert_eq!(0o700, dir.metadata().unwrap().permissions().mode() & 0o777);
+
+        // a directory other users can write to may contain anything
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            liaison.attempt_listen().unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_shared_dir_older_versions() {
+        let tmpdir = tempdir().unwrap();
+        let older = super::SharedDirLiaison::new_versioned(tmpdir.path(), "0.0.0");
+        let _listener = older.attempt_listen().unwrap().unwrap();
+        let newer = super::SharedDirLiaison::new_versioned(tmpdir.path(), "1000.0.0");
+        let _newer_listener = newer.attempt_listen().unwrap().unwrap();
+        let per_process = super::SharedDirLiaison::new_for_process(tmpdir.path(), 1);
+        let _per_process_listener = per_process.attempt_listen().unwrap().unwrap();
+
+        assert_eq!(older.socket_path, tmpdir.path().join("libdd.0.0.0..sock"));
+        assert_eq!(
+            older.lock_path,
+            tmpdir.path().join("libdd.0.0.0..sock.lock")
+        );
+
+        let liaison = super::SharedDirLiaison::new(tmpdir.path());
+        let found = liaison.older_versions();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].socket_path, older.socket_path);
+        assert_eq!(found[0].lock_path, older.lock_path);
+    }
+
+    pub fn parent_pid() -> libc::pid_t {
+        unsafe { libc::getppid() }
+    }
+
+    /// The pid of a process which already exited
+    fn exited_pid() -> libc::pid_t {
+        let mut child = Command::new(env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.wait().unwrap();
+        child.id() as libc::pid_t
+    }
+
+    /// Each liaison gets its own sidecar: both can listen at the same time, and connections
+    /// reach the sidecar of their own liaison only
+    pub fn distinct_sidecars_test<T: Liaison>(first: &T, second: &T) {
+        let first_listener = first.attempt_listen().unwrap().unwrap();
+        let second_listener = second.attempt_listen().unwrap().unwrap();
+        for listener in [&first_listener, &second_listener] {
+            listener.set_nonblocking(true).unwrap();
+        }
+
+        let _client = first.connect_to_server().unwrap();
+        assert!(first_listener.accept().is_ok());
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            second_listener.accept().unwrap_err().kind()
+        );
+    }
+
     pub fn basic_liaison_connection_test<T>(liaison: &T) -> Result<(), anyhow::Error>
     where
         T: Liaison,
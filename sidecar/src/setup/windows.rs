@@ -0,0 +1,170 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    fs, io,
+    os::windows::io::{AsRawHandle, OwnedHandle},
+    path::PathBuf,
+    time::Duration,
+};
+
+use datadog_ipc::platform::{named_pipe, Channel};
+use spawn_worker::getpid;
+use windows_sys::Win32::{Foundation::HANDLE, System::Pipes::GetNamedPipeServerProcessId};
+
+use super::{is_older_version, Liaison};
+use crate::credentials::{current_user, process_user};
+
+pub type IpcClient = Channel;
+/// The server end of an accepted connection
+pub type IpcConnection = tokio::net::windows::named_pipe::NamedPipeServer;
+
+/// The first server end of the pipe, handed over to the sidecar which creates the next ones
+pub struct IpcServer {
+    pub pipe: OwnedHandle,
+    pub path: PathBuf,
+}
+const PIPE_PREFIX: &str = "libdd.";
+const PIPE_SUFFIX: &str = ".sidecar";
+/// How long connecting waits for the sidecar to have a server end of the pipe available
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct NamedPipeLiaison {
+    path: PathBuf,
+    // pipe names are global, anybody can create any pipe, so the server must be checked to be ours
+    private: bool,
+}
+pub type DefaultLiason = NamedPipeLiaison;
+
+impl Liaison for NamedPipeLiaison {
+    fn connect_to_server(&self) -> io::Result<Channel> {
+        let channel = named_pipe::connect(&self.path, CONNECT_TIMEOUT)?;
+        if self.private {
+            let mut server_pid = 0;
+            if unsafe {
+                GetNamedPipeServerProcessId(channel.as_raw_handle() as HANDLE, &mut server_pid)
+            } == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let owner = process_user(server_pid)?;
+            if owner != current_user() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("sidecar pipe is owned by {owner}"),
+                ));
+            }
+        }
+        Ok(channel)
+    }
+
+    fn attempt_listen(&self) -> io::Result<Option<IpcServer>> {
+        Ok(
+            named_pipe::create_first_instance(&self.path)?.map(|pipe| IpcServer {
+                pipe,
+                path: self.path.clone(),
+            }),
+        )
+    }
+
+    fn ipc_shared() -> Self {
+        Self::new_versioned(env!("CARGO_PKG_VERSION"))
+    }
+
+    fn ipc_per_process() -> Self {
+        Self::new_for_process(getpid())
+    }
+
+    fn ipc_per_uid() -> Self {
+        Self::new_for_user(&current_user())
+    }
+
+    fn older_versions(&self) -> Vec<Self> {
+        if self.path != Self::ipc_shared().path {
+            return vec![];
+        }
+        let pipes = match fs::read_dir(named_pipe::pipe_path("")) {
+            Ok(pipes) => pipes,
+            Err(_) => return vec![],
+        };
+        pipes
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                let version = name
+                    .to_str()?
+                    .strip_prefix(PIPE_PREFIX)?
+                    .strip_suffix(PIPE_SUFFIX)?;
+                is_older_version(version).then(|| Self::new_versioned(version))
+            })
+            .collect()
+    }
+}
+
+impl NamedPipeLiaison {
+    fn new_versioned(version: &str) -> Self {
+        Self {
+            path: named_pipe::pipe_path(&format!("{PIPE_PREFIX}{version}{PIPE_SUFFIX}")),
+            private: false,
+        }
+    }
+
+    /// Pipes are removed with their last server end, so there is nothing left over to clean up
+    pub fn new_for_process(pid: u32) -> Self {
+        Self {
+            path: named_pipe::pipe_path(&format!(
+                concat!("{}", env!("CARGO_PKG_VERSION"), ".pid{}{}"),
+                PIPE_PREFIX, pid, PIPE_SUFFIX
+            )),
+            private: true,
+        }
+    }
+
+    /// `user` is the string form of the SID of the user
+    pub fn new_for_user(user: &str) -> Self {
+        Self {
+            path: named_pipe::pipe_path(&format!(
+                concat!("{}", env!("CARGO_PKG_VERSION"), ".{}{}"),
+                PIPE_PREFIX, user, PIPE_SUFFIX
+            )),
+            private: true,
+        }
+    }
+}
+
+impl Default for NamedPipeLiaison {
+    fn default() -> Self {
+        Self::ipc_shared()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::windows::io::IntoRawHandle;
+
+    use tokio::net::windows::named_pipe::NamedPipeServer;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_named_pipe_can_connect() {
+        let liaison = NamedPipeLiaison::ipc_per_process();
+        let listener = liaison.attempt_listen().unwrap().unwrap();
+        assert!(liaison.attempt_listen().unwrap().is_none());
+
+        let server =
+            unsafe { NamedPipeServer::from_raw_handle(listener.pipe.into_raw_handle()) }.unwrap();
+        // the server end belongs to the current user
+        let _client = liaison.connect_to_server().unwrap();
+        server.connect().await.unwrap();
+    }
+
+    #[test]
+    fn test_older_versions_are_only_looked_up_for_the_shared_pipe() {
+        let name = |liaison: NamedPipeLiaison| liaison.path.to_string_lossy().into_owned();
+        assert!(name(NamedPipeLiaison::new_versioned("0.0.0")).ends_with(r"\libdd.0.0.0.sidecar"));
+        assert!(NamedPipeLiaison::ipc_per_uid().older_versions().is_empty());
+        assert!(NamedPipeLiaison::ipc_per_process()
+            .older_versions()
+            .is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+//! On-disk buffer for the traces which couldn't be sent, replayed in order once their endpoint
+//! is reachable again.
+//!
+//! The segments are kept in a subdirectory of the configured directory, only accessible to the
+//! user of the sidecar. Each endpoint has its own directory of segment files, one per payload,
+//! named after their sequence number. A segment is a header (magic, format version, crc32 of the body, creation
+//! time, body length) followed by the payload serialized with msgpack.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use datadog_trace_utils::trace_utils::SendData;
+use ddcommon::Endpoint;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use zwohash::ZwoHasher;
+
+const MAGIC: &[u8; 4] = b"DDTS";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 4 + 1 + 4 + 8 + 8;
+const SEGMENT_EXTENSION: &str = "seg";
+const TMP_EXTENSION: &str = "tmp";
+const SPOOL_DIR: &str = "traces";
+
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    /// Per endpoint, the oldest payloads are dropped beyond it
+    pub max_bytes: u64,
+    /// Payloads older than this are dropped instead of being replayed
+    pub max_age: Duration,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SpoolStats {
+    pub pending_payloads: usize,
+    pub pending_bytes: u64,
+    pub spooled_payloads: u64,
+    pub replayed_payloads: u64,
+    /// Payloads dropped because of the age or byte limits
+    pub dropped_payloads: u64,
+    pub dropped_bytes: u64,
+    pub corrupted_segments: u64,
+    /// Payloads dropped because the endpoint refused them
+    pub rejected_payloads: u64,
+}
+
+struct Segment {
+    path: PathBuf,
+    len: u64,
+    created: u64,
+}
+
+#[derive(Default)]
+struct EndpointSpool {
+    segments: VecDeque<Segment>,
+    bytes: u64,
+    next_seq: u64,
+}
+
+pub struct TraceSpool {
+    config: SpoolConfig,
+    // the private subdirectory of config.dir
+    root: PathBuf,
+    endpoints: HashMap<String, EndpointSpool>,
+    stats: SpoolStats,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Stable across restarts, so that the payloads spooled by a previous sidecar are found again.
+/// The api key is part of it, as different keys may send to the same url.
+fn endpoint_key(endpoint: &Endpoint) -> String {
+    let mut hasher = ZwoHasher::default();
+    endpoint.url.to_string().hash(&mut hasher);
+    endpoint.api_key.as_deref().hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn remove_spooled_file(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Failed removing spooled traces {}: {e}", path.display());
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn encode_segment(data: &SendData, created: u64) -> io::Result<Vec<u8>> {
+    let body = rmp_serde::to_vec_named(data).map_err(|e| invalid_data(&e.to_string()))?;
+    let mut segment = Vec::with_capacity(HEADER_LEN + body.len());
+    segment.extend_from_slice(MAGIC);
+    segment.push(FORMAT_VERSION);
+    segment.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    segment.extend_from_slice(&created.to_le_bytes());
+    segment.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    segment.extend_from_slice(&body);
+    Ok(segment)
+}
+
+/// Returns the crc, the creation time and the body length
+fn decode_header(header: &[u8; HEADER_LEN]) -> io::Result<(u32, u64, u64)> {
+    if &header[0..4] != MAGIC || header[4] != FORMAT_VERSION {
+        return Err(invalid_data("not a trace spool segment"));
+    }
+    Ok((
+        u32::from_le_bytes(header[5..9].try_into().unwrap()),
+        u64::from_le_bytes(header[9..17].try_into().unwrap()),
+        u64::from_le_bytes(header[17..25].try_into().unwrap()),
+    ))
+}
+
+fn read_header(file: &mut File) -> io::Result<(u32, u64, u64)> {
+    let mut header = [0; HEADER_LEN];
+    file.read_exact(&mut header)?;
+    decode_header(&header)
+}
+
+fn read_segment(path: &Path) -> io::Result<SendData> {
+    let mut file = File::open(path)?;
+    let (crc, _, len) = read_header(&mut file)?;
+    let mut body = vec![];
+    file.read_to_end(&mut body)?;
+    if body.len() as u64 != len || crc32fast::hash(&body) != crc {
+        return Err(invalid_data("checksum mismatch"));
+    }
+    rmp_serde::from_slice(&body).map_err(|e| invalid_data(&e.to_string()))
+}
+
+/// The files which can't be read are skipped, the ones which are not segments removed
+fn scan_endpoint_dir(dir: &Path, stats: &mut SpoolStats) -> io::Result<EndpointSpool> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                warn!("Skipping spooled traces in {}: {e}", dir.display());
+                continue;
+            }
+        };
+        let seq: Option<u64> = match path.extension() {
+            Some(ext) if ext == SEGMENT_EXTENSION => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok()),
+            // leftover of an interrupted write
+            Some(ext) if ext == TMP_EXTENSION => {
+                remove_spooled_file(&path);
+                continue;
+            }
+            _ => None,
+        };
+        let header = seq.and_then(|_| read_header(&mut File::open(&path).ok()?).ok());
+        match (seq, header) {
+            (Some(seq), Some((_, created, len))) => segments.push((seq, created, len, path)),
+            _ => {
+                stats.corrupted_segments += 1;
+                remove_spooled_file(&path);
+            }
+        }
+    }
+    segments.sort_unstable_by_key(|(seq, ..)| *seq);
+
+    let mut spool = EndpointSpool {
+        next_seq: segments.last().map_or(0, |(seq, ..)| seq + 1),
+        ..Default::default()
+    };
+    for (_, created, body_len, path) in segments {
+        let len = HEADER_LEN as u64 + body_len;
+        spool.bytes += len;
+        spool.segments.push_back(Segment { path, len, created });
+    }
+    Ok(spool)
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o700))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The directory inherits the access rights of the configured one
+#[cfg(windows)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    match fs::create_dir(path) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl TraceSpool {
+    /// Picks up the payloads left over by a previous sidecar. Does blocking I/O, as all methods
+    /// changing the spool.
+    pub fn open(config: SpoolConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        // payloads may contain api keys
+        let root = config.dir.join(SPOOL_DIR);
+        create_private_dir(&root)?;
+
+        let mut spool = TraceSpool {
+            config,
+            root,
+            endpoints: HashMap::new(),
+            stats: SpoolStats::default(),
+        };
+        for entry in fs::read_dir(&spool.root)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skipping spooled traces in {}: {e}", spool.root.display());
+                    continue;
+                }
+            };
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            if let Some(key) = entry.file_name().to_str() {
+                match scan_endpoint_dir(&entry.path(), &mut spool.stats) {
+                    Ok(endpoint) => _ = spool.endpoints.insert(key.to_owned(), endpoint),
+                    Err(e) => warn!("Skipping spooled traces in {}: {e}", entry.path().display()),
+                }
+            }
+        }
+        Ok(spool)
+    }
+
+    pub fn push(&mut self, data: &SendData) -> io::Result<()> {
+        let key = endpoint_key(&data.target);
+        let dir = self.root.join(&key);
+        let endpoint = self.endpoints.entry(key).or_default();
+        if endpoint.segments.is_empty() {
+            fs::create_dir_all(&dir)?;
+        }
+
+        let created = now_secs();
+        let segment = encode_segment(data, created)?;
+        let path = dir.join(format!("{:020}.{SEGMENT_EXTENSION}", endpoint.next_seq));
+        let tmp_path = path.with_extension(TMP_EXTENSION);
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&tmp_path)?.write_all(&segment)?;
+        fs::rename(&tmp_path, &path)?;
+
+        endpoint.next_seq += 1;
+        endpoint.bytes += segment.len() as u64;
+        endpoint.segments.push_back(Segment {
+            path,
+            len: segment.len() as u64,
+            created,
+        });
+        self.stats.spooled_payloads += 1;
+        self.enforce_limits(&data.target);
+        Ok(())
+    }
+
+    fn drop_front(&mut self, key: &str) {
+        if let Some(endpoint) = self.endpoints.get_mut(key) {
+            if let Some(segment) = endpoint.segments.pop_front() {
+                endpoint.bytes -= segment.len;
+                remove_spooled_file(&segment.path);
+            }
+        }
+    }
+
+    fn enforce_limits(&mut self, target: &Endpoint) {
+        let key = endpoint_key(target);
+        let min_created = now_secs().saturating_sub(self.config.max_age.as_secs());
+        while let Some(endpoint) = self.endpoints.get(&key) {
+            match endpoint.segments.front() {
+                Some(segment)
+                    if endpoint.bytes > self.config.max_bytes || segment.created < min_created =>
+                {
+                    self.stats.dropped_payloads += 1;
+                    self.stats.dropped_bytes += segment.len;
+                    self.drop_front(&key);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    pub fn has_pending(&self, target: &Endpoint) -> bool {
+        self.endpoints
+            .get(&endpoint_key(target))
+            .is_some_and(|endpoint| !endpoint.segments.is_empty())
+    }
+
+    /// The oldest payload for the endpoint. It stays spooled until it's removed with
+    /// [`TraceSpool::remove_front`], once sent.
+    pub fn front(&mut self, target: &Endpoint) -> Option<SendData> {
+        self.enforce_limits(target);
+        let key = endpoint_key(target);
+        loop {
+            let path = self.endpoints.get(&key)?.segments.front()?.path.clone();
+            match read_segment(&path) {
+                Ok(data) => return Some(data),
+                Err(e) => {
+                    warn!("Dropping corrupted spooled traces {}: {e}", path.display());
+                    self.stats.corrupted_segments += 1;
+                    self.drop_front(&key);
+                }
+            }
+        }
+    }
+
+    pub fn remove_front(&mut self, target: &Endpoint) {
+        self.drop_front(&endpoint_key(target));
+        self.stats.replayed_payloads += 1;
+    }
+
+    /// Drops the oldest payload for the endpoint, which refused it
+    pub fn reject_front(&mut self, target: &Endpoint) {
+        self.drop_front(&endpoint_key(target));
+        self.stats.rejected_payloads += 1;
+    }
+
+    /// The endpoints payloads are spooled for
+    pub fn pending_targets(&mut self) -> Vec<Endpoint> {
+        let keys: Vec<String> = self
+            .endpoints
+            .iter()
+            .filter(|(_, endpoint)| !endpoint.segments.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let path = &self.endpoints[&key].segments.front()?.path;
+                match read_segment(path) {
+                    Ok(data) => Some(data.target),
+                    Err(e) => {
+                        warn!("Dropping corrupted spooled traces {}: {e}", path.display());
+                        self.stats.corrupted_segments += 1;
+                        self.drop_front(&key);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> SpoolStats {
+        SpoolStats {
+            pending_payloads: self.endpoints.values().map(|e| e.segments.len()).sum(),
+            pending_bytes: self.endpoints.values().map(|e| e.bytes).sum(),
+            ..self.stats.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datadog_trace_protobuf::pb;
+    use datadog_trace_utils::trace_utils::TracerHeaderTags;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn send_data(endpoint: &Endpoint, env: &str) -> SendData {
+        let payload = pb::TracerPayload {
+            env: env.to_owned(),
+            ..Default::default()
+        };
+        SendData::new(10, payload, TracerHeaderTags::default(), endpoint)
+    }
+
+    fn env(data: SendData) -> String {
+        data.get_payloads()[0].env.clone()
+    }
+
+    fn config(dir: &Path) -> SpoolConfig {
+        SpoolConfig {
+            dir: dir.to_path_buf(),
+            max_bytes: u64::MAX,
+            max_age: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn test_replay_in_order() {
+        let tmpdir = tempdir().unwrap();
+        let agent = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8126/v0.4/traces"),
+            api_key: None,
+        };
+        let other = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8127/v0.4/traces"),
+            api_key: None,
+        };
+
+        let mut spool = TraceSpool::open(config(tmpdir.path())).unwrap();
+        assert!(spool.front(&agent).is_none());
+        spool.push(&send_data(&agent, "first")).unwrap();
+        spool.push(&send_data(&other, "other")).unwrap();
+        spool.push(&send_data(&agent, "second")).unwrap();
+
+        // a new sidecar finds them again
+        let mut spool = TraceSpool::open(config(tmpdir.path())).unwrap();
+        assert_eq!(3, spool.stats().pending_payloads);
+        assert!(spool.has_pending(&agent));
+        assert_eq!("first", env(spool.front(&agent).unwrap()));
+        // not removed until sent
+        assert_eq!("first", env(spool.front(&agent).unwrap()));
+        spool.remove_front(&agent);
+        spool.push(&send_data(&agent, "third")).unwrap();
+        assert_eq!("second", env(spool.front(&agent).unwrap()));
+        spool.remove_front(&agent);
+        assert_eq!("third", env(spool.front(&agent).unwrap()));
+        spool.remove_front(&agent);
+        assert!(!spool.has_pending(&agent));
+        assert_eq!("other", env(spool.front(&other).unwrap()));
+
+        let stats = spool.stats();
+        assert_eq!(1, stats.pending_payloads);
+        assert_eq!(1, stats.spooled_payloads);
+        assert_eq!(3, stats.replayed_payloads);
+    }
+
+    #[test]
+    fn test_limits_and_corruption() {
+        let tmpdir = tempdir().unwrap();
+        let agent = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8126/v0.4/traces"),
+            api_key: None,
+        };
+
+        // payloads of the same size
+        let mut spool = TraceSpool::open(config(tmpdir.path())).unwrap();
+        spool.push(&send_data(&agent, "1st")).unwrap();
+        let segment_len = spool.stats().pending_bytes;
+        spool.config.max_bytes = segment_len * 2;
+        spool.push(&send_data(&agent, "2nd")).unwrap();
+        spool.push(&send_data(&agent, "3rd")).unwrap();
+        let stats = spool.stats();
+        assert_eq!(2, stats.pending_payloads);
+        assert_eq!(1, stats.dropped_payloads);
+        assert_eq!(segment_len, stats.dropped_bytes);
+
+        // flip a bit of the body of the oldest segment
+        let path = &spool.endpoints[&endpoint_key(&agent)].segments[0].path;
+        let mut contents = fs::read(path).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        fs::write(path, contents).unwrap();
+        assert_eq!("3rd", env(spool.front(&agent).unwrap()));
+        assert_eq!(1, spool.stats().corrupted_segments);
+
+        spool.config.max_age = Duration::ZERO;
+        spool
+            .endpoints
+            .get_mut(&endpoint_key(&agent))
+            .unwrap()
+            .segments[0]
+            .created -= 1;
+        assert!(spool.front(&agent).is_none());
+        assert_eq!(2, spool.stats().dropped_payloads);
+    }
+
+    #[test]
+    fn test_private_dir_and_leftovers() {
+        let tmpdir = tempdir().unwrap();
+        let agent = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8126/v0.4/traces"),
+            api_key: None,
+        };
+        #[cfg(unix)]
+        fs::set_permissions(tmpdir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut spool = TraceSpool::open(config(tmpdir.path())).unwrap();
+        spool.push(&send_data(&agent, "first")).unwrap();
+        spool.push(&send_data(&agent, "rejected")).unwrap();
+        #[cfg(unix)]
+        {
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(0o755, mode(tmpdir.path()));
+            assert_eq!(0o700, mode(&spool.root));
+        }
+        assert_eq!(vec![agent.clone()], spool.pending_targets());
+
+        let dir = spool.root.join(endpoint_key(&agent));
+        fs::write(dir.join("00000000000000000007.tmp"), b"interrupted").unwrap();
+        fs::write(dir.join("unknown"), b"garbage").unwrap();
+
+        let mut spool = TraceSpool::open(config(tmpdir.path())).unwrap();
+        let stats = spool.stats();
+        assert_eq!(2, stats.pending_payloads);
+        assert_eq!(1, stats.corrupted_segments);
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+
+        spool.remove_front(&agent);
+        assert_eq!("rejected", env(spool.front(&agent).unwrap()));
+        spool.reject_front(&agent);
+        assert!(spool.pending_targets().is_empty());
+        assert_eq!(1, spool.stats().rejected_payloads);
+    }
+}
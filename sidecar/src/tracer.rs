@@ -1,7 +1,7 @@
 // Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
 
-use datadog_trace_utils::config_utils::trace_intake_url_prefixed;
+use datadog_trace_utils::config_utils::{trace_intake_url_prefixed, trace_stats_url_prefixed};
 use ddcommon::Endpoint;
 use http::uri::PathAndQuery;
 use std::str::FromStr;
@@ -9,19 +9,33 @@ use std::str::FromStr;
 #[derive(Default)]
 pub struct Config {
     pub endpoint: Option<Endpoint>,
+    pub stats_endpoint: Option<Endpoint>,
 }
 
 impl Config {
     pub fn set_endpoint(&mut self, endpoint: Endpoint) -> anyhow::Result<()> {
-        let uri = if endpoint.api_key.is_some() {
-            hyper::Uri::from_str(&trace_intake_url_prefixed(&endpoint.url.to_string()))?
+        let (uri, stats_uri) = if endpoint.api_key.is_some() {
+            let prefix = endpoint.url.to_string();
+            (
+                hyper::Uri::from_str(&trace_intake_url_prefixed(&prefix))?,
+                hyper::Uri::from_str(&trace_stats_url_prefixed(&prefix))?,
+            )
         } else {
-            let mut parts = endpoint.url.into_parts();
+            let mut parts = endpoint.url.clone().into_parts();
             parts.path_and_query = Some(PathAndQuery::from_static("/v0.7/traces"));
-            hyper::Uri::from_parts(parts)?
+            let mut stats_parts = endpoint.url.into_parts();
+            stats_parts.path_and_query = Some(PathAndQuery::from_static("/v0.6/stats"));
+            (
+                hyper::Uri::from_parts(parts)?,
+                hyper::Uri::from_parts(stats_parts)?,
+            )
         };
         self.endpoint = Some(Endpoint {
             url: uri,
+            api_key: endpoint.api_key.clone(),
+        });
+        self.stats_endpoint = Some(Endpoint {
+            url: stats_uri,
             api_key: endpoint.api_key,
         });
         Ok(())
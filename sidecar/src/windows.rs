@@ -0,0 +1,109 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use spawn_worker::getpid;
+
+use std::os::windows::io::{IntoRawHandle, OwnedHandle};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use std::{env, io, mem};
+
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+use tokio::select;
+use tokio::sync::Notify;
+
+use crate::config::Config;
+#[cfg(feature = "tracing")]
+use crate::entry::enable_tracing;
+use crate::entry::{main_loop, sidecar_worker};
+use crate::setup::IpcServer;
+
+/// The pipe which the sidecar creates further server ends of, once clients connected to the first
+const ENV_PIPE_PATH: &str = "__DD_INTERNAL_SIDECAR_PIPE_PATH";
+
+async fn accept_pipe_loop(first_server: NamedPipeServer, path: PathBuf) -> io::Result<()> {
+    let cancelled = Arc::new(Notify::new());
+    let cloned_cancelled = cancelled.clone();
+    // the permit is stored if the loop doesn't wait for it yet
+    let cancel = move || cloned_cancelled.notify_one();
+
+    main_loop(
+        |handler| async move {
+            let mut server = first_server;
+            loop {
+                let connected = select! {
+                    connected = server.connect() => connected,
+                    _ = cancelled.notified() => break,
+                };
+                // a pipe server end serves a single client, the next ones connect to a new one
+                let next_server = match ServerOptions::new().create(&path) {
+                    Ok(next_server) => next_server,
+                    Err(e) => {
+                        tracing::error!("Failed creating a server end of the sidecar pipe: {e}");
+                        break;
+                    }
+                };
+                let server = mem::replace(&mut server, next_server);
+                match connected {
+                    Ok(()) => handler(server),
+                    // e.g. the client already went away
+                    Err(e) => tracing::debug!("Failed connecting a client: {e}"),
+                }
+            }
+        },
+        cancel,
+    )
+    .await
+}
+
+fn enter_listener_loop(pipe: OwnedHandle, path: PathBuf) -> anyhow::Result<()> {
+    #[cfg(feature = "tokio-console")]
+    console_subscriber::init();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let _g = runtime.enter();
+
+    let server = unsafe { NamedPipeServer::from_raw_handle(pipe.into_raw_handle()) }?;
+
+    runtime
+        .block_on(accept_pipe_loop(server, path))
+        .map_err(|e| e.into())
+}
+
+#[no_mangle]
+pub extern "C" fn ddog_daemon_entry_point() {
+    #[cfg(feature = "tracing")]
+    enable_tracing().ok();
+    let now = Instant::now();
+
+    let path = env::var_os(ENV_PIPE_PATH);
+    if let (Some(pipe), Some(path)) = (spawn_worker::recv_passed_handle(), path) {
+        tracing::info!("Starting sidecar, pid: {}", getpid());
+        if let Err(err) = enter_listener_loop(pipe, path.into()) {
+            tracing::error!("Error: {err}")
+        }
+    }
+
+    tracing::info!(
+        "shutting down sidecar, pid: {}, total runtime: {:.3}s",
+        getpid(),
+        now.elapsed().as_secs_f64()
+    )
+}
+
+pub(crate) fn daemonize(listener: IpcServer, cfg: Config) -> io::Result<()> {
+    let mut spawn_cfg = sidecar_worker(&cfg)?;
+    spawn_cfg
+        .pass_handle(listener.pipe)
+        .append_env(ENV_PIPE_PATH, listener.path);
+
+    // the sidecar itself is spawned, not an intermediate process to wait for like on unix
+    spawn_cfg
+        .spawn()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+#![cfg(unix)]
+
+use std::{
+    env,
+    io::{self, Read, Write},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use datadog_sidecar::config::Config;
+use datadog_sidecar::setup::{DefaultLiason, Liaison};
+use datadog_sidecar::start_or_connect_to_sidecar;
+use spawn_worker::fork::set_default_child_panic_handler;
+
+/// A process forked from the test, which started its own sidecar
+struct Parent {
+    pid: libc::pid_t,
+    sidecar_pid: libc::pid_t,
+    // the parent exits once it reads from this
+    control: UnixStream,
+}
+
+impl Parent {
+    fn exit(mut self) {
+        // closing it wouldn't do, the parents forked later hold it too
+        self.control.write_all(&[0]).unwrap();
+        let mut status = 0;
+        assert_eq!(self.pid, unsafe { libc::waitpid(self.pid, &mut status, 0) });
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(0, libc::WEXITSTATUS(status));
+    }
+}
+
+fn fork_parent() -> Parent {
+    let (mut control, mut child_control) = UnixStream::pair().unwrap();
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+        0 => {
+            set_default_child_panic_handler();
+            drop(control);
+            let _transport = start_or_connect_to_sidecar(Config::get()).unwrap();
+            let sidecar = DefaultLiason::ipc_per_process()
+                .connect_to_server()
+                .unwrap();
+            child_control
+                .write_all(&peer_pid(&sidecar).to_ne_bytes())
+                .unwrap();
+            // blocks until the test lets the parent exit
+            let _ = child_control.read(&mut [0]);
+            std::process::exit(0)
+        }
+        pid => {
+            drop(child_control);
+            let mut sidecar_pid = [0; 4];
+            control.read_exact(&mut sidecar_pid).unwrap();
+            Parent {
+                pid,
+                sidecar_pid: libc::pid_t::from_ne_bytes(sidecar_pid),
+                control,
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn peer_pid(stream: &UnixStream) -> libc::pid_t {
+    use nix::sys::socket::{getsockopt, sockopt};
+    getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials)
+        .unwrap()
+        .pid()
+}
+
+#[cfg(target_os = "macos")]
+fn peer_pid(stream: &UnixStream) -> libc::pid_t {
+    let mut pid: libc::pid_t = 0;
+    let mut len = std::mem::size_of::<libc::pid_t>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_LOCAL,
+            libc::LOCAL_PEERPID,
+            &mut pid as *mut libc::pid_t as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    assert_eq!(0, res, "{}", io::Error::last_os_error());
+    pid
+}
+
+/// Abstract sockets can only be bound again once the sidecar closed them
+#[cfg(target_os = "linux")]
+fn is_socket_released(pid: libc::pid_t) -> bool {
+    DefaultLiason::new_for_process(pid)
+        .attempt_listen()
+        .unwrap()
+        .is_some()
+}
+
+/// The socket files of the sidecars which stopped are removed by the next process starting one,
+/// once the process they were started for is gone
+#[cfg(target_os = "macos")]
+fn is_socket_released(pid: libc::pid_t) -> bool {
+    drop(DefaultLiason::ipc_per_process().attempt_listen().unwrap());
+    let suffix = format!(".{pid}.sock");
+    !std::fs::read_dir(env::temp_dir().join("libdatadog"))
+        .unwrap()
+        .any(|entry| {
+            entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(&suffix)
+        })
+}
+
+#[test]
+#[ignore] // run all tests that can fork in a separate run, to avoid any race conditions with default rust test harness
+fn test_sidecar_per_process_isolation() {
+    env::set_var("_DD_DEBUG_SIDECAR_IPC_MODE", "instance_per_process");
+    // so that the sidecar of a parent which exited stops soon
+    env::set_var("_DD_DEBUG_SIDECAR_IDLE_LINGER_TIME_SECS", "1");
+
+    let first = fork_parent();
+    let second = fork_parent();
+    assert_ne!(first.sidecar_pid, second.sidecar_pid);
+
+    let first_pid = first.pid;
+    first.exit();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !is_socket_released(first_pid) {
+        assert!(
+            Instant::now() < deadline,
+            "the socket of the sidecar of an exited parent is left over"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(!is_socket_released(second.pid));
+    second.exit();
+}
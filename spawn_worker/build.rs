@@ -4,18 +4,20 @@
 pub use cc_utils::cc;
 
 fn main() {
+    // cfg!() would describe the host, not the target
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+
     let mut builder = cc_utils::ImprovedBuild::new();
     builder
         .file("src/trampoline.c")
         .warnings(true)
-        .link_dynamically("dl")
         .warnings_into_errors(true)
-        .flag("-std=c99")
         .emit_rerun_if_env_changed(true);
 
-    if !cfg!(target_os = "windows") {
+    if target_os != "windows" {
+        builder.flag("-std=c99");
         builder.link_dynamically("dl");
-        if cfg!(target_os = "linux") {
+        if target_os == "linux" {
             builder.flag("-Wl,--no-as-needed");
         }
         builder.link_dynamically("m"); // rust code generally requires libm. Just link against it.
@@ -23,7 +25,7 @@ fn main() {
 
     builder.try_compile_executable("trampoline.bin").unwrap();
 
-    if !cfg!(target_os = "windows") {
+    if target_os != "windows" {
         cc_utils::ImprovedBuild::new()
             .file("src/ld_preload_trampoline.c")
             .link_dynamically("dl")
@@ -9,6 +9,8 @@
 #include <dlfcn.h>
 #include <unistd.h>
 #else
+#include <io.h>
+#include <windows.h>
 #define unlink _unlink
 #endif
 
@@ -76,6 +78,60 @@ int main(int argc, char *argv[]) {
       }
       free(handles);
     }
+#else
+    int additional_shared_libraries_args = argc - 4;
+    HMODULE *handles = NULL;
+    // loaded libraries can't be removed on windows, only once they are freed again
+    const char **temp_libraries = NULL;
+
+    if (additional_shared_libraries_args > 0) {
+      handles = calloc(additional_shared_libraries_args, sizeof(HMODULE));
+      temp_libraries = calloc(additional_shared_libraries_args, sizeof(char *));
+    }
+
+    int additional_shared_libraries_count = 0;
+    int temp_libraries_count = 0;
+    bool unlink_next = false;
+    for (int i = 0; i < additional_shared_libraries_args; i++) {
+      const char *lib_path = argv[3 + i];
+      if (*lib_path == '-' && !lib_path[1]) {
+          unlink_next = true;
+          continue;
+      }
+      if (!(handles[additional_shared_libraries_count++] = LoadLibraryA(lib_path))) {
+          fprintf(stderr, "failed loading %s: %lu", lib_path, GetLastError());
+          return 9;
+      }
+      if (unlink_next) {
+        temp_libraries[temp_libraries_count++] = lib_path;
+        unlink_next = false;
+      }
+    }
+
+    HMODULE handle = LoadLibraryA(library_path);
+    if (!handle) {
+      fprintf(stderr, "failed loading %s: %lu", library_path, GetLastError());
+      return 10;
+    }
+
+    void (*fn)(void) = (void (*)(void))GetProcAddress(handle, symbol_name);
+    if (!fn) {
+      fprintf(stderr, "failed looking up %s: %lu", symbol_name, GetLastError());
+      return 11;
+    }
+    (*fn)();
+    FreeLibrary(handle);
+
+    if (handles != NULL) {
+      for (int i = 0; i < additional_shared_libraries_count; i++) {
+        FreeLibrary(handles[i]);
+      }
+      free(handles);
+    }
+    for (int i = 0; i < temp_libraries_count; i++) {
+      unlink(temp_libraries[i]);
+    }
+    free(temp_libraries);
 #endif
     return 0;
   }
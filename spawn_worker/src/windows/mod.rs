@@ -0,0 +1,86 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    env,
+    ffi::OsString,
+    os::windows::{
+        ffi::OsStringExt,
+        io::{FromRawHandle, OwnedHandle, RawHandle},
+    },
+    path::PathBuf,
+};
+
+use windows_sys::Win32::{
+    Foundation::{GetHandleInformation, SetHandleInformation, HANDLE, HANDLE_FLAG_INHERIT},
+    System::LibraryLoader::{
+        GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+        GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+    },
+};
+
+mod spawn;
+pub use spawn::*;
+
+use crate::Entrypoint;
+
+/// returns the path of the module (executable or dll) containing the address *addr*
+pub(crate) fn get_module_path(addr: *const u8) -> Option<PathBuf> {
+    let mut module = 0;
+    if unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            addr as *const u16,
+            &mut module,
+        )
+    } == 0
+    {
+        return None;
+    }
+
+    let mut buf = vec![0u16; 260];
+    loop {
+        let len = unsafe { GetModuleFileNameW(module, buf.as_mut_ptr(), buf.len() as u32) };
+        match len as usize {
+            0 => return None,
+            // the path was truncated
+            len if len == buf.len() => buf.resize(buf.len() * 2, 0),
+            len => return Some(OsString::from_wide(&buf[..len]).into()),
+        }
+    }
+}
+
+/// Returns PID of current process
+pub fn getpid() -> u32 {
+    std::process::id()
+}
+
+impl From<Entrypoint> for spawn::Target {
+    fn from(entrypoint: Entrypoint) -> Self {
+        spawn::Target::Entrypoint(entrypoint)
+    }
+}
+
+impl Entrypoint {
+    pub fn get_fs_path(&self) -> Option<PathBuf> {
+        get_module_path(self.ptr as *const u8)
+    }
+}
+
+pub(crate) static ENV_PASS_HANDLE_KEY: &str = "__DD_INTERNAL_PASSED_HANDLE";
+
+/// The handle passed by the parent with [SpawnWorker::pass_handle]
+pub fn recv_passed_handle() -> Option<OwnedHandle> {
+    let val = env::var(ENV_PASS_HANDLE_KEY).ok()?;
+    let handle = val.parse::<isize>().ok()? as HANDLE;
+
+    // check if the handle is valid
+    let mut flags = 0;
+    if unsafe { GetHandleInformation(handle, &mut flags) } == 0 {
+        return None;
+    }
+    // not to leak it to the processes spawned by this one
+    unsafe { SetHandleInformation(handle, HANDLE_FLAG_INHERIT, 0) };
+
+    Some(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) })
+}
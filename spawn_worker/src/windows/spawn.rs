@@ -0,0 +1,272 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2021-Present Datadog, Inc.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    ffi::{self, CString, OsString},
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    os::windows::{
+        io::{AsRawHandle, OwnedHandle},
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::PathBuf,
+    process::{self, Command, ExitStatus},
+};
+
+use windows_sys::Win32::{
+    Foundation::{SetHandleInformation, HANDLE, HANDLE_FLAG_INHERIT},
+    System::Threading::{CREATE_NEW_PROCESS_GROUP, DETACHED_PROCESS},
+};
+
+fn write_to_tmp_file(data: &[u8], suffix: &str) -> anyhow::Result<PathBuf> {
+    let mut tmp_file = tempfile::Builder::new().suffix(suffix).tempfile()?;
+    tmp_file.write_all(data)?;
+
+    // ensure the file is not auto cleaned in parent process
+    Ok(tmp_file.into_temp_path().keep()?)
+}
+
+/// Unlike on unix, the trampoline can't remove itself while it runs: it is written once per
+/// process name and trampoline version, and shared by the workers
+fn write_trampoline(process_name: &str) -> anyhow::Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    crate::TRAMPOLINE_BIN.hash(&mut hasher);
+    let path = env::temp_dir().join(format!("{process_name}-{:016x}.exe", hasher.finish()));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    let mut tmp_file = tempfile::NamedTempFile::new_in(env::temp_dir())?;
+    tmp_file.write_all(crate::TRAMPOLINE_BIN)?;
+    if let Err(e) = tmp_file.persist_noclobber(&path) {
+        // unless another process wrote it concurrently
+        if !path.exists() {
+            return Err(e.error.into());
+        }
+    }
+    Ok(path)
+}
+
+pub enum Target {
+    Entrypoint(crate::Entrypoint),
+    Manual(CString, CString),
+    Noop,
+}
+
+pub enum Stdio {
+    Inherit,
+    Handle(OwnedHandle),
+    Null,
+}
+
+impl Stdio {
+    fn as_child_stdio(&self) -> io::Result<process::Stdio> {
+        match self {
+            Stdio::Inherit => Ok(process::Stdio::inherit()),
+            Stdio::Handle(handle) => Ok(handle.try_clone()?.into()),
+            Stdio::Null => Ok(process::Stdio::null()),
+        }
+    }
+}
+
+impl From<File> for Stdio {
+    fn from(val: File) -> Self {
+        Stdio::Handle(val.into())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LibDependency {
+    Path(PathBuf),
+    Binary(&'static [u8]),
+}
+
+pub struct SpawnWorker {
+    stdin: Stdio,
+    stderr: Stdio,
+    stdout: Stdio,
+    daemonize: bool,
+    handle_to_pass: Option<OwnedHandle>,
+    target: Target,
+    shared_lib_dependencies: Vec<LibDependency>,
+    env: Vec<(ffi::OsString, ffi::OsString)>,
+    process_name: Option<String>,
+}
+
+impl SpawnWorker {
+    pub fn from_env<E: IntoIterator<Item = (ffi::OsString, ffi::OsString)>>(env: E) -> Self {
+        Self {
+            stdin: Stdio::Inherit,
+            stdout: Stdio::Inherit,
+            stderr: Stdio::Inherit,
+            daemonize: false,
+            target: Target::Noop,
+            handle_to_pass: None,
+            env: env.into_iter().collect(),
+            process_name: None,
+            shared_lib_dependencies: vec![],
+        }
+    }
+
+    /// # Safety
+    /// since the rust library code can coexist with other code written in other languages
+    /// access to environment (required to be read to be passed to subprocess) is unsafe
+    ///
+    /// ensure no other threads read the environment at the same time as this method is called
+    pub unsafe fn new() -> Self {
+        Self::from_env(env::vars_os())
+    }
+
+    pub fn target<T: Into<Target>>(&mut self, target: T) -> &mut Self {
+        self.target = target.into();
+        self
+    }
+
+    pub fn shared_lib_dependencies(&mut self, deps: Vec<LibDependency>) -> &mut Self {
+        self.shared_lib_dependencies = deps;
+        self
+    }
+
+    /// The name of the trampoline executable, which the process is shown as
+    pub fn process_name<S: Into<String>>(&mut self, process_name: S) -> &mut Self {
+        self.process_name = Some(process_name.into());
+        self
+    }
+
+    pub fn stdin<S: Into<Stdio>>(&mut self, stdio: S) -> &mut Self {
+        self.stdin = stdio.into();
+        self
+    }
+
+    pub fn stdout<S: Into<Stdio>>(&mut self, stdio: S) -> &mut Self {
+        self.stdout = stdio.into();
+        self
+    }
+
+    /// Detaches the process from the console and the ctrl+c handling of the parent
+    pub fn daemonize(&mut self, daemonize: bool) -> &mut Self {
+        self.daemonize = daemonize;
+        self
+    }
+
+    pub fn stderr<S: Into<Stdio>>(&mut self, stdio: S) -> &mut Self {
+        self.stderr = stdio.into();
+        self
+    }
+
+    /// The handle is inherited by the process, which gets it with [crate::recv_passed_handle]
+    pub fn pass_handle<T: Into<OwnedHandle>>(&mut self, handle: T) -> &mut Self {
+        self.handle_to_pass = Some(handle.into());
+        self
+    }
+
+    pub fn append_env<K: Into<OsString>, V: Into<OsString>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn spawn(&mut self) -> anyhow::Result<Child> {
+        let process = self.do_spawn()?;
+
+        Ok(Child {
+            pid: process.as_ref().map(process::Child::id),
+            process,
+        })
+    }
+
+    fn do_spawn(&self) -> anyhow::Result<Option<process::Child>> {
+        let (library_path, entrypoint_symbol_name) = match &self.target {
+            Target::Entrypoint(entrypoint) => (
+                entrypoint
+                    .get_fs_path()
+                    .ok_or_else(|| anyhow::format_err!("can't read symbol pointer data"))?
+                    .into_os_string(),
+                entrypoint.symbol_name.clone(),
+            ),
+            Target::Manual(path, symbol_name) => {
+                (OsString::from(path.to_str()?), symbol_name.clone())
+            }
+            Target::Noop => return Ok(None),
+        };
+
+        let trampoline =
+            write_trampoline(self.process_name.as_deref().unwrap_or("spawned_worker"))?;
+        let mut cmd = Command::new(trampoline);
+        // the trampoline is shared, and must not be removed
+        cmd.arg("").arg(library_path);
+
+        let mut temp_files = vec![];
+        for dep in &self.shared_lib_dependencies {
+            match dep {
+                LibDependency::Path(path) => {
+                    cmd.arg(path);
+                }
+                LibDependency::Binary(bin) => {
+                    let path = write_to_tmp_file(bin, ".dll")?;
+                    cmd.arg("-").arg(&path);
+                    temp_files.push(path);
+                }
+            }
+        }
+
+        cmd.arg(entrypoint_symbol_name.to_str()?);
+        cmd.env_clear().envs(self.env.iter().map(|(k, v)| (k, v)));
+
+        // processes are spawned inheriting all the inheritable handles
+        let handle_to_pass = if let Some(src_handle) = &self.handle_to_pass {
+            let handle = src_handle.try_clone()?;
+            let raw_handle = handle.as_raw_handle() as HANDLE;
+            if unsafe { SetHandleInformation(raw_handle, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT) }
+                == 0
+            {
+                return Err(io::Error::last_os_error().into());
+            }
+            cmd.env(crate::ENV_PASS_HANDLE_KEY, raw_handle.to_string());
+            Some(handle)
+        } else {
+            None
+        };
+
+        cmd.stdin(self.stdin.as_child_stdio()?)
+            .stdout(self.stdout.as_child_stdio()?)
+            .stderr(self.stderr.as_child_stdio()?);
+
+        if self.daemonize {
+            cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+        }
+
+        let process = cmd.spawn();
+        // the inheritable copy of the handle is only needed until the process is spawned
+        drop(handle_to_pass);
+        match process {
+            Ok(process) => Ok(Some(process)),
+            Err(e) => {
+                for temp_file in temp_files {
+                    _ = fs::remove_file(temp_file);
+                }
+                Err(e.into())
+            }
+        }
+    }
+}
+
+pub struct Child {
+    pub pid: Option<u32>,
+    process: Option<process::Child>,
+}
+
+impl Child {
+    pub fn wait(self) -> anyhow::Result<ExitStatus> {
+        match self.process {
+            Some(mut process) => Ok(process.wait()?),
+            None => Ok(ExitStatus::from_raw(0)),
+        }
+    }
+}
@@ -44,9 +44,19 @@ impl Linkable {
         ret
     }
 
-    pub fn to_compiler_args(&self, _compiler: &cc::Tool) -> Vec<OsString> {
+    pub fn to_compiler_args(&self, compiler: &cc::Tool) -> Vec<OsString> {
         // todo: improve handling of static and dynamic link cases
 
+        if compiler.is_like_msvc() {
+            // msvc links against import libraries, which are passed like the sources
+            return match self {
+                Linkable::Static(target) | Linkable::Dynamic(target) => match target {
+                    LinkableTarget::Path(p) => vec![p.as_os_str().to_owned()],
+                    LinkableTarget::Name(name) => vec![format!("{name}.lib").into()],
+                },
+            };
+        }
+
         match self {
             Linkable::Static(target) => match target {
                 LinkableTarget::Path(p) => vec![p.as_os_str().to_owned()],
@@ -182,16 +192,28 @@ impl ImprovedBuild {
 
         let mut cmd = compiler.to_command();
 
-        match output_type {
-            OutputType::Executable => {
-                cmd.args(["-o".into(), output_path.as_os_str().to_owned()]);
+        if compiler.is_like_msvc() {
+            let mut output_arg = OsString::from("/Fe");
+            output_arg.push(&output_path);
+            // keep the object files out of the source directory
+            let mut objects_arg = OsString::from("/Fo");
+            objects_arg.push(self.get_out_dir()?.join(""));
+            if let OutputType::Shared = output_type {
+                cmd.arg("/LD");
             }
-            OutputType::Shared => {
-                cmd.args([
-                    "-shared".into(),
-                    "-o".into(),
-                    output_path.as_os_str().to_owned(),
-                ]);
+            cmd.args([output_arg, objects_arg]);
+        } else {
+            match output_type {
+                OutputType::Executable => {
+                    cmd.args(["-o".into(), output_path.as_os_str().to_owned()]);
+                }
+                OutputType::Shared => {
+                    cmd.args([
+                        "-shared".into(),
+                        "-o".into(),
+                        output_path.as_os_str().to_owned(),
+                    ]);
+                }
             }
         }
 
@@ -0,0 +1,242 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2023-Present Datadog, Inc.
+
+//! Aggregates spans into the time bucketed stats the agent would otherwise compute, so the
+//! tracer can drop unsampled spans and still keep accurate metrics.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use datadog_trace_protobuf::pb;
+
+use crate::ddsketch::DDSketch;
+use crate::trace_utils::TOP_LEVEL_KEY;
+
+pub const DEFAULT_BUCKET_DURATION: Duration = Duration::from_secs(10);
+
+const MEASURED_KEY: &str = "_dd.measured";
+const HTTP_STATUS_CODE_KEY: &str = "http.status_code";
+const PEER_SERVICE_KEY: &str = "peer.service";
+const SYNTHETICS_ORIGIN_PREFIX: &str = "synthetics";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct AggregationKey {
+    service: String,
+    name: String,
+    resource: String,
+    r#type: String,
+    http_status_code: u32,
+    synthetics: bool,
+    peer_service: String,
+}
+
+impl AggregationKey {
+    fn new(span: &pb::Span, origin: &str) -> Self {
+        let http_status_code = match span.meta.get(HTTP_STATUS_CODE_KEY) {
+            Some(code) => code.parse().unwrap_or_default(),
+            None => span
+                .metrics
+                .get(HTTP_STATUS_CODE_KEY)
+                .map(|code| *code as u32)
+                .unwrap_or_default(),
+        };
+        AggregationKey {
+            service: span.service.clone(),
+            name: span.name.clone(),
+            resource: span.resource.clone(),
+            r#type: span.r#type.clone(),
+            http_status_code,
+            synthetics: origin.starts_with(SYNTHETICS_ORIGIN_PREFIX),
+            peer_service: span.meta.get(PEER_SERVICE_KEY).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct GroupedStats {
+    hits: u64,
+    errors: u64,
+    duration: u64,
+    top_level_hits: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+impl GroupedStats {
+    fn into_pb(self, key: AggregationKey) -> pb::ClientGroupedStats {
+        pb::ClientGroupedStats {
+            service: key.service,
+            name: key.name,
+            resource: key.resource,
+            http_status_code: key.http_status_code,
+            r#type: key.r#type,
+            db_type: String::new(),
+            hits: self.hits,
+            errors: self.errors,
+            duration: self.duration,
+            ok_summary: self.ok_summary.encode_to_vec(),
+            error_summary: self.error_summary.encode_to_vec(),
+            synthetics: key.synthetics,
+            top_level_hits: self.top_level_hits,
+            peer_service: key.peer_service,
+        }
+    }
+}
+
+fn has_metric(span: &pb::Span, key: &str) -> bool {
+    span.metrics.get(key).is_some_and(|v| *v == 1.0)
+}
+
+/// Stats of the top level and measured spans, aggregated by span end time into buckets
+pub struct SpanConcentrator {
+    bucket_duration: u64,
+    buckets: HashMap<u64, HashMap<AggregationKey, GroupedStats>>,
+}
+
+impl Default for SpanConcentrator {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUCKET_DURATION)
+    }
+}
+
+impl SpanConcentrator {
+    pub fn new(bucket_duration: Duration) -> Self {
+        SpanConcentrator {
+            bucket_duration: bucket_duration.as_nanos() as u64,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// `origin` is the origin of the chunk the span belongs to
+    pub fn add_span(&mut self, span: &pb::Span, origin: &str) {
+        let top_level = has_metric(span, TOP_LEVEL_KEY);
+        if !top_level && !has_metric(span, MEASURED_KEY) {
+            return;
+        }
+        let end = match u64::try_from(span.start + span.duration) {
+            Ok(end) => end,
+            Err(_) => return,
+        };
+        let duration = span.duration.max(0) as u64;
+
+        let stats = self
+            .buckets
+            .entry(end - end % self.bucket_duration)
+            .or_default()
+            .entry(AggregationKey::new(span, origin))
+            .or_default();
+        stats.hits += 1;
+        stats.duration += duration;
+        if top_level {
+            stats.top_level_hits += 1;
+        }
+        if span.error != 0 {
+            stats.errors += 1;
+            stats.error_summary.add(duration as f64);
+        } else {
+            stats.ok_summary.add(duration as f64);
+        }
+    }
+
+    pub fn add_chunk(&mut self, chunk: &pb::TraceChunk) {
+        for span in chunk.spans.iter() {
+            self.add_span(span, &chunk.origin);
+        }
+    }
+
+    /// Removes and returns the buckets which ended before `now`, or all of them if `force` is set
+    pub fn flush(&mut self, now: SystemTime, force: bool) -> Vec<pb::ClientStatsBucket> {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let bucket_duration = self.bucket_duration;
+        let flushed: Vec<u64> = self
+            .buckets
+            .keys()
+            .copied()
+            .filter(|start| force || start + bucket_duration <= now)
+            .collect();
+
+        flushed
+            .into_iter()
+            .filter_map(|start| {
+                let stats = self.buckets.remove(&start)?;
+                Some(pb::ClientStatsBucket {
+                    start,
+                    duration: bucket_duration,
+                    stats: stats
+                        .into_iter()
+                        .map(|(key, stats)| stats.into_pb(key))
+                        .collect(),
+                    agent_time_shift: 0,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(service: &str, start: i64, duration: i64, error: i32, top_level: bool) -> pb::Span {
+        let mut span = pb::Span {
+            service: service.to_owned(),
+            name: "http.request".to_owned(),
+            resource: "GET /".to_owned(),
+            start,
+            duration,
+            error,
+            ..Default::default()
+        };
+        span.meta
+            .insert(HTTP_STATUS_CODE_KEY.to_owned(), "200".to_owned());
+        if top_level {
+            span.metrics.insert(TOP_LEVEL_KEY.to_owned(), 1.0);
+        }
+        span
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let second = 1_000_000_000;
+        let mut concentrator = SpanConcentrator::default();
+        concentrator.add_span(&span("web", 0, 100, 0, true), "");
+        concentrator.add_span(&span("web", 10, 200, 1, true), "");
+        // neither top level nor measured
+        concentrator.add_span(&span("web", 10, 200, 0, false), "");
+        let mut measured = span("db", 20, 50, 0, false);
+        measured.metrics.insert(MEASURED_KEY.to_owned(), 1.0);
+        concentrator.add_span(&measured, "synthetics-browser");
+        // next bucket
+        concentrator.add_span(&span("web", 10 * second, 100, 0, true), "");
+
+        let now = UNIX_EPOCH + Duration::from_secs(15);
+        let buckets = concentrator.flush(now, false);
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].start, 0);
+        assert_eq!(buckets[0].duration, 10 * second as u64);
+
+        let mut stats = buckets[0].stats.clone();
+        stats.sort_by(|a, b| a.service.cmp(&b.service));
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].service, "db");
+        assert!(stats[0].synthetics);
+        assert_eq!(stats[0].top_level_hits, 0);
+        assert_eq!(stats[1].service, "web");
+        assert_eq!(stats[1].http_status_code, 200);
+        assert_eq!(stats[1].hits, 2);
+        assert_eq!(stats[1].errors, 1);
+        assert_eq!(stats[1].duration, 300);
+        assert_eq!(stats[1].top_level_hits, 2);
+
+        assert!(!concentrator.is_empty());
+        assert_eq!(concentrator.flush(now, true).len(), 1);
+        assert!(concentrator.is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2023-Present Datadog, Inc.
+
+//! Minimal DDSketch, encoded in the protobuf format the agent decodes the ok/error latency
+//! summaries of the stats payloads with.
+
+use std::collections::HashMap;
+
+use prost::Message;
+
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// DDSketch with a logarithmic index mapping and sparse bins. Values <= 0 are counted in the zero
+/// bin.
+#[derive(Clone, Debug)]
+pub struct DDSketch {
+    gamma: f64,
+    multiplier: f64,
+    bins: HashMap<i32, f64>,
+    zero_count: f64,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        DDSketch {
+            gamma,
+            multiplier: 1.0 / gamma.ln(),
+            bins: HashMap::new(),
+            zero_count: 0.0,
+        }
+    }
+}
+
+impl DDSketch {
+    fn index(&self, value: f64) -> i32 {
+        (value.ln() * self.multiplier).floor() as i32
+    }
+
+    /// Representative value of the bin at `index`, within the relative accuracy of the values
+    /// stored in it
+    fn value(&self, index: i32) -> f64 {
+        (index as f64 / self.multiplier).exp() * (1.0 + RELATIVE_ACCURACY)
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value <= f64::MIN_POSITIVE * self.gamma {
+            self.zero_count += 1.0;
+        } else {
+            *self.bins.entry(self.index(value)).or_default() += 1.0;
+        }
+    }
+
+    pub fn count(&self) -> f64 {
+        self.zero_count + self.bins.values().sum::<f64>()
+    }
+
+    /// Approximate value at quantile `q`, in [0, 1]
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0.0 {
+            return None;
+        }
+        let rank = q.clamp(0.0, 1.0) * (count - 1.0);
+        let mut seen = self.zero_count;
+        if seen > rank {
+            return Some(0.0);
+        }
+        let mut indexes: Vec<_> = self.bins.keys().copied().collect();
+        indexes.sort_unstable();
+        for index in indexes {
+            seen += self.bins[&index];
+            if seen > rank {
+                return Some(self.value(index));
+            }
+        }
+        None
+    }
+
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        proto::DDSketch {
+            mapping: Some(proto::IndexMapping {
+                gamma: self.gamma,
+                index_offset: 0.0,
+                interpolation: 0, // NONE
+            }),
+            positive_values: Some(proto::Store {
+                bin_counts: self.bins.clone(),
+                contiguous_bin_counts: vec![],
+                contiguous_bin_index_offset: 0,
+            }),
+            negative_values: None,
+            zero_count: self.zero_count,
+        }
+        .encode_to_vec()
+    }
+}
+
+/// Messages of sketches-go's ddsketch.proto
+mod proto {
+    use std::collections::HashMap;
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DDSketch {
+        #[prost(message, optional, tag = "1")]
+        pub mapping: Option<IndexMapping>,
+        #[prost(message, optional, tag = "2")]
+        pub positive_values: Option<Store>,
+        #[prost(message, optional, tag = "3")]
+        pub negative_values: Option<Store>,
+        #[prost(double, tag = "4")]
+        pub zero_count: f64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct IndexMapping {
+        #[prost(double, tag = "1")]
+        pub gamma: f64,
+        #[prost(double, tag = "2")]
+        pub index_offset: f64,
+        #[prost(int32, tag = "3")]
+        pub interpolation: i32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Store {
+        #[prost(map = "sint32, double", tag = "1")]
+        pub bin_counts: HashMap<i32, f64>,
+        #[prost(double, repeated, tag = "2")]
+        pub contiguous_bin_counts: Vec<f64>,
+        #[prost(sint32, tag = "3")]
+        pub contiguous_bin_index_offset: i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles() {
+        let mut sketch = DDSketch::default();
+        for value in 1..=1000 {
+            sketch.add(value as f64);
+        }
+        sketch.add(0.0);
+
+        assert_eq!(sketch.count(), 1001.0);
+        assert_eq!(sketch.quantile(0.0), Some(0.0));
+        for (q, expected) in [(0.5, 500.0), (0.99, 990.0), (1.0, 1000.0)] {
+            let value = sketch.quantile(q).unwrap();
+            assert!(
+                (value - expected).abs() <= expected * RELATIVE_ACCURACY * 2.0,
+                "q{q}: {value} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut sketch = DDSketch::default();
+        sketch.add(0.0);
+        sketch.add(1000.0);
+        sketch.add(1001.0);
+
+        let decoded = proto::DDSketch::decode(sketch.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.zero_count, 1.0);
+        assert_eq!(decoded.mapping.unwrap().gamma, sketch.gamma);
+        let bins = decoded.positive_values.unwrap().bin_counts;
+        assert_eq!(bins, HashMap::from([(sketch.index(1000.0), 2.0)]));
+    }
+}
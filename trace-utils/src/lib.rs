@@ -1,6 +1,8 @@
 // Unless explicitly stated otherwise all files in this repository are licensed under the Apache License Version 2.0.
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2023-Present Datadog, Inc.
 
+pub mod concentrator;
 pub mod config_utils;
+pub mod ddsketch;
 pub mod stats_utils;
 pub mod trace_utils;
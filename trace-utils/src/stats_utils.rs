@@ -8,7 +8,7 @@ use log::debug;
 use std::io::Write;
 
 use datadog_trace_protobuf::pb;
-use ddcommon::Endpoint;
+use ddcommon::{connector, Endpoint};
 
 pub async fn get_stats_from_request_body(body: Body) -> anyhow::Result<pb::ClientStatsPayload> {
     let buffer = hyper::body::aggregate(body).await?;
@@ -78,3 +78,30 @@ pub async fn send_stats_payload(
         Err(e) => anyhow::bail!("Failed to send trace stats: {e}"),
     }
 }
+
+/// Sends stats computed on the tracer side to the agent, which merges them with its own
+pub async fn send_client_stats_payload(
+    payload: &pb::ClientStatsPayload,
+    target: &Endpoint,
+) -> anyhow::Result<()> {
+    let req = target
+        .into_request_builder(concat!("Tracer/", env!("CARGO_PKG_VERSION")))?
+        .method(Method::POST)
+        .header("Content-Type", "application/msgpack")
+        .header("Datadog-Meta-Lang", &payload.lang)
+        .header("Datadog-Meta-Tracer-Version", &payload.tracer_version)
+        .body(Body::from(rmp_serde::to_vec_named(payload)?))?;
+
+    let client: Client<_, hyper::Body> = Client::builder().build(connector::Connector::default());
+    match client.request(req).await {
+        Ok(response) => {
+            if !response.status().is_success() {
+                let body_bytes = hyper::body::to_bytes(response.into_body()).await?;
+                let response_body = String::from_utf8(body_bytes.to_vec()).unwrap_or_default();
+                anyhow::bail!("Agent did not accept trace stats: {response_body}");
+            }
+            Ok(())
+        }
+        Err(e) => anyhow::bail!("Failed to send trace stats: {e}"),
+    }
+}
@@ -8,7 +8,9 @@ use hyper::http::HeaderValue;
 use hyper::{body::Buf, Body, Client, HeaderMap, Method, Response, StatusCode};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 
 use datadog_trace_normalization::normalizer;
 use datadog_trace_protobuf::pb;
@@ -16,7 +18,7 @@ use datadog_trace_protobuf::pb::TraceChunk;
 use ddcommon::{connector, Endpoint, HttpRequestBuilder};
 
 /// Span metric the mini agent must set for the backend to recognize top level span
-const TOP_LEVEL_KEY: &str = "_top_level";
+pub(crate) const TOP_LEVEL_KEY: &str = "_top_level";
 /// Span metric the tracer sets to denote a top level span
 const TRACER_TOP_LEVEL_KEY: &str = "_dd.top_level";
 
@@ -35,6 +37,43 @@ macro_rules! parse_string_header {
     }
 }
 
+/// Failure of the request sending a [SendData]
+#[derive(Debug)]
+pub enum SendError {
+    /// The request could not be sent, or its response not received
+    Request(hyper::Error),
+    /// The agent or the intake answered with an unexpected status
+    Status(StatusCode, String),
+}
+
+impl SendError {
+    /// Whether sending the same payload again may succeed. Other client errors mean the payload
+    /// itself is rejected
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SendError::Request(_) => true,
+            SendError::Status(status, _) => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Request(e) => write!(f, "Failed to send traces: {e}"),
+            SendError::Status(status, body) => {
+                write!(f, "Server did not accept traces ({status}): {body}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
 /// First value of returned tuple is the payload size
 pub async fn get_traces_from_request_body(
     body: Body,
@@ -86,6 +125,9 @@ impl<'a> From<TracerHeaderTags<'a>> for HashMap<&'static str, String> {
             ),
             ("datadog-container-id", tags.container_id.to_string()),
         ]);
+        if tags.client_computed_stats {
+            headers.insert("datadog-client-computed-stats", "yes".to_string());
+        }
         headers.retain(|_, v| !v.is_empty());
         headers
     }
@@ -176,12 +218,13 @@ where
     Ok(buf)
 }
 
-#[derive(Debug, Clone)]
+/// Serializable, so that it can be persisted until it can be sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendData {
     tracer_payloads: Vec<pb::TracerPayload>,
     size: usize, // have a rough size estimate to force flushing if it's large
     pub target: Endpoint,
-    headers: HashMap<&'static str, String>,
+    headers: HashMap<Cow<'static, str>, String>,
 }
 
 impl SendData {
@@ -191,7 +234,7 @@ impl SendData {
         tracer_header_tags: TracerHeaderTags,
         target: &Endpoint,
     ) -> SendData {
-        let headers = if let Some(api_key) = &target.api_key {
+        let headers: HashMap<&'static str, String> = if let Some(api_key) = &target.api_key {
             HashMap::from([("DD-API-KEY", api_key.as_ref().to_string())])
         } else {
             tracer_header_tags.into()
@@ -201,7 +244,10 @@ impl SendData {
             tracer_payloads: vec![tracer_payload],
             size,
             target: target.clone(),
-            headers,
+            headers: headers
+                .into_iter()
+                .map(|(key, value)| (Cow::Borrowed(key), value))
+                .collect(),
         }
     }
 
@@ -209,6 +255,14 @@ impl SendData {
         self.size
     }
 
+    /// Sends the payloads to the intake when the target has an api key, to the agent otherwise,
+    /// one request per payload.
+    ///
+    /// A `/v0.4/traces` target only accepts a plain array of traces, so each TracerPayload is
+    /// flattened to the spans of its chunks, which loses the chunk `priority`, `origin`, `tags`
+    /// and `dropped_trace`. The agent still gets the sampling priority and the origin through
+    /// the `_sampling_priority_v1` metric and the `_dd.origin` meta of the spans, which
+    /// [collect_trace_chunks] derives the chunk ones from.
     pub async fn send<'a>(self) -> anyhow::Result<Response<Body>> {
         let target = &self.target;
 
@@ -221,7 +275,7 @@ impl SendData {
             .method(Method::POST);
 
         for (key, value) in &self.headers {
-            req = req.header(*key, value);
+            req = req.header(key.as_ref(), value);
         }
 
         async fn send_request(
@@ -237,15 +291,18 @@ impl SendData {
                 .await
             {
                 Ok(response) => {
-                    if response.status() != expected_status {
-                        let body_bytes = hyper::body::to_bytes(response.into_body()).await?;
+                    let status = response.status();
+                    if status != expected_status {
+                        let body_bytes = hyper::body::to_bytes(response.into_body())
+                            .await
+                            .map_err(SendError::Request)?;
                         let response_body =
                             String::from_utf8(body_bytes.to_vec()).unwrap_or_default();
-                        anyhow::bail!("Server did not accept traces: {response_body}");
+                        return Err(SendError::Status(status, response_body).into());
                     }
                     Ok(response)
                 }
-                Err(e) => anyhow::bail!("Failed to send traces: {e}"),
+                Err(e) => Err(SendError::Request(e).into()),
             }
         }
 
@@ -261,6 +318,8 @@ impl SendData {
             req = req.header("Content-type", "application/msgpack");
 
             let (template, _) = req.body(()).unwrap().into_parts();
+            // agents without the v0.7 endpoint only accept a plain array of traces
+            let v04 = target.url.path().ends_with("/v0.4/traces");
 
             let mut futures = FuturesUnordered::new();
             for tracer_payload in self.tracer_payloads.into_iter() {
@@ -277,11 +336,18 @@ impl SendData {
                     .unwrap()
                     .extend(template.headers.clone());
 
-                futures.push(send_request(
-                    builder,
-                    rmp_serde::to_vec_named(&tracer_payload)?,
-                    StatusCode::OK,
-                ));
+                let payload = if v04 {
+                    // the chunk fields are lost, see the doc of send()
+                    let traces: Vec<&Vec<pb::Span>> = tracer_payload
+                        .chunks
+                        .iter()
+                        .map(|chunk| &chunk.spans)
+                        .collect();
+                    rmp_serde::to_vec_named(&traces)?
+                } else {
+                    rmp_serde::to_vec_named(&tracer_payload)?
+                };
+                futures.push(send_request(builder, payload, StatusCode::OK));
             }
             let mut last_response = Err(anyhow::format_err!("No futures completed...?!"));
             loop {
@@ -539,6 +605,7 @@ mod tests {
     use super::{get_root_span_index, set_serverless_root_span_tags};
     use crate::trace_utils;
     use datadog_trace_protobuf::pb;
+    use ddcommon::Endpoint;
 
     #[tokio::test]
     async fn test_get_traces_from_request_body() {
@@ -630,6 +697,95 @@ mod tests {
         }
     }
 
+    /// Answers a single request, returning its body
+    fn receive_request(listener: std::net::TcpListener) -> Vec<u8> {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let (socket, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(socket);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .unwrap();
+        body
+    }
+
+    #[tokio::test]
+    async fn test_send_v04_keeps_priority_and_origin_in_spans() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || receive_request(listener));
+
+        let mut root_span = create_test_span(1, 1, 0);
+        root_span
+            .metrics
+            .insert("_sampling_priority_v1".to_string(), 2.0);
+        root_span
+            .meta
+            .insert("_dd.origin".to_string(), "synthetics".to_string());
+        let traces = vec![vec![root_span, create_test_span(1, 2, 1)]];
+        let header_tags = trace_utils::TracerHeaderTags::default();
+        let payload = trace_utils::collect_trace_chunks(traces, &header_tags, |_, _| {});
+        assert_eq!(payload.chunks[0].priority, 2);
+        assert_eq!(payload.chunks[0].origin, "synthetics");
+
+        let endpoint = Endpoint {
+            url: format!("http://127.0.0.1:{port}/v0.4/traces")
+                .parse()
+                .unwrap(),
+            api_key: None,
+        };
+        let data = trace_utils::SendData::new(100, payload, header_tags, &endpoint);
+        data.send().await.unwrap();
+
+        let traces: Vec<Vec<pb::Span>> = rmp_serde::from_slice(&server.join().unwrap()).unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].len(), 2);
+        let root_span = traces[0].iter().find(|span| span.parent_id == 0).unwrap();
+        assert_eq!(root_span.metrics.get("_sampling_priority_v1"), Some(&2.0));
+        assert_eq!(
+            root_span.meta.get("_dd.origin").map(String::as_str),
+            Some("synthetics")
+        );
+    }
+
+    #[test]
+    fn test_send_error_is_retryable() {
+        use super::SendError;
+        use hyper::StatusCode;
+
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            assert!(SendError::Status(status, String::new()).is_retryable());
+        }
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::FORBIDDEN,
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ] {
+            assert!(!SendError::Status(status, String::new()).is_retryable());
+        }
+    }
+
     #[test]
     fn test_get_root_span_index_from_complete_trace() {
         let trace = vec![